@@ -4,6 +4,13 @@ use std::process::Command;
 
 fn main() {
     println!("cargo:rerun-if-changed=src/lib.rs");
+    println!("cargo:rerun-if-changed=../utils/src/random.rs");
+    
+    // Nothing to do without the `cuda` feature: the host crate falls back
+    // to its CPU backend and never looks for the PTX file.
+    if env::var_os("CARGO_FEATURE_CUDA").is_none() {
+        return;
+    }
     
     let out_dir = PathBuf::from(env::var("OUT_DIR").unwrap());
     let manifest_dir = PathBuf::from(env::var("CARGO_MANIFEST_DIR").unwrap());
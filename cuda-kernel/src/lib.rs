@@ -4,6 +4,13 @@
 
 use cuda_std::prelude::*;
 
+// Shared with the host crate so both sides generate identical matrices.
+#[path = "../../utils/src/random.rs"]
+mod random;
+// Shared with the host crate so its launch geometry matches the warp layout.
+#[path = "../../utils/src/wmma.rs"]
+mod wmma;
+
 const TILE_M: usize = 128;
 const TILE_N: usize = 128;
 const TILE_K: usize = 16;
@@ -19,9 +26,12 @@ const WARP_SIZE: u32 = 32;
 /// * `k` - Number of columns in A and rows in B
 /// * `alpha` - Scalar multiplier for A*B
 /// * `a` - Input matrix A (m x k) in row-major order
+/// * `lda` - Leading dimension (row pitch in elements) of A
 /// * `b` - Input matrix B (k x n) in row-major order
+/// * `ldb` - Leading dimension of B
 /// * `beta` - Scalar multiplier for C
 /// * `c` - Output matrix C (m x n) in row-major order
+/// * `ldc` - Leading dimension of C
 #[kernel]
 pub unsafe fn gemm_kernel(
     m: u32,
@@ -29,9 +39,12 @@ pub unsafe fn gemm_kernel(
     k: u32,
     alpha: f32,
     a: *const f32,
+    lda: u32,
     b: *const f32,
+    ldb: u32,
     beta: f32,
     c: *mut f32,
+    ldc: u32,
 ) {
     let tx = thread::index_1d() as u32;
     let bx = block::index_x();
@@ -56,8 +69,8 @@ pub unsafe fn gemm_kernel(
         let tile_end = (tile_start + TILE_K as u32).min(k);
         
         for p in tile_start..tile_end {
-            let a_idx = (row * k + p) as isize;
-            let b_idx = (p * n + col) as isize;
+            let a_idx = (row * lda + p) as isize;
+            let b_idx = (p * ldb + col) as isize;
             
             let a_val = *a.offset(a_idx);
             let b_val = *b.offset(b_idx);
@@ -66,7 +79,7 @@ pub unsafe fn gemm_kernel(
         }
     }
     
-    let c_idx = (row * n + col) as isize;
+    let c_idx = (row * ldc + col) as isize;
     let c_val = if beta == 0.0 {
         alpha * sum
     } else {
@@ -87,9 +100,12 @@ pub unsafe fn gemm_kernel_tiled(
     k: u32,
     alpha: f32,
     a: *const f32,
+    lda: u32,
     b: *const f32,
+    ldb: u32,
     beta: f32,
     c: *mut f32,
+    ldc: u32,
 ) {
     // Declare shared memory tiles for A and B
     #[shared]
@@ -122,7 +138,7 @@ pub unsafe fn gemm_kernel_tiled(
             let global_col_k = tile_start_k + tx;
             
             if global_row < m && global_col_k < k {
-                let a_idx = (global_row * k + global_col_k) as isize;
+                let a_idx = (global_row * lda + global_col_k) as isize;
                 TILE_A[ty as usize][tx as usize] = *a.offset(a_idx);
             } else {
                 TILE_A[ty as usize][tx as usize] = 0.0;
@@ -136,7 +152,7 @@ pub unsafe fn gemm_kernel_tiled(
             let global_col = bx * TILE_N as u32 + tx;
             
             if global_row_k < k && global_col < n {
-                let b_idx = (global_row_k * n + global_col) as isize;
+                let b_idx = (global_row_k * ldb + global_col) as isize;
                 TILE_B[ty as usize][tx as usize] = *b.offset(b_idx);
             } else {
                 TILE_B[ty as usize][tx as usize] = 0.0;
@@ -161,7 +177,7 @@ pub unsafe fn gemm_kernel_tiled(
     
     // Write result to global memory
     if row < m && col < n {
-        let c_idx = (row * n + col) as isize;
+        let c_idx = (row * ldc + col) as isize;
         let c_val = if beta == 0.0 {
             alpha * sum
         } else {
@@ -186,9 +202,12 @@ pub unsafe fn gemm_kernel_wmma(
     k: u32,
     alpha: f32,
     a: *const f32,
+    lda: u32,
     b: *const f32,
+    ldb: u32,
     beta: f32,
     c: *mut f32,
+    ldc: u32,
 ) {
     // WMMA tile dimensions for FP32
     const WMMA_M: u32 = 16;
@@ -208,9 +227,10 @@ pub unsafe fn gemm_kernel_wmma(
     let bx = block::index_x();
     let by = block::index_y();
     
-    // Calculate warp's output tile position
-    let warp_row = (by * 4 + (warp_id / 2)) * WMMA_M;
-    let warp_col = (bx * 4 + (warp_id % 2)) * WMMA_N;
+    // Calculate warp's output tile position (16 warps as a 4x4 grid of 16x16 tiles)
+    let (warp_row_local, warp_col_local) = wmma::wmma_warp_origin(warp_id);
+    let warp_row = by * wmma::WMMA_BLOCK_TILE + warp_row_local;
+    let warp_col = bx * wmma::WMMA_BLOCK_TILE + warp_col_local;
     
     // WMMA fragment storage (8 f32 values per fragment for FP32)
     // For 16x16x16: A needs 8 elements, B needs 8 elements, C needs 8 elements
@@ -243,7 +263,7 @@ pub unsafe fn gemm_kernel_wmma(
             let global_col = k_offset + col_a;
             
             if global_row < m && global_col < k {
-                let a_idx = (global_row * k + global_col) as isize;
+                let a_idx = (global_row * lda + global_col) as isize;
                 SMEM_A[row_a as usize][col_a as usize] = *a.offset(a_idx);
             } else {
                 SMEM_A[row_a as usize][col_a as usize] = 0.0;
@@ -262,7 +282,7 @@ pub unsafe fn gemm_kernel_wmma(
             let global_col = bx * WMMA_N * 4 + col_b;
             
             if global_row < k && global_col < n {
-                let b_idx = (global_row * n + global_col) as isize;
+                let b_idx = (global_row * ldb + global_col) as isize;
                 SMEM_B[row_b as usize][col_b as usize] = *b.offset(b_idx);
             } else {
                 SMEM_B[row_b as usize][col_b as usize] = 0.0;
@@ -274,9 +294,6 @@ pub unsafe fn gemm_kernel_wmma(
         // Load fragments from shared memory into WMMA fragments
         // WMMA requires specific memory layouts and lane-to-element mappings
         // Each lane in the warp loads specific elements based on fragment layout
-        let warp_row_local = (warp_id / 2) * WMMA_M;
-        let warp_col_local = (warp_id % 2) * WMMA_N;
-        
         // Load A fragment (16x16 tile, row-major)
         // Fragment distribution: Each of 32 lanes holds 8 elements
         // Lane mapping follows NVIDIA's WMMA fragment layout specification
@@ -321,7 +338,7 @@ pub unsafe fn gemm_kernel_wmma(
             let global_col = warp_col + col_offset;
             
             if global_row < m && global_col < n {
-                let c_idx = (global_row * n + global_col) as isize;
+                let c_idx = (global_row * ldc + global_col) as isize;
                 let c_val = if beta == 0.0 {
                     frag_c[i]
                 } else {
@@ -333,6 +350,41 @@ pub unsafe fn gemm_kernel_wmma(
     }
 }
 
+/// Matrix generator entry point
+/// 
+/// Fills a row-major matrix with `random::element` values, one thread per
+/// element. Results match the host generator bit for bit.
+/// 
+/// # Arguments
+/// * `rows`, `cols` - Matrix dimensions
+/// * `ld` - Leading dimension (row pitch in elements) of `out`
+/// * `key0`, `key1` - Low and high halves of the seed
+/// * `stream` - Operand stream, so A, B and C differ under one seed
+/// * `kind`, `param` - Distribution code and its parameter
+/// * `out` - Output matrix
+#[kernel]
+pub unsafe fn generate_kernel(
+    rows: u32,
+    cols: u32,
+    ld: u32,
+    key0: u32,
+    key1: u32,
+    stream: u32,
+    kind: u32,
+    param: u32,
+    out: *mut f32,
+) {
+    let col = block::index_x() * block::dim_x() + thread::index_x();
+    let row = block::index_y() * block::dim_y() + thread::index_y();
+    
+    if row >= rows || col >= cols {
+        return;
+    }
+    
+    let value = random::element([key0, key1], stream, row, col, kind, param);
+    *out.offset(row as isize * ld as isize + col as isize) = value;
+}
+
 /// WMMA matrix multiply-accumulate operation using Tensor Cores
 /// 
 /// Executes: D = A * B + C using NVIDIA Tensor Cores
@@ -0,0 +1,319 @@
+use anyhow::{ensure, Context, Result};
+use std::path::Path;
+use std::sync::Arc;
+use utils::TensorLayout;
+
+use crate::bench::{HostTimer, Timer};
+use crate::cpu_gemm::CpuGemm;
+use crate::device::DeviceMatrix;
+use crate::driver::{DeviceInfo, Driver};
+use crate::generate::Generator;
+use crate::kernel::GemmKernel;
+use crate::matrix::HostMatrix;
+use crate::variant::KernelVariant;
+
+/// Execution backend for the host-side GEMM pipeline.
+///
+/// A backend owns its matrices and knows how to move data in and out of them
+/// and run `C = alpha * A * B + beta * C` on them. The CUDA path and the pure
+/// Rust CPU path both implement this, so the benchmark loop and verification
+/// can be written once against the trait.
+pub trait GemmBackend {
+    type Matrix;
+
+    /// Short human-readable name, used in logs and reports.
+    fn name(&self) -> &str;
+
+    /// Allocates a matrix with the given layout. Contents are unspecified.
+    fn alloc(&self, layout: TensorLayout) -> Result<Self::Matrix>;
+
+    /// Copies `data` (in the matrix's storage order) into the matrix.
+    fn upload(&self, matrix: &mut Self::Matrix, data: &[f32]) -> Result<()>;
+
+    /// Copies `data` into the matrix storage starting at element `offset`,
+    /// so large inputs can be uploaded a chunk at a time.
+    fn upload_at(&self, matrix: &mut Self::Matrix, offset: usize, data: &[f32]) -> Result<()>;
+
+    fn download(&self, matrix: &Self::Matrix, data: &mut [f32]) -> Result<()>;
+
+    /// Enqueues a GEMM whose dimensions come from the operand layouts.
+    /// Completion is only guaranteed after [`GemmBackend::synchronize`].
+    ///
+    /// Backends without separate kernels compute the same result for every
+    /// `variant`.
+    fn gemm(
+        &self,
+        variant: KernelVariant,
+        alpha: f32,
+        a: &Self::Matrix,
+        b: &Self::Matrix,
+        beta: f32,
+        c: &mut Self::Matrix,
+    ) -> Result<()>;
+
+    fn synchronize(&self) -> Result<()>;
+
+    /// Fills `matrix` with stream `stream` of `generator`. Every backend
+    /// produces the same bits as [`Generator::matrix`].
+    fn generate(&self, matrix: &mut Self::Matrix, generator: &Generator, stream: u32) -> Result<()>;
+
+    /// The GPU the backend runs on, if any.
+    fn device_info(&self) -> Option<&DeviceInfo> {
+        None
+    }
+
+    /// Timer for benchmarking this backend's GEMMs. Defaults to the host
+    /// clock.
+    fn timer(&self) -> Result<Box<dyn Timer + '_>> {
+        Ok(Box::new(HostTimer))
+    }
+
+    fn alloc_from(&self, layout: TensorLayout, data: &[f32]) -> Result<Self::Matrix> {
+        let mut matrix = self.alloc(layout)?;
+        self.upload(&mut matrix, data)?;
+        Ok(matrix)
+    }
+}
+
+/// [`GemmBackend`] that runs the PTX kernels through a [`Driver`].
+///
+/// With [`crate::CudaContext`] this is the real CUDA backend
+/// ([`crate::CudaBackend`]); with [`crate::driver::MockDriver`] it records
+/// launches instead of executing them.
+pub struct DeviceBackend<D: Driver> {
+    kernel: GemmKernel<D>,
+    driver: Arc<D>,
+}
+
+impl<D: Driver> DeviceBackend<D> {
+    pub fn load<P: AsRef<Path>>(driver: D, ptx_path: P) -> Result<Self> {
+        let driver = Arc::new(driver);
+        let kernel = GemmKernel::load(&driver, ptx_path)?;
+        Ok(Self { kernel, driver })
+    }
+
+    pub fn from_ptx(driver: D, ptx: &str) -> Result<Self> {
+        let driver = Arc::new(driver);
+        let kernel = GemmKernel::from_ptx(&driver, ptx)?;
+        Ok(Self { kernel, driver })
+    }
+
+    pub fn driver(&self) -> &D {
+        &self.driver
+    }
+
+    pub fn kernel(&self) -> &GemmKernel<D> {
+        &self.kernel
+    }
+}
+
+impl<D: Driver> GemmBackend for DeviceBackend<D> {
+    type Matrix = DeviceMatrix<f32, D>;
+
+    fn name(&self) -> &str {
+        "cuda"
+    }
+
+    fn alloc(&self, layout: TensorLayout) -> Result<Self::Matrix> {
+        DeviceMatrix::alloc(&self.driver, layout)
+    }
+
+    fn upload(&self, matrix: &mut Self::Matrix, data: &[f32]) -> Result<()> {
+        matrix.copy_from_host(data)
+    }
+
+    fn upload_at(&self, matrix: &mut Self::Matrix, offset: usize, data: &[f32]) -> Result<()> {
+        matrix.copy_from_host_at(offset, data)
+    }
+
+    fn download(&self, matrix: &Self::Matrix, data: &mut [f32]) -> Result<()> {
+        matrix.copy_to_host(data)
+    }
+
+    fn gemm(
+        &self,
+        variant: KernelVariant,
+        alpha: f32,
+        a: &Self::Matrix,
+        b: &Self::Matrix,
+        beta: f32,
+        c: &mut Self::Matrix,
+    ) -> Result<()> {
+        self.kernel.launch(variant, alpha, a, b, beta, c)
+    }
+
+    fn synchronize(&self) -> Result<()> {
+        self.driver.synchronize().context("Kernel execution failed")
+    }
+
+    fn generate(&self, matrix: &mut Self::Matrix, generator: &Generator, stream: u32) -> Result<()> {
+        self.kernel.generate(generator, stream, matrix)
+    }
+
+    fn device_info(&self) -> Option<&DeviceInfo> {
+        Some(self.kernel.device_info())
+    }
+
+    fn timer(&self) -> Result<Box<dyn Timer + '_>> {
+        self.driver.timer()
+    }
+}
+
+/// Pure Rust backend that runs everything on the host.
+///
+/// Matrices are [`HostMatrix`] values of any layout and the GEMM is computed eagerly with
+/// [`CpuGemm`], so `synchronize` is a no-op. Intended for machines without a CUDA driver.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct CpuBackend {
+    gemm: CpuGemm,
+}
+
+impl CpuBackend {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_gemm(gemm: CpuGemm) -> Self {
+        Self { gemm }
+    }
+}
+
+impl GemmBackend for CpuBackend {
+    type Matrix = HostMatrix;
+
+    fn name(&self) -> &str {
+        "cpu"
+    }
+
+    fn alloc(&self, layout: TensorLayout) -> Result<Self::Matrix> {
+        HostMatrix::zeros(layout)
+    }
+
+    fn upload(&self, matrix: &mut Self::Matrix, data: &[f32]) -> Result<()> {
+        let storage = matrix.as_mut_slice();
+        ensure!(
+            storage.len() == data.len(),
+            "Upload size mismatch: matrix has {} elements, host slice has {}",
+            storage.len(),
+            data.len()
+        );
+        storage.copy_from_slice(data);
+        Ok(())
+    }
+
+    fn upload_at(&self, matrix: &mut Self::Matrix, offset: usize, data: &[f32]) -> Result<()> {
+        let storage = matrix.as_mut_slice();
+        ensure!(
+            offset <= storage.len() && data.len() <= storage.len() - offset,
+            "Upload of {} elements at {} overruns a matrix of {} elements",
+            data.len(),
+            offset,
+            storage.len()
+        );
+        storage[offset..offset + data.len()].copy_from_slice(data);
+        Ok(())
+    }
+
+    fn download(&self, matrix: &Self::Matrix, data: &mut [f32]) -> Result<()> {
+        let storage = matrix.as_slice();
+        ensure!(
+            storage.len() == data.len(),
+            "Download size mismatch: matrix has {} elements, host slice has {}",
+            storage.len(),
+            data.len()
+        );
+        data.copy_from_slice(storage);
+        Ok(())
+    }
+
+    fn gemm(
+        &self,
+        _variant: KernelVariant,
+        alpha: f32,
+        a: &Self::Matrix,
+        b: &Self::Matrix,
+        beta: f32,
+        c: &mut Self::Matrix,
+    ) -> Result<()> {
+        self.gemm.run(alpha, a.view(), b.view(), beta, c.view_mut())
+    }
+
+    fn synchronize(&self) -> Result<()> {
+        Ok(())
+    }
+
+    fn generate(&self, matrix: &mut Self::Matrix, generator: &Generator, stream: u32) -> Result<()> {
+        *matrix = generator.matrix(stream, *matrix.layout())?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cpu_backend_round_trip() {
+        let backend = CpuBackend::new();
+        let data = [1.0, 2.0, 3.0, 4.0];
+        let mut matrix = backend.alloc_from(TensorLayout::row_major(2, 2), &data).unwrap();
+
+        let mut out = [0.0; 4];
+        backend.download(&matrix, &mut out).unwrap();
+        assert_eq!(out, data);
+
+        let mut short = [0.0; 3];
+        assert!(backend.download(&matrix, &mut short).is_err());
+
+        backend.upload_at(&mut matrix, 1, &[-2.0, -3.0]).unwrap();
+        assert_eq!(matrix.as_slice(), [1.0, -2.0, -3.0, 4.0]);
+        assert!(backend.upload_at(&mut matrix, 3, &[0.0, 0.0]).is_err());
+
+        let generator = Generator::new(3, crate::generate::Distribution::Normal);
+        let layout = TensorLayout::column_major(3, 5);
+        let mut generated = backend.alloc(layout).unwrap();
+        backend.generate(&mut generated, &generator, 2).unwrap();
+        assert_eq!(generated, generator.matrix(2, layout).unwrap());
+    }
+
+    #[test]
+    fn test_cpu_backend_gemm() {
+        let backend = CpuBackend::new();
+        let a_layout = TensorLayout::row_major(2, 3);
+        let a = backend.alloc_from(a_layout, &[1.0, 2.0, 3.0, 4.0, 5.0, 6.0]).unwrap();
+        // Same B values as row-major [[7, 8], [9, 10], [11, 12]].
+        let b_layout = TensorLayout::column_major(3, 2);
+        let b = backend.alloc_from(b_layout, &[7.0, 9.0, 11.0, 8.0, 10.0, 12.0]).unwrap();
+        let mut c = backend.alloc_from(TensorLayout::row_major(2, 2), &[1.0; 4]).unwrap();
+
+        backend.gemm(KernelVariant::Naive, 2.0, &a, &b, 0.5, &mut c).unwrap();
+        backend.synchronize().unwrap();
+
+        let mut out = [0.0; 4];
+        backend.download(&c, &mut out).unwrap();
+        assert_eq!(out, [116.5, 128.5, 278.5, 308.5]);
+
+        let mut bad_c = backend.alloc(TensorLayout::row_major(3, 2)).unwrap();
+        assert!(backend.gemm(KernelVariant::Naive, 1.0, &a, &b, 0.0, &mut bad_c).is_err());
+    }
+
+    #[test]
+    fn test_device_backend_on_mock_driver() {
+        use crate::driver::{MockDriver, MockFault};
+
+        let backend = DeviceBackend::from_ptx(MockDriver::new(), "").unwrap();
+
+        let a = backend.alloc_from(TensorLayout::row_major(2, 3), &[1.0; 6]).unwrap();
+        let b = backend.alloc_from(TensorLayout::row_major(3, 2), &[1.0; 6]).unwrap();
+        let mut c = backend.alloc(TensorLayout::row_major(2, 2)).unwrap();
+        backend.gemm(KernelVariant::Wmma, 1.0, &a, &b, 0.0, &mut c).unwrap();
+        let launches = backend.driver().launches();
+        assert_eq!(launches.len(), 1);
+        assert_eq!(launches[0].function, "gemm_kernel_wmma");
+
+        backend.driver().inject(MockFault::Synchronize);
+        backend.gemm(KernelVariant::Naive, 1.0, &a, &b, 0.0, &mut c).unwrap();
+        let err = backend.synchronize().unwrap_err();
+        assert_eq!(err.to_string(), "Kernel execution failed");
+    }
+}
@@ -0,0 +1,546 @@
+use anyhow::{ensure, Result};
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use crate::backend::GemmBackend;
+use crate::variant::KernelVariant;
+
+/// Measures how long a piece of work takes.
+///
+/// [`HostTimer`] reads the host clock; device backends hand out timers based
+/// on GPU events through [`GemmBackend::timer`], so the benchmark loop does
+/// not care which one it gets.
+pub trait Timer {
+    /// Runs `work` once and returns its duration in seconds. For host timers
+    /// `work` must wait for its own completion.
+    fn time(&mut self, work: &mut dyn FnMut() -> Result<()>) -> Result<f64>;
+}
+
+/// [`Timer`] reading the host's monotonic clock.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct HostTimer;
+
+impl Timer for HostTimer {
+    fn time(&mut self, work: &mut dyn FnMut() -> Result<()>) -> Result<f64> {
+        let start = Instant::now();
+        work()?;
+        Ok(start.elapsed().as_secs_f64())
+    }
+}
+
+/// Durations of repeated runs, in seconds.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Timings {
+    pub samples: Vec<f64>,
+}
+
+impl Timings {
+    pub fn min(&self) -> f64 {
+        self.samples.iter().copied().fold(f64::INFINITY, f64::min)
+    }
+
+    pub fn max(&self) -> f64 {
+        self.samples.iter().copied().fold(f64::NEG_INFINITY, f64::max)
+    }
+
+    pub fn mean(&self) -> f64 {
+        self.samples.iter().sum::<f64>() / self.samples.len() as f64
+    }
+
+    pub fn median(&self) -> f64 {
+        self.percentile(50.0)
+    }
+
+    pub fn p95(&self) -> f64 {
+        self.percentile(95.0)
+    }
+
+    /// The `p`-th percentile, interpolating linearly between samples.
+    pub fn percentile(&self, p: f64) -> f64 {
+        let mut sorted = self.samples.clone();
+        sorted.sort_by(f64::total_cmp);
+        let rank = p.clamp(0.0, 100.0) / 100.0 * (sorted.len() - 1) as f64;
+        let (lower, upper) = (rank.floor() as usize, rank.ceil() as usize);
+        sorted[lower] + (sorted[upper] - sorted[lower]) * (rank - lower as f64)
+    }
+
+    /// Sample standard deviation; zero for a single sample.
+    pub fn stddev(&self) -> f64 {
+        if self.samples.len() < 2 {
+            return 0.0;
+        }
+        let mean = self.mean();
+        let sum_squares: f64 = self.samples.iter().map(|t| (t - mean) * (t - mean)).sum();
+        (sum_squares / (self.samples.len() - 1) as f64).sqrt()
+    }
+
+    /// Median absolute deviation from the median.
+    pub fn mad(&self) -> f64 {
+        let median = self.median();
+        Timings {
+            samples: self.samples.iter().map(|t| (t - median).abs()).collect(),
+        }
+        .median()
+    }
+
+    /// Student-t confidence interval for the mean at `level` (e.g. 0.95).
+    /// Collapses to the mean for a single sample.
+    pub fn confidence_interval(&self, level: f64) -> (f64, f64) {
+        let mean = self.mean();
+        let n = self.samples.len();
+        if n < 2 {
+            return (mean, mean);
+        }
+        let t = student_t_quantile(0.5 + level / 2.0, (n - 1) as f64);
+        let half_width = t * self.stddev() / (n as f64).sqrt();
+        (mean - half_width, mean + half_width)
+    }
+
+    /// Splits off samples whose modified z-score `0.6745 * |t - median| / MAD`
+    /// exceeds `threshold` (3.5 is the usual choice). Nothing is rejected
+    /// when the MAD is zero, or when every sample would be.
+    pub fn without_outliers(&self, threshold: f64) -> (Timings, Vec<f64>) {
+        let (median, mad) = (self.median(), self.mad());
+        if mad == 0.0 {
+            return (self.clone(), Vec::new());
+        }
+        let (kept, outliers): (Vec<f64>, Vec<f64>) = self
+            .samples
+            .iter()
+            .partition(|&&t| 0.6745 * (t - median).abs() / mad <= threshold);
+        if kept.is_empty() {
+            return (self.clone(), Vec::new());
+        }
+        (Timings { samples: kept }, outliers)
+    }
+
+    /// Throughput of an `m x n x k` GEMM at the mean time.
+    pub fn gflops(&self, m: usize, n: usize, k: usize) -> f64 {
+        2.0 * m as f64 * n as f64 * k as f64 / (self.mean() * 1e9)
+    }
+}
+
+/// Inverse of the standard normal CDF (Acklam's rational approximation,
+/// relative error below 1.2e-9).
+fn normal_quantile(p: f64) -> f64 {
+    const A: [f64; 6] = [
+        -3.969683028665376e1,
+        2.209460984245205e2,
+        -2.759285104469687e2,
+        1.38357751867269e2,
+        -3.066479806614716e1,
+        2.506628277459239,
+    ];
+    const B: [f64; 5] = [
+        -5.447609879822406e1,
+        1.615858368580409e2,
+        -1.556989798598866e2,
+        6.680131188771972e1,
+        -1.328068155288572e1,
+    ];
+    const C: [f64; 6] = [
+        -7.784894002430293e-3,
+        -3.223964580411365e-1,
+        -2.400758277161838,
+        -2.549732539343734,
+        4.374664141464968,
+        2.938163982698783,
+    ];
+    const D: [f64; 4] = [7.784695709041462e-3, 3.224671290700398e-1, 2.445134137142996, 3.754408661907416];
+
+    let tail = |q: f64| {
+        (((((C[0] * q + C[1]) * q + C[2]) * q + C[3]) * q + C[4]) * q + C[5])
+            / ((((D[0] * q + D[1]) * q + D[2]) * q + D[3]) * q + 1.0)
+    };
+    if p < 0.02425 {
+        tail((-2.0 * p.ln()).sqrt())
+    } else if p > 1.0 - 0.02425 {
+        -tail((-2.0 * (1.0 - p).ln()).sqrt())
+    } else {
+        let q = p - 0.5;
+        let r = q * q;
+        (((((A[0] * r + A[1]) * r + A[2]) * r + A[3]) * r + A[4]) * r + A[5]) * q
+            / (((((B[0] * r + B[1]) * r + B[2]) * r + B[3]) * r + B[4]) * r + 1.0)
+    }
+}
+
+/// Quantile of Student's t distribution with `dof` degrees of freedom.
+/// Exact for one and two degrees of freedom; beyond that a Cornish-Fisher
+/// expansion around the normal quantile, within 0.2% from `dof = 3` on.
+pub(crate) fn student_t_quantile(p: f64, dof: f64) -> f64 {
+    if dof == 1.0 {
+        return (std::f64::consts::PI * (p - 0.5)).tan();
+    }
+    if dof == 2.0 {
+        return (2.0 * p - 1.0) / (2.0 * p * (1.0 - p)).sqrt();
+    }
+    let z = normal_quantile(p);
+    let z2 = z * z;
+    let g1 = (z2 + 1.0) * z / 4.0;
+    let g2 = ((5.0 * z2 + 16.0) * z2 + 3.0) * z / 96.0;
+    let g3 = (((3.0 * z2 + 19.0) * z2 + 17.0) * z2 - 15.0) * z / 384.0;
+    let g4 = ((((79.0 * z2 + 776.0) * z2 + 1482.0) * z2 - 1920.0) * z2 - 945.0) * z / 92160.0;
+    z + g1 / dof + g2 / dof.powi(2) + g3 / dof.powi(3) + g4 / dof.powi(4)
+}
+
+/// Keeps warming up until the medians of the last two `window`-sized groups
+/// of runs agree within `tolerance` (relative), e.g. once GPU clocks have
+/// ramped up. Gives up after `max_runs` extra runs.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SteadyState {
+    pub window: usize,
+    pub tolerance: f64,
+    pub max_runs: usize,
+}
+
+impl SteadyState {
+    pub fn new(tolerance: f64) -> Self {
+        Self {
+            window: 5,
+            tolerance,
+            max_runs: 100,
+        }
+    }
+
+    fn reached(&self, samples: &[f64]) -> bool {
+        if self.window == 0 || samples.len() < 2 * self.window {
+            return false;
+        }
+        let median = |window: &[f64]| Timings { samples: window.to_vec() }.median();
+        let recent = &samples[samples.len() - 2 * self.window..];
+        let (previous, last) = (median(&recent[..self.window]), median(&recent[self.window..]));
+        (last - previous).abs() <= self.tolerance * previous
+    }
+}
+
+/// How to benchmark: warmup, iteration bounds, time budget, steady-state
+/// detection and outlier rejection.
+///
+/// At least `min_iterations` runs are measured. With a time budget,
+/// measurement continues until the budget (host wall-clock time, including
+/// per-run setup) is spent or `max_iterations` is reached.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Benchmark {
+    pub warmup: usize,
+    pub min_iterations: usize,
+    pub max_iterations: usize,
+    pub time_budget: Option<Duration>,
+    pub steady_state: Option<SteadyState>,
+    /// Modified z-score above which samples are dropped; `None` keeps all.
+    pub outlier_threshold: Option<f64>,
+    /// Level of the reported confidence interval.
+    pub confidence: f64,
+}
+
+impl Default for Benchmark {
+    fn default() -> Self {
+        Self::new(5)
+    }
+}
+
+impl Benchmark {
+    /// Exactly `iterations` measured runs after one warmup run.
+    pub fn new(iterations: usize) -> Self {
+        Self {
+            warmup: 1,
+            min_iterations: iterations,
+            max_iterations: iterations,
+            time_budget: None,
+            steady_state: None,
+            outlier_threshold: Some(3.5),
+            confidence: 0.95,
+        }
+    }
+
+    pub fn with_warmup(mut self, warmup: usize) -> Self {
+        self.warmup = warmup;
+        self
+    }
+
+    pub fn with_max_iterations(mut self, max_iterations: usize) -> Self {
+        self.max_iterations = max_iterations;
+        self
+    }
+
+    pub fn with_time_budget(mut self, budget: Duration) -> Self {
+        self.time_budget = Some(budget);
+        self
+    }
+
+    pub fn with_steady_state(mut self, steady_state: SteadyState) -> Self {
+        self.steady_state = Some(steady_state);
+        self
+    }
+
+    pub fn with_outlier_threshold(mut self, threshold: Option<f64>) -> Self {
+        self.outlier_threshold = threshold;
+        self
+    }
+
+    pub fn with_confidence(mut self, level: f64) -> Self {
+        self.confidence = level;
+        self
+    }
+
+    /// Collects samples from `sample`, which performs one run (including
+    /// any untimed setup) and returns its measured duration in seconds.
+    pub fn run(&self, mut sample: impl FnMut() -> Result<f64>) -> Result<BenchmarkResult> {
+        ensure!(self.min_iterations > 0, "At least one timed iteration is required");
+        ensure!(
+            self.max_iterations >= self.min_iterations,
+            "Maximum iterations ({}) is below the minimum ({})",
+            self.max_iterations,
+            self.min_iterations
+        );
+        ensure!(
+            self.confidence > 0.0 && self.confidence < 1.0,
+            "Confidence level must be in (0, 1), got {}",
+            self.confidence
+        );
+        if let Some(threshold) = self.outlier_threshold {
+            ensure!(
+                threshold.is_finite() && threshold > 0.0,
+                "Outlier threshold must be a positive number, got {}",
+                threshold
+            );
+        }
+
+        let mut warmup = Vec::with_capacity(self.warmup);
+        for _ in 0..self.warmup {
+            warmup.push(sample()?);
+        }
+        let steady = match self.steady_state {
+            Some(steady_state) => {
+                let mut extra = 0;
+                while !steady_state.reached(&warmup) && extra < steady_state.max_runs {
+                    warmup.push(sample()?);
+                    extra += 1;
+                }
+                Some(steady_state.reached(&warmup))
+            }
+            None => None,
+        };
+
+        let start = Instant::now();
+        let mut samples = Vec::with_capacity(self.min_iterations);
+        while samples.len() < self.max_iterations {
+            let budget_spent = self.time_budget.is_none_or(|budget| start.elapsed() >= budget);
+            if samples.len() >= self.min_iterations && budget_spent {
+                break;
+            }
+            samples.push(sample()?);
+        }
+
+        let measured = samples.len();
+        let timings = Timings { samples };
+        let (timings, outliers) = match self.outlier_threshold {
+            Some(threshold) => timings.without_outliers(threshold),
+            None => (timings, Vec::new()),
+        };
+        Ok(BenchmarkResult {
+            timings,
+            outliers,
+            measured,
+            warmup_runs: warmup.len(),
+            steady,
+            confidence: self.confidence,
+        })
+    }
+}
+
+/// Outcome of [`Benchmark::run`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct BenchmarkResult {
+    /// Measured samples left after outlier rejection.
+    pub timings: Timings,
+    /// Rejected samples.
+    pub outliers: Vec<f64>,
+    /// Number of measured runs, including outliers.
+    pub measured: usize,
+    pub warmup_runs: usize,
+    /// Whether steady state was reached; `None` when detection was off.
+    pub steady: Option<bool>,
+    pub confidence: f64,
+}
+
+impl BenchmarkResult {
+    pub fn confidence_interval(&self) -> (f64, f64) {
+        self.timings.confidence_interval(self.confidence)
+    }
+}
+
+impl fmt::Display for BenchmarkResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let t = &self.timings;
+        let ms = |seconds: f64| seconds * 1e3;
+        let (low, high) = self.confidence_interval();
+        writeln!(
+            f,
+            "  Runs: {} measured, {} outlier(s) rejected, {} warm-up",
+            self.measured,
+            self.outliers.len(),
+            self.warmup_runs
+        )?;
+        if let Some(steady) = self.steady {
+            writeln!(f, "  Steady state: {}", if steady { "reached" } else { "not reached" })?;
+        }
+        writeln!(
+            f,
+            "  Min / median / mean / p95: {:.3} / {:.3} / {:.3} / {:.3} ms",
+            ms(t.min()),
+            ms(t.median()),
+            ms(t.mean()),
+            ms(t.p95())
+        )?;
+        write!(
+            f,
+            "  Stddev: {:.3} ms, {:.0}% CI of mean: [{:.3}, {:.3}] ms",
+            ms(t.stddev()),
+            self.confidence * 100.0,
+            ms(low),
+            ms(high)
+        )
+    }
+}
+
+/// Benchmarks one GEMM with the backend's [`Timer`].
+///
+/// C is restored from `c_init` before every run, outside the timed region,
+/// so runs with `beta != 0` all see the same input and leave `c` holding the
+/// result of a single GEMM.
+#[allow(clippy::too_many_arguments)]
+pub fn time_gemm<B: GemmBackend>(
+    backend: &B,
+    variant: KernelVariant,
+    alpha: f32,
+    a: &B::Matrix,
+    b: &B::Matrix,
+    beta: f32,
+    c: &mut B::Matrix,
+    c_init: &[f32],
+    benchmark: &Benchmark,
+) -> Result<BenchmarkResult> {
+    let mut timer = backend.timer()?;
+    benchmark.run(|| {
+        backend.upload(c, c_init)?;
+        backend.synchronize()?;
+        timer.time(&mut || {
+            backend.gemm(variant, alpha, a, b, beta, c)?;
+            backend.synchronize()
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::CpuBackend;
+    use utils::TensorLayout;
+
+    #[test]
+    fn test_timing_stats() {
+        let timings = Timings { samples: vec![0.004, 0.001, 0.003, 0.002] };
+        assert_eq!(timings.min(), 0.001);
+        assert_eq!(timings.max(), 0.004);
+        assert!((timings.mean() - 0.0025).abs() < 1e-12);
+        assert!((timings.median() - 0.0025).abs() < 1e-12);
+        assert!((timings.p95() - 0.00385).abs() < 1e-12);
+        assert!((timings.stddev() - 0.001290994).abs() < 1e-9);
+        assert!((timings.mad() - 0.001).abs() < 1e-12);
+        assert!((timings.gflops(100, 100, 100) - 0.8).abs() < 1e-9);
+        assert_eq!(Timings { samples: vec![1.0] }.stddev(), 0.0);
+        assert_eq!(Timings { samples: vec![1.0] }.confidence_interval(0.95), (1.0, 1.0));
+
+        // t(0.975, 4) = 2.776
+        let (low, high) = Timings { samples: vec![1.0, 2.0, 3.0, 4.0, 5.0] }.confidence_interval(0.95);
+        let half_width = 2.776 * 2.5f64.sqrt() / 5f64.sqrt();
+        assert!((low - (3.0 - half_width)).abs() < 1e-2);
+        assert!((high - (3.0 + half_width)).abs() < 1e-2);
+        assert!((normal_quantile(0.975) - 1.959964).abs() < 1e-6);
+        assert!((student_t_quantile(0.975, 1.0) - 12.706205).abs() < 1e-5);
+        assert!((student_t_quantile(0.975, 2.0) - 4.302653).abs() < 1e-5);
+        assert!((student_t_quantile(0.975, 3.0) - 3.182446).abs() < 5e-3);
+        assert!((student_t_quantile(0.975, 30.0) - 2.042272).abs() < 1e-4);
+    }
+
+    #[test]
+    fn test_outlier_rejection() {
+        let timings = Timings { samples: vec![1.0, 1.1, 0.9, 1.0, 1.05, 0.95, 9.0] };
+        let (kept, outliers) = timings.without_outliers(3.5);
+        assert_eq!(outliers, [9.0]);
+        assert_eq!(kept.samples.len(), 6);
+
+        let constant = Timings { samples: vec![1.0, 1.0, 1.0, 2.0] };
+        assert_eq!(constant.without_outliers(3.5).1, Vec::<f64>::new());
+
+        // A threshold that rejects everything keeps the samples instead.
+        let even = Timings { samples: vec![1.0, 2.0, 3.0, 4.0] };
+        for threshold in [0.0, -1.0] {
+            let (kept, outliers) = even.without_outliers(threshold);
+            assert_eq!((kept, outliers), (even.clone(), Vec::new()));
+        }
+        assert!(Benchmark::new(3).with_outlier_threshold(Some(0.0)).run(|| Ok(1.0)).is_err());
+        assert!(Benchmark::new(3).with_outlier_threshold(Some(f64::NAN)).run(|| Ok(1.0)).is_err());
+    }
+
+    #[test]
+    fn test_benchmark_iterations_and_steady_state() {
+        let mut runs = 0;
+        let result = Benchmark::new(3).with_warmup(2).run(|| {
+            runs += 1;
+            Ok(1.0)
+        });
+        let result = result.unwrap();
+        assert_eq!((runs, result.measured, result.warmup_runs), (5, 3, 2));
+        assert_eq!(result.steady, None);
+
+        // Runs get faster until they settle at 1.0.
+        let mut times = [8.0, 6.0, 4.0, 2.0, 1.0].into_iter().chain(std::iter::repeat(1.0));
+        let result = Benchmark::new(4)
+            .with_warmup(0)
+            .with_steady_state(SteadyState { window: 2, tolerance: 0.01, max_runs: 50 })
+            .run(|| Ok(times.next().unwrap()))
+            .unwrap();
+        assert_eq!(result.steady, Some(true));
+        assert_eq!(result.warmup_runs, 8);
+        assert_eq!(result.timings.mean(), 1.0);
+
+        let mut slower = 0.0;
+        let result = Benchmark::new(1)
+            .with_warmup(0)
+            .with_steady_state(SteadyState { window: 2, tolerance: 0.01, max_runs: 10 })
+            .run(|| {
+                slower += 1.0;
+                Ok(slower)
+            })
+            .unwrap();
+        assert_eq!((result.steady, result.warmup_runs), (Some(false), 10));
+
+        // A zero budget stops at the minimum; a generous one runs to the maximum.
+        let budget = |budget: Duration| {
+            Benchmark::new(2).with_max_iterations(6).with_time_budget(budget).run(|| Ok(1.0)).unwrap().measured
+        };
+        assert_eq!(budget(Duration::ZERO), 2);
+        assert_eq!(budget(Duration::from_secs(60)), 6);
+
+        assert!(Benchmark::new(0).run(|| Ok(1.0)).is_err());
+        assert!(Benchmark::new(4).with_max_iterations(2).run(|| Ok(1.0)).is_err());
+        assert!(Benchmark::new(2).run(|| anyhow::bail!("launch failed")).is_err());
+    }
+
+    #[test]
+    fn test_time_gemm_restores_c() {
+        let backend = CpuBackend::new();
+        let a = backend.alloc_from(TensorLayout::row_major(2, 2), &[1.0, 2.0, 3.0, 4.0]).unwrap();
+        let b = backend.alloc_from(TensorLayout::row_major(2, 2), &[1.0, 0.0, 0.0, 1.0]).unwrap();
+        let mut c = backend.alloc(TensorLayout::row_major(2, 2)).unwrap();
+
+        let benchmark = Benchmark::new(3).with_warmup(2).with_outlier_threshold(None);
+        let result = time_gemm(&backend, KernelVariant::Naive, 1.0, &a, &b, 1.0, &mut c, &[1.0; 4], &benchmark);
+        assert_eq!(result.unwrap().timings.samples.len(), 3);
+
+        let mut out = [0.0; 4];
+        backend.download(&c, &mut out).unwrap();
+        assert_eq!(out, [2.0, 3.0, 4.0, 5.0]);
+    }
+}
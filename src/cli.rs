@@ -0,0 +1,862 @@
+//! Command-line surface of `gemm-optimizer`, kept out of `main` so it can be
+//! unit tested.
+
+use anyhow::{anyhow, bail, ensure, Context, Error, Result};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+use utils::{DataType, TensorLayout};
+
+use crate::backend::GemmBackend;
+use crate::bench::{Benchmark, SteadyState};
+use crate::compare::CompareOptions;
+use crate::error_bound::ErrorModel;
+use crate::generate::{Generator, STREAM_A, STREAM_B, STREAM_C};
+use crate::golden::DEFAULT_GOLDEN_DIR;
+use crate::mapped;
+use crate::matrix::MatrixRef;
+use crate::matrix_io;
+use crate::reference::Summation;
+use crate::sweep::{parse_shapes, read_shapes_file, GemmShape, OutputFormat};
+use crate::variant::KernelVariant;
+use crate::verify::{AbsoluteTolerance, KScaledTolerance, RelativeTolerance, TolerancePolicy, UlpTolerance};
+
+/// Cap on timed runs when only `--time-budget` bounds the benchmark.
+pub const DEFAULT_MAX_ITERATIONS: usize = 1000;
+
+pub const DEFAULT_PTX_PATH: &str = "cuda-kernel/target/nvptx64-nvidia-cuda/release/gemm_kernel.ptx";
+
+pub const USAGE: &str = "\
+Usage: gemm-optimizer [COMMAND] [OPTIONS]
+
+Commands:
+  run        Benchmark a kernel and verify its result (default)
+  verify     Run a kernel once and verify its result
+  sweep      Benchmark kernel variants over a list of shapes
+  compare    Compare two record files from `sweep --record`; fails on regressions
+  generate   Write seeded A, B and C inputs for a shape
+  golden     Store seeded inputs and their f64 reference result as goldens
+  replay     Check kernel variants against stored goldens
+  info       Print the device and the kernel variants it supports
+  help       Print this message
+
+Options:
+  -m, -n, -k <N>           Matrix dimensions (default 1024)
+  --size <MxNxK>           All three dimensions at once
+  --alpha <F>              Scale of A * B (default 1)
+  --beta <F>               Scale of the original C (default 0)
+  --variant <NAME>         naive, tiled or wmma (default naive)
+  --warmup <N>             Untimed runs before the benchmark (default 1)
+  --iterations <N>         Minimum timed runs (default 5)
+  --max-iterations <N>     Maximum timed runs with --time-budget (default 1000)
+  --time-budget <MS>       Keep timing until this much time has passed
+  --steady-state <TOL>     Extend warm-up until run times settle within TOL (relative)
+  --outliers <Z|off>       Reject runs beyond this MAD-based z-score (default 3.5)
+  --no-verify              Skip verification
+  --tolerance <POLICY>     bound, abs:<E>, rel:<E>, ulp:<N> or k-scaled (default bound)
+  --precision <TYPE>       Rounding model for `bound`: fp32, tf32, bf16, fp16, fp8 or e5m2
+  --reference <SUM>        f32, or an f64 reference with naive, kahan or pairwise summation
+  --backend <NAME>         auto, cuda or cpu (default auto)
+  --device <N>             CUDA device ordinal (default 0)
+  --ptx <PATH>             Kernel PTX file
+  --a, --b, --c <PATH>     Input matrices: .npy, .npz (member a, b or c), raw
+                           row-major f32 or headered
+  --save-c <PATH>          Save the computed C as .npy, .npz, raw (_MxN.bin) or headered
+  --dist <DIST>            Contents of generated operands (default uniform): uniform,
+                           normal, small-int[:BOUND], index, ill-conditioned[:BITS],
+                           wide-range[:EXPONENT] or special[:RATE]
+  --seed <N>               Seed of generated operands (default 0)
+
+Sweep options:
+  --shapes <LIST>          Comma-separated MxNxK shapes and presets: small, medium,
+                           large, standard, non-square or all
+  --shapes-file <PATH>     Shape list file, one or more entries per line, `#` comments
+  --variants <LIST>        Comma-separated variants, or all (default)
+  --format <FORMAT>        csv (default) or json
+  --output <PATH>          Write the table to a file instead of stdout
+  --record <PATH>          Also save every run time as a record file for compare
+
+Generate options (plus the shape, --dist, --seed and device options):
+  --output <PATH>          FILE.npz with members A, B and C, or a directory for
+                           input_A_<M>x<K>.bin, input_B_<K>x<N>.bin and input_C_<M>x<N>.bin
+
+Golden options (plus the shape, --alpha, --beta, --dist and --seed options):
+  --shapes, --shapes-file  Shapes to store, as for sweep (default: the --size shape)
+  --reference <SUM>        Summation of the f64 reference: naive, kahan (default) or pairwise
+  --output <DIR>           Root of the golden directories (default data/golden)
+
+Replay usage: gemm-optimizer replay [DIR] [OPTIONS]
+  DIR                      A golden, or a root of goldens (default data/golden)
+  --variants <LIST>        Comma-separated variants, or all (default)
+  Plus the device, --tolerance and --precision options.
+
+Compare usage: gemm-optimizer compare <BASELINE> <CURRENT> [OPTIONS]
+  --threshold <PCT>        Slowdown that fails the check (default 5)
+  --confidence <LEVEL>     Level changes must be significant at (default 0.95)
+  --outliers <Z|off>       Outlier rejection applied to both files (default 3.5)
+  --fail-on-missing        Also fail when a baseline configuration has no result";
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    Run(RunArgs),
+    Verify(RunArgs),
+    Sweep(SweepArgs),
+    Compare(CompareArgs),
+    Generate(GenerateArgs),
+    Golden(GoldenArgs),
+    Replay(ReplayArgs),
+    Info(DeviceArgs),
+    Help,
+}
+
+impl Command {
+    /// Parses the arguments after the program name.
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Self> {
+        let mut args = args.into_iter().peekable();
+        let command = match args.peek().map(String::as_str) {
+            Some("run") | Some("verify") | Some("sweep") | Some("compare") | Some("generate") | Some("golden")
+            | Some("replay") | Some("info") | Some("help") => args.next(),
+            _ => None,
+        };
+        if command.as_deref() == Some("compare") {
+            return CompareArgs::parse(args);
+        }
+        let is_sweep = command.as_deref() == Some("sweep");
+        let is_generate = command.as_deref() == Some("generate");
+        let is_golden = command.as_deref() == Some("golden");
+        let is_replay = command.as_deref() == Some("replay");
+
+        let mut run = RunArgs::default();
+        let mut sweep = SweepArgs::default();
+        let mut variants = None;
+        let mut max_iterations = None;
+        let mut output = None;
+        let mut goldens = None;
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().with_context(|| format!("{} requires a value", arg));
+            match arg.as_str() {
+                "-h" | "--help" => return Ok(Command::Help),
+                "--alpha" => run.alpha = parse_value(&arg, value()?)?,
+                "--beta" => run.beta = parse_value(&arg, value()?)?,
+                "--variant" if is_sweep || is_replay => variants = Some(vec![value()?.parse()?]),
+                "--variant" => run.variant = value()?.parse()?,
+                "--warmup" => run.benchmark.warmup = parse_value(&arg, value()?)?,
+                "--iterations" => run.benchmark.min_iterations = parse_value(&arg, value()?)?,
+                "--max-iterations" => max_iterations = Some(parse_value(&arg, value()?)?),
+                "--time-budget" => {
+                    let millis: f64 = parse_value(&arg, value()?)?;
+                    ensure!(millis.is_finite() && millis >= 0.0, "--time-budget must be a non-negative number");
+                    run.benchmark.time_budget = Some(Duration::from_secs_f64(millis / 1e3));
+                }
+                "--steady-state" => run.benchmark.steady_state = Some(SteadyState::new(parse_value(&arg, value()?)?)),
+                "--outliers" => run.benchmark.outlier_threshold = parse_outliers(&arg, value()?)?,
+                "--no-verify" => run.verify = false,
+                "--tolerance" => run.tolerance = value()?.parse()?,
+                "--precision" => run.precision = value()?.parse()?,
+                "--backend" => run.device.backend = value()?.parse()?,
+                "--device" => run.device.ordinal = parse_value(&arg, value()?)?,
+                "--ptx" => run.device.ptx = value()?.into(),
+                "-m" | "-n" | "-k" | "--size" | "--reference" | "--a" | "--b" | "--c" | "--save-c" if is_sweep => {
+                    bail!("{} does not apply to sweep; use --shapes or --shapes-file", arg)
+                }
+                "-m" => run.m = parse_value(&arg, value()?)?,
+                "-n" => run.n = parse_value(&arg, value()?)?,
+                "-k" => run.k = parse_value(&arg, value()?)?,
+                "--size" => {
+                    let shape: GemmShape = value()?.parse()?;
+                    (run.m, run.n, run.k) = (shape.m, shape.n, shape.k);
+                }
+                "--reference" => {
+                    run.reference = match value()?.as_str() {
+                        "f32" => None,
+                        summation => Some(summation.parse()?),
+                    }
+                }
+                "--a" => run.inputs.a = Some(value()?.into()),
+                "--b" => run.inputs.b = Some(value()?.into()),
+                "--c" => run.inputs.c = Some(value()?.into()),
+                "--dist" => run.generator.distribution = value()?.parse()?,
+                "--seed" => run.generator.seed = parse_value(&arg, value()?)?,
+                "--save-c" => run.save_c = Some(value()?.into()),
+                "--output" if !is_sweep && !is_generate && !is_golden => {
+                    bail!("--output only applies to the sweep, generate and golden commands")
+                }
+                "--shapes" | "--shapes-file" if !is_sweep && !is_golden => {
+                    bail!("{} only applies to the sweep and golden commands", arg)
+                }
+                "--variants" if !is_sweep && !is_replay => bail!("--variants only applies to the sweep and replay commands"),
+                "--format" | "--record" if !is_sweep => bail!("{} only applies to the sweep command", arg),
+                "--shapes" => sweep.shapes.extend(parse_shapes(&value()?)?),
+                "--shapes-file" => sweep.shapes.extend(read_shapes_file(Path::new(&value()?))?),
+                "--variants" => variants = Some(parse_variants(&value()?)?),
+                "--format" => sweep.format = value()?.parse()?,
+                "--output" => output = Some(value()?.into()),
+                "--record" => sweep.record = Some(value()?.into()),
+                dir if is_replay && !dir.starts_with('-') && goldens.is_none() => goldens = Some(dir.into()),
+                other => bail!("Unknown argument `{}`", other),
+            }
+        }
+
+        let benchmark = &mut run.benchmark;
+        ensure!(benchmark.min_iterations > 0, "--iterations must be at least 1");
+        benchmark.max_iterations = match (max_iterations, benchmark.time_budget) {
+            (Some(max), _) => max,
+            (None, Some(_)) => DEFAULT_MAX_ITERATIONS.max(benchmark.min_iterations),
+            (None, None) => benchmark.min_iterations,
+        };
+        ensure!(
+            benchmark.max_iterations >= benchmark.min_iterations,
+            "--max-iterations must be at least --iterations"
+        );
+        Ok(match command.as_deref() {
+            None | Some("run") => Command::Run(run),
+            Some("verify") => {
+                ensure!(run.verify, "--no-verify makes no sense with the verify command");
+                Command::Verify(run)
+            }
+            Some("sweep") => {
+                ensure!(!sweep.shapes.is_empty(), "sweep requires --shapes or --shapes-file");
+                sweep.variants = variants.unwrap_or_else(|| KernelVariant::ALL.to_vec());
+                sweep.output = output;
+                sweep.run = run;
+                Command::Sweep(sweep)
+            }
+            Some("generate") => {
+                let output = output.context("generate requires --output <DIR or FILE.npz>")?;
+                Command::Generate(GenerateArgs { run, output })
+            }
+            Some("golden") => {
+                if sweep.shapes.is_empty() {
+                    sweep.shapes.push(GemmShape::new(run.m, run.n, run.k));
+                }
+                Command::Golden(GoldenArgs {
+                    summation: run.reference.unwrap_or(Summation::Kahan),
+                    run,
+                    shapes: sweep.shapes,
+                    output: output.unwrap_or_else(|| DEFAULT_GOLDEN_DIR.into()),
+                })
+            }
+            Some("replay") => Command::Replay(ReplayArgs {
+                run,
+                goldens: goldens.unwrap_or_else(|| DEFAULT_GOLDEN_DIR.into()),
+                variants: variants.unwrap_or_else(|| KernelVariant::ALL.to_vec()),
+            }),
+            Some("info") => Command::Info(run.device),
+            _ => Command::Help,
+        })
+    }
+}
+
+fn parse_value<T: FromStr>(flag: &str, value: String) -> Result<T>
+where
+    T::Err: std::error::Error + Send + Sync + 'static,
+{
+    value
+        .parse()
+        .with_context(|| format!("Invalid value `{}` for {}", value, flag))
+}
+
+/// `--outliers`: `off`, or a positive modified z-score.
+fn parse_outliers(flag: &str, value: String) -> Result<Option<f64>> {
+    if value == "off" {
+        return Ok(None);
+    }
+    let threshold: f64 = parse_value(flag, value)?;
+    ensure!(threshold.is_finite() && threshold > 0.0, "{} must be a positive number or `off`", flag);
+    Ok(Some(threshold))
+}
+
+fn parse_variants(list: &str) -> Result<Vec<KernelVariant>> {
+    if list == "all" {
+        return Ok(KernelVariant::ALL.to_vec());
+    }
+    list.split(',').map(str::parse).collect()
+}
+
+/// Which [`crate::GemmBackend`] to run on.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum BackendKind {
+    /// CUDA when it initialises, the CPU otherwise.
+    #[default]
+    Auto,
+    Cuda,
+    Cpu,
+}
+
+impl fmt::Display for BackendKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            BackendKind::Auto => "auto",
+            BackendKind::Cuda => "cuda",
+            BackendKind::Cpu => "cpu",
+        })
+    }
+}
+
+impl FromStr for BackendKind {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "auto" => Ok(BackendKind::Auto),
+            "cuda" => Ok(BackendKind::Cuda),
+            "cpu" => Ok(BackendKind::Cpu),
+            other => bail!("Unknown backend `{}` (expected auto, cuda or cpu)", other),
+        }
+    }
+}
+
+/// The `--tolerance` policy, resolved against the operands by
+/// [`ToleranceSpec::policy`].
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum ToleranceSpec {
+    /// Per-element a-priori bounds from the `--precision` [`ErrorModel`].
+    #[default]
+    Bound,
+    Absolute(f64),
+    Relative(f64),
+    Ulp(u32),
+    KScaled,
+}
+
+impl ToleranceSpec {
+    /// Builds the policy for `alpha * A * B + beta * C` with row-major
+    /// operands. `model` only matters for [`ToleranceSpec::Bound`].
+    #[allow(clippy::too_many_arguments)]
+    pub fn policy(
+        &self,
+        model: ErrorModel,
+        (m, n, k): (usize, usize, usize),
+        alpha: f32,
+        a: &[f32],
+        b: &[f32],
+        beta: f32,
+        c: &[f32],
+    ) -> Result<Box<dyn TolerancePolicy>> {
+        Ok(match *self {
+            ToleranceSpec::Bound => Box::new(model.tolerance(
+                alpha,
+                MatrixRef::new(TensorLayout::row_major(m, k), a)?,
+                MatrixRef::new(TensorLayout::row_major(k, n), b)?,
+                beta,
+                MatrixRef::new(TensorLayout::row_major(m, n), c)?,
+            )?),
+            ToleranceSpec::Absolute(error) => Box::new(AbsoluteTolerance(error)),
+            // The absolute floor keeps elements that cancel to zero from
+            // failing on rounding noise alone.
+            ToleranceSpec::Relative(relative) => Box::new(RelativeTolerance { relative, absolute: relative }),
+            ToleranceSpec::Ulp(ulps) => Box::new(UlpTolerance(ulps)),
+            ToleranceSpec::KScaled => Box::new(KScaledTolerance::new(k)),
+        })
+    }
+}
+
+impl FromStr for ToleranceSpec {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (name, value) = match s.split_once(':') {
+            Some((name, value)) => (name, Some(value)),
+            None => (s, None),
+        };
+        let value = |kind: &str| value.with_context(|| format!("`{}` tolerance needs a value, e.g. {}:1e-3", kind, kind));
+        Ok(match name {
+            "bound" => ToleranceSpec::Bound,
+            "k-scaled" => ToleranceSpec::KScaled,
+            "abs" => ToleranceSpec::Absolute(parse_value("--tolerance", value("abs")?.to_string())?),
+            "rel" => ToleranceSpec::Relative(parse_value("--tolerance", value("rel")?.to_string())?),
+            "ulp" => ToleranceSpec::Ulp(parse_value("--tolerance", value("ulp")?.to_string())?),
+            other => bail!("Unknown tolerance `{}` (expected bound, abs, rel, ulp or k-scaled)", other),
+        })
+    }
+}
+
+/// Where to run.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeviceArgs {
+    pub backend: BackendKind,
+    pub ordinal: u32,
+    pub ptx: PathBuf,
+}
+
+impl Default for DeviceArgs {
+    fn default() -> Self {
+        Self {
+            backend: BackendKind::default(),
+            ordinal: 0,
+            ptx: DEFAULT_PTX_PATH.into(),
+        }
+    }
+}
+
+/// Optional input files for A, B and C. Missing operands are generated.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct InputFiles {
+    pub a: Option<PathBuf>,
+    pub b: Option<PathBuf>,
+    pub c: Option<PathBuf>,
+}
+
+/// Options of the `run` and `verify` commands.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RunArgs {
+    pub m: usize,
+    pub n: usize,
+    pub k: usize,
+    pub alpha: f32,
+    pub beta: f32,
+    pub variant: KernelVariant,
+    pub benchmark: Benchmark,
+    pub verify: bool,
+    pub tolerance: ToleranceSpec,
+    /// Rounding model for [`ToleranceSpec::Bound`].
+    pub precision: ErrorModel,
+    /// Verify against an f64 reference with this summation instead of the
+    /// f32 host GEMM.
+    pub reference: Option<Summation>,
+    pub device: DeviceArgs,
+    pub inputs: InputFiles,
+    /// Contents of the operands without input files.
+    pub generator: Generator,
+    /// Where to save C after the run, see [`matrix_io::write_operand`].
+    pub save_c: Option<PathBuf>,
+}
+
+impl Default for RunArgs {
+    fn default() -> Self {
+        Self {
+            m: 1024,
+            n: 1024,
+            k: 1024,
+            alpha: 1.0,
+            beta: 0.0,
+            variant: KernelVariant::default(),
+            benchmark: Benchmark::default(),
+            verify: true,
+            tolerance: ToleranceSpec::default(),
+            precision: ErrorModel::fp32(),
+            reference: None,
+            device: DeviceArgs::default(),
+            inputs: InputFiles::default(),
+            generator: Generator::default(),
+            save_c: None,
+        }
+    }
+}
+
+impl RunArgs {
+    /// The error model with the reference precision that will actually be
+    /// used for verification.
+    pub fn error_model(&self) -> ErrorModel {
+        match self.reference {
+            Some(_) => self.precision.with_reference(DataType::F64),
+            None => self.precision,
+        }
+    }
+
+    /// A, B and C as row-major host data: read from the input files (see
+    /// [`matrix_io::read_operand`]) where given, otherwise generated.
+    pub fn load_inputs(&self) -> Result<(Vec<f32>, Vec<f32>, Vec<f32>)> {
+        Ok((
+            self.load(&self.inputs.a, "a", self.m, self.k, STREAM_A)?,
+            self.load(&self.inputs.b, "b", self.k, self.n, STREAM_B)?,
+            self.load_c()?,
+        ))
+    }
+
+    /// C alone, as [`RunArgs::load_inputs`] loads it.
+    pub fn load_c(&self) -> Result<Vec<f32>> {
+        self.load(&self.inputs.c, "c", self.m, self.n, STREAM_C)
+    }
+
+    fn load(&self, path: &Option<PathBuf>, name: &str, rows: usize, cols: usize, stream: u32) -> Result<Vec<f32>> {
+        match path {
+            Some(path) => matrix_io::read_operand(path, name, rows, cols).map(|matrix| matrix.to_row_major()),
+            None => self.generator.values(stream, rows, cols),
+        }
+    }
+
+    /// A and B as row-major `backend` matrices, without host copies of
+    /// either: files are streamed with [`mapped::stream_operand`] and
+    /// generated operands are generated on the backend. For runs that need
+    /// no host reference.
+    pub fn upload_operands<B: GemmBackend>(&self, backend: &B) -> Result<(B::Matrix, B::Matrix)> {
+        let upload = |path: &Option<PathBuf>, name: &str, rows: usize, cols: usize, stream: u32| match path {
+            Some(path) => mapped::stream_operand(backend, path, name, rows, cols, mapped::DEFAULT_CHUNK_BYTES),
+            None => {
+                let mut matrix = backend.alloc(TensorLayout::row_major(rows, cols))?;
+                backend.generate(&mut matrix, &self.generator, stream)?;
+                Ok(matrix)
+            }
+        };
+        Ok((
+            upload(&self.inputs.a, "a", self.m, self.k, STREAM_A)?,
+            upload(&self.inputs.b, "b", self.k, self.n, STREAM_B)?,
+        ))
+    }
+}
+
+/// Options of the `sweep` command. `run` carries the options shared with
+/// `run`; its shape, variant and input fields are unused.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SweepArgs {
+    pub run: RunArgs,
+    pub shapes: Vec<GemmShape>,
+    pub variants: Vec<KernelVariant>,
+    pub format: OutputFormat,
+    pub output: Option<PathBuf>,
+    /// Where to save the runs as a [`crate::compare`] record file.
+    pub record: Option<PathBuf>,
+}
+
+/// Options of the `generate` command. `run` carries the shape, generator
+/// and device options.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GenerateArgs {
+    pub run: RunArgs,
+    /// A `.npz` archive with members A, B and C, or a directory for raw
+    /// `input_<X>_<ROWS>x<COLS>.bin` files.
+    pub output: PathBuf,
+}
+
+/// Options of the `golden` command. `run` carries the alpha, beta and
+/// generator options.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GoldenArgs {
+    pub run: RunArgs,
+    pub shapes: Vec<GemmShape>,
+    /// Summation of the f64 reference.
+    pub summation: Summation,
+    /// Each golden goes to a `<M>x<N>x<K>` directory under this one.
+    pub output: PathBuf,
+}
+
+/// Options of the `replay` command. `run` carries the device and tolerance
+/// options; alpha, beta and the inputs come from the goldens.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReplayArgs {
+    pub run: RunArgs,
+    /// A golden directory, or a directory of them.
+    pub goldens: PathBuf,
+    pub variants: Vec<KernelVariant>,
+}
+
+impl Default for ReplayArgs {
+    fn default() -> Self {
+        Self {
+            run: RunArgs::default(),
+            goldens: DEFAULT_GOLDEN_DIR.into(),
+            variants: KernelVariant::ALL.to_vec(),
+        }
+    }
+}
+
+/// Options of the `compare` command.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompareArgs {
+    pub baseline: PathBuf,
+    pub current: PathBuf,
+    pub options: CompareOptions,
+    pub fail_on_missing: bool,
+}
+
+impl CompareArgs {
+    /// Parses the arguments after `compare`.
+    fn parse(args: impl Iterator<Item = String>) -> Result<Command> {
+        let mut args = args.peekable();
+        let mut files = Vec::new();
+        let mut options = CompareOptions::default();
+        let mut fail_on_missing = false;
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().with_context(|| format!("{} requires a value", arg));
+            match arg.as_str() {
+                "-h" | "--help" => return Ok(Command::Help),
+                "--threshold" => {
+                    let percent: f64 = parse_value(&arg, value()?)?;
+                    ensure!(percent >= 0.0, "--threshold must be non-negative");
+                    options.threshold = percent / 100.0;
+                }
+                "--confidence" => {
+                    options.confidence = parse_value(&arg, value()?)?;
+                    ensure!(
+                        options.confidence > 0.0 && options.confidence < 1.0,
+                        "--confidence must be between 0 and 1"
+                    );
+                }
+                "--outliers" => options.outlier_threshold = parse_outliers(&arg, value()?)?,
+                "--fail-on-missing" => fail_on_missing = true,
+                flag if flag.starts_with('-') => bail!("Unknown argument `{}`", flag),
+                _ => files.push(PathBuf::from(arg)),
+            }
+        }
+
+        let [baseline, current]: [PathBuf; 2] = files
+            .try_into()
+            .map_err(|files: Vec<PathBuf>| anyhow!("compare takes a baseline and a current file, got {}", files.len()))?;
+        Ok(Command::Compare(CompareArgs {
+            baseline,
+            current,
+            options,
+            fail_on_missing,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generate::Distribution;
+    use crate::matrix::HostMatrix;
+    use crate::matrix_io::MatrixFormat;
+
+    fn parse(args: &[&str]) -> Result<Command> {
+        Command::parse(args.iter().map(|arg| arg.to_string()))
+    }
+
+    fn run_args(args: &[&str]) -> RunArgs {
+        match parse(args).unwrap() {
+            Command::Run(run) | Command::Verify(run) => run,
+            other => panic!("expected run or verify, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_defaults_and_commands() {
+        assert_eq!(parse(&[]).unwrap(), Command::Run(RunArgs::default()));
+        assert_eq!(parse(&["help"]).unwrap(), Command::Help);
+        assert_eq!(parse(&["run", "--help"]).unwrap(), Command::Help);
+        assert!(matches!(parse(&["verify"]).unwrap(), Command::Verify(_)));
+        assert!(parse(&["verify", "--no-verify"]).is_err());
+
+        let Command::Info(device) = parse(&["info", "--device", "2", "--backend", "cpu"]).unwrap() else {
+            panic!("expected info");
+        };
+        assert_eq!(device.ordinal, 2);
+        assert_eq!(device.backend, BackendKind::Cpu);
+    }
+
+    #[test]
+    fn test_run_options() {
+        let run = run_args(&[
+            "run", "-m", "64", "-n", "32", "-k", "16", "--alpha", "2", "--beta", "0.5", "--variant", "wmma",
+            "--warmup", "0", "--iterations", "3", "--no-verify", "--ptx", "k.ptx", "--a", "a.bin",
+            "--save-c", "c.npy",
+        ]);
+        assert_eq!((run.m, run.n, run.k), (64, 32, 16));
+        assert_eq!((run.alpha, run.beta), (2.0, 0.5));
+        assert_eq!(run.variant, KernelVariant::Wmma);
+        assert_eq!(run.benchmark, Benchmark::new(3).with_warmup(0));
+        assert!(!run.verify);
+        assert_eq!(run.device.ptx, PathBuf::from("k.ptx"));
+        assert_eq!(run.inputs.a, Some(PathBuf::from("a.bin")));
+        assert_eq!(run.save_c, Some(PathBuf::from("c.npy")));
+        assert_eq!(run.inputs.b, None);
+
+        let run = run_args(&["--size", "128x256x512", "--reference", "kahan", "--precision", "tf32"]);
+        assert_eq!((run.m, run.n, run.k), (128, 256, 512));
+        assert_eq!(run.reference, Some(Summation::Kahan));
+        assert_eq!(run.error_model().input, DataType::Tf32);
+        assert_eq!(run.error_model().reference, DataType::F64);
+        assert_eq!(run_args(&["--size", "16"]).k, 16);
+
+        let run = run_args(&["--iterations", "10", "--time-budget", "250", "--steady-state", "0.02", "--outliers", "off"]);
+        let expected = Benchmark::new(10)
+            .with_max_iterations(DEFAULT_MAX_ITERATIONS)
+            .with_time_budget(Duration::from_millis(250))
+            .with_steady_state(SteadyState::new(0.02))
+            .with_outlier_threshold(None);
+        assert_eq!(run.benchmark, expected);
+        assert_eq!(run_args(&["--max-iterations", "8"]).benchmark.max_iterations, 8);
+    }
+
+    #[test]
+    fn test_rejects_bad_arguments() {
+        for args in [
+            &["--bogus"][..],
+            &["-m"],
+            &["-m", "ten"],
+            &["--size", "1x2"],
+            &["--iterations", "0"],
+            &["--iterations", "5", "--max-iterations", "4"],
+            &["--time-budget", "-1"],
+            &["--outliers", "never"],
+            &["--outliers", "0"],
+            &["--outliers", "-1"],
+            &["--outliers", "inf"],
+            &["--backend", "opencl"],
+            &["--tolerance", "abs"],
+            &["--variant", "cublas"],
+        ] {
+            assert!(parse(args).is_err(), "{:?} should be rejected", args);
+        }
+        let err = parse(&["-k", "-1"]).unwrap_err();
+        assert_eq!(err.to_string(), "Invalid value `-1` for -k");
+    }
+
+    #[test]
+    fn test_sweep_options() {
+        let Command::Sweep(sweep) = parse(&[
+            "sweep", "--shapes", "16x8x4,small", "--variants", "naive,wmma", "--format", "json", "--output",
+            "out.json", "--backend", "cpu", "--iterations", "2", "--record", "runs.json",
+        ])
+        .unwrap() else {
+            panic!("expected sweep");
+        };
+        assert_eq!(sweep.shapes.len(), 4);
+        assert_eq!(sweep.variants, [KernelVariant::Naive, KernelVariant::Wmma]);
+        assert_eq!(sweep.format, OutputFormat::Json);
+        assert_eq!(sweep.output, Some(PathBuf::from("out.json")));
+        assert_eq!(sweep.record, Some(PathBuf::from("runs.json")));
+        assert_eq!(sweep.run.device.backend, BackendKind::Cpu);
+        assert_eq!(sweep.run.benchmark.min_iterations, 2);
+
+        let Command::Sweep(sweep) = parse(&["sweep", "--shapes", "8", "--variant", "naive"]).unwrap() else {
+            panic!("expected sweep");
+        };
+        assert_eq!(sweep.variants, [KernelVariant::Naive]);
+        let Command::Sweep(sweep) = parse(&["sweep", "--shapes", "8"]).unwrap() else {
+            panic!("expected sweep");
+        };
+        assert_eq!(sweep.variants, KernelVariant::ALL);
+
+        assert!(parse(&["sweep"]).is_err());
+        assert!(parse(&["sweep", "--shapes", "8", "-m", "4"]).is_err());
+        assert!(parse(&["sweep", "--shapes", "8", "--format", "xml"]).is_err());
+        assert!(parse(&["sweep", "--shapes-file", "/nonexistent/shapes.txt"]).is_err());
+        assert!(parse(&["run", "--shapes", "8"]).is_err());
+        assert!(parse(&["run", "--record", "runs.json"]).is_err());
+    }
+
+    #[test]
+    fn test_generate_options() {
+        let Command::Generate(generate) = parse(&[
+            "generate", "--size", "8x4x2", "--dist", "small-int:3", "--seed", "99", "--output", "in.npz",
+        ])
+        .unwrap() else {
+            panic!("expected generate");
+        };
+        assert_eq!((generate.run.m, generate.run.n, generate.run.k), (8, 4, 2));
+        assert_eq!(generate.run.generator, Generator::new(99, Distribution::SmallInt { bound: 3 }));
+        assert_eq!(generate.output, PathBuf::from("in.npz"));
+
+        assert!(parse(&["generate", "--size", "8"]).is_err());
+        assert!(parse(&["generate", "--output", "d", "--dist", "gaussian"]).is_err());
+        assert!(parse(&["run", "--output", "d"]).is_err());
+        assert_eq!(run_args(&["--dist", "index", "--seed", "5"]).generator, Generator::new(5, Distribution::Index));
+    }
+
+    #[test]
+    fn test_golden_options() {
+        let Command::Golden(golden) = parse(&["golden", "--size", "8x4x2", "--beta", "0.5"]).unwrap() else {
+            panic!("expected golden");
+        };
+        assert_eq!(golden.shapes, [GemmShape::new(8, 4, 2)]);
+        assert_eq!((golden.run.beta, golden.summation), (0.5, Summation::Kahan));
+        assert_eq!(golden.output, PathBuf::from(DEFAULT_GOLDEN_DIR));
+
+        let Command::Golden(golden) =
+            parse(&["golden", "--shapes", "small", "--reference", "pairwise", "--output", "g"]).unwrap()
+        else {
+            panic!("expected golden");
+        };
+        assert_eq!(golden.shapes.len(), 3);
+        assert_eq!((golden.summation, golden.output), (Summation::Pairwise, PathBuf::from("g")));
+
+        let Command::Replay(replay) = parse(&["replay", "--variants", "naive,tiled", "g/16x16x16"]).unwrap() else {
+            panic!("expected replay");
+        };
+        assert_eq!(replay.goldens, PathBuf::from("g/16x16x16"));
+        assert_eq!(replay.variants, [KernelVariant::Naive, KernelVariant::Tiled]);
+        let Command::Replay(replay) = parse(&["replay"]).unwrap() else {
+            panic!("expected replay");
+        };
+        assert_eq!(replay, ReplayArgs::default());
+
+        assert!(parse(&["replay", "a", "b"]).is_err());
+        assert!(parse(&["replay", "--format", "json"]).is_err());
+        assert!(parse(&["run", "--shapes", "small"]).is_err());
+        assert!(parse(&["golden", "--variants", "all"]).is_err());
+    }
+
+    #[test]
+    fn test_compare_options() {
+        let Command::Compare(compare) = parse(&[
+            "compare", "base.json", "--threshold", "2.5", "new.json", "--confidence", "0.99", "--outliers", "off",
+            "--fail-on-missing",
+        ])
+        .unwrap() else {
+            panic!("expected compare");
+        };
+        assert_eq!(compare.baseline, PathBuf::from("base.json"));
+        assert_eq!(compare.current, PathBuf::from("new.json"));
+        assert_eq!(compare.options.threshold, 0.025);
+        assert_eq!(compare.options.confidence, 0.99);
+        assert_eq!(compare.options.outlier_threshold, None);
+        assert!(compare.fail_on_missing);
+
+        let Command::Compare(compare) = parse(&["compare", "a.json", "b.json"]).unwrap() else {
+            panic!("expected compare");
+        };
+        assert_eq!(compare.options, CompareOptions::default());
+        assert!(!compare.fail_on_missing);
+
+        assert!(parse(&["compare", "a.json"]).is_err());
+        assert!(parse(&["compare", "a.json", "b.json", "c.json"]).is_err());
+        assert!(parse(&["compare", "a.json", "b.json", "--outliers", "0"]).is_err());
+        assert!(parse(&["compare", "a.json", "b.json", "--confidence", "95"]).is_err());
+        assert!(parse(&["compare", "a.json", "b.json", "-m", "4"]).is_err());
+        assert!(parse(&["compare", "a.json", "b.json", "--variant", "naive"]).is_err());
+    }
+
+    #[test]
+    fn test_tolerance_specs() {
+        assert_eq!("bound".parse::<ToleranceSpec>().unwrap(), ToleranceSpec::Bound);
+        assert_eq!("abs:1e-3".parse::<ToleranceSpec>().unwrap(), ToleranceSpec::Absolute(1e-3));
+        assert_eq!("rel:1e-4".parse::<ToleranceSpec>().unwrap(), ToleranceSpec::Relative(1e-4));
+        assert_eq!("ulp:8".parse::<ToleranceSpec>().unwrap(), ToleranceSpec::Ulp(8));
+        assert_eq!("k-scaled".parse::<ToleranceSpec>().unwrap(), ToleranceSpec::KScaled);
+
+        let ones = [1.0f32; 4];
+        let policy = ToleranceSpec::Ulp(2)
+            .policy(ErrorModel::fp32(), (2, 2, 1), 1.0, &ones[..2], &ones[..2], 0.0, &ones)
+            .unwrap();
+        assert_eq!(policy.describe(), "within 2 ULP");
+        let policy = ToleranceSpec::Bound
+            .policy(ErrorModel::fp32(), (2, 2, 1), 1.0, &ones[..2], &ones[..2], 0.0, &ones)
+            .unwrap();
+        assert!(policy.allowed_error(1, 1, 1.0) > 0.0);
+    }
+
+    #[test]
+    fn test_load_inputs() {
+        let dir = std::env::temp_dir().join(format!("gemm-cli-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("a.bin");
+        let bytes: Vec<u8> = [1.0f32, 2.0, 3.0, 4.0, 5.0, 6.0].iter().flat_map(|v| v.to_le_bytes()).collect();
+        std::fs::write(&path, bytes).unwrap();
+
+        let mut run = RunArgs { m: 2, n: 1, k: 3, ..RunArgs::default() };
+        run.inputs.a = Some(path.clone());
+        let (a, b, c) = run.load_inputs().unwrap();
+        assert_eq!(a, [1.0, 2.0, 3.0, 4.0, 5.0, 6.0]);
+        assert_eq!(b, run.generator.values(STREAM_B, 3, 1).unwrap());
+        assert_eq!(c, run.generator.values(STREAM_C, 2, 1).unwrap());
+
+        run.k = 2;
+        assert!(run.load_inputs().is_err());
+
+        // Headered files may use any layout; they come back row-major.
+        let mut b = HostMatrix::zeros(TensorLayout::column_major(2, 1)).unwrap();
+        b.set(1, 0, 7.0);
+        let b_path = dir.join("b.mat");
+        matrix_io::write_matrix(&b_path, &b, MatrixFormat::Headered).unwrap();
+        run.k = 3;
+        run.inputs.b = Some(b_path);
+        assert!(run.load_inputs().is_err());
+        run.k = 2;
+        run.inputs.a = None;
+        assert_eq!(run.load_inputs().unwrap().1, [0.0, 7.0]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,489 @@
+//! Benchmark record files and the regression check behind `compare`.
+//!
+//! A record file is JSON of the form
+//!
+//! ```text
+//! {
+//!   "format": "rust-gpu-gemm-bench/1",
+//!   "records": [
+//!     {"m": 1024, "n": 1024, "k": 1024, "variant": "tiled", "dtype": "f32",
+//!      "device": "NVIDIA H100 80GB HBM3", "samples_ms": [1.21, 1.19, 1.22]}
+//!   ]
+//! }
+//! ```
+//!
+//! with every measured run time, outliers included, so the comparison can
+//! apply its own statistics.
+
+use anyhow::{bail, ensure, Context, Result};
+use std::fmt;
+use std::io::Write;
+use std::path::Path;
+
+use crate::bench::{student_t_quantile, Timings};
+use crate::json::{self, Json};
+use crate::sweep::{GemmShape, SweepRow};
+use crate::variant::KernelVariant;
+
+pub const RECORD_FORMAT: &str = "rust-gpu-gemm-bench/1";
+
+/// What a record measured; records in two files are matched on this.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RecordKey {
+    pub shape: GemmShape,
+    pub variant: KernelVariant,
+    pub dtype: String,
+    pub device: String,
+}
+
+impl fmt::Display for RecordKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {} {} on {}", self.shape, self.variant, self.dtype, self.device)
+    }
+}
+
+/// Every measured run of one configuration.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BenchmarkRecord {
+    pub key: RecordKey,
+    /// Run times in seconds.
+    pub samples: Vec<f64>,
+}
+
+/// Records for the sweep rows that ran, with all their samples.
+pub fn records_from_sweep(rows: &[SweepRow], dtype: &str, device: &str) -> Vec<BenchmarkRecord> {
+    rows.iter()
+        .filter_map(|row| {
+            let result = row.benchmark.as_ref()?;
+            let mut samples = result.timings.samples.clone();
+            samples.extend(&result.outliers);
+            Some(BenchmarkRecord {
+                key: RecordKey {
+                    shape: row.shape,
+                    variant: row.variant,
+                    dtype: dtype.to_string(),
+                    device: device.to_string(),
+                },
+                samples,
+            })
+        })
+        .collect()
+}
+
+pub fn write_records(records: &[BenchmarkRecord], mut out: impl Write) -> Result<()> {
+    writeln!(out, "{{")?;
+    writeln!(out, "  \"format\": {},", json::quote(RECORD_FORMAT))?;
+    writeln!(out, "  \"records\": [")?;
+    for (i, record) in records.iter().enumerate() {
+        let key = &record.key;
+        let samples: Vec<String> = record.samples.iter().map(|t| format!("{:.6}", t * 1e3)).collect();
+        let separator = if i + 1 < records.len() { "," } else { "" };
+        writeln!(
+            out,
+            "    {{\"m\": {}, \"n\": {}, \"k\": {}, \"variant\": {}, \"dtype\": {}, \"device\": {}, \"samples_ms\": [{}]}}{}",
+            key.shape.m,
+            key.shape.n,
+            key.shape.k,
+            json::quote(key.variant.name()),
+            json::quote(&key.dtype),
+            json::quote(&key.device),
+            samples.join(", "),
+            separator
+        )?;
+    }
+    writeln!(out, "  ]")?;
+    writeln!(out, "}}")?;
+    out.flush()?;
+    Ok(())
+}
+
+pub fn parse_records(text: &str) -> Result<Vec<BenchmarkRecord>> {
+    let root = Json::parse(text)?;
+    let format = root.field("format")?.as_str()?;
+    ensure!(format == RECORD_FORMAT, "Unsupported record format `{}` (expected {})", format, RECORD_FORMAT);
+
+    let mut records: Vec<BenchmarkRecord> = Vec::new();
+    for (i, entry) in root.field("records")?.as_array()?.iter().enumerate() {
+        let record = parse_record(entry).with_context(|| format!("Invalid record {}", i))?;
+        if records.iter().any(|other| other.key == record.key) {
+            bail!("Duplicate record for {}", record.key);
+        }
+        records.push(record);
+    }
+    Ok(records)
+}
+
+fn parse_record(entry: &Json) -> Result<BenchmarkRecord> {
+    let key = RecordKey {
+        shape: GemmShape::new(
+            entry.field("m")?.as_usize()?,
+            entry.field("n")?.as_usize()?,
+            entry.field("k")?.as_usize()?,
+        ),
+        variant: entry.field("variant")?.as_str()?.parse()?,
+        dtype: entry.field("dtype")?.as_str()?.to_string(),
+        device: entry.field("device")?.as_str()?.to_string(),
+    };
+    let samples = entry
+        .field("samples_ms")?
+        .as_array()?
+        .iter()
+        .map(|sample| Ok(sample.as_f64()? / 1e3))
+        .collect::<Result<Vec<f64>>>()?;
+    ensure!(!samples.is_empty(), "{} has no samples", key);
+    ensure!(
+        samples.iter().all(|t| t.is_finite() && *t > 0.0),
+        "{} has non-positive or non-finite samples",
+        key
+    );
+    Ok(BenchmarkRecord { key, samples })
+}
+
+pub fn read_records(path: &Path) -> Result<Vec<BenchmarkRecord>> {
+    let text = std::fs::read_to_string(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    parse_records(&text).with_context(|| format!("Failed to parse {}", path.display()))
+}
+
+/// When a change in mean run time counts as a regression.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompareOptions {
+    /// Smallest relative slowdown (e.g. 0.05 for 5%) that fails the check.
+    pub threshold: f64,
+    /// Confidence level the change must be significant at.
+    pub confidence: f64,
+    /// MAD-based outlier rejection applied to both sides, as in
+    /// [`crate::Benchmark`].
+    pub outlier_threshold: Option<f64>,
+}
+
+impl Default for CompareOptions {
+    fn default() -> Self {
+        Self {
+            threshold: 0.05,
+            confidence: 0.95,
+            outlier_threshold: Some(3.5),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict {
+    /// Significantly slower by more than the threshold.
+    Regressed,
+    /// Significantly faster by more than the threshold.
+    Improved,
+    Unchanged,
+    /// In the baseline only.
+    Missing,
+    /// In the current results only.
+    New,
+}
+
+impl Verdict {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Verdict::Regressed => "regressed",
+            Verdict::Improved => "improved",
+            Verdict::Unchanged => "unchanged",
+            Verdict::Missing => "missing",
+            Verdict::New => "new",
+        }
+    }
+}
+
+/// One configuration's baseline and current timings.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Comparison {
+    pub key: RecordKey,
+    pub baseline: Option<Timings>,
+    pub current: Option<Timings>,
+    /// Relative change of the mean run time; positive is slower.
+    pub change: Option<f64>,
+    /// Half-width of the Welch confidence interval of `change`.
+    pub margin: Option<f64>,
+    pub verdict: Verdict,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ComparisonReport {
+    pub comparisons: Vec<Comparison>,
+    pub options: CompareOptions,
+}
+
+impl ComparisonReport {
+    pub fn count(&self, verdict: Verdict) -> usize {
+        self.comparisons.iter().filter(|c| c.verdict == verdict).count()
+    }
+}
+
+/// Matches `current` against `baseline` by [`RecordKey`], in baseline order
+/// followed by new configurations.
+///
+/// A change is significant when zero lies outside the Welch t confidence
+/// interval of the difference in means; that needs at least two samples on
+/// each side.
+pub fn compare_records(
+    baseline: &[BenchmarkRecord],
+    current: &[BenchmarkRecord],
+    options: &CompareOptions,
+) -> Result<ComparisonReport> {
+    ensure!(options.threshold >= 0.0, "Regression threshold must be non-negative");
+    ensure!(
+        options.confidence > 0.0 && options.confidence < 1.0,
+        "Confidence level must be in (0, 1), got {}",
+        options.confidence
+    );
+    if let Some(threshold) = options.outlier_threshold {
+        ensure!(
+            threshold.is_finite() && threshold > 0.0,
+            "Outlier threshold must be a positive number, got {}",
+            threshold
+        );
+    }
+
+    let timings = |record: &BenchmarkRecord| -> Result<Timings> {
+        let timings = Timings { samples: record.samples.clone() };
+        let timings = match options.outlier_threshold {
+            Some(threshold) => timings.without_outliers(threshold).0,
+            None => timings,
+        };
+        ensure!(!timings.samples.is_empty(), "{} has no samples left after outlier rejection", record.key);
+        Ok(timings)
+    };
+
+    let mut comparisons = Vec::with_capacity(baseline.len());
+    for base in baseline {
+        let comparison = match current.iter().find(|record| record.key == base.key) {
+            Some(record) => compare_timings(base.key.clone(), timings(base)?, timings(record)?, options),
+            None => Comparison {
+                key: base.key.clone(),
+                baseline: Some(timings(base)?),
+                current: None,
+                change: None,
+                margin: None,
+                verdict: Verdict::Missing,
+            },
+        };
+        comparisons.push(comparison);
+    }
+    for record in current {
+        if !baseline.iter().any(|base| base.key == record.key) {
+            comparisons.push(Comparison {
+                key: record.key.clone(),
+                baseline: None,
+                current: Some(timings(record)?),
+                change: None,
+                margin: None,
+                verdict: Verdict::New,
+            });
+        }
+    }
+
+    Ok(ComparisonReport {
+        comparisons,
+        options: options.clone(),
+    })
+}
+
+fn compare_timings(key: RecordKey, baseline: Timings, current: Timings, options: &CompareOptions) -> Comparison {
+    let (n1, n2) = (baseline.samples.len() as f64, current.samples.len() as f64);
+    let (mean1, mean2) = (baseline.mean(), current.mean());
+    let change = (mean2 - mean1) / mean1;
+
+    let margin = if n1 < 2.0 || n2 < 2.0 {
+        None
+    } else {
+        let (v1, v2) = (baseline.stddev().powi(2) / n1, current.stddev().powi(2) / n2);
+        let se = (v1 + v2).sqrt();
+        if se == 0.0 {
+            Some(0.0)
+        } else {
+            // Welch-Satterthwaite; rounding down keeps the interval conservative.
+            let dof = (v1 + v2).powi(2) / (v1 * v1 / (n1 - 1.0) + v2 * v2 / (n2 - 1.0));
+            let t = student_t_quantile(0.5 + options.confidence / 2.0, dof.floor().max(1.0));
+            Some(t * se / mean1)
+        }
+    };
+
+    let significant = margin.is_some_and(|margin| change.abs() > margin);
+    let verdict = if significant && change > options.threshold {
+        Verdict::Regressed
+    } else if significant && change < -options.threshold {
+        Verdict::Improved
+    } else {
+        Verdict::Unchanged
+    };
+
+    Comparison {
+        key,
+        baseline: Some(baseline),
+        current: Some(current),
+        change: Some(change),
+        margin,
+        verdict,
+    }
+}
+
+impl fmt::Display for ComparisonReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let ms = |timings: &Option<Timings>| match timings {
+            Some(timings) => format!("{:.4}", timings.mean() * 1e3),
+            None => "-".to_string(),
+        };
+        let width = self.comparisons.iter().map(|c| c.key.to_string().len()).max().unwrap_or(0).max(13);
+
+        writeln!(
+            f,
+            "{:<width$}  {:>12}  {:>12}  {:>18}  verdict",
+            "configuration", "baseline ms", "current ms", "change",
+        )?;
+        for comparison in &self.comparisons {
+            let change = match (comparison.change, comparison.margin) {
+                (Some(change), Some(margin)) => format!("{:+.2}% ±{:.2}%", change * 100.0, margin * 100.0),
+                (Some(change), None) => format!("{:+.2}% (n<2)", change * 100.0),
+                _ => "-".to_string(),
+            };
+            writeln!(
+                f,
+                "{:<width$}  {:>12}  {:>12}  {:>18}  {}",
+                comparison.key.to_string(),
+                ms(&comparison.baseline),
+                ms(&comparison.current),
+                change,
+                comparison.verdict.name(),
+            )?;
+        }
+        write!(
+            f,
+            "{} regressed, {} improved, {} unchanged, {} missing, {} new (threshold {}%, {}% confidence)",
+            self.count(Verdict::Regressed),
+            self.count(Verdict::Improved),
+            self.count(Verdict::Unchanged),
+            self.count(Verdict::Missing),
+            self.count(Verdict::New),
+            self.options.threshold * 100.0,
+            self.options.confidence * 100.0
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(size: usize, variant: KernelVariant, samples_ms: &[f64]) -> BenchmarkRecord {
+        BenchmarkRecord {
+            key: RecordKey {
+                shape: GemmShape::cube(size),
+                variant,
+                dtype: "f32".to_string(),
+                device: "cpu".to_string(),
+            },
+            samples: samples_ms.iter().map(|t| t / 1e3).collect(),
+        }
+    }
+
+    #[test]
+    fn test_record_round_trip() {
+        let records = vec![
+            record(64, KernelVariant::Naive, &[1.25, 1.5, 1.0 / 3.0]),
+            BenchmarkRecord {
+                key: RecordKey { device: "GPU \"0\"".to_string(), ..record(8, KernelVariant::Wmma, &[]).key },
+                samples: vec![2e-3],
+            },
+        ];
+        let mut out = Vec::new();
+        write_records(&records, &mut out).unwrap();
+        let parsed = parse_records(&String::from_utf8(out).unwrap()).unwrap();
+        assert_eq!(parsed.len(), 2);
+        assert_eq!(parsed[1].key, records[1].key);
+        for (parsed, original) in parsed[0].samples.iter().zip(&records[0].samples) {
+            assert!((parsed - original).abs() < 1e-9);
+        }
+
+        let entry = r#"{"m": 8, "n": 8, "k": 8, "variant": "naive", "dtype": "f32", "device": "cpu", "samples_ms": [1]}"#;
+        let file = |records: &str| format!(r#"{{"format": "{}", "records": [{}]}}"#, RECORD_FORMAT, records);
+        assert_eq!(parse_records(&file(entry)).unwrap()[0].samples, [1e-3]);
+        assert!(parse_records(&file(&format!("{},{}", entry, entry))).is_err());
+        assert!(parse_records(&file(&entry.replace("[1]", "[]"))).is_err());
+        assert!(parse_records(&file(&entry.replace("[1]", "[-1]"))).is_err());
+        assert!(parse_records(&file(&entry.replace("naive", "fast"))).is_err());
+        assert!(parse_records(&file(entry).replace(RECORD_FORMAT, "other/1")).is_err());
+    }
+
+    #[test]
+    fn test_compare_verdicts() {
+        let steady = [1.00, 1.01, 0.99, 1.02, 0.98, 1.00];
+        let slower: Vec<f64> = steady.iter().map(|t| t * 1.2).collect();
+        let faster: Vec<f64> = steady.iter().map(|t| t * 0.8).collect();
+        let slightly_slower: Vec<f64> = steady.iter().map(|t| t * 1.02).collect();
+        let noisy = [0.5, 1.6, 0.7, 1.5, 0.6, 1.4];
+
+        let baseline = vec![
+            record(16, KernelVariant::Naive, &steady),
+            record(16, KernelVariant::Tiled, &steady),
+            record(32, KernelVariant::Naive, &steady),
+            record(32, KernelVariant::Tiled, &steady),
+            record(64, KernelVariant::Naive, &steady),
+            record(64, KernelVariant::Tiled, &[1.0]),
+        ];
+        let current = vec![
+            record(16, KernelVariant::Naive, &slower),
+            record(16, KernelVariant::Tiled, &faster),
+            record(32, KernelVariant::Naive, &slightly_slower),
+            record(32, KernelVariant::Tiled, &noisy),
+            record(64, KernelVariant::Tiled, &[2.0]),
+            record(128, KernelVariant::Naive, &steady),
+        ];
+        let report = compare_records(&baseline, &current, &CompareOptions::default()).unwrap();
+        let verdicts: Vec<Verdict> = report.comparisons.iter().map(|c| c.verdict).collect();
+        assert_eq!(
+            verdicts,
+            [
+                Verdict::Regressed,
+                Verdict::Improved,
+                // Significant but below the 5% threshold.
+                Verdict::Unchanged,
+                // Beyond the threshold but within the noise.
+                Verdict::Unchanged,
+                Verdict::Missing,
+                // A single sample cannot show significance.
+                Verdict::Unchanged,
+                Verdict::New,
+            ]
+        );
+        assert!((report.comparisons[0].change.unwrap() - 0.2).abs() < 1e-9);
+        assert_eq!(report.count(Verdict::Regressed), 1);
+
+        let strict = CompareOptions { threshold: 0.01, ..CompareOptions::default() };
+        let report = compare_records(&baseline, &current, &strict).unwrap();
+        assert_eq!(report.comparisons[2].verdict, Verdict::Regressed);
+
+        let table = report.to_string();
+        assert!(table.contains("16x16x16 naive f32 on cpu"));
+        assert!(table.contains("+20.00% ±"));
+        assert!(table.ends_with("2 regressed, 1 improved, 2 unchanged, 1 missing, 1 new (threshold 1%, 95% confidence)"));
+
+        assert!(compare_records(&baseline, &current, &CompareOptions { confidence: 1.0, ..strict }).is_err());
+    }
+
+    #[test]
+    fn test_outlier_threshold() {
+        let steady = [1.00, 1.01, 0.99, 1.02, 0.98, 1.00];
+        let slower: Vec<f64> = steady.iter().map(|t| t * 1.2).collect();
+        let baseline = [record(16, KernelVariant::Naive, &steady)];
+        let current = [record(16, KernelVariant::Naive, &slower)];
+
+        // A threshold that rejects most samples still leaves a 20% slowdown
+        // a regression.
+        let aggressive = CompareOptions { outlier_threshold: Some(0.1), ..CompareOptions::default() };
+        let report = compare_records(&baseline, &current, &aggressive).unwrap();
+        assert_eq!(report.comparisons[0].verdict, Verdict::Regressed);
+        assert!((report.comparisons[0].change.unwrap() - 0.2).abs() < 1e-9);
+
+        for threshold in [0.0, -1.0, f64::NAN, f64::INFINITY] {
+            let options = CompareOptions { outlier_threshold: Some(threshold), ..CompareOptions::default() };
+            assert!(compare_records(&baseline, &current, &options).is_err(), "{}", threshold);
+        }
+    }
+}
@@ -0,0 +1,412 @@
+use anyhow::{ensure, Result};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use utils::TensorLayout;
+
+use crate::matrix::{MatrixMut, MatrixRef};
+
+/// Rows and columns of C held in registers by the microkernel.
+const MR: usize = 8;
+const NR: usize = 8;
+/// Columns of C one work item covers inside an NC panel.
+const COLUMN_GROUP: usize = 32 * NR;
+/// Below this many multiply-adds, spawning threads costs more than it saves.
+const PARALLEL_THRESHOLD: usize = 64 * 64 * 64;
+
+/// Cache-blocked, multithreaded host GEMM.
+///
+/// Follows the usual BLIS structure: B is packed into KC x NC panels of
+/// NR-wide column strips, A into M x KC panels of MR-tall row strips, and an
+/// MR x NR register-blocked microkernel runs over the packed data. Both
+/// panels are packed once per K block; work items, an MC-row block of C by
+/// a column group, are then shared out between threads. Operands may use any
+/// [`TensorLayout`]; only packing and the final store depend on it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CpuGemm {
+    pub threads: usize,
+    /// Rows of A packed per block; sized so the block stays in L2.
+    pub mc: usize,
+    /// Depth of the packed panels; sized so a strip of B stays in L1.
+    pub kc: usize,
+    /// Columns of B packed per panel; sized for L3.
+    pub nc: usize,
+}
+
+impl Default for CpuGemm {
+    fn default() -> Self {
+        Self {
+            threads: std::thread::available_parallelism().map_or(1, |n| n.get()),
+            mc: 128,
+            kc: 256,
+            nc: 4096,
+        }
+    }
+}
+
+impl CpuGemm {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_threads(mut self, threads: usize) -> Self {
+        self.threads = threads.max(1);
+        self
+    }
+
+    /// Computes `C = alpha * A * B + beta * C`. With `beta == 0` the
+    /// previous contents of C are never read, so they may be uninitialised
+    /// garbage or NaN.
+    pub fn run(&self, alpha: f32, a: MatrixRef<'_>, b: MatrixRef<'_>, beta: f32, mut c: MatrixMut<'_>) -> Result<()> {
+        check_shapes(a.layout(), b.layout(), c.layout())?;
+        ensure!(
+            self.mc > 0 && self.kc > 0 && self.nc > 0,
+            "Blocking sizes must be non-zero, got mc={}, kc={}, nc={}",
+            self.mc,
+            self.kc,
+            self.nc
+        );
+
+        let (m, n, k) = (c.rows(), c.cols(), a.cols());
+        if m == 0 || n == 0 {
+            return Ok(());
+        }
+        if k == 0 || alpha == 0.0 {
+            scale(&mut c, beta);
+            return Ok(());
+        }
+
+        // Round the block height to whole microkernel strips.
+        let mc = self.mc.div_ceil(MR) * MR;
+        let threads = if m * n * k < PARALLEL_THRESHOLD { 1 } else { self.threads.max(1) };
+
+        let a_addr = Addressing::new(a.layout());
+        let b_addr = Addressing::new(b.layout());
+        let c_addr = Addressing::new(c.layout());
+        let c_ptr = SyncPtr(c.as_mut_slice().as_mut_ptr());
+
+        let (mut a_pack, mut b_pack) = (Vec::new(), Vec::new());
+        for jc in (0..n).step_by(self.nc) {
+            let nc = self.nc.min(n - jc);
+            for pc in (0..k).step_by(self.kc) {
+                let kc = self.kc.min(k - pc);
+                pack_b(b.as_slice(), &b_addr, pc, kc, jc, nc, &mut b_pack);
+                pack_a(a.as_slice(), &a_addr, 0, m, pc, kc, &mut a_pack);
+
+                // The first K block applies beta; later blocks accumulate.
+                let update = if pc == 0 { Update::first(beta) } else { Update::Accumulate };
+                let row_blocks = m.div_ceil(mc);
+                let column_groups = nc.div_ceil(COLUMN_GROUP);
+                let items = row_blocks * column_groups;
+                let next = AtomicUsize::new(0);
+
+                let work = || {
+                    loop {
+                        let item = next.fetch_add(1, Ordering::Relaxed);
+                        if item >= items {
+                            break;
+                        }
+                        let ic = (item / column_groups) * mc;
+                        let group = (item % column_groups) * COLUMN_GROUP;
+                        let rows = mc.min(m - ic);
+                        let cols = COLUMN_GROUP.min(nc - group);
+
+                        let block = Block {
+                            row: ic,
+                            col: jc + group,
+                            rows,
+                            cols,
+                            kc,
+                        };
+                        // `ic` is a multiple of MR, so its strips start at `ic * kc`.
+                        let a_panels = &a_pack[ic * kc..];
+                        let b_panels = &b_pack[(group / NR) * kc * NR..];
+                        // SAFETY: work items cover disjoint (row, col) ranges of
+                        // C, and a valid layout maps distinct coordinates to
+                        // distinct offsets, so no element is written twice.
+                        unsafe { macro_kernel(&block, a_panels, b_panels, alpha, update, c_ptr, &c_addr) };
+                    }
+                };
+
+                let workers = threads.min(items);
+                if workers == 1 {
+                    work();
+                } else {
+                    std::thread::scope(|s| {
+                        for _ in 1..workers {
+                            s.spawn(work);
+                        }
+                        work();
+                    });
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Straightforward triple loop, kept as the reference the blocked kernel is
+/// tested against.
+pub fn naive_gemm(alpha: f32, a: MatrixRef<'_>, b: MatrixRef<'_>, beta: f32, mut c: MatrixMut<'_>) -> Result<()> {
+    check_shapes(a.layout(), b.layout(), c.layout())?;
+
+    for i in 0..c.rows() {
+        for j in 0..c.cols() {
+            let mut sum = 0.0;
+            for p in 0..a.cols() {
+                sum += a.get(i, p) * b.get(p, j);
+            }
+            let value = if beta == 0.0 {
+                alpha * sum
+            } else {
+                alpha * sum + beta * c.get(i, j)
+            };
+            c.set(i, j, value);
+        }
+    }
+
+    Ok(())
+}
+
+pub(crate) fn check_shapes(a: &TensorLayout, b: &TensorLayout, c: &TensorLayout) -> Result<()> {
+    ensure!(
+        TensorLayout::is_gemm_compatible(a, b, c),
+        "Incompatible GEMM operands: A is {}, B is {}, C is {}",
+        a.shape,
+        b.shape,
+        c.shape
+    );
+    Ok(())
+}
+
+fn scale(c: &mut MatrixMut<'_>, beta: f32) {
+    for i in 0..c.rows() {
+        for j in 0..c.cols() {
+            let value = if beta == 0.0 { 0.0 } else { beta * c.get(i, j) };
+            c.set(i, j, value);
+        }
+    }
+}
+
+/// Element offsets with the layout match hoisted out for the strided cases.
+#[derive(Debug, Clone, Copy)]
+enum Addressing {
+    Strided { row_stride: usize, col_stride: usize },
+    General(TensorLayout),
+}
+
+impl Addressing {
+    fn new(layout: &TensorLayout) -> Self {
+        match (layout.row_stride(), layout.col_stride()) {
+            (Some(row_stride), Some(col_stride)) => Addressing::Strided { row_stride, col_stride },
+            _ => Addressing::General(*layout),
+        }
+    }
+
+    #[inline]
+    fn offset(&self, row: usize, col: usize) -> usize {
+        match self {
+            Addressing::Strided { row_stride, col_stride } => row * row_stride + col * col_stride,
+            Addressing::General(layout) => layout.index(row, col),
+        }
+    }
+}
+
+/// Packs rows `ic..ic + rows` and columns `pc..pc + kc` of A into MR-tall
+/// strips, each stored column by column and zero-padded to MR rows.
+fn pack_a(a: &[f32], addr: &Addressing, ic: usize, rows: usize, pc: usize, kc: usize, out: &mut Vec<f32>) {
+    out.clear();
+    out.resize(rows.div_ceil(MR) * MR * kc, 0.0);
+    for (strip, panel) in out.chunks_exact_mut(MR * kc).enumerate() {
+        let row0 = ic + strip * MR;
+        let valid = MR.min(ic + rows - row0);
+        for p in 0..kc {
+            for i in 0..valid {
+                panel[p * MR + i] = a[addr.offset(row0 + i, pc + p)];
+            }
+        }
+    }
+}
+
+/// Packs rows `pc..pc + kc` and columns `jc..jc + nc` of B into NR-wide
+/// strips, each stored row by row and zero-padded to NR columns.
+fn pack_b(b: &[f32], addr: &Addressing, pc: usize, kc: usize, jc: usize, nc: usize, out: &mut Vec<f32>) {
+    out.clear();
+    out.resize(nc.div_ceil(NR) * NR * kc, 0.0);
+    for (strip, panel) in out.chunks_exact_mut(NR * kc).enumerate() {
+        let col0 = jc + strip * NR;
+        let valid = NR.min(jc + nc - col0);
+        for p in 0..kc {
+            for j in 0..valid {
+                panel[p * NR + j] = b[addr.offset(pc + p, col0 + j)];
+            }
+        }
+    }
+}
+
+/// How a computed tile is combined with the existing contents of C.
+#[derive(Debug, Clone, Copy)]
+enum Update {
+    Overwrite,
+    Scale(f32),
+    Accumulate,
+}
+
+impl Update {
+    fn first(beta: f32) -> Self {
+        if beta == 0.0 {
+            Update::Overwrite
+        } else {
+            Update::Scale(beta)
+        }
+    }
+}
+
+/// The part of C covered by one work item.
+struct Block {
+    row: usize,
+    col: usize,
+    rows: usize,
+    cols: usize,
+    kc: usize,
+}
+
+#[derive(Clone, Copy)]
+struct SyncPtr(*mut f32);
+
+// SAFETY: the pointer is only used for writes to disjoint elements, see
+// `CpuGemm::run`.
+unsafe impl Send for SyncPtr {}
+unsafe impl Sync for SyncPtr {}
+
+/// # Safety
+///
+/// `c` must be valid for every offset `addr` produces inside `block`, and no
+/// other thread may access those elements concurrently.
+unsafe fn macro_kernel(
+    block: &Block,
+    a_pack: &[f32],
+    b_pack: &[f32],
+    alpha: f32,
+    update: Update,
+    c: SyncPtr,
+    addr: &Addressing,
+) {
+    let kc = block.kc;
+    for jr in (0..block.cols).step_by(NR) {
+        let b_strip = &b_pack[(jr / NR) * kc * NR..][..kc * NR];
+        let cols = NR.min(block.cols - jr);
+        for ir in (0..block.rows).step_by(MR) {
+            let a_strip = &a_pack[(ir / MR) * kc * MR..][..kc * MR];
+            let rows = MR.min(block.rows - ir);
+            let acc = microkernel(a_strip, b_strip);
+
+            for (i, acc_row) in acc.iter().enumerate().take(rows) {
+                for (j, &value) in acc_row.iter().enumerate().take(cols) {
+                    let dst = c.0.add(addr.offset(block.row + ir + i, block.col + jr + j));
+                    let value = alpha * value;
+                    *dst = match update {
+                        Update::Overwrite => value,
+                        Update::Scale(beta) => value + beta * *dst,
+                        Update::Accumulate => value + *dst,
+                    };
+                }
+            }
+        }
+    }
+}
+
+/// MR x NR outer-product accumulation over one packed strip pair. Written so
+/// the inner loop over NR vectorises.
+#[inline(always)]
+fn microkernel(a_strip: &[f32], b_strip: &[f32]) -> [[f32; NR]; MR] {
+    let mut acc = [[0.0f32; NR]; MR];
+    for (a_col, b_row) in a_strip.chunks_exact(MR).zip(b_strip.chunks_exact(NR)) {
+        for (acc_row, &a_i) in acc.iter_mut().zip(a_col) {
+            for (acc_ij, &b_j) in acc_row.iter_mut().zip(b_row) {
+                *acc_ij += a_i * b_j;
+            }
+        }
+    }
+    acc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::matrix::HostMatrix;
+
+    fn random_matrix(layout: TensorLayout, seed: u32) -> HostMatrix {
+        let mut m = HostMatrix::zeros(layout).unwrap();
+        let mut state = seed;
+        for i in 0..layout.shape.rows {
+            for j in 0..layout.shape.cols {
+                state = state.wrapping_mul(1664525).wrapping_add(1013904223);
+                m.set(i, j, (state >> 8) as f32 / (1 << 24) as f32 - 0.5);
+            }
+        }
+        m
+    }
+
+    fn assert_close(expected: &HostMatrix, actual: &HostMatrix) {
+        for i in 0..expected.rows() {
+            for j in 0..expected.cols() {
+                let (e, a) = (expected.get(i, j), actual.get(i, j));
+                assert!((e - a).abs() <= 1e-4 * (1.0 + e.abs()), "({}, {}): expected {}, got {}", i, j, e, a);
+            }
+        }
+    }
+
+    /// Small blocking so every edge case of the packing and blocking loops is
+    /// hit on test-sized matrices.
+    fn small_blocks(threads: usize) -> CpuGemm {
+        CpuGemm { threads, mc: 12, kc: 7, nc: 20 }
+    }
+
+    #[test]
+    fn test_matches_naive_on_ragged_shapes() {
+        for &(m, n, k) in &[(1, 1, 1), (37, 29, 53), (64, 300, 17), (9, 8, 100)] {
+            let a = random_matrix(TensorLayout::row_major(m, k), 1);
+            let b = random_matrix(TensorLayout::row_major(k, n), 2);
+            let c0 = random_matrix(TensorLayout::row_major(m, n), 3);
+
+            let mut expected = c0.clone();
+            naive_gemm(1.5, a.view(), b.view(), -0.5, expected.view_mut()).unwrap();
+
+            for gemm in [small_blocks(1), small_blocks(3), CpuGemm::default()] {
+                let mut actual = c0.clone();
+                gemm.run(1.5, a.view(), b.view(), -0.5, actual.view_mut()).unwrap();
+                assert_close(&expected, &actual);
+            }
+        }
+    }
+
+    #[test]
+    fn test_mixed_layouts() {
+        let (m, n, k) = (70, 45, 33);
+        let a = random_matrix(TensorLayout::column_major(m, k), 4);
+        let b = random_matrix(TensorLayout::tiled(k, n, 8, 16), 5);
+        let mut c_layout = TensorLayout::row_major(m, n);
+        c_layout.leading_dim = 64;
+
+        let mut expected = HostMatrix::zeros(c_layout).unwrap();
+        naive_gemm(1.0, a.view(), b.view(), 0.0, expected.view_mut()).unwrap();
+
+        // beta == 0 must not read C, so NaN in C (including padding) is fine.
+        let mut actual = HostMatrix::from_vec(c_layout, vec![f32::NAN; expected.as_slice().len()]).unwrap();
+        small_blocks(4).run(1.0, a.view(), b.view(), 0.0, actual.view_mut()).unwrap();
+        assert_close(&expected, &actual);
+    }
+
+    #[test]
+    fn test_empty_k_scales_c() {
+        let a = HostMatrix::zeros(TensorLayout::row_major(2, 0)).unwrap();
+        let b = HostMatrix::zeros(TensorLayout::row_major(0, 2)).unwrap();
+        let mut c = HostMatrix::from_vec(TensorLayout::row_major(2, 2), vec![1.0, 2.0, 3.0, 4.0]).unwrap();
+
+        CpuGemm::default().run(1.0, a.view(), b.view(), 2.0, c.view_mut()).unwrap();
+        assert_eq!(c.as_slice(), &[2.0, 4.0, 6.0, 8.0]);
+
+        let wrong = HostMatrix::zeros(TensorLayout::row_major(3, 2)).unwrap();
+        assert!(CpuGemm::default().run(1.0, wrong.view(), b.view(), 0.0, c.view_mut()).is_err());
+    }
+}
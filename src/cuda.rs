@@ -0,0 +1,204 @@
+use anyhow::{Context as _, Result};
+use cust::device::DeviceAttribute;
+use cust::memory::{cuda_free, cuda_malloc, CopyDestination, DevicePointer, DeviceSlice};
+use cust::prelude::*;
+use std::ffi::c_void;
+use std::path::Path;
+use utils::GpuArch;
+
+use crate::backend::DeviceBackend;
+use crate::bench::Timer;
+use crate::driver::{DeviceInfo, DevicePtr, Driver, KernelArg, LaunchParams};
+use crate::launch::DeviceLimits;
+
+pub struct CudaContext {
+    // Field order matters: the stream must be destroyed before the context.
+    stream: Stream,
+    _context: Context,
+    device: Device,
+}
+
+impl CudaContext {
+    pub fn new() -> Result<Self> {
+        Self::with_device(0)
+    }
+
+    /// Creates a context on the device with the given ordinal.
+    pub fn with_device(ordinal: u32) -> Result<Self> {
+        cust::init(CudaFlags::empty())?;
+
+        let device = Device::get_device(ordinal)
+            .with_context(|| format!("CUDA device {} not found", ordinal))?;
+        let _context = Context::new(device)?;
+        _context.set_flags(ContextFlags::MAP_HOST | ContextFlags::SCHED_AUTO)?;
+
+        let stream = Stream::new(StreamFlags::NON_BLOCKING, None)
+            .context("Failed to create CUDA stream")?;
+
+        let major = device.get_attribute(DeviceAttribute::ComputeCapabilityMajor)? as u32;
+        let minor = device.get_attribute(DeviceAttribute::ComputeCapabilityMinor)? as u32;
+        eprintln!("Initialized CUDA device: {}", device.name()?);
+        match GpuArch::for_compute_capability(major, minor) {
+            Some(arch) => eprintln!("Compute Capability: {}.{} ({})", major, minor, arch),
+            None => eprintln!("Compute Capability: {}.{} (unknown architecture)", major, minor),
+        }
+
+        Ok(Self { stream, _context, device })
+    }
+
+    pub fn device(&self) -> Device {
+        self.device
+    }
+}
+
+impl Driver for CudaContext {
+    type Module = Module;
+
+    fn device_info(&self) -> Result<DeviceInfo> {
+        let attribute = |attr| -> Result<u32> { Ok(self.device.get_attribute(attr)? as u32) };
+
+        let (major, minor) = (
+            attribute(DeviceAttribute::ComputeCapabilityMajor)?,
+            attribute(DeviceAttribute::ComputeCapabilityMinor)?,
+        );
+        // cust 0.3 does not expose the opt-in attribute, so take it from the
+        // architecture table. For devices missing from the table, assume the
+        // driver reserves 1 KB of the SM's shared memory per block.
+        let max_shared_mem_per_block_optin = match GpuArch::lookup(major, minor) {
+            Some(arch) => arch.sm.max_shared_mem_per_block,
+            None => attribute(DeviceAttribute::MaxSharedMemoryPerMultiprocessor)?.saturating_sub(1024),
+        };
+
+        Ok(DeviceInfo {
+            name: self.device.name()?,
+            compute_capability: (major, minor),
+            limits: DeviceLimits {
+                max_threads_per_block: attribute(DeviceAttribute::MaxThreadsPerBlock)?,
+                max_block_dim: (
+                    attribute(DeviceAttribute::MaxBlockDimX)?,
+                    attribute(DeviceAttribute::MaxBlockDimY)?,
+                    attribute(DeviceAttribute::MaxBlockDimZ)?,
+                ),
+                max_grid_dim: (
+                    attribute(DeviceAttribute::MaxGridDimX)?,
+                    attribute(DeviceAttribute::MaxGridDimY)?,
+                    attribute(DeviceAttribute::MaxGridDimZ)?,
+                ),
+                max_shared_mem_per_block: attribute(DeviceAttribute::MaxSharedMemoryPerBlock)?,
+                max_shared_mem_per_block_optin,
+                warp_size: attribute(DeviceAttribute::WarpSize)?,
+            },
+        })
+    }
+
+    fn load_module(&self, ptx: &str) -> Result<Self::Module> {
+        Ok(Module::from_ptx(ptx, &[])?)
+    }
+
+    fn malloc(&self, bytes: usize) -> Result<DevicePtr> {
+        let ptr = unsafe { cuda_malloc::<u8>(bytes)? };
+        Ok(ptr.as_raw())
+    }
+
+    fn free(&self, ptr: DevicePtr) -> Result<()> {
+        unsafe { cuda_free(DevicePointer::<u8>::from_raw(ptr))? };
+        Ok(())
+    }
+
+    fn copy_to_device(&self, dst: DevicePtr, src: &[u8]) -> Result<()> {
+        let mut slice = unsafe {
+            DeviceSlice::from_raw_parts_mut(DevicePointer::<u8>::from_raw(dst), src.len())
+        };
+        slice.copy_from(src)?;
+        Ok(())
+    }
+
+    fn copy_from_device(&self, dst: &mut [u8], src: DevicePtr) -> Result<()> {
+        let slice = unsafe {
+            DeviceSlice::from_raw_parts(DevicePointer::<u8>::from_raw(src), dst.len())
+        };
+        slice.copy_to(dst)?;
+        Ok(())
+    }
+
+    fn launch(&self, module: &Self::Module, params: &LaunchParams) -> Result<()> {
+        let function = module.get_function(&params.function)
+            .with_context(|| format!("Kernel `{}` not found in module", params.function))?;
+
+        // cuLaunchKernel takes a pointer to each argument value.
+        let args: Vec<*mut c_void> = params.args.iter()
+            .map(|arg| match arg {
+                KernelArg::U32(v) => v as *const u32 as *mut c_void,
+                KernelArg::F32(v) => v as *const f32 as *mut c_void,
+                KernelArg::Ptr(v) => v as *const u64 as *mut c_void,
+            })
+            .collect();
+
+        unsafe {
+            self.stream.launch(
+                &function,
+                params.grid,
+                params.block,
+                params.shared_mem_bytes,
+                &args,
+            )?;
+        }
+
+        Ok(())
+    }
+
+    fn synchronize(&self) -> Result<()> {
+        self.stream.synchronize()?;
+        Ok(())
+    }
+
+    fn timer(&self) -> Result<Box<dyn Timer + '_>> {
+        Ok(Box::new(EventTimer {
+            stream: &self.stream,
+            start: Event::new(EventFlags::DEFAULT)?,
+            stop: Event::new(EventFlags::DEFAULT)?,
+        }))
+    }
+}
+
+/// [`Timer`] reading the GPU time between two events recorded on the
+/// context's stream around the work.
+pub struct EventTimer<'a> {
+    stream: &'a Stream,
+    start: Event,
+    stop: Event,
+}
+
+impl Timer for EventTimer<'_> {
+    fn time(&mut self, work: &mut dyn FnMut() -> Result<()>) -> Result<f64> {
+        self.start.record(self.stream)?;
+        work()?;
+        self.stop.record(self.stream)?;
+        self.stop.synchronize()?;
+        Ok(self.stop.elapsed_time_f32(&self.start)? as f64 / 1e3)
+    }
+}
+
+/// The CUDA implementation of [`crate::GemmBackend`].
+pub type CudaBackend = DeviceBackend<CudaContext>;
+
+impl CudaBackend {
+    pub fn new<P: AsRef<Path>>(ptx_path: P) -> Result<Self> {
+        DeviceBackend::load(CudaContext::new()?, ptx_path)
+    }
+
+    pub fn on_device<P: AsRef<Path>>(ordinal: u32, ptx_path: P) -> Result<Self> {
+        DeviceBackend::load(CudaContext::with_device(ordinal)?, ptx_path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cuda_context_creation() {
+        let ctx = CudaContext::new();
+        assert!(ctx.is_ok(), "Failed to create CUDA context");
+    }
+}
@@ -0,0 +1,167 @@
+use anyhow::{ensure, Context, Result};
+use std::marker::PhantomData;
+use std::sync::Arc;
+use utils::TensorLayout;
+
+use crate::driver::{as_bytes, as_bytes_mut, DevicePtr, DeviceScalar, Driver};
+use crate::matrix::storage_len;
+
+/// Device-resident matrix: an owned allocation sized for its layout.
+///
+/// The allocation is released through the driver when the matrix is dropped.
+pub struct DeviceMatrix<T: DeviceScalar, D: Driver> {
+    driver: Arc<D>,
+    ptr: DevicePtr,
+    layout: TensorLayout,
+    len: usize,
+    _marker: PhantomData<T>,
+}
+
+impl<T: DeviceScalar, D: Driver> DeviceMatrix<T, D> {
+    pub fn from_host(driver: &Arc<D>, layout: TensorLayout, data: &[T]) -> Result<Self> {
+        let mut matrix = DeviceMatrix::alloc(driver, layout)?;
+        matrix.copy_from_host(data)?;
+        Ok(matrix)
+    }
+
+    /// Allocates storage for `layout`. The contents are unspecified until
+    /// written.
+    pub fn alloc(driver: &Arc<D>, layout: TensorLayout) -> Result<Self> {
+        let len = storage_len(&layout)?;
+        let ptr = driver
+            .malloc(len * std::mem::size_of::<T>())
+            .context("Failed to allocate device memory")?;
+        Ok(Self {
+            driver: Arc::clone(driver),
+            ptr,
+            layout,
+            len,
+            _marker: PhantomData,
+        })
+    }
+
+    pub fn copy_from_host(&mut self, data: &[T]) -> Result<()> {
+        ensure!(
+            data.len() == self.len,
+            "Host slice has {} elements, {} needs {}",
+            data.len(),
+            self.layout,
+            self.len
+        );
+        self.driver
+            .copy_to_device(self.ptr, as_bytes(data))
+            .context("Failed to copy data to device")?;
+        Ok(())
+    }
+
+    /// Copies `data` into the allocation starting at element `offset`.
+    pub fn copy_from_host_at(&mut self, offset: usize, data: &[T]) -> Result<()> {
+        ensure!(
+            offset <= self.len && data.len() <= self.len - offset,
+            "Copy of {} elements at {} overruns {} of {} elements",
+            data.len(),
+            offset,
+            self.layout,
+            self.len
+        );
+        self.driver
+            .copy_to_device(self.ptr + (offset * std::mem::size_of::<T>()) as u64, as_bytes(data))
+            .context("Failed to copy data to device")?;
+        Ok(())
+    }
+
+    pub fn copy_to_host(&self, data: &mut [T]) -> Result<()> {
+        ensure!(
+            data.len() == self.len,
+            "Host slice has {} elements, {} needs {}",
+            data.len(),
+            self.layout,
+            self.len
+        );
+        self.driver
+            .copy_from_device(as_bytes_mut(data), self.ptr)
+            .context("Failed to copy data from device")?;
+        Ok(())
+    }
+
+    pub fn layout(&self) -> &TensorLayout {
+        &self.layout
+    }
+
+    pub fn rows(&self) -> usize {
+        self.layout.shape.rows
+    }
+
+    pub fn cols(&self) -> usize {
+        self.layout.shape.cols
+    }
+
+    pub fn as_device_ptr(&self) -> DevicePtr {
+        self.ptr
+    }
+
+    /// Number of elements in the backing allocation.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+impl<T: DeviceScalar, D: Driver> Drop for DeviceMatrix<T, D> {
+    fn drop(&mut self) {
+        // Nothing useful can be done with a failed free during drop.
+        let _ = self.driver.free(self.ptr);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::driver::{MockDriver, MockFault};
+
+    #[test]
+    fn test_device_matrix_round_trip() {
+        let driver = Arc::new(MockDriver::new());
+        let layout = TensorLayout::row_major(2, 3);
+        let data = [1.0f32, 2.0, 3.0, 4.0, 5.0, 6.0];
+        let mut matrix = DeviceMatrix::from_host(&driver, layout, &data).unwrap();
+        assert_eq!(driver.allocated_bytes(), 24);
+
+        let mut out = [0.0f32; 6];
+        matrix.copy_to_host(&mut out).unwrap();
+        assert_eq!(out, data);
+
+        matrix.copy_from_host_at(4, &[-5.0, -6.0]).unwrap();
+        matrix.copy_to_host(&mut out).unwrap();
+        assert_eq!(out, [1.0, 2.0, 3.0, 4.0, -5.0, -6.0]);
+        assert!(matrix.copy_from_host_at(5, &[0.0, 0.0]).is_err());
+
+        drop(matrix);
+        assert_eq!(driver.live_allocations(), 0);
+    }
+
+    #[test]
+    fn test_device_matrix_rejects_size_mismatch() {
+        let driver = Arc::new(MockDriver::new());
+        let layout = TensorLayout::row_major(2, 3);
+
+        assert!(DeviceMatrix::from_host(&driver, layout, &[0.0f32; 5]).is_err());
+
+        let matrix = DeviceMatrix::<f32, _>::alloc(&driver, layout).unwrap();
+        assert!(matrix.copy_to_host(&mut [0.0f32; 7]).is_err());
+    }
+
+    #[test]
+    fn test_device_matrix_propagates_oom() {
+        let driver = Arc::new(MockDriver::new());
+        driver.inject(MockFault::OutOfMemory);
+
+        let layout = TensorLayout::row_major(32, 32);
+        let err = DeviceMatrix::<f32, _>::alloc(&driver, layout).err().unwrap();
+        assert_eq!(err.to_string(), "Failed to allocate device memory");
+        assert_eq!(err.root_cause().to_string(), "CUDA_ERROR_OUT_OF_MEMORY");
+    }
+}
@@ -0,0 +1,355 @@
+use anyhow::{bail, Result};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::Mutex;
+
+use utils::GpuArch;
+
+use crate::bench::{HostTimer, Timer};
+use crate::launch::DeviceLimits;
+
+/// Raw device address as seen by the driver.
+pub type DevicePtr = u64;
+
+/// Element types that can be copied to and from device memory bytewise.
+///
+/// # Safety
+///
+/// Implementors must be plain old data: `Copy`, no padding, and valid for
+/// every bit pattern.
+pub unsafe trait DeviceScalar: Copy + 'static {}
+
+unsafe impl DeviceScalar for f32 {}
+unsafe impl DeviceScalar for f64 {}
+unsafe impl DeviceScalar for u8 {}
+unsafe impl DeviceScalar for i8 {}
+unsafe impl DeviceScalar for u16 {}
+unsafe impl DeviceScalar for u32 {}
+unsafe impl DeviceScalar for i32 {}
+
+pub(crate) fn as_bytes<T: DeviceScalar>(data: &[T]) -> &[u8] {
+    // SAFETY: `DeviceScalar` guarantees `T` has no padding.
+    unsafe { std::slice::from_raw_parts(data.as_ptr().cast(), std::mem::size_of_val(data)) }
+}
+
+pub(crate) fn as_bytes_mut<T: DeviceScalar>(data: &mut [T]) -> &mut [u8] {
+    // SAFETY: `DeviceScalar` guarantees every bit pattern is a valid `T`.
+    unsafe {
+        std::slice::from_raw_parts_mut(data.as_mut_ptr().cast(), std::mem::size_of_val(data))
+    }
+}
+
+/// A single kernel argument, passed by value.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum KernelArg {
+    U32(u32),
+    F32(f32),
+    Ptr(DevicePtr),
+}
+
+/// Everything the driver needs to launch one kernel.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LaunchParams {
+    pub function: String,
+    pub grid: (u32, u32, u32),
+    pub block: (u32, u32, u32),
+    pub shared_mem_bytes: u32,
+    pub args: Vec<KernelArg>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeviceInfo {
+    pub name: String,
+    pub compute_capability: (u32, u32),
+    pub limits: DeviceLimits,
+}
+
+impl DeviceInfo {
+    /// The closest entry in the architecture table, if the device is known.
+    pub fn arch(&self) -> Option<&'static GpuArch> {
+        let (major, minor) = self.compute_capability;
+        GpuArch::for_compute_capability(major, minor)
+    }
+}
+
+/// Thin layer over the handful of CUDA driver calls the host library makes.
+///
+/// [`crate::CudaContext`] implements this on top of `cust`; [`MockDriver`]
+/// records calls and can inject failures so the host logic can be tested
+/// without hardware.
+pub trait Driver {
+    type Module;
+
+    fn device_info(&self) -> Result<DeviceInfo>;
+
+    fn load_module(&self, ptx: &str) -> Result<Self::Module>;
+
+    fn malloc(&self, bytes: usize) -> Result<DevicePtr>;
+
+    fn free(&self, ptr: DevicePtr) -> Result<()>;
+
+    fn copy_to_device(&self, dst: DevicePtr, src: &[u8]) -> Result<()>;
+
+    fn copy_from_device(&self, dst: &mut [u8], src: DevicePtr) -> Result<()>;
+
+    /// Enqueues a launch on the driver's stream.
+    fn launch(&self, module: &Self::Module, params: &LaunchParams) -> Result<()>;
+
+    fn synchronize(&self) -> Result<()>;
+
+    /// Timer for work on the driver's stream. Defaults to the host clock;
+    /// real devices time with events instead.
+    fn timer(&self) -> Result<Box<dyn Timer + '_>> {
+        Ok(Box::new(HostTimer))
+    }
+}
+
+/// Failure modes [`MockDriver`] can inject.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MockFault {
+    OutOfMemory,
+    ModuleLoad,
+    Launch,
+    Copy,
+    Synchronize,
+}
+
+impl fmt::Display for MockFault {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let msg = match self {
+            MockFault::OutOfMemory => "CUDA_ERROR_OUT_OF_MEMORY",
+            MockFault::ModuleLoad => "CUDA_ERROR_INVALID_PTX",
+            MockFault::Launch => "CUDA_ERROR_LAUNCH_FAILED",
+            MockFault::Copy => "CUDA_ERROR_ILLEGAL_ADDRESS",
+            MockFault::Synchronize => "CUDA_ERROR_UNKNOWN",
+        };
+        f.write_str(msg)
+    }
+}
+
+impl std::error::Error for MockFault {}
+
+#[derive(Debug)]
+struct MockState {
+    next_ptr: DevicePtr,
+    allocations: BTreeMap<DevicePtr, Vec<u8>>,
+    allocated_bytes: usize,
+    memory_limit: Option<usize>,
+    pending_faults: Vec<MockFault>,
+    launches: Vec<LaunchParams>,
+    synchronize_calls: usize,
+}
+
+/// Recording driver for tests.
+///
+/// Device memory is backed by host vectors, so copies round-trip, but
+/// launches are only recorded and never executed.
+#[derive(Debug)]
+pub struct MockDriver {
+    info: DeviceInfo,
+    state: Mutex<MockState>,
+}
+
+impl Default for MockDriver {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MockDriver {
+    pub fn new() -> Self {
+        Self::with_device_info(DeviceInfo {
+            name: "Mock Device".to_string(),
+            compute_capability: (8, 0),
+            limits: DeviceLimits::sm80(),
+        })
+    }
+
+    pub fn with_device_info(info: DeviceInfo) -> Self {
+        Self {
+            info,
+            state: Mutex::new(MockState {
+                // Keep address zero free so a null pointer is never handed out.
+                next_ptr: 0x1000,
+                allocations: BTreeMap::new(),
+                allocated_bytes: 0,
+                memory_limit: None,
+                pending_faults: Vec::new(),
+                launches: Vec::new(),
+                synchronize_calls: 0,
+            }),
+        }
+    }
+
+    /// Makes allocations beyond `bytes` in total fail with out-of-memory.
+    pub fn with_memory_limit(self, bytes: usize) -> Self {
+        self.state.lock().unwrap().memory_limit = Some(bytes);
+        self
+    }
+
+    /// Arms a one-shot failure for the next operation of the matching kind.
+    pub fn inject(&self, fault: MockFault) {
+        self.state.lock().unwrap().pending_faults.push(fault);
+    }
+
+    pub fn launches(&self) -> Vec<LaunchParams> {
+        self.state.lock().unwrap().launches.clone()
+    }
+
+    pub fn last_launch(&self) -> Option<LaunchParams> {
+        self.state.lock().unwrap().launches.last().cloned()
+    }
+
+    pub fn synchronize_calls(&self) -> usize {
+        self.state.lock().unwrap().synchronize_calls
+    }
+
+    pub fn live_allocations(&self) -> usize {
+        self.state.lock().unwrap().allocations.len()
+    }
+
+    pub fn allocated_bytes(&self) -> usize {
+        self.state.lock().unwrap().allocated_bytes
+    }
+}
+
+impl MockState {
+    fn take_fault(&mut self, fault: MockFault) -> Result<()> {
+        if let Some(pos) = self.pending_faults.iter().position(|f| *f == fault) {
+            self.pending_faults.remove(pos);
+            return Err(fault.into());
+        }
+        Ok(())
+    }
+
+    /// Resolves `ptr` to (allocation base, offset into it), checking that
+    /// `len` bytes starting there are in bounds.
+    fn resolve(&self, ptr: DevicePtr, len: usize) -> Result<(DevicePtr, usize)> {
+        let Some((&base, bytes)) = self.allocations.range(..=ptr).next_back() else {
+            bail!("Address {:#x} is not a device allocation", ptr);
+        };
+        let offset = (ptr - base) as usize;
+        if offset + len > bytes.len() {
+            bail!(
+                "Access of {} bytes at {:#x} overruns allocation of {} bytes at {:#x}",
+                len,
+                ptr,
+                bytes.len(),
+                base
+            );
+        }
+        Ok((base, offset))
+    }
+}
+
+impl Driver for MockDriver {
+    type Module = ();
+
+    fn device_info(&self) -> Result<DeviceInfo> {
+        Ok(self.info.clone())
+    }
+
+    fn load_module(&self, _ptx: &str) -> Result<Self::Module> {
+        self.state.lock().unwrap().take_fault(MockFault::ModuleLoad)
+    }
+
+    fn malloc(&self, bytes: usize) -> Result<DevicePtr> {
+        let mut state = self.state.lock().unwrap();
+        state.take_fault(MockFault::OutOfMemory)?;
+        if let Some(limit) = state.memory_limit {
+            if state.allocated_bytes + bytes > limit {
+                return Err(MockFault::OutOfMemory.into());
+            }
+        }
+
+        let ptr = state.next_ptr;
+        // Mimic cudaMalloc's 256-byte alignment and never hand out the same
+        // address twice, even for zero-sized allocations.
+        state.next_ptr += (bytes.max(1) as u64).div_ceil(256) * 256;
+        state.allocated_bytes += bytes;
+        state.allocations.insert(ptr, vec![0; bytes]);
+        Ok(ptr)
+    }
+
+    fn free(&self, ptr: DevicePtr) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        match state.allocations.remove(&ptr) {
+            Some(bytes) => {
+                state.allocated_bytes -= bytes.len();
+                Ok(())
+            }
+            None => bail!("Double free or invalid pointer {:#x}", ptr),
+        }
+    }
+
+    fn copy_to_device(&self, dst: DevicePtr, src: &[u8]) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        state.take_fault(MockFault::Copy)?;
+        let (base, offset) = state.resolve(dst, src.len())?;
+        let bytes = state.allocations.get_mut(&base).unwrap();
+        bytes[offset..offset + src.len()].copy_from_slice(src);
+        Ok(())
+    }
+
+    fn copy_from_device(&self, dst: &mut [u8], src: DevicePtr) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        state.take_fault(MockFault::Copy)?;
+        let (base, offset) = state.resolve(src, dst.len())?;
+        let bytes = &state.allocations[&base];
+        dst.copy_from_slice(&bytes[offset..offset + dst.len()]);
+        Ok(())
+    }
+
+    fn launch(&self, _module: &Self::Module, params: &LaunchParams) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        state.take_fault(MockFault::Launch)?;
+        state.launches.push(params.clone());
+        Ok(())
+    }
+
+    fn synchronize(&self) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        state.synchronize_calls += 1;
+        state.take_fault(MockFault::Synchronize)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mock_memory_round_trip() {
+        let driver = MockDriver::new();
+        let ptr = driver.malloc(16).unwrap();
+        driver.copy_to_device(ptr + 4, &[1, 2, 3, 4]).unwrap();
+
+        let mut out = [0u8; 8];
+        driver.copy_from_device(&mut out, ptr).unwrap();
+        assert_eq!(out, [0, 0, 0, 0, 1, 2, 3, 4]);
+
+        assert!(driver.copy_to_device(ptr + 12, &[0; 8]).is_err());
+        driver.free(ptr).unwrap();
+        assert!(driver.free(ptr).is_err());
+    }
+
+    #[test]
+    fn test_mock_faults_are_one_shot() {
+        let driver = MockDriver::new();
+        driver.inject(MockFault::OutOfMemory);
+
+        let err = driver.malloc(4).unwrap_err();
+        assert_eq!(err.downcast_ref::<MockFault>(), Some(&MockFault::OutOfMemory));
+        assert!(driver.malloc(4).is_ok());
+    }
+
+    #[test]
+    fn test_mock_memory_limit() {
+        let driver = MockDriver::new().with_memory_limit(100);
+        let ptr = driver.malloc(64).unwrap();
+        assert!(driver.malloc(64).is_err());
+
+        driver.free(ptr).unwrap();
+        assert!(driver.malloc(64).is_ok());
+    }
+}
@@ -0,0 +1,271 @@
+use anyhow::{bail, ensure, Error, Result};
+use std::fmt;
+use std::str::FromStr;
+use utils::{DataType, TensorLayout};
+
+use crate::cpu_gemm::{check_shapes, CpuGemm};
+use crate::matrix::{HostMatrix, MatrixRef};
+use crate::verify::TolerancePolicy;
+
+/// `gamma_n = n * u / (1 - n * u)`, the classic bound on the relative error
+/// of `n` chained roundings with unit roundoff `u`.
+fn gamma(n: usize, u: f64) -> Result<f64> {
+    let nu = n as f64 * u;
+    ensure!(
+        nu < 1.0,
+        "No meaningful error bound for {} roundings at unit roundoff {:e}",
+        n,
+        u
+    );
+    Ok(nu / (1.0 - nu))
+}
+
+fn unit_roundoff(dtype: DataType) -> Result<f64> {
+    match dtype.unit_roundoff() {
+        Some(u) => Ok(u),
+        None => bail!("{} is not a floating-point type", dtype),
+    }
+}
+
+/// Rounding behaviour of a GEMM implementation, for the a-priori forward
+/// error bound
+///
+/// `|C_ij - fl(C)_ij| <= c_ab * |alpha| (|A||B|)_ij + c_c * |beta| |C_ij|`
+///
+/// where the coefficients come from the input, accumulator and reference
+/// precisions. Underflow is ignored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ErrorModel {
+    /// Type A and B are rounded to before multiplying. Inputs at least as
+    /// precise as f32 are not rounded, since the host data is already f32.
+    pub input: DataType,
+    /// Type the K-length dot products are accumulated in.
+    pub accumulator: DataType,
+    /// Accumulator of the reference the result is checked against. Its own
+    /// rounding error is added to the bound; with [`DataType::F64`] that
+    /// share is negligible.
+    pub reference: DataType,
+}
+
+impl ErrorModel {
+    /// Plain f32 GEMM checked against the f32 host reference.
+    pub fn fp32() -> Self {
+        Self::tensor_core(DataType::F32)
+    }
+
+    /// Tensor-core GEMM: inputs rounded to `input`, f32 accumulation.
+    pub fn tensor_core(input: DataType) -> Self {
+        Self {
+            input,
+            accumulator: DataType::F32,
+            reference: DataType::F32,
+        }
+    }
+
+    pub fn with_accumulator(mut self, accumulator: DataType) -> Self {
+        self.accumulator = accumulator;
+        self
+    }
+
+    pub fn with_reference(mut self, reference: DataType) -> Self {
+        self.reference = reference;
+        self
+    }
+
+    /// `(c_ab, c_c)` for reduction length `k`. Fails when the accumulator is
+    /// too coarse for `k`, i.e. `(k + 2) * u >= 1`.
+    pub fn coefficients(&self, k: usize) -> Result<(f64, f64)> {
+        let u_in = unit_roundoff(self.input)?;
+        let u_in = if u_in <= unit_roundoff(DataType::F32)? { 0.0 } else { u_in };
+        // Each product carries the rounding of both of its inputs.
+        let input = (1.0 + u_in) * (1.0 + u_in) - 1.0;
+        // K products and sums, then the alpha and beta scalings.
+        let accumulate = gamma(k + 2, unit_roundoff(self.accumulator)?)?;
+        let reference = gamma(k + 2, unit_roundoff(self.reference)?)?;
+
+        let c_ab = (1.0 + input) * (1.0 + accumulate) - 1.0 + reference;
+        let c_c = accumulate + reference;
+        Ok((c_ab, c_c))
+    }
+
+    /// Per-element bounds for `alpha * A * B + beta * C`, where `c` is C
+    /// before the GEMM. As with [`CpuGemm::run`], `c` is not read when
+    /// `beta == 0`.
+    pub fn tolerance(
+        &self,
+        alpha: f32,
+        a: MatrixRef<'_>,
+        b: MatrixRef<'_>,
+        beta: f32,
+        c: MatrixRef<'_>,
+    ) -> Result<ErrorBoundTolerance> {
+        check_shapes(a.layout(), b.layout(), c.layout())?;
+        let (m, n, k) = (c.rows(), c.cols(), a.cols());
+        let (c_ab, c_c) = self.coefficients(k)?;
+
+        // |A||B| in f32; the sum of non-negative terms is low by at most
+        // gamma_k(f32) relative, which is folded back in below.
+        let abs = |x: MatrixRef<'_>| {
+            HostMatrix::from_vec(*x.layout(), x.as_slice().iter().map(|v| v.abs()).collect())
+        };
+        let mut magnitude = HostMatrix::zeros(TensorLayout::row_major(m, n))?;
+        CpuGemm::default().run(1.0, abs(a)?.view(), abs(b)?.view(), 0.0, magnitude.view_mut())?;
+        let slack = 1.0 + gamma(k.max(1), unit_roundoff(DataType::F32)?)?;
+
+        let mut bounds = Vec::with_capacity(m * n);
+        for i in 0..m {
+            for j in 0..n {
+                let product = alpha.abs() as f64 * magnitude.get(i, j) as f64 * slack;
+                let accumulated = if beta == 0.0 {
+                    0.0
+                } else {
+                    beta.abs() as f64 * c.get(i, j).abs() as f64
+                };
+                bounds.push(c_ab * product + c_c * accumulated);
+            }
+        }
+
+        Ok(ErrorBoundTolerance {
+            model: *self,
+            k,
+            cols: n,
+            bounds,
+        })
+    }
+}
+
+impl fmt::Display for ErrorModel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} inputs, {} accumulation, {} reference",
+            self.input, self.accumulator, self.reference
+        )
+    }
+}
+
+impl FromStr for ErrorModel {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let input = match s {
+            "fp32" | "f32" => DataType::F32,
+            "tf32" => DataType::Tf32,
+            "bf16" => DataType::Bf16,
+            "fp16" | "f16" => DataType::F16,
+            "fp8" | "e4m3" => DataType::Fp8E4M3,
+            "e5m2" => DataType::Fp8E5M2,
+            other => bail!("Unknown precision `{}` (expected fp32, tf32, bf16, fp16, fp8 or e5m2)", other),
+        };
+        Ok(Self::tensor_core(input))
+    }
+}
+
+/// [`TolerancePolicy`] holding one a-priori error bound per output element,
+/// built by [`ErrorModel::tolerance`] for a specific GEMM.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ErrorBoundTolerance {
+    model: ErrorModel,
+    k: usize,
+    cols: usize,
+    bounds: Vec<f64>,
+}
+
+impl ErrorBoundTolerance {
+    /// Bound for the element at `(row, col)`.
+    pub fn bound(&self, row: usize, col: usize) -> f64 {
+        self.bounds[row * self.cols + col]
+    }
+}
+
+impl TolerancePolicy for ErrorBoundTolerance {
+    fn allowed_error(&self, row: usize, col: usize, _expected: f64) -> f64 {
+        self.bound(row, col)
+    }
+
+    fn describe(&self) -> String {
+        format!("a-priori bound for K={} ({})", self.k, self.model)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::verify::Verifier;
+
+    fn round_to_bf16(x: f32) -> f32 {
+        let bits = x.to_bits();
+        let rounded = bits.wrapping_add(0x7fff + ((bits >> 16) & 1));
+        f32::from_bits(rounded & 0xffff_0000)
+    }
+
+    fn gemm(a: &HostMatrix, b: &HostMatrix) -> HostMatrix {
+        let mut c = HostMatrix::zeros(TensorLayout::row_major(a.rows(), b.cols())).unwrap();
+        CpuGemm::default().run(1.0, a.view(), b.view(), 0.0, c.view_mut()).unwrap();
+        c
+    }
+
+    #[test]
+    fn test_coefficients() {
+        let u = f32::EPSILON as f64 / 2.0;
+        let (c_ab, c_c) = ErrorModel::fp32().with_reference(DataType::F64).coefficients(14).unwrap();
+        assert!((c_ab - 16.0 * u).abs() < 1e-3 * c_ab);
+        assert!(c_c >= c_ab);
+
+        // bf16 inputs dominate any f32 accumulation error at moderate K.
+        let (bf16, _) = ErrorModel::tensor_core(DataType::Bf16).coefficients(1024).unwrap();
+        assert!(bf16 > 2.0 / 256.0);
+
+        let fp16_acc = ErrorModel::tensor_core(DataType::F16).with_accumulator(DataType::F16);
+        assert!(fp16_acc.coefficients(1024).is_ok());
+        assert!(fp16_acc.coefficients(4096).is_err());
+        assert!(ErrorModel::tensor_core(DataType::Int8).coefficients(16).is_err());
+
+        assert_eq!("bf16".parse::<ErrorModel>().unwrap().input, DataType::Bf16);
+        assert!("int4".parse::<ErrorModel>().is_err());
+    }
+
+    #[test]
+    fn test_bounds_scale_with_magnitude() {
+        let a = HostMatrix::from_vec(TensorLayout::row_major(2, 2), vec![1.0, -1.0, 0.0, 0.0]).unwrap();
+        let b = HostMatrix::from_vec(TensorLayout::row_major(2, 1), vec![3.0, 3.0]).unwrap();
+        let c = HostMatrix::from_vec(TensorLayout::row_major(2, 1), vec![0.0, 8.0]).unwrap();
+
+        let tolerance = ErrorModel::fp32().tolerance(2.0, a.view(), b.view(), 0.5, c.view()).unwrap();
+        let (c_ab, c_c) = ErrorModel::fp32().coefficients(2).unwrap();
+        // Row 0 cancels to zero but |A||B| = 6 still allows rounding error.
+        assert!(tolerance.bound(0, 0) >= c_ab * 12.0);
+        assert!((tolerance.bound(1, 0) - c_c * 4.0).abs() < 1e-12);
+        assert!(tolerance.describe().contains("K=2"));
+    }
+
+    #[test]
+    fn test_reduced_precision_kernel() {
+        let (m, n, k) = (8, 8, 512);
+        let values = |len: usize, seed: usize| -> Vec<f32> {
+            (0..len).map(|i| ((i * 7919 + seed) % 1000) as f32 / 997.0 - 0.5).collect()
+        };
+        let a = HostMatrix::from_vec(TensorLayout::row_major(m, k), values(m * k, 1)).unwrap();
+        let b = HostMatrix::from_vec(TensorLayout::row_major(k, n), values(k * n, 2)).unwrap();
+        let zeros = HostMatrix::zeros(TensorLayout::row_major(m, n)).unwrap();
+
+        let round = |x: &HostMatrix| {
+            HostMatrix::from_vec(*x.layout(), x.as_slice().iter().map(|&v| round_to_bf16(v)).collect()).unwrap()
+        };
+        let expected = gemm(&a, &b);
+        let bf16_result = gemm(&round(&a), &round(&b));
+
+        let bf16 = ErrorModel::tensor_core(DataType::Bf16)
+            .tolerance(1.0, a.view(), b.view(), 0.0, zeros.view())
+            .unwrap();
+        let fp32 = ErrorModel::fp32().tolerance(1.0, a.view(), b.view(), 0.0, zeros.view()).unwrap();
+
+        let compare = |policy: &ErrorBoundTolerance| {
+            Verifier::new(policy)
+                .compare(m, n, expected.as_slice(), bf16_result.as_slice())
+                .unwrap()
+        };
+        compare(&bf16).assert_passed();
+        assert!(!compare(&fp32).passed());
+    }
+}
@@ -0,0 +1,265 @@
+//! Seeded, reproducible matrix contents.
+//!
+//! Elements come from [`utils::random::element`], a pure function of the
+//! seed, the operand stream and the element's row and column. The host
+//! generator here and `generate_kernel` on the device therefore agree bit
+//! for bit, whatever the thread count or launch geometry.
+
+use anyhow::{bail, ensure, Context, Error, Result};
+use std::fmt;
+use std::str::FromStr;
+use utils::random;
+use utils::{MemoryLayout, TensorLayout};
+
+use crate::matrix::HostMatrix;
+
+/// Streams of the GEMM operands, so one seed gives different A, B and C.
+pub const STREAM_A: u32 = 0;
+pub const STREAM_B: u32 = 1;
+pub const STREAM_C: u32 = 2;
+
+/// What the generated elements look like.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum Distribution {
+    /// Uniform in [-1, 1).
+    #[default]
+    Uniform,
+    /// Approximately standard normal (an Irwin-Hall sum), within ±6.
+    Normal,
+    /// Integers in [-bound, bound]. Every product and partial sum of a GEMM
+    /// is exact while `bound² · K < 2^24`.
+    SmallInt { bound: u32 },
+    /// `row · 2^b + col` with `2^b >= cols`, modulo 2^24: every element
+    /// encodes its own position, so a transposed or mis-strided read shows.
+    Index,
+    /// Nearly parallel columns; the condition number is about `2^bits`.
+    IllConditioned { bits: u32 },
+    /// Random sign and mantissa with binary exponents in [-exponent, exponent].
+    WideRange { exponent: u32 },
+    /// Uniform, with a fraction `rate` of NaN, ±Inf and subnormal elements.
+    Special { rate: f64 },
+}
+
+impl Distribution {
+    /// Every distribution, with default parameters.
+    pub const ALL: [Distribution; 7] = [
+        Distribution::Uniform,
+        Distribution::Normal,
+        Distribution::SmallInt { bound: 4 },
+        Distribution::Index,
+        Distribution::IllConditioned { bits: 20 },
+        Distribution::WideRange { exponent: 20 },
+        Distribution::Special { rate: 0.01 },
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Distribution::Uniform => "uniform",
+            Distribution::Normal => "normal",
+            Distribution::SmallInt { .. } => "small-int",
+            Distribution::Index => "index",
+            Distribution::IllConditioned { .. } => "ill-conditioned",
+            Distribution::WideRange { .. } => "wide-range",
+            Distribution::Special { .. } => "special",
+        }
+    }
+
+    /// Distribution code and parameter as `utils::random::element` and
+    /// `generate_kernel` take them, for a matrix with `cols` columns.
+    pub fn raw(&self, cols: usize) -> (u32, u32) {
+        match *self {
+            Distribution::Uniform => (random::UNIFORM, 0),
+            Distribution::Normal => (random::NORMAL, 0),
+            Distribution::SmallInt { bound } => (random::SMALL_INT, bound),
+            Distribution::Index => (random::INDEX, usize::BITS - cols.saturating_sub(1).leading_zeros()),
+            Distribution::IllConditioned { bits } => (random::ILL_CONDITIONED, bits),
+            Distribution::WideRange { exponent } => (random::WIDE_RANGE, exponent),
+            Distribution::Special { rate } => (random::SPECIAL, (rate * 2f64.powi(32)).min(u32::MAX as f64) as u32),
+        }
+    }
+}
+
+impl fmt::Display for Distribution {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())?;
+        match self {
+            Distribution::SmallInt { bound } => write!(f, ":{}", bound),
+            Distribution::IllConditioned { bits } => write!(f, ":{}", bits),
+            Distribution::WideRange { exponent } => write!(f, ":{}", exponent),
+            Distribution::Special { rate } => write!(f, ":{}", rate),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl FromStr for Distribution {
+    type Err = Error;
+
+    /// `NAME` or `NAME:PARAM`, e.g. `normal` or `small-int:8`.
+    fn from_str(s: &str) -> Result<Self> {
+        let (name, param) = match s.split_once(':') {
+            Some((name, param)) => (name, Some(param)),
+            None => (s, None),
+        };
+        let default = Distribution::ALL
+            .into_iter()
+            .find(|dist| dist.name() == name)
+            .with_context(|| {
+                format!(
+                    "Unknown distribution `{}` (expected uniform, normal, small-int[:BOUND], index, \
+                     ill-conditioned[:BITS], wide-range[:EXPONENT] or special[:RATE])",
+                    name
+                )
+            })?;
+        let Some(param) = param else {
+            return Ok(default);
+        };
+
+        let int = |max: u32| -> Result<u32> {
+            let value = param
+                .parse()
+                .with_context(|| format!("Invalid parameter `{}` for {}", param, name))?;
+            ensure!(value <= max, "The {} parameter must be at most {}, got {}", name, max, value);
+            Ok(value)
+        };
+        Ok(match default {
+            Distribution::SmallInt { .. } => Distribution::SmallInt { bound: int((1 << 23) - 1)? },
+            Distribution::IllConditioned { .. } => Distribution::IllConditioned { bits: int(126)? },
+            Distribution::WideRange { .. } => Distribution::WideRange { exponent: int(126)? },
+            Distribution::Special { .. } => {
+                let rate: f64 = param
+                    .parse()
+                    .with_context(|| format!("Invalid parameter `{}` for {}", param, name))?;
+                ensure!((0.0..=1.0).contains(&rate), "The special rate must be in [0, 1], got {}", rate);
+                Distribution::Special { rate }
+            }
+            other => bail!("The {} distribution takes no parameter", other),
+        })
+    }
+}
+
+/// Seeded source of matrix contents.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Generator {
+    pub seed: u64,
+    pub distribution: Distribution,
+    /// Host threads to generate with; never affects the values.
+    pub threads: usize,
+}
+
+impl Default for Generator {
+    fn default() -> Self {
+        Self::new(0, Distribution::default())
+    }
+}
+
+impl Generator {
+    pub fn new(seed: u64, distribution: Distribution) -> Self {
+        Self {
+            seed,
+            distribution,
+            threads: std::thread::available_parallelism().map_or(1, |n| n.get()),
+        }
+    }
+
+    pub fn with_threads(mut self, threads: usize) -> Self {
+        self.threads = threads.max(1);
+        self
+    }
+
+    /// The seed as the two-word Philox key.
+    pub fn key(&self) -> [u32; 2] {
+        [self.seed as u32, (self.seed >> 32) as u32]
+    }
+
+    /// Row-major `rows x cols` elements of `stream`.
+    pub fn values(&self, stream: u32, rows: usize, cols: usize) -> Result<Vec<f32>> {
+        ensure!(
+            u32::try_from(rows).is_ok() && u32::try_from(cols).is_ok(),
+            "Cannot generate a {}x{} matrix: dimensions must fit in u32",
+            rows,
+            cols
+        );
+        let (kind, param) = self.distribution.raw(cols);
+        let key = self.key();
+        let mut values = vec![0.0f32; rows * cols];
+        if values.is_empty() {
+            return Ok(values);
+        }
+
+        let rows_per_thread = rows.div_ceil(self.threads.max(1));
+        std::thread::scope(|s| {
+            for (block, chunk) in values.chunks_mut(rows_per_thread * cols).enumerate() {
+                s.spawn(move || {
+                    for (i, value) in chunk.iter_mut().enumerate() {
+                        let row = block * rows_per_thread + i / cols;
+                        *value = random::element(key, stream, row as u32, (i % cols) as u32, kind, param);
+                    }
+                });
+            }
+        });
+        Ok(values)
+    }
+
+    /// A matrix with `layout` holding the elements of `stream`.
+    pub fn matrix(&self, stream: u32, layout: TensorLayout) -> Result<HostMatrix> {
+        let (rows, cols) = (layout.shape.rows, layout.shape.cols);
+        let values = self.values(stream, rows, cols)?;
+        if layout.layout == MemoryLayout::RowMajor && layout.leading_dim == cols {
+            return HostMatrix::from_vec(layout, values);
+        }
+        let mut matrix = HostMatrix::zeros(layout)?;
+        for (i, value) in values.into_iter().enumerate() {
+            matrix.set(i / cols, i % cols, value);
+        }
+        Ok(matrix)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_distribution_specs() {
+        for dist in Distribution::ALL {
+            assert_eq!(dist.to_string().parse::<Distribution>().unwrap(), dist);
+            assert_eq!(dist.name().parse::<Distribution>().unwrap(), dist);
+        }
+        assert_eq!("small-int:8".parse::<Distribution>().unwrap(), Distribution::SmallInt { bound: 8 });
+        assert_eq!("special:0.5".parse::<Distribution>().unwrap().raw(1), (random::SPECIAL, 1 << 31));
+        assert_eq!(Distribution::Index.raw(64), (random::INDEX, 6));
+        assert_eq!(Distribution::Index.raw(65), (random::INDEX, 7));
+        for bad in ["gaussian", "normal:2", "small-int:x", "wide-range:127", "special:1.5"] {
+            assert!(bad.parse::<Distribution>().is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn test_generation_is_deterministic() {
+        for dist in Distribution::ALL {
+            let generator = Generator::new(42, dist);
+            let one = generator.with_threads(1).values(STREAM_A, 37, 11).unwrap();
+            let many = generator.with_threads(5).values(STREAM_A, 37, 11).unwrap();
+            // Bitwise, so NaNs compare too.
+            let bits = |values: &[f32]| values.iter().map(|v| v.to_bits()).collect::<Vec<_>>();
+            assert_eq!(bits(&one), bits(&many), "{}", dist);
+            assert_eq!(one[3 * 11 + 4].to_bits(), generator.values(STREAM_A, 4, 11).unwrap()[3 * 11 + 4].to_bits());
+            if dist != Distribution::Index {
+                assert_ne!(bits(&one), bits(&generator.values(STREAM_B, 37, 11).unwrap()), "{}", dist);
+                assert_ne!(bits(&one), bits(&Generator::new(43, dist).values(STREAM_A, 37, 11).unwrap()), "{}", dist);
+            }
+        }
+
+        let index = Generator::new(0, Distribution::Index).values(STREAM_A, 3, 5).unwrap();
+        assert_eq!(index[2 * 5 + 4], (2 * 8 + 4) as f32);
+
+        // Any layout holds the same logical elements.
+        let generator = Generator::new(7, Distribution::Normal);
+        let row_major = generator.matrix(STREAM_C, TensorLayout::row_major(9, 6)).unwrap();
+        for layout in [TensorLayout::column_major(9, 6), TensorLayout::tiled(9, 6, 4, 4)] {
+            assert_eq!(generator.matrix(STREAM_C, layout).unwrap().to_row_major(), row_major.as_slice());
+        }
+        assert!(generator.values(STREAM_A, 0, 5).unwrap().is_empty());
+    }
+}
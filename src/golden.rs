@@ -0,0 +1,424 @@
+//! Golden outputs: seeded GEMMs whose C comes from the f64
+//! [`ReferenceGemm`], stored on disk so any backend and kernel variant can be
+//! replayed against a fixed corpus, independently of how `verify_gemm`
+//! computes its own reference.
+//!
+//! Each golden lives in a directory named after its shape, e.g.
+//! `data/golden/64x64x64/`, holding raw row-major f32 files with the names
+//! documented in `data/README.md` and a `golden.json` metadata file:
+//!
+//! ```text
+//! {
+//!   "format": "rust-gpu-gemm-golden/1",
+//!   "m": 64, "n": 64, "k": 64, "alpha": 1, "beta": 0,
+//!   "seed": "0", "distribution": "uniform", "summation": "kahan",
+//!   "files": [
+//!     {"operand": "A", "path": "input_A_64x64.bin", "crc32": 1951898083},
+//!     {"operand": "B", "path": "input_B_64x64.bin", "crc32": 2491163302},
+//!     {"operand": "C", "path": "output_C_64x64.bin", "crc32": 3824199672}
+//!   ]
+//! }
+//! ```
+//!
+//! `input_C_<M>x<N>.bin`, C before the GEMM, is only stored when beta is
+//! non-zero. The seed is a string because a u64 does not survive a JSON
+//! number.
+
+use anyhow::{bail, ensure, Context, Result};
+use std::io::Write;
+use std::path::{Component, Path, PathBuf};
+use utils::{DataType, TensorLayout};
+
+use crate::backend::GemmBackend;
+use crate::cli::ReplayArgs;
+use crate::generate::{Distribution, Generator, STREAM_A, STREAM_B, STREAM_C};
+use crate::json::{self, Json};
+use crate::matrix::{HostMatrix, MatrixRef};
+use crate::matrix_io::{self, MatrixFormat};
+use crate::reference::{ReferenceGemm, Summation};
+use crate::sweep::{GemmShape, SweepStatus};
+use crate::variant::KernelVariant;
+use crate::verify::{VerificationReport, Verifier};
+
+pub const GOLDEN_FORMAT: &str = "rust-gpu-gemm-golden/1";
+
+/// Name of the metadata file in every golden directory.
+pub const METADATA_FILE: &str = "golden.json";
+
+/// Where `golden` writes and `replay` looks by default.
+pub const DEFAULT_GOLDEN_DIR: &str = "data/golden";
+
+/// One stored GEMM with its expected result. Operands are row-major.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Golden {
+    pub shape: GemmShape,
+    pub alpha: f32,
+    pub beta: f32,
+    pub seed: u64,
+    pub distribution: Distribution,
+    /// Summation of the f64 reference C was computed with.
+    pub summation: Summation,
+    pub a: Vec<f32>,
+    pub b: Vec<f32>,
+    /// C before the GEMM; all zeros when beta is 0.
+    pub c_in: Vec<f32>,
+    /// The f64 reference result, rounded to f32.
+    pub c: Vec<f32>,
+}
+
+impl Golden {
+    /// Generates the inputs with `generator` and computes C with the f64
+    /// reference.
+    pub fn compute(shape: GemmShape, alpha: f32, beta: f32, generator: &Generator, summation: Summation) -> Result<Self> {
+        let GemmShape { m, n, k } = shape;
+        let a = generator.values(STREAM_A, m, k)?;
+        let b = generator.values(STREAM_B, k, n)?;
+        let c_in = if beta == 0.0 {
+            vec![0.0; m * n]
+        } else {
+            generator.values(STREAM_C, m, n)?
+        };
+        let truth = ReferenceGemm::new(summation).run(
+            alpha,
+            MatrixRef::new(TensorLayout::row_major(m, k), &a)?,
+            MatrixRef::new(TensorLayout::row_major(k, n), &b)?,
+            beta,
+            MatrixRef::new(TensorLayout::row_major(m, n), &c_in)?,
+        )?;
+        Ok(Self {
+            shape,
+            alpha,
+            beta,
+            seed: generator.seed,
+            distribution: generator.distribution,
+            summation,
+            a,
+            b,
+            c_in,
+            c: truth.into_iter().map(|value| value as f32).collect(),
+        })
+    }
+
+    /// Operand, file name, layout and data of every stored matrix.
+    fn files(&self) -> Vec<(&'static str, String, TensorLayout, &[f32])> {
+        let GemmShape { m, n, k } = self.shape;
+        let file = |operand, prefix, rows, cols, data| {
+            (operand, format!("{}_{}x{}.bin", prefix, rows, cols), TensorLayout::row_major(rows, cols), data)
+        };
+        let mut files = vec![file("A", "input_A", m, k, &self.a[..]), file("B", "input_B", k, n, &self.b[..])];
+        if self.beta != 0.0 {
+            files.push(file("C_in", "input_C", m, n, &self.c_in[..]));
+        }
+        files.push(file("C", "output_C", m, n, &self.c[..]));
+        files
+    }
+
+    /// Writes the golden to `<root>/<M>x<N>x<K>/` and returns that directory.
+    pub fn write(&self, root: &Path) -> Result<PathBuf> {
+        let dir = root.join(self.shape.to_string());
+        std::fs::create_dir_all(&dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+
+        let mut entries = Vec::new();
+        for (operand, name, layout, data) in self.files() {
+            let path = dir.join(&name);
+            matrix_io::write_matrix(&path, &HostMatrix::from_vec(layout, data.to_vec())?, MatrixFormat::Raw)?;
+            let bytes = std::fs::read(&path).with_context(|| format!("Failed to read back {}", path.display()))?;
+            entries.push(format!(
+                "    {{\"operand\": {}, \"path\": {}, \"crc32\": {}}}",
+                json::quote(operand),
+                json::quote(&name),
+                matrix_io::crc32(&bytes)
+            ));
+        }
+
+        let path = dir.join(METADATA_FILE);
+        let mut out = std::fs::File::create(&path).with_context(|| format!("Failed to create {}", path.display()))?;
+        let GemmShape { m, n, k } = self.shape;
+        writeln!(out, "{{")?;
+        writeln!(out, "  \"format\": {},", json::quote(GOLDEN_FORMAT))?;
+        writeln!(out, "  \"m\": {}, \"n\": {}, \"k\": {}, \"alpha\": {}, \"beta\": {},", m, n, k, self.alpha, self.beta)?;
+        writeln!(
+            out,
+            "  \"seed\": {}, \"distribution\": {}, \"summation\": {},",
+            json::quote(&self.seed.to_string()),
+            json::quote(&self.distribution.to_string()),
+            json::quote(self.summation.name())
+        )?;
+        writeln!(out, "  \"files\": [")?;
+        writeln!(out, "{}", entries.join(",\n"))?;
+        writeln!(out, "  ]")?;
+        writeln!(out, "}}")?;
+        Ok(dir)
+    }
+
+    /// Reads the golden in `dir`, checking every file against its recorded
+    /// CRC-32.
+    pub fn read(dir: &Path) -> Result<Self> {
+        let path = dir.join(METADATA_FILE);
+        let text = std::fs::read_to_string(&path).with_context(|| format!("Failed to read {}", path.display()))?;
+        Self::parse(dir, &text).with_context(|| format!("Invalid golden {}", path.display()))
+    }
+
+    fn parse(dir: &Path, text: &str) -> Result<Self> {
+        let root = Json::parse(text)?;
+        let format = root.field("format")?.as_str()?;
+        ensure!(format == GOLDEN_FORMAT, "Unsupported golden format `{}` (expected {})", format, GOLDEN_FORMAT);
+
+        let shape = GemmShape::new(
+            root.field("m")?.as_usize()?,
+            root.field("n")?.as_usize()?,
+            root.field("k")?.as_usize()?,
+        );
+        let seed = root.field("seed")?.as_str()?;
+        let mut golden = Self {
+            shape,
+            alpha: root.field("alpha")?.as_f64()? as f32,
+            beta: root.field("beta")?.as_f64()? as f32,
+            seed: seed.parse().with_context(|| format!("Invalid seed `{}`", seed))?,
+            distribution: root.field("distribution")?.as_str()?.parse()?,
+            summation: root.field("summation")?.as_str()?.parse()?,
+            a: Vec::new(),
+            b: Vec::new(),
+            c_in: Vec::new(),
+            c: Vec::new(),
+        };
+
+        let GemmShape { m, n, k } = shape;
+        let mut seen = Vec::new();
+        for entry in root.field("files")?.as_array()? {
+            let operand = entry.field("operand")?.as_str()?;
+            let name = entry.field("path")?.as_str()?;
+            let (rows, cols, data) = match operand {
+                "A" => (m, k, &mut golden.a),
+                "B" => (k, n, &mut golden.b),
+                "C_in" => (m, n, &mut golden.c_in),
+                "C" => (m, n, &mut golden.c),
+                other => bail!("Unknown operand `{}`", other),
+            };
+            ensure!(!seen.contains(&operand), "Operand {} is listed twice", operand);
+            seen.push(operand);
+
+            ensure!(
+                Path::new(name).components().all(|component| matches!(component, Component::Normal(_))),
+                "File `{}` for operand {} is not inside the golden directory",
+                name,
+                operand
+            );
+            let path = dir.join(name);
+            let bytes = std::fs::read(&path).with_context(|| format!("Failed to read {}", path.display()))?;
+            let crc = entry.field("crc32")?.as_usize()? as u32;
+            ensure!(
+                matrix_io::crc32(&bytes) == crc,
+                "{} is corrupt: checksum {:08x} does not match {:08x}",
+                path.display(),
+                matrix_io::crc32(&bytes),
+                crc
+            );
+            *data = matrix_io::decode_raw(&bytes, rows, cols)
+                .with_context(|| format!("Invalid {}", path.display()))?
+                .to_row_major();
+        }
+        for operand in ["A", "B", "C"] {
+            ensure!(seen.contains(&operand), "No file for operand {}", operand);
+        }
+        ensure!(
+            golden.beta == 0.0 || seen.contains(&"C_in"),
+            "beta is {} but no C_in file is listed",
+            golden.beta
+        );
+        if !seen.contains(&"C_in") {
+            // C's file has been checked to hold m x n values by now.
+            golden.c_in = vec![0.0; m.checked_mul(n).context("C is too large")?];
+        }
+        Ok(golden)
+    }
+}
+
+/// Golden directories under `root`: `root` itself when it holds a golden,
+/// otherwise its subdirectories that do, smallest shape first.
+pub fn find_goldens(root: &Path) -> Result<Vec<PathBuf>> {
+    if root.join(METADATA_FILE).is_file() {
+        return Ok(vec![root.to_path_buf()]);
+    }
+    let entries = std::fs::read_dir(root).with_context(|| format!("Failed to read {}", root.display()))?;
+    let mut dirs = Vec::new();
+    for entry in entries {
+        let path = entry?.path();
+        if path.join(METADATA_FILE).is_file() {
+            dirs.push(path);
+        }
+    }
+    ensure!(!dirs.is_empty(), "No goldens in {}", root.display());
+
+    let flops = |path: &PathBuf| {
+        let shape = path.file_name().and_then(|name| name.to_str()).and_then(|name| name.parse::<GemmShape>().ok());
+        shape.map(|GemmShape { m, n, k }| m * n * k)
+    };
+    dirs.sort_by(|x, y| flops(x).cmp(&flops(y)).then_with(|| x.cmp(y)));
+    Ok(dirs)
+}
+
+/// Result of replaying one golden with one variant.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReplayRow {
+    pub shape: GemmShape,
+    pub variant: KernelVariant,
+    /// [`SweepStatus::Skipped`] never occurs: replay always verifies.
+    pub status: SweepStatus,
+    /// `None` when the variant did not run to completion.
+    pub report: Option<VerificationReport>,
+}
+
+/// Runs every variant in `args` on `golden` and checks C against the stored
+/// result with the `--tolerance` policy. The stored C is f64 rounded once to
+/// f32, so `bound` tolerances model it as an f32 reference.
+pub fn replay<B: GemmBackend>(backend: &B, golden: &Golden, args: &ReplayArgs) -> Result<Vec<ReplayRow>> {
+    let GemmShape { m, n, k } = golden.shape;
+    let run = &args.run;
+    let a = backend.alloc_from(TensorLayout::row_major(m, k), &golden.a)?;
+    let b = backend.alloc_from(TensorLayout::row_major(k, n), &golden.b)?;
+    let mut c = backend.alloc(TensorLayout::row_major(m, n))?;
+    let policy = run.tolerance.policy(
+        run.precision.with_reference(DataType::F32),
+        (m, n, k),
+        golden.alpha,
+        &golden.a,
+        &golden.b,
+        golden.beta,
+        &golden.c_in,
+    )?;
+
+    let mut rows = Vec::with_capacity(args.variants.len());
+    for &variant in &args.variants {
+        let mut row = ReplayRow {
+            shape: golden.shape,
+            variant,
+            status: SweepStatus::Unsupported,
+            report: None,
+        };
+        if let Some(info) = backend.device_info() {
+            if !variant.supports(info.compute_capability) {
+                rows.push(row);
+                continue;
+            }
+        }
+
+        let mut c_out = vec![0.0f32; m * n];
+        let report = backend
+            .upload(&mut c, &golden.c_in)
+            .and_then(|()| backend.gemm(variant, golden.alpha, &a, &b, golden.beta, &mut c))
+            .and_then(|()| backend.synchronize())
+            .and_then(|()| backend.download(&c, &mut c_out))
+            .and_then(|()| Verifier::new(&*policy).compare(m, n, &golden.c, &c_out));
+        match report {
+            Ok(report) => {
+                row.status = if report.passed() { SweepStatus::Passed } else { SweepStatus::Failed };
+                row.report = Some(report);
+            }
+            Err(e) => row.status = SweepStatus::Error(format!("{:#}", e)),
+        }
+        rows.push(row);
+    }
+    Ok(rows)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::CpuBackend;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("gemm-golden-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn test_golden_round_trip() {
+        let root = temp_dir("round-trip");
+        let generator = Generator::new(u64::MAX, Distribution::SmallInt { bound: 3 });
+        let golden = Golden::compute(GemmShape::new(5, 3, 4), 1.5, -0.5, &generator, Summation::Kahan).unwrap();
+        let dir = golden.write(&root).unwrap();
+        assert_eq!(dir, root.join("5x3x4"));
+        assert!(dir.join("input_A_5x4.bin").is_file() && dir.join("input_C_5x3.bin").is_file());
+        assert_eq!(Golden::read(&dir).unwrap(), golden);
+        assert_eq!(find_goldens(&root).unwrap(), [dir.as_path()]);
+        assert_eq!(find_goldens(&dir).unwrap(), [dir.as_path()]);
+
+        // Small integers make every C exact.
+        let mut expected = golden.c_in.iter().map(|c| -0.5 * c).collect::<Vec<f32>>();
+        for i in 0..5 {
+            for j in 0..3 {
+                for p in 0..4 {
+                    expected[i * 3 + j] += 1.5 * golden.a[i * 4 + p] * golden.b[p * 3 + j];
+                }
+            }
+        }
+        assert_eq!(golden.c, expected);
+
+        // Beta = 0 stores no input C.
+        let zero_beta = Golden::compute(GemmShape::new(2, 2, 2), 1.0, 0.0, &generator, Summation::Naive).unwrap();
+        let dir = zero_beta.write(&root).unwrap();
+        assert!(!dir.join("input_C_2x2.bin").exists());
+        assert_eq!(Golden::read(&dir).unwrap(), zero_beta);
+        assert_eq!(find_goldens(&root).unwrap(), [root.join("2x2x2"), root.join("5x3x4")]);
+
+        // A flipped bit is caught by the checksum.
+        let path = dir.join("output_C_2x2.bin");
+        let mut bytes = std::fs::read(&path).unwrap();
+        bytes[0] ^= 1;
+        std::fs::write(&path, bytes).unwrap();
+        let err = Golden::read(&dir).unwrap_err();
+        assert!(format!("{:#}", err).contains("corrupt"), "{:#}", err);
+
+        assert!(find_goldens(&root.join("missing")).is_err());
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_untrusted_metadata() {
+        let root = temp_dir("untrusted");
+        let generator = Generator::new(1, Distribution::Normal);
+        let golden = Golden::compute(GemmShape::new(2, 2, 2), 1.0, 0.0, &generator, Summation::Naive).unwrap();
+        let dir = golden.write(&root).unwrap();
+        let text = std::fs::read_to_string(dir.join(METADATA_FILE)).unwrap();
+        let error = |text: String| format!("{:#}", Golden::parse(&dir, &text).unwrap_err());
+
+        // Huge dimensions fail on the files instead of allocating C.
+        let huge = text.replace("\"m\": 2, \"n\": 2", "\"m\": 4000000000, \"n\": 4000000000");
+        assert!(error(huge).contains("input_A_2x2.bin"));
+
+        for escape in ["../2x2x2/output_C_2x2.bin", "/etc/passwd"] {
+            let text = text.replace("\"output_C_2x2.bin\"", &json::quote(escape));
+            assert!(error(text).contains("not inside the golden directory"), "{}", escape);
+        }
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_replay() {
+        let generator = Generator::new(3, Distribution::Normal);
+        let mut golden = Golden::compute(GemmShape::new(6, 5, 7), 1.0, 2.0, &generator, Summation::Pairwise).unwrap();
+        let args = ReplayArgs::default();
+        let rows = replay(&CpuBackend::new(), &golden, &args).unwrap();
+        assert_eq!(rows.len(), KernelVariant::ALL.len());
+        assert!(rows.iter().all(|row| row.status == SweepStatus::Passed), "{:?}", rows);
+
+        golden.c[4] += 1.0;
+        let rows = replay(&CpuBackend::new(), &golden, &args).unwrap();
+        let report = rows[0].report.as_ref().unwrap();
+        assert_eq!((rows[0].status.clone(), report.failures), (SweepStatus::Failed, 1));
+    }
+
+    #[test]
+    fn test_committed_goldens() {
+        let root = Path::new(env!("CARGO_MANIFEST_DIR")).join(DEFAULT_GOLDEN_DIR);
+        let backend = CpuBackend::new();
+        for dir in find_goldens(&root).unwrap() {
+            let golden = Golden::read(&dir).unwrap();
+            for row in replay(&backend, &golden, &ReplayArgs::default()).unwrap() {
+                assert_eq!(row.status, SweepStatus::Passed, "{} {}", row.shape, row.variant);
+            }
+        }
+    }
+}
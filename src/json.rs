@@ -0,0 +1,267 @@
+//! Just enough JSON for the benchmark record files: a value tree, a parser
+//! and string quoting. Numbers are always `f64`.
+
+use anyhow::{bail, ensure, Context, Result};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Json>),
+    /// Members in file order.
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut parser = Parser { bytes: text.as_bytes(), pos: 0 };
+        let value = parser.value()?;
+        parser.skip_whitespace();
+        ensure!(parser.pos == parser.bytes.len(), "Trailing characters at byte {}", parser.pos);
+        Ok(value)
+    }
+
+    /// Member `key` of an object.
+    pub fn get(&self, key: &str) -> Option<&Json> {
+        match self {
+            Json::Object(members) => members.iter().find(|(name, _)| name == key).map(|(_, value)| value),
+            _ => None,
+        }
+    }
+
+    /// Member `key`, failing with a message naming it when absent.
+    pub fn field(&self, key: &str) -> Result<&Json> {
+        self.get(key).with_context(|| format!("Missing field `{}`", key))
+    }
+
+    pub fn as_f64(&self) -> Result<f64> {
+        match self {
+            Json::Number(value) => Ok(*value),
+            other => bail!("Expected a number, found {}", other.kind()),
+        }
+    }
+
+    pub fn as_usize(&self) -> Result<usize> {
+        let value = self.as_f64()?;
+        ensure!(
+            value >= 0.0 && value.fract() == 0.0 && value <= u32::MAX as f64,
+            "Expected a non-negative integer, found {}",
+            value
+        );
+        Ok(value as usize)
+    }
+
+    pub fn as_str(&self) -> Result<&str> {
+        match self {
+            Json::String(value) => Ok(value),
+            other => bail!("Expected a string, found {}", other.kind()),
+        }
+    }
+
+    pub fn as_array(&self) -> Result<&[Json]> {
+        match self {
+            Json::Array(values) => Ok(values),
+            other => bail!("Expected an array, found {}", other.kind()),
+        }
+    }
+
+    fn kind(&self) -> &'static str {
+        match self {
+            Json::Null => "null",
+            Json::Bool(_) => "a boolean",
+            Json::Number(_) => "a number",
+            Json::String(_) => "a string",
+            Json::Array(_) => "an array",
+            Json::Object(_) => "an object",
+        }
+    }
+}
+
+/// `text` as a quoted JSON string.
+pub fn quote(text: &str) -> String {
+    let mut out = String::with_capacity(text.len() + 2);
+    out.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+struct Parser<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn skip_whitespace(&mut self) {
+        while self.bytes.get(self.pos).is_some_and(u8::is_ascii_whitespace) {
+            self.pos += 1;
+        }
+    }
+
+    fn peek(&mut self) -> Result<u8> {
+        self.skip_whitespace();
+        self.bytes.get(self.pos).copied().context("Unexpected end of JSON")
+    }
+
+    fn expect(&mut self, byte: u8) -> Result<()> {
+        let found = self.peek()?;
+        ensure!(
+            found == byte,
+            "Expected `{}` at byte {}, found `{}`",
+            byte as char,
+            self.pos,
+            found as char
+        );
+        self.pos += 1;
+        Ok(())
+    }
+
+    fn literal(&mut self, word: &str, value: Json) -> Result<Json> {
+        ensure!(self.bytes[self.pos..].starts_with(word.as_bytes()), "Invalid literal at byte {}", self.pos);
+        self.pos += word.len();
+        Ok(value)
+    }
+
+    fn value(&mut self) -> Result<Json> {
+        match self.peek()? {
+            b'{' => self.object(),
+            b'[' => self.array(),
+            b'"' => Ok(Json::String(self.string()?)),
+            b't' => self.literal("true", Json::Bool(true)),
+            b'f' => self.literal("false", Json::Bool(false)),
+            b'n' => self.literal("null", Json::Null),
+            _ => self.number(),
+        }
+    }
+
+    /// Comma-separated items up to `close`, each read by `item`.
+    fn items(&mut self, close: u8, mut item: impl FnMut(&mut Self) -> Result<()>) -> Result<()> {
+        self.pos += 1;
+        if self.peek()? == close {
+            self.pos += 1;
+            return Ok(());
+        }
+        loop {
+            item(self)?;
+            if self.peek()? == b',' {
+                self.pos += 1;
+            } else {
+                return self.expect(close);
+            }
+        }
+    }
+
+    fn object(&mut self) -> Result<Json> {
+        let mut members = Vec::new();
+        self.items(b'}', |parser| {
+            ensure!(parser.peek()? == b'"', "Expected a member name at byte {}", parser.pos);
+            let name = parser.string()?;
+            parser.expect(b':')?;
+            members.push((name, parser.value()?));
+            Ok(())
+        })?;
+        Ok(Json::Object(members))
+    }
+
+    fn array(&mut self) -> Result<Json> {
+        let mut values = Vec::new();
+        self.items(b']', |parser| {
+            values.push(parser.value()?);
+            Ok(())
+        })?;
+        Ok(Json::Array(values))
+    }
+
+    fn string(&mut self) -> Result<String> {
+        self.pos += 1;
+        let mut out = String::new();
+        loop {
+            let start = self.pos;
+            while self.bytes.get(self.pos).is_some_and(|&b| b != b'"' && b != b'\\') {
+                self.pos += 1;
+            }
+            out.push_str(std::str::from_utf8(&self.bytes[start..self.pos])?);
+            match self.bytes.get(self.pos) {
+                Some(b'"') => {
+                    self.pos += 1;
+                    return Ok(out);
+                }
+                Some(_) => {
+                    let escape = *self.bytes.get(self.pos + 1).context("Unterminated string")?;
+                    self.pos += 2;
+                    match escape {
+                        b'"' => out.push('"'),
+                        b'\\' => out.push('\\'),
+                        b'/' => out.push('/'),
+                        b'n' => out.push('\n'),
+                        b't' => out.push('\t'),
+                        b'r' => out.push('\r'),
+                        b'b' => out.push('\u{8}'),
+                        b'f' => out.push('\u{c}'),
+                        b'u' => {
+                            let hex = self.bytes.get(self.pos..self.pos + 4).context("Truncated \\u escape")?;
+                            let code = u32::from_str_radix(std::str::from_utf8(hex)?, 16)?;
+                            self.pos += 4;
+                            // Surrogate pairs are not needed for record files.
+                            out.push(char::from_u32(code).unwrap_or(char::REPLACEMENT_CHARACTER));
+                        }
+                        other => bail!("Invalid escape `\\{}` in string", other as char),
+                    }
+                }
+                None => bail!("Unterminated string"),
+            }
+        }
+    }
+
+    fn number(&mut self) -> Result<Json> {
+        let start = self.pos;
+        while self
+            .bytes
+            .get(self.pos)
+            .is_some_and(|b| b.is_ascii_digit() || matches!(b, b'-' | b'+' | b'.' | b'e' | b'E'))
+        {
+            self.pos += 1;
+        }
+        let text = std::str::from_utf8(&self.bytes[start..self.pos])?;
+        let value = text
+            .parse()
+            .with_context(|| format!("Invalid JSON value at byte {}", start))?;
+        Ok(Json::Number(value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let value = Json::parse(r#" {"a": [1, -2.5e3, true, null], "b": {"c": "x\"y\u0041"}, "d": []} "#).unwrap();
+        let a = value.field("a").unwrap().as_array().unwrap();
+        assert_eq!(a, [Json::Number(1.0), Json::Number(-2500.0), Json::Bool(true), Json::Null]);
+        assert_eq!(value.field("b").unwrap().field("c").unwrap().as_str().unwrap(), "x\"yA");
+        assert!(value.field("d").unwrap().as_array().unwrap().is_empty());
+        assert!(value.field("e").is_err());
+        assert!(Json::Number(1.5).as_usize().is_err());
+
+        for bad in ["", "{", "[1,]", "{\"a\" 1}", "tru", "\"open", "1 2", "{1: 2}"] {
+            assert!(Json::parse(bad).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn test_quote_round_trip() {
+        let text = "tab\there \"quoted\" \\ line\n\u{1}";
+        assert_eq!(Json::parse(&quote(text)).unwrap().as_str().unwrap(), text);
+    }
+}
@@ -0,0 +1,386 @@
+use anyhow::{ensure, Context, Result};
+use std::path::Path;
+use std::sync::Arc;
+use utils::{MemoryLayout, TensorLayout, TileConfig};
+
+use crate::device::DeviceMatrix;
+use crate::driver::{DeviceInfo, Driver, KernelArg, LaunchParams};
+use crate::generate::Generator;
+use crate::launch::{validate_launch, LaunchError};
+use crate::variant::{KernelVariant, TILED_KERNEL_TILE};
+
+const GENERATE_ENTRY_POINT: &str = "generate_kernel";
+/// Threads per block side of `generate_kernel` launches.
+const GENERATE_BLOCK: u32 = 16;
+
+pub struct GemmKernel<D: Driver> {
+    module: D::Module,
+    device: DeviceInfo,
+    tile: TileConfig,
+    driver: Arc<D>,
+}
+
+impl<D: Driver> GemmKernel<D> {
+    pub fn load<P: AsRef<Path>>(driver: &Arc<D>, ptx_path: P) -> Result<Self> {
+        let ptx = std::fs::read_to_string(ptx_path)
+            .context("Failed to read PTX file")?;
+
+        Self::from_ptx(driver, &ptx)
+    }
+
+    pub fn from_ptx(driver: &Arc<D>, ptx: &str) -> Result<Self> {
+        let module = driver.load_module(ptx)
+            .context("Failed to load PTX module")?;
+        let device = driver.device_info()?;
+
+        Ok(Self {
+            module,
+            device,
+            tile: TILED_KERNEL_TILE,
+            driver: Arc::clone(driver),
+        })
+    }
+
+    /// Replaces the tile config used to validate launches. The block tile
+    /// must match [`TILED_KERNEL_TILE`], since `gemm_kernel_tiled` cannot
+    /// change its shared-memory tiles at run time.
+    pub fn with_tile_config(mut self, tile: TileConfig) -> Result<Self> {
+        let compiled = TILED_KERNEL_TILE;
+        if (tile.tile_m, tile.tile_n, tile.tile_k) != (compiled.tile_m, compiled.tile_n, compiled.tile_k) {
+            return Err(LaunchError::InvalidTile(format!(
+                "{}x{}x{} block tile does not match the {}x{}x{} compiled into `gemm_kernel_tiled`",
+                tile.tile_m, tile.tile_n, tile.tile_k, compiled.tile_m, compiled.tile_n, compiled.tile_k
+            ))
+            .into());
+        }
+        self.tile = tile;
+        Ok(self)
+    }
+
+    pub fn tile_config(&self) -> &TileConfig {
+        &self.tile
+    }
+
+    pub fn device_info(&self) -> &DeviceInfo {
+        &self.device
+    }
+
+    /// Enqueues `C = alpha * A * B + beta * C` with the given kernel variant,
+    /// taking the problem size and leading dimensions from the operands'
+    /// layouts and the launch geometry from the variant. Execution errors
+    /// surface at the next synchronize.
+    pub fn launch(
+        &self,
+        variant: KernelVariant,
+        alpha: f32,
+        a: &DeviceMatrix<f32, D>,
+        b: &DeviceMatrix<f32, D>,
+        beta: f32,
+        c: &mut DeviceMatrix<f32, D>,
+    ) -> Result<()> {
+        let (major, minor) = variant.min_compute_capability();
+        ensure!(
+            variant.supports(self.device.compute_capability),
+            "Kernel variant `{}` requires compute capability {}.{}, {} is {}.{}",
+            variant,
+            major,
+            minor,
+            self.device.name,
+            self.device.compute_capability.0,
+            self.device.compute_capability.1
+        );
+
+        let dims = GemmDims::from_layouts(a.layout(), b.layout(), c.layout())?;
+        let config = variant.launch_config(dims.m, dims.n, &self.tile);
+        validate_launch(&config, &self.tile, &self.device.limits)
+            .with_context(|| format!("Cannot launch `{}` kernel on {}", variant, self.device.name))?;
+
+        let params = LaunchParams {
+            function: variant.entry_point().to_string(),
+            grid: config.grid,
+            block: config.block,
+            shared_mem_bytes: config.dynamic_shared_mem_bytes,
+            args: vec![
+                KernelArg::U32(dims.m),
+                KernelArg::U32(dims.n),
+                KernelArg::U32(dims.k),
+                KernelArg::F32(alpha),
+                KernelArg::Ptr(a.as_device_ptr()),
+                KernelArg::U32(dims.lda),
+                KernelArg::Ptr(b.as_device_ptr()),
+                KernelArg::U32(dims.ldb),
+                KernelArg::F32(beta),
+                KernelArg::Ptr(c.as_device_ptr()),
+                KernelArg::U32(dims.ldc),
+            ],
+        };
+
+        self.driver.launch(&self.module, &params)
+            .context("Failed to launch kernel")
+    }
+
+    /// Enqueues `generate_kernel` to fill `matrix` with stream `stream` of
+    /// `generator`, bit-identical to [`Generator::matrix`] on the host.
+    pub fn generate(&self, generator: &Generator, stream: u32, matrix: &mut DeviceMatrix<f32, D>) -> Result<()> {
+        let layout = *matrix.layout();
+        ensure!(
+            layout.layout == MemoryLayout::RowMajor,
+            "Device generation requires a row-major matrix, got {:?}",
+            layout.layout
+        );
+        let to_u32 = |value: usize, what: &str| {
+            u32::try_from(value).with_context(|| format!("{} = {} does not fit in u32", what, value))
+        };
+        let rows = to_u32(layout.shape.rows, "rows")?;
+        let cols = to_u32(layout.shape.cols, "cols")?;
+        let (kind, param) = generator.distribution.raw(layout.shape.cols);
+        let [key0, key1] = generator.key();
+
+        let params = LaunchParams {
+            function: GENERATE_ENTRY_POINT.to_string(),
+            grid: (cols.div_ceil(GENERATE_BLOCK), rows.div_ceil(GENERATE_BLOCK), 1),
+            block: (GENERATE_BLOCK, GENERATE_BLOCK, 1),
+            shared_mem_bytes: 0,
+            args: vec![
+                KernelArg::U32(rows),
+                KernelArg::U32(cols),
+                KernelArg::U32(to_u32(layout.leading_dim, "ld")?),
+                KernelArg::U32(key0),
+                KernelArg::U32(key1),
+                KernelArg::U32(stream),
+                KernelArg::U32(kind),
+                KernelArg::U32(param),
+                KernelArg::Ptr(matrix.as_device_ptr()),
+            ],
+        };
+
+        self.driver.launch(&self.module, &params)
+            .context("Failed to launch generator kernel")
+    }
+}
+
+/// Problem size and leading dimensions as the kernels receive them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GemmDims {
+    pub m: u32,
+    pub n: u32,
+    pub k: u32,
+    pub lda: u32,
+    pub ldb: u32,
+    pub ldc: u32,
+}
+
+impl GemmDims {
+    /// Derives kernel dimensions from operand layouts, rejecting shape
+    /// mismatches and layouts the kernels cannot address.
+    pub fn from_layouts(a: &TensorLayout, b: &TensorLayout, c: &TensorLayout) -> Result<Self> {
+        ensure!(
+            TensorLayout::is_gemm_compatible(a, b, c),
+            "Incompatible GEMM operands: A is {}, B is {}, C is {}",
+            a.shape,
+            b.shape,
+            c.shape
+        );
+        for (name, layout) in [("A", a), ("B", b), ("C", c)] {
+            ensure!(
+                layout.layout == MemoryLayout::RowMajor,
+                "Kernels require row-major operands, but {} is {:?}",
+                name,
+                layout.layout
+            );
+        }
+
+        let to_u32 = |value: usize, what: &str| {
+            u32::try_from(value).with_context(|| format!("{} = {} does not fit in u32", what, value))
+        };
+
+        Ok(Self {
+            m: to_u32(c.shape.rows, "M")?,
+            n: to_u32(c.shape.cols, "N")?,
+            k: to_u32(a.shape.cols, "K")?,
+            lda: to_u32(a.leading_dim, "lda")?,
+            ldb: to_u32(b.leading_dim, "ldb")?,
+            ldc: to_u32(c.leading_dim, "ldc")?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::driver::{MockDriver, MockFault};
+    use crate::generate::Distribution;
+    use crate::launch::DeviceLimits;
+
+    fn setup() -> (Arc<MockDriver>, GemmKernel<MockDriver>) {
+        let driver = Arc::new(MockDriver::new());
+        let kernel = GemmKernel::from_ptx(&driver, "").unwrap();
+        (driver, kernel)
+    }
+
+    #[test]
+    fn test_launch_geometry_and_args() {
+        let (driver, kernel) = setup();
+        let a = DeviceMatrix::alloc(&driver, TensorLayout::row_major(100, 8)).unwrap();
+        let b = DeviceMatrix::alloc(&driver, TensorLayout::row_major(8, 70)).unwrap();
+        let mut c = DeviceMatrix::alloc(&driver, TensorLayout::row_major(100, 70)).unwrap();
+
+        kernel.launch(KernelVariant::Naive, 2.0, &a, &b, 0.5, &mut c).unwrap();
+
+        let launch = driver.last_launch().unwrap();
+        assert_eq!(launch.function, "gemm_kernel");
+        assert_eq!(launch.grid, (5, 7, 1));
+        assert_eq!(launch.block, (16, 16, 1));
+        assert_eq!(
+            launch.args,
+            vec![
+                KernelArg::U32(100),
+                KernelArg::U32(70),
+                KernelArg::U32(8),
+                KernelArg::F32(2.0),
+                KernelArg::Ptr(a.as_device_ptr()),
+                KernelArg::U32(8),
+                KernelArg::Ptr(b.as_device_ptr()),
+                KernelArg::U32(70),
+                KernelArg::F32(0.5),
+                KernelArg::Ptr(c.as_device_ptr()),
+                KernelArg::U32(70),
+            ]
+        );
+        // Launches are only enqueued; waiting is up to the caller.
+        assert_eq!(driver.synchronize_calls(), 0);
+    }
+
+    #[test]
+    fn test_module_load_failure() {
+        let driver = Arc::new(MockDriver::new());
+        driver.inject(MockFault::ModuleLoad);
+
+        let err = GemmKernel::from_ptx(&driver, "").err().unwrap();
+        assert_eq!(err.to_string(), "Failed to load PTX module");
+        assert_eq!(err.downcast_ref::<MockFault>(), Some(&MockFault::ModuleLoad));
+    }
+
+    #[test]
+    fn test_launch_failure() {
+        let (driver, kernel) = setup();
+        let layout = TensorLayout::row_major(1, 1);
+        let a = DeviceMatrix::alloc(&driver, layout).unwrap();
+        let b = DeviceMatrix::alloc(&driver, layout).unwrap();
+        let mut c = DeviceMatrix::alloc(&driver, layout).unwrap();
+        driver.inject(MockFault::Launch);
+
+        let err = kernel.launch(KernelVariant::Naive, 1.0, &a, &b, 0.0, &mut c).unwrap_err();
+        assert_eq!(err.to_string(), "Failed to launch kernel");
+        assert!(driver.launches().is_empty());
+    }
+
+    #[test]
+    fn test_dims_from_layouts() {
+        let mut a = TensorLayout::row_major(64, 32);
+        a.leading_dim = 40;
+        let b = TensorLayout::row_major(32, 16);
+        let c = TensorLayout::row_major(64, 16);
+
+        let dims = GemmDims::from_layouts(&a, &b, &c).unwrap();
+        assert_eq!(
+            dims,
+            GemmDims { m: 64, n: 16, k: 32, lda: 40, ldb: 16, ldc: 16 }
+        );
+
+        let wrong_k = TensorLayout::row_major(31, 16);
+        assert!(GemmDims::from_layouts(&a, &wrong_k, &c).is_err());
+
+        let col_major = TensorLayout::column_major(32, 16);
+        let err = GemmDims::from_layouts(&a, &col_major, &c).unwrap_err();
+        assert!(err.to_string().contains("row-major"));
+    }
+
+    #[test]
+    fn test_variant_selects_entry_point_and_geometry() {
+        let (driver, kernel) = setup();
+        let a = DeviceMatrix::alloc(&driver, TensorLayout::row_major(100, 16)).unwrap();
+        let b = DeviceMatrix::alloc(&driver, TensorLayout::row_major(16, 130)).unwrap();
+        let mut c = DeviceMatrix::alloc(&driver, TensorLayout::row_major(100, 130)).unwrap();
+
+        kernel.launch(KernelVariant::Wmma, 1.0, &a, &b, 0.0, &mut c).unwrap();
+
+        let launch = driver.last_launch().unwrap();
+        assert_eq!(launch.function, "gemm_kernel_wmma");
+        assert_eq!(launch.grid, (3, 2, 1));
+        assert_eq!(launch.block, (512, 1, 1));
+    }
+
+    #[test]
+    fn test_variant_rejected_on_old_device() {
+        let driver = Arc::new(MockDriver::with_device_info(DeviceInfo {
+            name: "Tesla T4".to_string(),
+            compute_capability: (7, 5),
+            limits: DeviceLimits::sm80(),
+        }));
+        let kernel = GemmKernel::from_ptx(&driver, "").unwrap();
+        let layout = TensorLayout::row_major(16, 16);
+        let a = DeviceMatrix::alloc(&driver, layout).unwrap();
+        let b = DeviceMatrix::alloc(&driver, layout).unwrap();
+        let mut c = DeviceMatrix::alloc(&driver, layout).unwrap();
+
+        let err = kernel.launch(KernelVariant::Wmma, 1.0, &a, &b, 0.0, &mut c).unwrap_err();
+        assert!(err.to_string().contains("requires compute capability 8.0"));
+        assert!(driver.launches().is_empty());
+    }
+
+    #[test]
+    fn test_invalid_geometry_rejected_before_launch() {
+        let (driver, kernel) = setup();
+        let layout = TensorLayout::row_major(256, 256);
+        let a = DeviceMatrix::alloc(&driver, layout).unwrap();
+        let b = DeviceMatrix::alloc(&driver, layout).unwrap();
+        let mut c = DeviceMatrix::alloc(&driver, layout).unwrap();
+
+        // The default 128x128 tile maps to a 16384-thread block.
+        let err = kernel.launch(KernelVariant::Tiled, 1.0, &a, &b, 0.0, &mut c).unwrap_err();
+        assert_eq!(err.to_string(), "Cannot launch `tiled` kernel on Mock Device");
+        assert_eq!(
+            err.downcast_ref::<LaunchError>(),
+            Some(&LaunchError::TooManyThreads { threads: 16384, max: 1024 })
+        );
+        assert!(driver.launches().is_empty());
+
+        // A smaller tile would fit, but the kernel is compiled for 128x128.
+        let small_tile = TileConfig { tile_m: 16, tile_n: 16, tile_k: 16, warp_m: 16, warp_n: 16, warp_k: 16 };
+        let err = kernel.with_tile_config(small_tile).err().unwrap();
+        assert!(matches!(err.downcast_ref::<LaunchError>(), Some(LaunchError::InvalidTile(_))));
+        assert!(driver.launches().is_empty());
+    }
+
+    #[test]
+    fn test_generate_launch() {
+        let (driver, kernel) = setup();
+        let mut layout = TensorLayout::row_major(40, 20);
+        layout.leading_dim = 24;
+        let mut m = DeviceMatrix::alloc(&driver, layout).unwrap();
+        let generator = Generator::new(0x1_0000_0002, Distribution::Index);
+
+        kernel.generate(&generator, 1, &mut m).unwrap();
+        let launch = driver.last_launch().unwrap();
+        assert_eq!(launch.function, "generate_kernel");
+        assert_eq!((launch.grid, launch.block), ((2, 3, 1), (16, 16, 1)));
+        assert_eq!(
+            launch.args,
+            vec![
+                KernelArg::U32(40),
+                KernelArg::U32(20),
+                KernelArg::U32(24),
+                KernelArg::U32(2),
+                KernelArg::U32(1),
+                KernelArg::U32(1),
+                KernelArg::U32(utils::random::INDEX),
+                KernelArg::U32(5),
+                KernelArg::Ptr(m.as_device_ptr()),
+            ]
+        );
+
+        let mut column_major = DeviceMatrix::alloc(&driver, TensorLayout::column_major(4, 4)).unwrap();
+        assert!(kernel.generate(&generator, 1, &mut column_major).is_err());
+    }
+}
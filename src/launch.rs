@@ -0,0 +1,304 @@
+use std::fmt;
+use utils::{GpuArch, TileConfig};
+
+/// Hardware limits a launch configuration is checked against.
+///
+/// Normally queried from the driver, but the presets make it possible to
+/// validate geometries for a device that is not present.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DeviceLimits {
+    pub max_threads_per_block: u32,
+    pub max_block_dim: (u32, u32, u32),
+    pub max_grid_dim: (u32, u32, u32),
+    /// Static shared memory a kernel may declare.
+    pub max_shared_mem_per_block: u32,
+    /// Upper bound for static plus dynamic shared memory once a kernel opts
+    /// in to the larger carve-out.
+    pub max_shared_mem_per_block_optin: u32,
+    pub warp_size: u32,
+}
+
+impl DeviceLimits {
+    /// Limits of `arch`, for validating launches without the device.
+    pub fn from_arch(arch: &GpuArch) -> Self {
+        Self {
+            max_threads_per_block: arch.sm.max_threads_per_block,
+            max_block_dim: (1024, 1024, 64),
+            max_grid_dim: (i32::MAX as u32, 65535, 65535),
+            max_shared_mem_per_block: 48 * 1024,
+            max_shared_mem_per_block_optin: arch.sm.max_shared_mem_per_block,
+            warp_size: arch.sm.warp_size,
+        }
+    }
+
+    /// A100 (sm_80).
+    pub fn sm80() -> Self {
+        Self::from_arch(GpuArch::lookup(8, 0).expect("sm_80 is in the architecture table"))
+    }
+
+    /// H100 (sm_90).
+    pub fn sm90() -> Self {
+        Self::from_arch(GpuArch::lookup(9, 0).expect("sm_90 is in the architecture table"))
+    }
+}
+
+/// A proposed kernel launch.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LaunchConfig {
+    pub grid: (u32, u32, u32),
+    pub block: (u32, u32, u32),
+    /// Shared memory declared statically by the kernel.
+    pub static_shared_mem_bytes: u32,
+    /// Shared memory requested at launch time.
+    pub dynamic_shared_mem_bytes: u32,
+}
+
+impl LaunchConfig {
+    pub fn threads_per_block(&self) -> u64 {
+        self.block.0 as u64 * self.block.1 as u64 * self.block.2 as u64
+    }
+
+    pub fn total_shared_mem_bytes(&self) -> u64 {
+        self.static_shared_mem_bytes as u64 + self.dynamic_shared_mem_bytes as u64
+    }
+}
+
+/// Why a launch configuration cannot run on a device.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LaunchError {
+    EmptyDimension { what: &'static str, dims: (u32, u32, u32) },
+    TooManyThreads { threads: u64, max: u32 },
+    BlockDimExceeded { axis: char, value: u32, max: u32 },
+    GridDimExceeded { axis: char, value: u32, max: u32 },
+    NotWarpMultiple { threads: u64, warp_size: u32 },
+    StaticSharedMemoryExceeded { bytes: u32, max: u32 },
+    SharedMemoryExceeded { bytes: u64, max: u32 },
+    InvalidTile(String),
+}
+
+impl fmt::Display for LaunchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LaunchError::EmptyDimension { what, dims } => {
+                write!(f, "{} dimensions {:?} contain a zero extent", what, dims)
+            }
+            LaunchError::TooManyThreads { threads, max } => write!(
+                f,
+                "block has {} threads, device allows at most {} per block",
+                threads, max
+            ),
+            LaunchError::BlockDimExceeded { axis, value, max } => {
+                write!(f, "block.{} = {} exceeds the device limit of {}", axis, value, max)
+            }
+            LaunchError::GridDimExceeded { axis, value, max } => {
+                write!(f, "grid.{} = {} exceeds the device limit of {}", axis, value, max)
+            }
+            LaunchError::NotWarpMultiple { threads, warp_size } => write!(
+                f,
+                "block has {} threads, which is not a multiple of the warp size {}",
+                threads, warp_size
+            ),
+            LaunchError::StaticSharedMemoryExceeded { bytes, max } => write!(
+                f,
+                "kernel declares {} bytes of static shared memory, limit is {}",
+                bytes, max
+            ),
+            LaunchError::SharedMemoryExceeded { bytes, max } => write!(
+                f,
+                "launch needs {} bytes of shared memory per block, device allows at most {}",
+                bytes, max
+            ),
+            LaunchError::InvalidTile(reason) => write!(f, "invalid tile config: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for LaunchError {}
+
+fn check_tile(tile: &TileConfig) -> Result<(), LaunchError> {
+    let dims = [
+        ("tile_m", tile.tile_m, "warp_m", tile.warp_m),
+        ("tile_n", tile.tile_n, "warp_n", tile.warp_n),
+        ("tile_k", tile.tile_k, "warp_k", tile.warp_k),
+    ];
+    for (tile_name, tile_dim, warp_name, warp_dim) in dims {
+        if tile_dim == 0 || warp_dim == 0 {
+            return Err(LaunchError::InvalidTile(format!(
+                "{} = {} and {} = {} must be non-zero",
+                tile_name, tile_dim, warp_name, warp_dim
+            )));
+        }
+        if !tile_dim.is_multiple_of(warp_dim) {
+            return Err(LaunchError::InvalidTile(format!(
+                "{} = {} is not a multiple of {} = {}",
+                tile_name, tile_dim, warp_name, warp_dim
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Checks `config` against `limits` before it reaches the driver, so an
+/// impossible geometry produces a descriptive error instead of an opaque
+/// launch failure.
+pub fn validate_launch(
+    config: &LaunchConfig,
+    tile: &TileConfig,
+    limits: &DeviceLimits,
+) -> Result<(), LaunchError> {
+    check_tile(tile)?;
+
+    for (what, dims) in [("grid", config.grid), ("block", config.block)] {
+        if dims.0 == 0 || dims.1 == 0 || dims.2 == 0 {
+            return Err(LaunchError::EmptyDimension { what, dims });
+        }
+    }
+
+    let block_axes = [
+        ('x', config.block.0, limits.max_block_dim.0),
+        ('y', config.block.1, limits.max_block_dim.1),
+        ('z', config.block.2, limits.max_block_dim.2),
+    ];
+    for (axis, value, max) in block_axes {
+        if value > max {
+            return Err(LaunchError::BlockDimExceeded { axis, value, max });
+        }
+    }
+
+    let threads = config.threads_per_block();
+    if threads > limits.max_threads_per_block as u64 {
+        return Err(LaunchError::TooManyThreads {
+            threads,
+            max: limits.max_threads_per_block,
+        });
+    }
+    if !threads.is_multiple_of(limits.warp_size as u64) {
+        return Err(LaunchError::NotWarpMultiple {
+            threads,
+            warp_size: limits.warp_size,
+        });
+    }
+
+    let grid_axes = [
+        ('x', config.grid.0, limits.max_grid_dim.0),
+        ('y', config.grid.1, limits.max_grid_dim.1),
+        ('z', config.grid.2, limits.max_grid_dim.2),
+    ];
+    for (axis, value, max) in grid_axes {
+        if value > max {
+            return Err(LaunchError::GridDimExceeded { axis, value, max });
+        }
+    }
+
+    if config.static_shared_mem_bytes > limits.max_shared_mem_per_block {
+        return Err(LaunchError::StaticSharedMemoryExceeded {
+            bytes: config.static_shared_mem_bytes,
+            max: limits.max_shared_mem_per_block,
+        });
+    }
+    let shared_mem = config.total_shared_mem_bytes();
+    if shared_mem > limits.max_shared_mem_per_block_optin as u64 {
+        return Err(LaunchError::SharedMemoryExceeded {
+            bytes: shared_mem,
+            max: limits.max_shared_mem_per_block_optin,
+        });
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(grid: (u32, u32, u32), block: (u32, u32, u32)) -> LaunchConfig {
+        LaunchConfig {
+            grid,
+            block,
+            static_shared_mem_bytes: 0,
+            dynamic_shared_mem_bytes: 0,
+        }
+    }
+
+    #[test]
+    fn test_valid_launch() {
+        let tile = TileConfig::ampere_default();
+        let ok = config((64, 64, 1), (16, 16, 1));
+        assert_eq!(validate_launch(&ok, &tile, &DeviceLimits::sm80()), Ok(()));
+    }
+
+    #[test]
+    fn test_rejects_oversized_blocks() {
+        let tile = TileConfig::ampere_default();
+        let limits = DeviceLimits::sm80();
+
+        // The 128x128 thread block gemm_kernel_tiled used to assume.
+        let err = validate_launch(&config((1, 1, 1), (128, 128, 1)), &tile, &limits).unwrap_err();
+        assert_eq!(err, LaunchError::TooManyThreads { threads: 16384, max: 1024 });
+        assert_eq!(
+            err.to_string(),
+            "block has 16384 threads, device allows at most 1024 per block"
+        );
+
+        let err = validate_launch(&config((1, 1, 1), (1, 1, 128)), &tile, &limits).unwrap_err();
+        assert_eq!(err, LaunchError::BlockDimExceeded { axis: 'z', value: 128, max: 64 });
+    }
+
+    #[test]
+    fn test_rejects_grid_and_warp_violations() {
+        let tile = TileConfig::ampere_default();
+        let limits = DeviceLimits::sm80();
+
+        let err = validate_launch(&config((1, 70000, 1), (32, 1, 1)), &tile, &limits).unwrap_err();
+        assert_eq!(err, LaunchError::GridDimExceeded { axis: 'y', value: 70000, max: 65535 });
+
+        let err = validate_launch(&config((1, 1, 1), (10, 10, 1)), &tile, &limits).unwrap_err();
+        assert_eq!(err, LaunchError::NotWarpMultiple { threads: 100, warp_size: 32 });
+
+        let err = validate_launch(&config((0, 1, 1), (32, 1, 1)), &tile, &limits).unwrap_err();
+        assert!(matches!(err, LaunchError::EmptyDimension { what: "grid", .. }));
+    }
+
+    #[test]
+    fn test_rejects_shared_memory_overflow() {
+        let tile = TileConfig::hopper_default();
+        let mut launch = config((1, 1, 1), (256, 1, 1));
+        launch.static_shared_mem_bytes = tile.shared_mem_bytes(4) as u32;
+        assert_eq!(launch.static_shared_mem_bytes, 96 * 1024);
+
+        let err = validate_launch(&launch, &tile, &DeviceLimits::sm90()).unwrap_err();
+        assert_eq!(
+            err,
+            LaunchError::StaticSharedMemoryExceeded { bytes: 96 * 1024, max: 48 * 1024 }
+        );
+
+        // The same footprint is fine as dynamic shared memory on Hopper but
+        // not on Ampere once it grows past the opt-in limit.
+        launch.static_shared_mem_bytes = 0;
+        launch.dynamic_shared_mem_bytes = 200 * 1024;
+        assert_eq!(validate_launch(&launch, &tile, &DeviceLimits::sm90()), Ok(()));
+        let err = validate_launch(&launch, &tile, &DeviceLimits::sm80()).unwrap_err();
+        assert!(matches!(err, LaunchError::SharedMemoryExceeded { .. }));
+    }
+
+    #[test]
+    fn test_limits_from_arch() {
+        let turing = DeviceLimits::from_arch(GpuArch::lookup(7, 5).unwrap());
+        assert_eq!(turing.max_shared_mem_per_block_optin, 64 * 1024);
+        assert_eq!(DeviceLimits::sm80().max_shared_mem_per_block_optin, 163 * 1024);
+        assert_eq!(DeviceLimits::sm90().max_shared_mem_per_block_optin, 227 * 1024);
+    }
+
+    #[test]
+    fn test_rejects_inconsistent_tile() {
+        let mut tile = TileConfig::ampere_default();
+        tile.warp_n = 48;
+
+        let err = validate_launch(&config((1, 1, 1), (32, 1, 1)), &tile, &DeviceLimits::sm80())
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "invalid tile config: tile_n = 128 is not a multiple of warp_n = 48"
+        );
+    }
+}
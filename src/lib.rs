@@ -1,148 +1,86 @@
-use anyhow::{Context, Result};
-use cust::prelude::*;
-use std::path::Path;
-use utils::TensorLayout;
-
-pub struct CudaContext {
-    _context: Context,
-    device: Device,
-}
-
-impl CudaContext {
-    pub fn new() -> Result<Self> {
-        cust::init(CudaFlags::empty())?;
-        
-        let device = Device::get_device(0)?;
-        let _context = Context::create_and_push(
-            ContextFlags::MAP_HOST | ContextFlags::SCHED_AUTO,
-            device,
-        )?;
-        
-        println!("Initialized CUDA device: {}", device.name()?);
-        println!("Compute Capability: {}.{}", 
-                 device.get_attribute(DeviceAttribute::ComputeCapabilityMajor)?,
-                 device.get_attribute(DeviceAttribute::ComputeCapabilityMinor)?);
-        
-        Ok(Self { _context, device })
-    }
-    
-    pub fn device(&self) -> Device {
-        self.device
-    }
-}
+pub mod backend;
+pub mod bench;
+pub mod cli;
+pub mod compare;
+pub mod cpu_gemm;
+#[cfg(feature = "cuda")]
+pub mod cuda;
+pub mod device;
+pub mod driver;
+pub mod error_bound;
+pub mod generate;
+pub mod golden;
+pub mod json;
+pub mod kernel;
+pub mod launch;
+pub mod mapped;
+pub mod matrix;
+pub mod matrix_io;
+pub mod npy;
+pub mod npz;
+pub mod reference;
+pub mod sweep;
+pub mod variant;
+pub mod verify;
 
-pub struct DeviceBuffer<T> {
-    buffer: DeviceBox<T>,
-}
-
-impl<T: DeviceCopy> DeviceBuffer<T> {
-    pub fn from_slice(data: &[T]) -> Result<Self> {
-        let mut buffer = unsafe { DeviceBuffer::alloc(data.len())? };
-        buffer.copy_from_host(data)?;
-        Ok(buffer)
-    }
-    
-    pub unsafe fn alloc(len: usize) -> Result<Self> {
-        let buffer = cust::memory::malloc::<T>(len)
-            .context("Failed to allocate device memory")?;
-        Ok(Self { buffer })
-    }
-    
-    pub fn copy_from_host(&mut self, data: &[T]) -> Result<()> {
-        self.buffer.copy_from(data)
-            .context("Failed to copy data to device")?;
-        Ok(())
-    }
-    
-    pub fn copy_to_host(&self, data: &mut [T]) -> Result<()> {
-        self.buffer.copy_to(data)
-            .context("Failed to copy data from device")?;
-        Ok(())
-    }
-    
-    pub fn as_device_ptr(&self) -> DevicePointer<T> {
-        *self.buffer.as_device_ptr()
-    }
-}
+pub use backend::{CpuBackend, DeviceBackend, GemmBackend};
+pub use bench::{time_gemm, Benchmark, BenchmarkResult, HostTimer, SteadyState, Timer, Timings};
+pub use cpu_gemm::CpuGemm;
+#[cfg(feature = "cuda")]
+pub use cuda::{CudaBackend, CudaContext, EventTimer};
+pub use device::DeviceMatrix;
+pub use driver::{Driver, MockDriver};
+pub use error_bound::{ErrorBoundTolerance, ErrorModel};
+pub use generate::{Distribution, Generator};
+pub use golden::Golden;
+pub use kernel::{GemmDims, GemmKernel};
+pub use launch::{validate_launch, DeviceLimits, LaunchConfig, LaunchError};
+pub use mapped::MappedMatrix;
+pub use matrix::{HostMatrix, MatrixMut, MatrixRef};
+pub use reference::{ReferenceGemm, Summation};
+pub use sweep::{GemmShape, SweepRow, SweepStatus};
+pub use variant::{KernelVariant, TILED_KERNEL_TILE};
+pub use verify::{
+    AbsoluteTolerance, ElementError, KScaledTolerance, ReferenceComparison, RelativeTolerance, TolerancePolicy,
+    UlpTolerance, VerificationReport, Verifier,
+};
 
-pub struct GemmKernel {
-    module: Module,
-    stream: Stream,
-}
+use anyhow::Result;
+use utils::TensorLayout;
 
-impl GemmKernel {
-    pub fn load<P: AsRef<Path>>(ptx_path: P) -> Result<Self> {
-        let ptx = std::fs::read_to_string(ptx_path)
-            .context("Failed to read PTX file")?;
-        
-        let module = Module::load_from_string(&ptx)
-            .context("Failed to load PTX module")?;
-        
-        let stream = Stream::new(StreamFlags::NON_BLOCKING, None)
-            .context("Failed to create CUDA stream")?;
-        
-        Ok(Self { module, stream })
-    }
-    
-    pub fn launch(
-        &self,
-        m: u32,
-        n: u32,
-        k: u32,
-        alpha: f32,
-        a: &DeviceBuffer<f32>,
-        b: &DeviceBuffer<f32>,
-        beta: f32,
-        c: &mut DeviceBuffer<f32>,
-        block_size: (u32, u32, u32),
-    ) -> Result<()> {
-        let grid_x = (n + block_size.0 - 1) / block_size.0;
-        let grid_y = (m + block_size.1 - 1) / block_size.1;
-        let grid_size = (grid_x, grid_y, 1);
-        
-        println!("Launching kernel with grid: {:?}, block: {:?}", grid_size, block_size);
-        
-        let kernel = self.module.get_function("gemm_kernel")
-            .context("Failed to get kernel function")?;
-        
-        unsafe {
-            launch!(
-                kernel<<<grid_size, block_size, 0, self.stream>>>(
-                    m,
-                    n,
-                    k,
-                    alpha,
-                    a.as_device_ptr(),
-                    b.as_device_ptr(),
-                    beta,
-                    c.as_device_ptr()
-                )
-            )?;
-        }
-        
-        self.stream.synchronize()
-            .context("Kernel execution failed")?;
-        
-        Ok(())
-    }
-}
+/// Compares `c_out` against `alpha * A * B + beta * c_in` computed with
+/// [`CpuGemm`], where `c_in` is C as it was before the GEMM ran. All operands
+/// are row-major.
+#[allow(clippy::too_many_arguments)]
+pub fn verify_gemm(
+    m: usize,
+    n: usize,
+    k: usize,
+    alpha: f32,
+    a: &[f32],
+    b: &[f32],
+    beta: f32,
+    c_in: &[f32],
+    c_out: &[f32],
+    tolerance: &dyn TolerancePolicy,
+) -> Result<VerificationReport> {
+    let mut c_ref = c_in.to_vec();
+    CpuGemm::default().run(
+        alpha,
+        MatrixRef::new(TensorLayout::row_major(m, k), a)?,
+        MatrixRef::new(TensorLayout::row_major(k, n), b)?,
+        beta,
+        MatrixMut::new(TensorLayout::row_major(m, n), &mut c_ref)?,
+    )?;
 
-pub fn calculate_block_size(device: Device) -> Result<(u32, u32, u32)> {
-    let max_threads_per_block = device
-        .get_attribute(DeviceAttribute::MaxThreadsPerBlock)? as u32;
-    
-    // For GEMM, typically use 16x16 or 32x32 thread blocks
-    // To be  tuned based on profiling results
-    let block_dim = if max_threads_per_block >= 1024 {
-        32
-    } else {
-        16
-    };
-    
-    Ok((block_dim, block_dim, 1))
+    Verifier::new(tolerance).compare(m, n, &c_ref, c_out)
 }
 
-pub fn verify_gemm(
+/// Like [`verify_gemm`], but measures `c_out` and the f32 [`CpuGemm`]
+/// result against an f64 [`ReferenceGemm`], so kernel error and f32 rounding
+/// drift can be told apart.
+#[allow(clippy::too_many_arguments)]
+pub fn verify_gemm_precise(
     m: usize,
     n: usize,
     k: usize,
@@ -150,40 +88,117 @@ pub fn verify_gemm(
     a: &[f32],
     b: &[f32],
     beta: f32,
-    c: &[f32],
-    tolerance: f32,
-) -> bool {
-    let mut c_ref = vec![0.0f32; m * n];
-    
-    for i in 0..m {
-        for j in 0..n {
-            let mut sum = 0.0;
-            for p in 0..k {
-                sum += a[i * k + p] * b[p * n + j];
-            }
-            c_ref[i * n + j] = alpha * sum + beta * c[i * n + j];
-        }
-    }
-    
-    for i in 0..(m * n) {
-        let diff = (c[i] - c_ref[i]).abs();
-        if diff > tolerance {
-            println!("Mismatch at index {}: GPU={}, CPU={}, diff={}", 
-                     i, c[i], c_ref[i], diff);
-            return false;
-        }
-    }
-    
-    true
+    c_in: &[f32],
+    c_out: &[f32],
+    summation: Summation,
+    tolerance: &dyn TolerancePolicy,
+) -> Result<ReferenceComparison> {
+    let a = MatrixRef::new(TensorLayout::row_major(m, k), a)?;
+    let b = MatrixRef::new(TensorLayout::row_major(k, n), b)?;
+    let c = MatrixRef::new(TensorLayout::row_major(m, n), c_in)?;
+    let truth = ReferenceGemm::new(summation).run(alpha, a, b, beta, c)?;
+
+    let mut c_f32 = c_in.to_vec();
+    CpuGemm::default().run(alpha, a, b, beta, MatrixMut::new(TensorLayout::row_major(m, n), &mut c_f32)?)?;
+
+    let verifier = Verifier::new(tolerance);
+    Ok(ReferenceComparison {
+        kernel: verifier.compare(m, n, &truth, c_out)?,
+        f32_reference: verifier.compare(m, n, &truth, &c_f32)?,
+    })
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    
+
+    const A: [f32; 4] = [1.0, 2.0, 3.0, 4.0];
+    const B: [f32; 4] = [5.0, 6.0, 7.0, 8.0];
+    const C_IN: [f32; 4] = [1.0, -2.0, 0.5, 4.0];
+    const TOLERANCE: AbsoluteTolerance = AbsoluteTolerance(1e-5);
+
     #[test]
-    fn test_cuda_context_creation() {
-        let ctx = CudaContext::new();
-        assert!(ctx.is_ok(), "Failed to create CUDA context");
+    fn test_verify_gemm() {
+        let c = [19.0, 22.0, 43.0, 50.0];
+        assert!(verify_gemm(2, 2, 2, 1.0, &A, &B, 0.0, &[0.0; 4], &c, &TOLERANCE).unwrap().passed());
+
+        let wrong = [19.0, 22.0, 43.0, 51.0];
+        let report = verify_gemm(2, 2, 2, 1.0, &A, &B, 0.0, &[0.0; 4], &wrong, &TOLERANCE).unwrap();
+        assert_eq!(report.failures, 1);
+        assert_eq!((report.worst[0].row, report.worst[0].col), (1, 1));
+        assert_eq!(report.max_abs_error, 1.0);
+
+        assert!(verify_gemm(2, 2, 3, 1.0, &A, &B, 0.0, &[0.0; 4], &c, &TOLERANCE).is_err());
+        assert!(verify_gemm(2, 2, 2, 1.0, &A, &B, 0.0, &[0.0; 3], &c, &TOLERANCE).is_err());
+    }
+
+    #[test]
+    fn test_verify_gemm_beta() {
+        // Beta = 0 ignores C, even when it holds NaN.
+        let c_out = [19.0, 22.0, 43.0, 50.0];
+        assert!(verify_gemm(2, 2, 2, 1.0, &A, &B, 0.0, &[f32::NAN; 4], &c_out, &TOLERANCE).unwrap().passed());
+
+        let c_out = [20.0, 20.0, 43.5, 54.0];
+        assert!(verify_gemm(2, 2, 2, 1.0, &A, &B, 1.0, &C_IN, &c_out, &TOLERANCE).unwrap().passed());
+
+        let c_out = [37.5, 45.0, 85.75, 98.0];
+        assert!(verify_gemm(2, 2, 2, 2.0, &A, &B, -0.5, &C_IN, &c_out, &TOLERANCE).unwrap().passed());
+
+        // Checking the output against itself as C would hide the beta term.
+        let report = verify_gemm(2, 2, 2, 2.0, &A, &B, -0.5, &c_out, &c_out, &TOLERANCE).unwrap();
+        assert_eq!(report.failures, 4);
+    }
+
+    #[test]
+    fn test_verify_backend_with_restored_c() {
+        let backend = CpuBackend::new();
+        let a = backend.alloc_from(TensorLayout::row_major(2, 2), &A).unwrap();
+        let b = backend.alloc_from(TensorLayout::row_major(2, 2), &B).unwrap();
+        let mut c = backend.alloc(TensorLayout::row_major(2, 2)).unwrap();
+
+        let mut c_out = [0.0; 4];
+        for _ in 0..3 {
+            backend.upload(&mut c, &C_IN).unwrap();
+            backend.gemm(KernelVariant::Naive, 1.5, &a, &b, 0.25, &mut c).unwrap();
+            backend.synchronize().unwrap();
+        }
+        backend.download(&c, &mut c_out).unwrap();
+
+        let report = verify_gemm(2, 2, 2, 1.5, &A, &B, 0.25, &C_IN, &c_out, &TOLERANCE).unwrap();
+        report.assert_passed();
+    }
+
+    #[test]
+    fn test_verify_gemm_precise_separates_drift() {
+        let (m, n, k) = (2, 2, 4096);
+        let a: Vec<f32> = (0..m * k).map(|i| 0.1 + (i % 13) as f32 / 3.0).collect();
+        let b: Vec<f32> = (0..k * n).map(|i| 0.2 + (i % 7) as f32 / 9.0).collect();
+        let c_in = [0.0; 4];
+
+        let mut drifted = c_in.to_vec();
+        CpuGemm::default()
+            .run(
+                1.0,
+                MatrixRef::new(TensorLayout::row_major(m, k), &a).unwrap(),
+                MatrixRef::new(TensorLayout::row_major(k, n), &b).unwrap(),
+                0.0,
+                MatrixMut::new(TensorLayout::row_major(m, n), &mut drifted).unwrap(),
+            )
+            .unwrap();
+
+        let tolerance = KScaledTolerance::new(k);
+        let report =
+            verify_gemm_precise(m, n, k, 1.0, &a, &b, 0.0, &c_in, &drifted, Summation::Kahan, &tolerance).unwrap();
+        assert!(report.passed());
+        assert!(report.f32_reference.max_abs_error > 0.0);
+        assert_eq!(report.drift_ratio(), 1.0);
+
+        let mut buggy = drifted.clone();
+        buggy[3] += 50.0;
+        let report =
+            verify_gemm_precise(m, n, k, 1.0, &a, &b, 0.0, &c_in, &buggy, Summation::Pairwise, &tolerance).unwrap();
+        assert!(!report.passed());
+        assert!(report.f32_reference.passed());
+        assert!(report.drift_ratio() > 100.0);
     }
 }
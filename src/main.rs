@@ -1,135 +1,463 @@
-use anyhow::Result;
-use rust_gpu_gemm::{
-    calculate_block_size, CudaContext, DeviceBuffer, GemmKernel, verify_gemm,
+use anyhow::{bail, Context, Result};
+use rust_gpu_gemm::cli::{
+    BackendKind, Command, CompareArgs, DeviceArgs, GenerateArgs, GoldenArgs, ReplayArgs, RunArgs, SweepArgs, USAGE,
 };
-use std::time::Instant;
-
-struct GemmConfig {
-    m: usize,
-    n: usize,
-    k: usize,
-    alpha: f32,
-    beta: f32,
-    verify: bool,
-}
-
-impl Default for GemmConfig {
-    fn default() -> Self {
-        Self {
-            m: 1024,
-            n: 1024,
-            k: 1024,
-            alpha: 1.0,
-            beta: 0.0,
-            verify: true,
-        }
-    }
-}
+use rust_gpu_gemm::compare::{compare_records, read_records, records_from_sweep, write_records, Verdict};
+use rust_gpu_gemm::generate::{STREAM_A, STREAM_B, STREAM_C};
+use rust_gpu_gemm::golden::{find_goldens, replay as replay_golden, Golden};
+use rust_gpu_gemm::sweep::{run_sweep, write_rows, SweepRow, SweepStatus};
+use rust_gpu_gemm::{matrix_io, npz};
+#[cfg(feature = "cuda")]
+use rust_gpu_gemm::{CudaBackend, CudaContext, Driver};
+use rust_gpu_gemm::{time_gemm, verify_gemm, verify_gemm_precise, CpuBackend, GemmBackend, HostMatrix, KernelVariant};
+use std::fs::File;
+use std::io::BufWriter;
+use utils::{DataType, TensorLayout};
 
 fn main() -> Result<()> {
+    let command = Command::parse(std::env::args().skip(1))?;
+
+    let (config, benchmark) = match command {
+        Command::Help => {
+            println!("{}", USAGE);
+            return Ok(());
+        }
+        Command::Info(device) => return info(&device),
+        Command::Sweep(args) => return sweep(&args),
+        Command::Compare(args) => return compare(&args),
+        Command::Generate(args) => return generate(&args),
+        Command::Golden(args) => return golden(&args),
+        Command::Replay(args) => return replay(&args),
+        Command::Run(config) => (config, true),
+        Command::Verify(config) => (config, false),
+    };
+
     println!("🦀 Rust GEMM Microkernel Optimizer");
     println!("===================================\n");
-    
-    let config = GemmConfig::default();
-    
+
     println!("Matrix dimensions: M={}, N={}, K={}", config.m, config.n, config.k);
-    println!("Alpha={}, Beta={}\n", config.alpha, config.beta);
-    
-    println!("Initializing CUDA...");
-    let ctx = CudaContext::new()?;
-    let block_size = calculate_block_size(ctx.device())?;
-    println!("Using block size: {:?}\n", block_size);
-    
-    println!("Allocating and initializing matrices...");
-    let a_host = vec![1.0f32; config.m * config.k];
-    let b_host = vec![1.0f32; config.k * config.n];
-    let c_host = vec![0.0f32; config.m * config.n];
-    
-    let d_a = DeviceBuffer::from_slice(&a_host)?;
-    let d_b = DeviceBuffer::from_slice(&b_host)?;
-    let mut d_c = DeviceBuffer::from_slice(&c_host)?;
-    
-    println!("Host memory: {} MB", 
+    println!("Alpha={}, Beta={}", config.alpha, config.beta);
+    println!("Kernel variant: {}\n", config.variant);
+
+    let passed = with_backend(&config.device, Execute { config: &config, benchmark })?;
+    if !benchmark && !passed {
+        bail!("Verification failed");
+    }
+
+    println!("\n🎉 GEMM optimization complete!");
+
+    Ok(())
+}
+
+/// Work generic over the backend, so [`with_backend`] can pick the backend
+/// at run time.
+trait BackendVisitor {
+    type Output;
+
+    fn visit<B: GemmBackend>(self, backend: &B) -> Result<Self::Output>;
+}
+
+/// Picks the backend requested by `--backend` and runs `visitor` on it.
+/// Backend selection is reported on stderr so it never mixes with output
+/// that goes to stdout.
+fn with_backend<V: BackendVisitor>(device: &DeviceArgs, visitor: V) -> Result<V::Output> {
+    match device.backend {
+        BackendKind::Cpu => {
+            eprintln!("Using CPU backend\n");
+            visitor.visit(&CpuBackend::new())
+        }
+        #[cfg(feature = "cuda")]
+        BackendKind::Cuda => visitor.visit(&cuda_backend(device)?),
+        #[cfg(feature = "cuda")]
+        BackendKind::Auto => match cuda_backend(device) {
+            Ok(backend) => visitor.visit(&backend),
+            Err(e) => {
+                eprintln!("CUDA unavailable ({:#}), falling back to CPU backend\n", e);
+                visitor.visit(&CpuBackend::new())
+            }
+        },
+        #[cfg(not(feature = "cuda"))]
+        BackendKind::Cuda => bail!("Built without the `cuda` feature; rebuild with it or pass --backend cpu"),
+        #[cfg(not(feature = "cuda"))]
+        BackendKind::Auto => {
+            eprintln!("Built without the `cuda` feature, using CPU backend\n");
+            visitor.visit(&CpuBackend::new())
+        }
+    }
+}
+
+#[cfg(feature = "cuda")]
+fn cuda_backend(device: &DeviceArgs) -> Result<CudaBackend> {
+    eprintln!("Initializing CUDA...");
+    let backend = CudaBackend::on_device(device.ordinal, &device.ptx)?;
+    eprintln!("Kernel loaded successfully\n");
+    Ok(backend)
+}
+
+/// Runs a sweep on the backend requested by `--backend`. Rows go to stdout
+/// or `--output`; progress goes to stderr so it never mixes with them.
+fn sweep(args: &SweepArgs) -> Result<()> {
+    let (rows, device_name) = with_backend(&args.run.device, args)?;
+
+    match &args.output {
+        Some(path) => {
+            let file = File::create(path).with_context(|| format!("Failed to create {}", path.display()))?;
+            write_rows(&rows, args.format, BufWriter::new(file))?;
+            eprintln!("Wrote {} rows to {}", rows.len(), path.display());
+        }
+        None => write_rows(&rows, args.format, std::io::stdout().lock())?,
+    }
+
+    if let Some(path) = &args.record {
+        let records = records_from_sweep(&rows, args.run.precision.input.name(), &device_name);
+        let file = File::create(path).with_context(|| format!("Failed to create {}", path.display()))?;
+        write_records(&records, BufWriter::new(file))?;
+        eprintln!("Recorded {} configurations to {}", records.len(), path.display());
+    }
+    Ok(())
+}
+
+impl BackendVisitor for &SweepArgs {
+    type Output = (Vec<SweepRow>, String);
+
+    fn visit<B: GemmBackend>(self, backend: &B) -> Result<Self::Output> {
+        sweep_on(backend, self)
+    }
+}
+
+/// Runs the sweep and returns its rows with the name of the device they
+/// ran on.
+fn sweep_on<B: GemmBackend>(backend: &B, args: &SweepArgs) -> Result<(Vec<SweepRow>, String)> {
+    eprintln!(
+        "Sweeping {} shape(s) x {} variant(s) on '{}' backend",
+        args.shapes.len(),
+        args.variants.len(),
+        backend.name()
+    );
+    let rows = run_sweep(backend, args, |row| {
+        let gflops = row.gflops().map_or_else(String::new, |g| format!("{:.2} GFLOPS", g));
+        eprintln!("  {:<16} {:<6} {:<12} {}", row.shape.to_string(), row.variant.name(), row.status.name(), gflops);
+    })?;
+    let device = backend.device_info().map_or_else(|| backend.name().to_string(), |info| info.name.clone());
+    Ok((rows, device))
+}
+
+/// Generates A, B and C on the backend requested by `--backend` and writes
+/// them to `--output`.
+fn generate(args: &GenerateArgs) -> Result<()> {
+    let operands = with_backend(&args.run.device, args)?;
+
+    if args.output.extension().is_some_and(|extension| extension == "npz") {
+        let members: Vec<(&str, &HostMatrix)> = operands.iter().map(|(name, matrix)| (*name, matrix)).collect();
+        npz::write_npz(&args.output, &members, DataType::F32)?;
+        println!("Wrote A, B and C to {}", args.output.display());
+        return Ok(());
+    }
+    std::fs::create_dir_all(&args.output)
+        .with_context(|| format!("Failed to create {}", args.output.display()))?;
+    for (name, matrix) in &operands {
+        let path = args.output.join(format!("input_{}_{}x{}.bin", name, matrix.rows(), matrix.cols()));
+        matrix_io::write_operand(&path, name, matrix)?;
+        println!("Wrote {}", path.display());
+    }
+    Ok(())
+}
+
+impl BackendVisitor for &GenerateArgs {
+    type Output = Vec<(&'static str, HostMatrix)>;
+
+    fn visit<B: GemmBackend>(self, backend: &B) -> Result<Self::Output> {
+        generate_on(backend, &self.run)
+    }
+}
+
+/// Generates the operands on `backend`, checking them against the host
+/// generator.
+fn generate_on<B: GemmBackend>(backend: &B, config: &RunArgs) -> Result<Vec<(&'static str, HostMatrix)>> {
+    let generator = &config.generator;
+    println!("Generating {} operands with seed {} on '{}' backend", generator.distribution, generator.seed, backend.name());
+    let operands = [
+        ("A", STREAM_A, config.m, config.k),
+        ("B", STREAM_B, config.k, config.n),
+        ("C", STREAM_C, config.m, config.n),
+    ];
+    operands
+        .into_iter()
+        .map(|(name, stream, rows, cols)| {
+            let layout = TensorLayout::row_major(rows, cols);
+            let mut matrix = backend.alloc(layout)?;
+            backend.generate(&mut matrix, generator, stream)?;
+            backend.synchronize()?;
+            let mut values = vec![0.0f32; rows * cols];
+            backend.download(&matrix, &mut values)?;
+
+            let expected = generator.values(stream, rows, cols)?;
+            if let Some(i) = (0..values.len()).find(|&i| values[i].to_bits() != expected[i].to_bits()) {
+                bail!("{} from '{}' differs from the host generator at element {}", name, backend.name(), i);
+            }
+            Ok((name, HostMatrix::from_vec(layout, values)?))
+        })
+        .collect()
+}
+
+/// Computes and stores a golden for every `--shapes` shape.
+fn golden(args: &GoldenArgs) -> Result<()> {
+    let run = &args.run;
+    for &shape in &args.shapes {
+        let golden = Golden::compute(shape, run.alpha, run.beta, &run.generator, args.summation)?;
+        let dir = golden.write(&args.output)?;
+        println!("Wrote {} golden ({}, seed {}) to {}", shape, run.generator.distribution, run.generator.seed, dir.display());
+    }
+    Ok(())
+}
+
+/// Replays the goldens on the backend requested by `--backend`; fails when
+/// any variant that ran does not match.
+fn replay(args: &ReplayArgs) -> Result<()> {
+    let failures = with_backend(&args.run.device, args)?;
+    if failures > 0 {
+        bail!("{} golden replay(s) failed", failures);
+    }
+    Ok(())
+}
+
+impl BackendVisitor for &ReplayArgs {
+    type Output = usize;
+
+    fn visit<B: GemmBackend>(self, backend: &B) -> Result<usize> {
+        replay_on(backend, self)
+    }
+}
+
+/// Replays every golden under `args.goldens` and returns how many runs
+/// failed or errored.
+fn replay_on<B: GemmBackend>(backend: &B, args: &ReplayArgs) -> Result<usize> {
+    let dirs = find_goldens(&args.goldens)?;
+    println!("Replaying {} golden(s) x {} variant(s) on '{}' backend", dirs.len(), args.variants.len(), backend.name());
+    let mut failures = 0;
+    for dir in dirs {
+        let golden = Golden::read(&dir)?;
+        for row in replay_golden(backend, &golden, args)? {
+            let detail = match (&row.status, &row.report) {
+                (SweepStatus::Error(message), _) => message.clone(),
+                (_, Some(report)) => format!(
+                    "max abs error {:.3e}, max ULP {}, {} failure(s)",
+                    report.max_abs_error, report.max_ulps, report.failures
+                ),
+                _ => String::new(),
+            };
+            if matches!(row.status, SweepStatus::Failed | SweepStatus::Error(_)) {
+                failures += 1;
+            }
+            println!("  {:<16} {:<6} {:<12} {}", row.shape.to_string(), row.variant.name(), row.status.name(), detail);
+        }
+    }
+    Ok(failures)
+}
+
+/// Compares two record files; fails when a configuration regressed, or is
+/// missing with `--fail-on-missing`.
+fn compare(args: &CompareArgs) -> Result<()> {
+    let baseline = read_records(&args.baseline)?;
+    let current = read_records(&args.current)?;
+    let report = compare_records(&baseline, &current, &args.options)?;
+    println!("{}", report);
+
+    let regressed = report.count(Verdict::Regressed);
+    let missing = report.count(Verdict::Missing);
+    if regressed > 0 {
+        bail!("{} configuration(s) regressed beyond {}%", regressed, args.options.threshold * 100.0);
+    }
+    if args.fail_on_missing && missing > 0 {
+        bail!("{} baseline configuration(s) missing from {}", missing, args.current.display());
+    }
+    Ok(())
+}
+
+fn info(device: &DeviceArgs) -> Result<()> {
+    #[cfg(feature = "cuda")]
+    if device.backend != BackendKind::Cpu {
+        let info = CudaContext::with_device(device.ordinal)?.device_info()?;
+        let limits = &info.limits;
+        println!("Device {}: {}", device.ordinal, info.name);
+        println!("  Max threads per block: {}", limits.max_threads_per_block);
+        println!("  Max shared memory per block: {} KB ({} KB opt-in)",
+                 limits.max_shared_mem_per_block / 1024,
+                 limits.max_shared_mem_per_block_optin / 1024);
+        println!("\nKernel variants:");
+        for variant in KernelVariant::ALL {
+            let status = variant.unavailable_reason(info.compute_capability, limits);
+            println!("  {:<6} {:<18} {}", variant.name(), variant.entry_point(), status.as_deref().unwrap_or("supported"));
+        }
+        return Ok(());
+    }
+
+    #[cfg(not(feature = "cuda"))]
+    if device.backend == BackendKind::Cuda {
+        bail!("Built without the `cuda` feature; rebuild with it or pass --backend cpu");
+    }
+
+    let threads = std::thread::available_parallelism().map_or(1, |n| n.get());
+    println!("CPU backend: {} threads", threads);
+    println!("\nKernel variants (all computed by the host GEMM):");
+    for variant in KernelVariant::ALL {
+        println!("  {:<6} {}", variant.name(), variant.entry_point());
+    }
+    Ok(())
+}
+
+/// A `run` or `verify` invocation; see [`execute`].
+struct Execute<'a> {
+    config: &'a RunArgs,
+    benchmark: bool,
+}
+
+impl BackendVisitor for Execute<'_> {
+    type Output = bool;
+
+    fn visit<B: GemmBackend>(self, backend: &B) -> Result<bool> {
+        execute(backend, self.config, self.benchmark)
+    }
+}
+
+/// Runs the GEMM on `backend`, benchmarking it first when `benchmark` is
+/// set. Returns whether verification passed (true when it was skipped).
+fn execute<B: GemmBackend>(backend: &B, config: &RunArgs, benchmark: bool) -> Result<bool> {
+    println!("Allocating and initializing matrices on '{}' backend...", backend.name());
+    // Without verification no host reference is computed, so A and B go
+    // straight to the backend and only C, restored before every run, is kept.
+    let (d_a, d_b, a_host, b_host, c_host) = if config.verify {
+        let (a_host, b_host, c_host) = config.load_inputs()?;
+        let d_a = backend.alloc_from(TensorLayout::row_major(config.m, config.k), &a_host)?;
+        let d_b = backend.alloc_from(TensorLayout::row_major(config.k, config.n), &b_host)?;
+        (d_a, d_b, a_host, b_host, c_host)
+    } else {
+        let (d_a, d_b) = config.upload_operands(backend)?;
+        (d_a, d_b, Vec::new(), Vec::new(), config.load_c()?)
+    };
+    let mut d_c = backend.alloc_from(TensorLayout::row_major(config.m, config.n), &c_host)?;
+
+    println!("Host memory: {} MB",
              (a_host.len() + b_host.len() + c_host.len()) * 4 / 1_000_000);
-    println!("Device memory allocated successfully\n");
-    
-    println!("Loading PTX kernel...");
-    let kernel_path = "cuda-kernel/target/nvptx64-nvidia-cuda/release/gemm_kernel.ptx";
-    let kernel = GemmKernel::load(kernel_path)?;
-    println!("Kernel loaded successfully\n");
-    
-    println!("Performing warm-up run...");
-    kernel.launch(
-        config.m as u32,
-        config.n as u32,
-        config.k as u32,
-        config.alpha,
-        &d_a,
-        &d_b,
-        config.beta,
-        &mut d_c,
-        block_size,
-    )?;
-    println!("Warm-up completed\n");
-    
-    println!("Running benchmark (5 iterations)...");
-    let num_runs = 5;
-    let mut total_time = 0.0;
-    
-    for i in 0..num_runs {
-        let start = Instant::now();
-        
-        kernel.launch(
-            config.m as u32,
-            config.n as u32,
-            config.k as u32,
-            config.alpha,
-            &d_a,
-            &d_b,
-            config.beta,
-            &mut d_c,
-            block_size,
-        )?;
-        
-        let elapsed = start.elapsed().as_secs_f64();
-        total_time += elapsed;
-        
-        println!("  Run {}: {:.3} ms", i + 1, elapsed * 1000.0);
-    }
-    
-    let avg_time = total_time / num_runs as f64;
-    let gflops = (2.0 * config.m as f64 * config.n as f64 * config.k as f64) 
-                 / (avg_time * 1e9);
-    
-    println!("\nPerformance Results:");
-    println!("  Average time: {:.3} ms", avg_time * 1000.0);
-    println!("  Performance: {:.2} GFLOPS", gflops);
-    
+    println!("Matrices allocated successfully\n");
+
+    // Every launch reads C when beta != 0, so C is restored from `c_host`
+    // before each one; the upload is not timed.
+    if benchmark {
+        benchmark_gemm(backend, config, &d_a, &d_b, &mut d_c, &c_host)?;
+    } else {
+        println!("Running kernel once...");
+        backend.upload(&mut d_c, &c_host)?;
+        backend.gemm(config.variant, config.alpha, &d_a, &d_b, config.beta, &mut d_c)?;
+        backend.synchronize()?;
+    }
+
+    if !config.verify && config.save_c.is_none() {
+        return Ok(true);
+    }
+    let mut c_result = vec![0.0f32; config.m * config.n];
+    backend.download(&d_c, &mut c_result)?;
+
+    if let Some(path) = &config.save_c {
+        let c = HostMatrix::from_vec(TensorLayout::row_major(config.m, config.n), c_result.clone())?;
+        matrix_io::write_operand(path, "c", &c)?;
+        println!("\nSaved C to {}", path.display());
+    }
+
     if config.verify {
         println!("\nVerifying results...");
-        let mut c_result = vec![0.0f32; config.m * config.n];
-        d_c.copy_to_host(&mut c_result)?;
-        
-        let is_correct = verify_gemm(
-            config.m,
-            config.n,
-            config.k,
+
+        let tolerance = config.tolerance.policy(
+            config.error_model(),
+            (config.m, config.n, config.k),
             config.alpha,
             &a_host,
             &b_host,
             config.beta,
-            &c_result,
-            1e-3,
-        );
-        
-        if is_correct {
+            &c_host,
+        )?;
+        let passed = match config.reference {
+            Some(summation) => {
+                let comparison = verify_gemm_precise(
+                    config.m,
+                    config.n,
+                    config.k,
+                    config.alpha,
+                    &a_host,
+                    &b_host,
+                    config.beta,
+                    &c_host,
+                    &c_result,
+                    summation,
+                    &*tolerance,
+                )?;
+                println!("{}", comparison);
+                comparison.passed()
+            }
+            None => {
+                let report = verify_gemm(
+                    config.m,
+                    config.n,
+                    config.k,
+                    config.alpha,
+                    &a_host,
+                    &b_host,
+                    config.beta,
+                    &c_host,
+                    &c_result,
+                    &*tolerance,
+                )?;
+                println!("{}", report);
+                report.passed()
+            }
+        };
+
+        if passed {
             println!("✓ Results verified successfully!");
         } else {
             println!("✗ Verification failed!");
         }
+        return Ok(passed);
     }
-    
-    println!("\n🎉 GEMM optimization complete!");
-    
+
+    Ok(true)
+}
+
+fn benchmark_gemm<B: GemmBackend>(
+    backend: &B,
+    config: &RunArgs,
+    d_a: &B::Matrix,
+    d_b: &B::Matrix,
+    d_c: &mut B::Matrix,
+    c_host: &[f32],
+) -> Result<()> {
+    let benchmark = &config.benchmark;
+    println!("Running benchmark ({} warm-up, {} timed iteration(s))...", benchmark.warmup, benchmark.min_iterations);
+    let result = time_gemm(
+        backend,
+        config.variant,
+        config.alpha,
+        d_a,
+        d_b,
+        config.beta,
+        d_c,
+        c_host,
+        benchmark,
+    )?;
+    let gflops = result.timings.gflops(config.m, config.n, config.k);
+
+    println!("\nPerformance Results:");
+    println!("{}", result);
+    println!("  Performance: {:.2} GFLOPS", gflops);
+
+    let peak = backend.device_info().and_then(|info| {
+        let sku = info.arch()?.sku(&info.name)?;
+        Some((sku.name, sku.peak_tflops(DataType::F32)?))
+    });
+    if let Some((sku, peak_tflops)) = peak {
+        println!("  Peak ({} FP32): {:.1} TFLOPS, {:.1}% of peak",
+                 sku, peak_tflops, gflops / (peak_tflops * 1e3) * 100.0);
+    }
+
     Ok(())
 }
@@ -0,0 +1,298 @@
+//! Matrix files too large to copy around on the host: read-only memory maps
+//! with zero-copy [`MatrixRef`] views, and chunked streaming straight into
+//! backend matrices.
+//!
+//! Both take the raw and headered formats of [`crate::matrix_io`].
+
+use anyhow::{ensure, Context, Result};
+use memmap2::Mmap;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+use utils::{MemoryLayout, TensorLayout};
+
+use crate::backend::GemmBackend;
+use crate::driver::{as_bytes, as_bytes_mut};
+use crate::matrix::{storage_len, MatrixRef};
+use crate::matrix_io::{self, Crc32, Header, HEADER_LEN};
+
+/// Chunk size [`stream_matrix`] callers use unless they have a reason not
+/// to; it bounds the host buffer.
+pub const DEFAULT_CHUNK_BYTES: usize = 64 << 20;
+
+/// A matrix file mapped read-only into memory.
+///
+/// Pages are read on first access and live in the page cache, which the OS
+/// can reclaim, so a mapped operand costs no heap memory. Headered files are
+/// checked against their CRC when opened, which reads every page once. The
+/// file must not be modified or truncated while it is mapped.
+pub struct MappedMatrix {
+    map: Mmap,
+    layout: TensorLayout,
+    /// Byte offset of the storage in the file.
+    offset: usize,
+}
+
+impl MappedMatrix {
+    /// Maps a headered file, or a raw one whose shape comes from its name.
+    pub fn open(path: &Path) -> Result<Self> {
+        Self::map(path, None).with_context(|| format!("Failed to map {}", path.display()))
+    }
+
+    /// Maps a `rows x cols` matrix. Headered files must match the shape; raw
+    /// files are taken to have it, whatever their name.
+    pub fn open_shaped(path: &Path, rows: usize, cols: usize) -> Result<Self> {
+        Self::map(path, Some((rows, cols))).with_context(|| format!("Failed to map {}", path.display()))
+    }
+
+    fn map(path: &Path, shape: Option<(usize, usize)>) -> Result<Self> {
+        ensure!(cfg!(target_endian = "little"), "Mapped matrices need a little-endian host");
+        let file = File::open(path)?;
+        // SAFETY: the map is read-only, and `MappedMatrix` documents that the
+        // file must not change while it is mapped.
+        let map = unsafe { Mmap::map(&file)? };
+
+        let (layout, offset) = if matrix_io::is_headered(&map) {
+            let header = matrix_io::read_header(&map, map.len())?;
+            header.check_payload_len(map.len() - HEADER_LEN)?;
+            header.check_checksum(matrix_io::checksum_start(&map).update(&map[HEADER_LEN..]))?;
+            if let Some((rows, cols)) = shape {
+                matrix_io::check_shape(&header.layout, rows, cols)?;
+            }
+            (header.layout, HEADER_LEN)
+        } else {
+            let (rows, cols) = match shape {
+                Some(shape) => shape,
+                None => matrix_io::legacy_shape(path)?,
+            };
+            matrix_io::check_raw_len(map.len(), rows, cols)?;
+            (TensorLayout::row_major(rows, cols), 0)
+        };
+
+        // Maps are page aligned and the header is 64 bytes, so this only
+        // guards against exotic platforms.
+        // SAFETY: every bit pattern is a valid f32.
+        let (prefix, _, _) = unsafe { map[offset..].align_to::<f32>() };
+        ensure!(prefix.is_empty(), "Mapped storage is not aligned for f32");
+        Ok(Self { map, layout, offset })
+    }
+
+    pub fn layout(&self) -> &TensorLayout {
+        &self.layout
+    }
+
+    pub fn rows(&self) -> usize {
+        self.layout.shape.rows
+    }
+
+    pub fn cols(&self) -> usize {
+        self.layout.shape.cols
+    }
+
+    /// The storage, in the file's layout, without copying it.
+    pub fn as_slice(&self) -> &[f32] {
+        // SAFETY: every bit pattern is a valid f32, the host is little-endian
+        // like the file, and `map` checked the alignment and length.
+        let (_, storage, _) = unsafe { self.map[self.offset..].align_to::<f32>() };
+        storage
+    }
+
+    pub fn view(&self) -> MatrixRef<'_> {
+        MatrixRef::new(self.layout, self.as_slice()).expect("storage length was checked when mapping")
+    }
+}
+
+/// An open matrix file, positioned at the start of its storage.
+struct Payload {
+    file: File,
+    layout: TensorLayout,
+    /// The header and the CRC state to check the storage against, for
+    /// headered files.
+    checksum: Option<(Header, Crc32)>,
+}
+
+impl Payload {
+    /// Opens a `rows x cols` matrix file, as [`MappedMatrix::open_shaped`]
+    /// takes it.
+    fn open(path: &Path, rows: usize, cols: usize) -> Result<Self> {
+        let mut file = File::open(path)?;
+        let len = file.metadata()?.len() as usize;
+
+        let mut head = Vec::with_capacity(HEADER_LEN);
+        (&mut file).take(HEADER_LEN as u64).read_to_end(&mut head)?;
+        if matrix_io::is_headered(&head) {
+            let header = matrix_io::read_header(&head, len)?;
+            matrix_io::check_shape(&header.layout, rows, cols)?;
+            header.check_payload_len(len - HEADER_LEN)?;
+            return Ok(Self {
+                file,
+                layout: header.layout,
+                checksum: Some((header, matrix_io::checksum_start(&head))),
+            });
+        }
+
+        matrix_io::check_raw_len(len, rows, cols)?;
+        file.seek(SeekFrom::Start(0))?;
+        Ok(Self {
+            file,
+            layout: TensorLayout::row_major(rows, cols),
+            checksum: None,
+        })
+    }
+
+    /// Copies the storage into a new `backend` matrix through one buffer of
+    /// at most `chunk_bytes`.
+    fn upload<B: GemmBackend>(mut self, backend: &B, chunk_bytes: usize) -> Result<B::Matrix> {
+        let mut matrix = backend.alloc(self.layout)?;
+        let total = storage_len(&self.layout)?;
+        let chunk_len = (chunk_bytes / 4).clamp(1, total.max(1));
+        let mut chunk = vec![0.0f32; chunk_len];
+        let mut offset = 0;
+        while offset < total {
+            let values = &mut chunk[..(total - offset).min(chunk_len)];
+            self.file.read_exact(as_bytes_mut(values))?;
+            if let Some((_, crc)) = &mut self.checksum {
+                *crc = crc.update(as_bytes(values));
+            }
+            for value in values.iter_mut() {
+                *value = f32::from_bits(u32::from_le(value.to_bits()));
+            }
+            backend.upload_at(&mut matrix, offset, values)?;
+            offset += values.len();
+        }
+        if let Some((header, crc)) = self.checksum {
+            header.check_checksum(crc)?;
+        }
+        Ok(matrix)
+    }
+}
+
+/// Reads the `rows x cols` matrix file at `path` into a new `backend`
+/// matrix, `chunk_bytes` at a time, so the host never holds more than one
+/// chunk of it. Raw files are taken to have the shape; headered files must
+/// match it and keep their layout.
+pub fn stream_matrix<B: GemmBackend>(
+    backend: &B,
+    path: &Path,
+    rows: usize,
+    cols: usize,
+    chunk_bytes: usize,
+) -> Result<B::Matrix> {
+    Payload::open(path, rows, cols)
+        .and_then(|payload| payload.upload(backend, chunk_bytes))
+        .with_context(|| format!("Failed to stream {}", path.display()))
+}
+
+/// Like [`matrix_io::read_operand`], but into a row-major `backend` matrix.
+/// Raw and row-major headered files are streamed as by [`stream_matrix`];
+/// NumPy files and other layouts are read whole and converted.
+pub fn stream_operand<B: GemmBackend>(
+    backend: &B,
+    path: &Path,
+    name: &str,
+    rows: usize,
+    cols: usize,
+    chunk_bytes: usize,
+) -> Result<B::Matrix> {
+    if !matches!(matrix_io::extension(path), Some("npy" | "npz")) {
+        let payload = Payload::open(path, rows, cols).with_context(|| format!("Failed to stream {}", path.display()))?;
+        if payload.layout.layout == MemoryLayout::RowMajor && payload.layout.leading_dim == cols {
+            return payload
+                .upload(backend, chunk_bytes)
+                .with_context(|| format!("Failed to stream {}", path.display()));
+        }
+    }
+    let matrix = matrix_io::read_operand(path, name, rows, cols)?;
+    backend.alloc_from(TensorLayout::row_major(rows, cols), &matrix.to_row_major())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::{CpuBackend, DeviceBackend};
+    use crate::driver::MockDriver;
+    use crate::matrix::HostMatrix;
+    use crate::matrix_io::MatrixFormat;
+
+    fn temp_dir(name: &str) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("gemm-mapped-{}-{}", name, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_mapped_views() {
+        let dir = temp_dir("views");
+        let data: Vec<f32> = (0..12).map(|i| i as f32 - 5.5).collect();
+        let row_major = HostMatrix::from_vec(TensorLayout::row_major(3, 4), data.clone()).unwrap();
+        let mut tiled = HostMatrix::zeros(TensorLayout::tiled(3, 4, 2, 2)).unwrap();
+        for (i, &value) in data.iter().enumerate() {
+            tiled.set(i / 4, i % 4, value);
+        }
+
+        let raw = dir.join("input_A_3x4.bin");
+        let headered = dir.join("tiled.mat");
+        matrix_io::write_matrix(&raw, &row_major, MatrixFormat::Raw).unwrap();
+        matrix_io::write_matrix(&headered, &tiled, MatrixFormat::Headered).unwrap();
+
+        let mapped = MappedMatrix::open(&raw).unwrap();
+        assert_eq!(mapped.as_slice(), data.as_slice());
+        assert_eq!(MappedMatrix::open_shaped(&raw, 4, 3).unwrap().layout(), &TensorLayout::row_major(4, 3));
+        let mapped = MappedMatrix::open(&headered).unwrap();
+        assert_eq!(mapped.layout(), tiled.layout());
+        assert_eq!(mapped.view().get(2, 3), data[11]);
+        assert_eq!(mapped.as_slice(), tiled.as_slice());
+
+        assert!(MappedMatrix::open_shaped(&headered, 4, 3).is_err());
+        assert!(MappedMatrix::open_shaped(&raw, 2, 2).is_err());
+        let mut bytes = std::fs::read(&headered).unwrap();
+        *bytes.last_mut().unwrap() ^= 1;
+        std::fs::write(&headered, bytes).unwrap();
+        assert!(MappedMatrix::open(&headered).is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_streaming() {
+        let dir = temp_dir("stream");
+        let data: Vec<f32> = (0..35).map(|i| (i * i) as f32 / 7.0).collect();
+        let matrix = HostMatrix::from_vec(TensorLayout::row_major(5, 7), data.clone()).unwrap();
+        let raw = dir.join("input_B_5x7.bin");
+        let headered = dir.join("b.mat");
+        matrix_io::write_matrix(&raw, &matrix, MatrixFormat::Raw).unwrap();
+        matrix_io::write_matrix(&headered, &matrix, MatrixFormat::Headered).unwrap();
+
+        // Chunks that do not divide the storage, and one larger than it.
+        for chunk_bytes in [1, 12, 1 << 20] {
+            for path in [&raw, &headered] {
+                let cpu = stream_matrix(&CpuBackend::new(), path, 5, 7, chunk_bytes).unwrap();
+                assert_eq!(cpu.as_slice(), data.as_slice());
+
+                let backend = DeviceBackend::from_ptx(MockDriver::new(), "// test").unwrap();
+                let device = stream_matrix(&backend, path, 5, 7, chunk_bytes).unwrap();
+                let mut out = vec![0.0; 35];
+                backend.download(&device, &mut out).unwrap();
+                assert_eq!(out, data);
+            }
+        }
+
+        // Column-major files are converted for the kernels.
+        let column_major = dir.join("b_col.mat");
+        let mut transposed = HostMatrix::zeros(TensorLayout::column_major(5, 7)).unwrap();
+        for (i, &value) in data.iter().enumerate() {
+            transposed.set(i / 7, i % 7, value);
+        }
+        matrix_io::write_matrix(&column_major, &transposed, MatrixFormat::Headered).unwrap();
+        let operand = stream_operand(&CpuBackend::new(), &column_major, "b", 5, 7, 16).unwrap();
+        assert_eq!(operand.layout(), &TensorLayout::row_major(5, 7));
+        assert_eq!(operand.as_slice(), data.as_slice());
+
+        let mut bytes = std::fs::read(&headered).unwrap();
+        bytes[HEADER_LEN + 3] ^= 0x40;
+        std::fs::write(&headered, bytes).unwrap();
+        let err = stream_matrix(&CpuBackend::new(), &headered, 5, 7, 16).err().unwrap();
+        assert!(format!("{:#}", err).contains("corrupt"), "{:#}", err);
+        assert!(stream_matrix(&CpuBackend::new(), &raw, 5, 8, 16).is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,266 @@
+use anyhow::{bail, ensure, Context, Result};
+use utils::{MemoryLayout, RunAxis, TensorLayout};
+
+/// Number of elements needed to back `layout`, including any padding implied
+/// by the leading dimension or by partial tiles.
+///
+/// Fails if the leading dimension is too small for the shape.
+pub fn storage_len(layout: &TensorLayout) -> Result<usize> {
+    let TensorLayout { shape, leading_dim, .. } = *layout;
+    if !shape.is_valid() {
+        return Ok(0);
+    }
+
+    match layout.layout {
+        MemoryLayout::RowMajor | MemoryLayout::Swizzled(_) => {
+            if let MemoryLayout::Swizzled(swizzle) = layout.layout {
+                ensure!(swizzle.is_valid(), "Invalid swizzle {}", swizzle);
+            }
+            ensure!(
+                leading_dim >= shape.cols,
+                "Leading dimension {} is smaller than {} columns",
+                leading_dim,
+                shape.cols
+            );
+        }
+        MemoryLayout::ColumnMajor => {
+            ensure!(
+                leading_dim >= shape.rows,
+                "Leading dimension {} is smaller than {} rows",
+                leading_dim,
+                shape.rows
+            );
+        }
+        MemoryLayout::Tiled { tile_m, tile_n } => {
+            if tile_m == 0 || tile_n == 0 {
+                bail!("Tile dimensions must be non-zero, got {}x{}", tile_m, tile_n);
+            }
+        }
+    }
+    layout
+        .checked_storage_len()
+        .with_context(|| format!("Layout {} needs more than usize::MAX elements", layout))
+}
+
+fn check_storage(layout: &TensorLayout, len: usize) -> Result<()> {
+    let needed = storage_len(layout)?;
+    ensure!(
+        len == needed,
+        "Layout {} needs {} elements, got {}",
+        layout,
+        needed,
+        len
+    );
+    Ok(())
+}
+
+/// Borrowed read-only matrix: a layout over someone else's storage.
+#[derive(Debug, Clone, Copy)]
+pub struct MatrixRef<'a> {
+    layout: TensorLayout,
+    data: &'a [f32],
+}
+
+impl<'a> MatrixRef<'a> {
+    pub fn new(layout: TensorLayout, data: &'a [f32]) -> Result<Self> {
+        check_storage(&layout, data.len())?;
+        Ok(Self { layout, data })
+    }
+
+    pub fn layout(&self) -> &TensorLayout {
+        &self.layout
+    }
+
+    pub fn rows(&self) -> usize {
+        self.layout.shape.rows
+    }
+
+    pub fn cols(&self) -> usize {
+        self.layout.shape.cols
+    }
+
+    pub fn get(&self, row: usize, col: usize) -> f32 {
+        self.data[self.layout.index(row, col)]
+    }
+
+    pub fn as_slice(&self) -> &'a [f32] {
+        self.data
+    }
+}
+
+/// Borrowed mutable matrix.
+#[derive(Debug)]
+pub struct MatrixMut<'a> {
+    layout: TensorLayout,
+    data: &'a mut [f32],
+}
+
+impl<'a> MatrixMut<'a> {
+    pub fn new(layout: TensorLayout, data: &'a mut [f32]) -> Result<Self> {
+        check_storage(&layout, data.len())?;
+        Ok(Self { layout, data })
+    }
+
+    pub fn layout(&self) -> &TensorLayout {
+        &self.layout
+    }
+
+    pub fn rows(&self) -> usize {
+        self.layout.shape.rows
+    }
+
+    pub fn cols(&self) -> usize {
+        self.layout.shape.cols
+    }
+
+    pub fn get(&self, row: usize, col: usize) -> f32 {
+        self.data[self.layout.index(row, col)]
+    }
+
+    pub fn set(&mut self, row: usize, col: usize, value: f32) {
+        let idx = self.layout.index(row, col);
+        self.data[idx] = value;
+    }
+
+    pub fn as_mut_slice(&mut self) -> &mut [f32] {
+        self.data
+    }
+}
+
+/// Host-resident matrix: a layout plus the storage it indexes into.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HostMatrix {
+    layout: TensorLayout,
+    data: Vec<f32>,
+}
+
+impl HostMatrix {
+    pub fn zeros(layout: TensorLayout) -> Result<Self> {
+        let len = storage_len(&layout)?;
+        Ok(Self { layout, data: vec![0.0; len] })
+    }
+
+    pub fn from_vec(layout: TensorLayout, data: Vec<f32>) -> Result<Self> {
+        check_storage(&layout, data.len())?;
+        Ok(Self { layout, data })
+    }
+
+    pub fn layout(&self) -> &TensorLayout {
+        &self.layout
+    }
+
+    pub fn rows(&self) -> usize {
+        self.layout.shape.rows
+    }
+
+    pub fn cols(&self) -> usize {
+        self.layout.shape.cols
+    }
+
+    pub fn get(&self, row: usize, col: usize) -> f32 {
+        self.data[self.layout.index(row, col)]
+    }
+
+    pub fn set(&mut self, row: usize, col: usize, value: f32) {
+        let idx = self.layout.index(row, col);
+        self.data[idx] = value;
+    }
+
+    pub fn as_slice(&self) -> &[f32] {
+        &self.data
+    }
+
+    pub fn as_mut_slice(&mut self) -> &mut [f32] {
+        &mut self.data
+    }
+
+    pub fn into_vec(self) -> Vec<f32> {
+        self.data
+    }
+
+    /// The logical elements in row-major order, without padding.
+    pub fn to_row_major(&self) -> Vec<f32> {
+        let cols = self.cols();
+        let mut out = vec![0.0; self.rows() * cols];
+        for run in self.layout.runs() {
+            let values = &self.data[run.offset..run.offset + run.len];
+            match run.axis {
+                RunAxis::AlongRow => {
+                    let start = run.row * cols + run.col;
+                    out[start..start + run.len].copy_from_slice(values);
+                }
+                RunAxis::DownColumn => {
+                    for (i, &value) in values.iter().enumerate() {
+                        out[(run.row + i) * cols + run.col] = value;
+                    }
+                }
+            }
+        }
+        out
+    }
+
+    pub fn view(&self) -> MatrixRef<'_> {
+        MatrixRef { layout: self.layout, data: &self.data }
+    }
+
+    pub fn view_mut(&mut self) -> MatrixMut<'_> {
+        MatrixMut { layout: self.layout, data: &mut self.data }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use utils::Swizzle;
+
+    #[test]
+    fn test_storage_len() {
+        assert_eq!(storage_len(&TensorLayout::row_major(4, 3)).unwrap(), 12);
+        assert_eq!(storage_len(&TensorLayout::column_major(4, 3)).unwrap(), 12);
+        // Partial tiles are padded out to full tiles.
+        assert_eq!(storage_len(&TensorLayout::tiled(5, 5, 4, 4)).unwrap(), 64);
+        // Swizzles move offsets within blocks, so the last block is whole.
+        assert_eq!(storage_len(&TensorLayout::swizzled(5, 6, Swizzle::new(3, 0, 3))).unwrap(), 32);
+        assert!(storage_len(&TensorLayout::swizzled(4, 4, Swizzle::new(3, 0, 2))).is_err());
+
+        let mut padded = TensorLayout::row_major(4, 3);
+        padded.leading_dim = 8;
+        assert_eq!(storage_len(&padded).unwrap(), 27);
+
+        padded.leading_dim = 2;
+        assert!(storage_len(&padded).is_err());
+
+        assert!(storage_len(&TensorLayout::row_major(1 << 40, 1 << 40)).is_err());
+    }
+
+    #[test]
+    fn test_host_matrix_rejects_size_mismatch() {
+        let layout = TensorLayout::row_major(2, 2);
+        assert!(HostMatrix::from_vec(layout, vec![0.0; 3]).is_err());
+
+        let mut m = HostMatrix::from_vec(layout, vec![1.0, 2.0, 3.0, 4.0]).unwrap();
+        m.set(1, 0, 5.0);
+        assert_eq!(m.get(1, 0), 5.0);
+        assert_eq!(m.as_slice(), &[1.0, 2.0, 5.0, 4.0]);
+    }
+
+    #[test]
+    fn test_to_row_major() {
+        let mut padded = TensorLayout::column_major(5, 3);
+        padded.leading_dim = 7;
+        let layouts = [
+            TensorLayout::row_major(5, 3),
+            padded,
+            TensorLayout::tiled(5, 3, 2, 2),
+            TensorLayout::swizzled(5, 3, Swizzle::new(1, 1, 2)),
+        ];
+        let expected: Vec<f32> = (0..15).map(|i| i as f32).collect();
+        for layout in layouts {
+            let mut m = HostMatrix::zeros(layout).unwrap();
+            for (i, &value) in expected.iter().enumerate() {
+                m.set(i / 3, i % 3, value);
+            }
+            assert_eq!(m.to_row_major(), expected, "{}", layout);
+        }
+    }
+}
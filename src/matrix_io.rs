@@ -0,0 +1,567 @@
+//! Matrix files: the legacy raw format from `data/README.md` and a headered
+//! format that carries its own shape, layout and checksum.
+//!
+//! Headered files start with a 64-byte little-endian header
+//!
+//! | offset | size | field                                                    |
+//! |--------|------|----------------------------------------------------------|
+//! | 0      | 8    | magic `GEMMMAT\0`                                        |
+//! | 8      | 4    | format version (1)                                       |
+//! | 12     | 4    | dtype code, see [`dtype_code`]                           |
+//! | 16     | 4    | layout: 0 row-major, 1 column-major, 2 tiled, 3 swizzled |
+//! | 20     | 4    | tile rows (tiled), swizzle bits (swizzled), else 0       |
+//! | 24     | 4    | tile columns (tiled), swizzle base, else 0               |
+//! | 28     | 4    | swizzle shift (swizzled only, else 0)                    |
+//! | 32     | 8    | rows                                                     |
+//! | 40     | 8    | columns                                                  |
+//! | 48     | 8    | leading dimension                                        |
+//! | 56     | 4    | CRC-32 of bytes 0..56 and the payload                    |
+//! | 60     | 4    | reserved, 0                                              |
+//!
+//! followed by the matrix storage exactly as [`HostMatrix::as_slice`] holds
+//! it, [`storage_len`] elements. Only f32 payloads are supported so far.
+//!
+//! Raw files are headerless row-major f32 whose shape lives in the file
+//! name, e.g. `input_A_1024x512.bin`.
+
+use anyhow::{bail, ensure, Context, Result};
+use std::path::Path;
+use utils::{DataType, MemoryLayout, Swizzle, TensorLayout, TensorShape};
+
+use crate::matrix::{storage_len, HostMatrix};
+use crate::{npy, npz};
+
+pub const MAGIC: [u8; 8] = *b"GEMMMAT\0";
+pub const VERSION: u32 = 1;
+pub const HEADER_LEN: usize = 64;
+
+const CRC_OFFSET: usize = 56;
+
+/// On-disk representation of a matrix file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MatrixFormat {
+    /// Headerless row-major f32, shape taken from the file name.
+    Raw,
+    /// Self-describing header followed by the storage.
+    Headered,
+}
+
+/// Stable on-disk code of each [`DataType`].
+pub fn dtype_code(dtype: DataType) -> u32 {
+    match dtype {
+        DataType::F64 => 0,
+        DataType::F32 => 1,
+        DataType::Tf32 => 2,
+        DataType::F16 => 3,
+        DataType::Bf16 => 4,
+        DataType::Fp8E4M3 => 5,
+        DataType::Fp8E5M2 => 6,
+        DataType::Fp4E2M1 => 7,
+        DataType::Int8 => 8,
+        DataType::Int4 => 9,
+        DataType::Int32 => 10,
+    }
+}
+
+fn dtype_from_code(code: u32) -> Result<DataType> {
+    Ok(match code {
+        0 => DataType::F64,
+        1 => DataType::F32,
+        2 => DataType::Tf32,
+        3 => DataType::F16,
+        4 => DataType::Bf16,
+        5 => DataType::Fp8E4M3,
+        6 => DataType::Fp8E5M2,
+        7 => DataType::Fp4E2M1,
+        8 => DataType::Int8,
+        9 => DataType::Int4,
+        10 => DataType::Int32,
+        other => bail!("Unknown dtype code {}", other),
+    })
+}
+
+const CRC_TABLE: [u32; 256] = {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+/// Running CRC-32 (IEEE 802.3, as used by zlib and PNG).
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Crc32(u32);
+
+impl Crc32 {
+    pub(crate) fn new() -> Self {
+        Self(!0)
+    }
+
+    pub(crate) fn update(mut self, bytes: &[u8]) -> Self {
+        for &byte in bytes {
+            self.0 = CRC_TABLE[((self.0 ^ byte as u32) & 0xff) as usize] ^ (self.0 >> 8);
+        }
+        self
+    }
+
+    pub(crate) fn finish(self) -> u32 {
+        !self.0
+    }
+}
+
+/// CRC-32 of `bytes`, also used for the members of `.npz` archives.
+pub(crate) fn crc32(bytes: &[u8]) -> u32 {
+    Crc32::new().update(bytes).finish()
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap())
+}
+
+fn to_usize(value: u64, field: &str) -> Result<usize> {
+    usize::try_from(value).with_context(|| format!("{} {} does not fit in memory", field, value))
+}
+
+/// `matrix` as a headered file.
+pub fn encode(matrix: &HostMatrix) -> Vec<u8> {
+    let layout = matrix.layout();
+    let params = match layout.layout {
+        MemoryLayout::RowMajor => [0u32, 0, 0, 0],
+        MemoryLayout::ColumnMajor => [1, 0, 0, 0],
+        MemoryLayout::Tiled { tile_m, tile_n } => [2, tile_m as u32, tile_n as u32, 0],
+        MemoryLayout::Swizzled(swizzle) => [3, swizzle.bits, swizzle.base, swizzle.shift],
+    };
+
+    let mut bytes = Vec::with_capacity(HEADER_LEN + matrix.as_slice().len() * 4);
+    bytes.extend_from_slice(&MAGIC);
+    bytes.extend_from_slice(&VERSION.to_le_bytes());
+    bytes.extend_from_slice(&dtype_code(DataType::F32).to_le_bytes());
+    for field in params {
+        bytes.extend_from_slice(&field.to_le_bytes());
+    }
+    for field in [layout.shape.rows, layout.shape.cols, layout.leading_dim] {
+        bytes.extend_from_slice(&(field as u64).to_le_bytes());
+    }
+    bytes.extend_from_slice(&[0; HEADER_LEN - CRC_OFFSET]);
+    for value in matrix.as_slice() {
+        bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    let crc = checksum_start(&bytes).update(&bytes[HEADER_LEN..]).finish();
+    bytes[CRC_OFFSET..CRC_OFFSET + 4].copy_from_slice(&crc.to_le_bytes());
+    bytes
+}
+
+/// Whether `bytes` start like a headered file.
+pub fn is_headered(bytes: &[u8]) -> bool {
+    bytes.starts_with(&MAGIC)
+}
+
+/// The validated header of a headered file.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Header {
+    pub layout: TensorLayout,
+    /// Payload size in bytes.
+    pub payload_len: usize,
+    /// CRC-32 stored in the header.
+    pub checksum: u32,
+}
+
+impl Header {
+    /// Checks that a file's payload is `found` bytes long.
+    pub fn check_payload_len(&self, found: usize) -> Result<()> {
+        let expected = self.payload_len;
+        ensure!(
+            found >= expected,
+            "Truncated matrix file: {} needs {} payload bytes, found {}",
+            self.layout,
+            expected,
+            found
+        );
+        ensure!(
+            found == expected,
+            "Matrix file has {} trailing bytes after the {} payload bytes of {}",
+            found - expected,
+            expected,
+            self.layout
+        );
+        Ok(())
+    }
+
+    /// Checks the stored CRC against the payload's. `payload` is the CRC-32
+    /// state after the first 56 header bytes and the payload, see
+    /// [`checksum_start`].
+    pub(crate) fn check_checksum(&self, payload: Crc32) -> Result<()> {
+        let actual = payload.finish();
+        ensure!(
+            self.checksum == actual,
+            "Matrix file is corrupt: checksum {:08x} does not match header {:08x}",
+            actual,
+            self.checksum
+        );
+        Ok(())
+    }
+}
+
+/// CRC-32 state after the checksummed part of `header`, ready for the
+/// payload.
+pub(crate) fn checksum_start(header: &[u8]) -> Crc32 {
+    Crc32::new().update(&header[..CRC_OFFSET])
+}
+
+/// Parses the header at the start of `bytes`, which may hold more of the
+/// `file_len`-byte file. Dimensions too large for the file are rejected
+/// before any size is computed from them.
+pub fn read_header(bytes: &[u8], file_len: usize) -> Result<Header> {
+    ensure!(is_headered(bytes), "Not a matrix file (bad magic)");
+    ensure!(
+        bytes.len() >= HEADER_LEN,
+        "Truncated matrix header: {} of {} bytes",
+        bytes.len(),
+        HEADER_LEN
+    );
+    let header = &bytes[..HEADER_LEN];
+
+    let version = read_u32(header, 8);
+    ensure!(version == VERSION, "Unsupported matrix file version {} (expected {})", version, VERSION);
+    let dtype = dtype_from_code(read_u32(header, 12))?;
+    ensure!(dtype == DataType::F32, "Matrix files of dtype {} are not supported (only f32)", dtype);
+    let params = [read_u32(header, 20), read_u32(header, 24), read_u32(header, 28)];
+    let layout = match read_u32(header, 16) {
+        0 => MemoryLayout::RowMajor,
+        1 => MemoryLayout::ColumnMajor,
+        2 => MemoryLayout::Tiled { tile_m: params[0] as usize, tile_n: params[1] as usize },
+        3 => MemoryLayout::Swizzled(Swizzle::new(params[0], params[1], params[2])),
+        other => bail!("Unknown layout code {}", other),
+    };
+    let mut tensor = TensorLayout::row_major(
+        to_usize(read_u64(header, 32), "Row count")?,
+        to_usize(read_u64(header, 40), "Column count")?,
+    );
+    tensor.layout = layout;
+    tensor.leading_dim = to_usize(read_u64(header, 48), "Leading dimension")?;
+    check_dims_fit(&tensor, file_len.saturating_sub(HEADER_LEN) / 4)?;
+
+    let len = storage_len(&tensor).context("Invalid layout in matrix header")?;
+    Ok(Header {
+        layout: tensor,
+        payload_len: len.checked_mul(4).context("Matrix in header is too large")?,
+        checksum: read_u32(header, CRC_OFFSET),
+    })
+}
+
+/// Every dimension of a non-empty layout that strides or tiles its
+/// storage is at most its storage length, so one larger than the
+/// `elements` a file holds means a corrupt header.
+fn check_dims_fit(layout: &TensorLayout, elements: usize) -> Result<()> {
+    let TensorShape { rows, cols } = layout.shape;
+    if !layout.shape.is_valid() {
+        return Ok(());
+    }
+    let mut dims = vec![("Row count", rows), ("Column count", cols)];
+    match layout.layout {
+        MemoryLayout::RowMajor | MemoryLayout::Swizzled(_) if rows > 1 => dims.push(("Leading dimension", layout.leading_dim)),
+        MemoryLayout::ColumnMajor if cols > 1 => dims.push(("Leading dimension", layout.leading_dim)),
+        MemoryLayout::Tiled { tile_m, tile_n } => dims.extend([("Tile height", tile_m), ("Tile width", tile_n)]),
+        _ => {}
+    }
+    for (field, value) in dims {
+        ensure!(
+            value <= elements,
+            "Matrix header is corrupt: {} {} exceeds the {} elements in the file",
+            field,
+            value,
+            elements
+        );
+    }
+    Ok(())
+}
+
+/// Parses a headered file.
+pub fn decode(bytes: &[u8]) -> Result<HostMatrix> {
+    let header = read_header(bytes, bytes.len())?;
+    let payload = &bytes[HEADER_LEN..];
+    header.check_payload_len(payload.len())?;
+    header.check_checksum(checksum_start(bytes).update(payload))?;
+    HostMatrix::from_vec(header.layout, decode_f32(payload))
+}
+
+pub(crate) fn decode_f32(bytes: &[u8]) -> Vec<f32> {
+    bytes
+        .chunks_exact(4)
+        .map(|chunk| f32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]))
+        .collect()
+}
+
+/// Checks that a raw `rows x cols` file is `len` bytes long.
+pub(crate) fn check_raw_len(len: usize, rows: usize, cols: usize) -> Result<()> {
+    let expected = rows
+        .checked_mul(cols)
+        .and_then(|elements| elements.checked_mul(4))
+        .with_context(|| format!("Raw {}x{} f32 matrix is too large", rows, cols))?;
+    ensure!(
+        len == expected,
+        "Raw {}x{} f32 matrix needs {} bytes, found {}",
+        rows,
+        cols,
+        expected,
+        len
+    );
+    Ok(())
+}
+
+/// Checks that a headered file's matrix is `rows x cols`.
+pub(crate) fn check_shape(layout: &TensorLayout, rows: usize, cols: usize) -> Result<()> {
+    ensure!(
+        (layout.shape.rows, layout.shape.cols) == (rows, cols),
+        "Matrix is {}x{}, expected {}x{}",
+        layout.shape.rows,
+        layout.shape.cols,
+        rows,
+        cols
+    );
+    Ok(())
+}
+
+/// Parses a raw row-major `rows x cols` file.
+pub fn decode_raw(bytes: &[u8], rows: usize, cols: usize) -> Result<HostMatrix> {
+    check_raw_len(bytes.len(), rows, cols)?;
+    HostMatrix::from_vec(TensorLayout::row_major(rows, cols), decode_f32(bytes))
+}
+
+/// `(rows, cols)` from a legacy file name ending in `_<ROWS>x<COLS>.bin`,
+/// such as `input_A_1024x512.bin`.
+pub fn legacy_shape(path: &Path) -> Result<(usize, usize)> {
+    let shape = path
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .and_then(|stem| stem.rsplit('_').next())
+        .and_then(|dims| dims.split_once('x'))
+        .and_then(|(rows, cols)| Some((rows.parse().ok()?, cols.parse().ok()?)));
+    shape.with_context(|| {
+        format!(
+            "Cannot infer the shape of raw matrix file {}: expected a name ending in _<ROWS>x<COLS>.bin",
+            path.display()
+        )
+    })
+}
+
+/// Reads a headered file, or a raw one whose shape comes from its name.
+pub fn read_matrix(path: &Path) -> Result<HostMatrix> {
+    let bytes = std::fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    let matrix = if is_headered(&bytes) {
+        decode(&bytes)
+    } else {
+        let (rows, cols) = legacy_shape(path)?;
+        decode_raw(&bytes, rows, cols)
+    };
+    matrix.with_context(|| format!("Failed to load {}", path.display()))
+}
+
+/// Reads a `rows x cols` matrix. Headered files must match the shape; raw
+/// files are taken to have it, whatever their name.
+pub fn read_matrix_shaped(path: &Path, rows: usize, cols: usize) -> Result<HostMatrix> {
+    let bytes = std::fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    let matrix = if is_headered(&bytes) {
+        decode(&bytes).and_then(|matrix| {
+            check_shape(matrix.layout(), rows, cols)?;
+            Ok(matrix)
+        })
+    } else {
+        decode_raw(&bytes, rows, cols)
+    };
+    matrix.with_context(|| format!("Failed to load {}", path.display()))
+}
+
+/// Writes `matrix` to `path`. Raw files are written row-major and need a
+/// file name that [`legacy_shape`] maps back to the matrix's shape.
+pub fn write_matrix(path: &Path, matrix: &HostMatrix, format: MatrixFormat) -> Result<()> {
+    let bytes = match format {
+        MatrixFormat::Headered => encode(matrix),
+        MatrixFormat::Raw => {
+            let shape = (matrix.rows(), matrix.cols());
+            ensure!(
+                legacy_shape(path)? == shape,
+                "Raw file name {} does not match the {}x{} matrix",
+                path.display(),
+                shape.0,
+                shape.1
+            );
+            matrix.to_row_major().iter().flat_map(|value| value.to_le_bytes()).collect()
+        }
+    };
+    std::fs::write(path, bytes).with_context(|| format!("Failed to write {}", path.display()))
+}
+
+pub(crate) fn extension(path: &Path) -> Option<&str> {
+    path.extension().and_then(|extension| extension.to_str())
+}
+
+/// Reads GEMM operand `name` (`a`, `b` or `c`) as a `rows x cols` matrix,
+/// picking the format from the extension: `.npy`, `.npz` (the member called
+/// `name`, or the only member), otherwise raw or headered.
+pub fn read_operand(path: &Path, name: &str, rows: usize, cols: usize) -> Result<HostMatrix> {
+    let matrix = match extension(path) {
+        Some("npy") => npy::read_npy(path)?,
+        Some("npz") => npz::select(npz::read_npz(path)?, name).with_context(|| format!("In {}", path.display()))?,
+        _ => return read_matrix_shaped(path, rows, cols),
+    };
+    ensure!(
+        (matrix.rows(), matrix.cols()) == (rows, cols),
+        "{} holds a {}x{} matrix, expected {}x{}",
+        path.display(),
+        matrix.rows(),
+        matrix.cols(),
+        rows,
+        cols
+    );
+    Ok(matrix)
+}
+
+/// Writes GEMM operand `name` as f32, picking the format from the
+/// extension: `.npy`, `.npz` (one member called `name`), raw for `.bin`
+/// names that [`legacy_shape`] accepts, otherwise headered.
+pub fn write_operand(path: &Path, name: &str, matrix: &HostMatrix) -> Result<()> {
+    match extension(path) {
+        Some("npy") => npy::write_npy(path, matrix, DataType::F32),
+        Some("npz") => npz::write_npz(path, &[(name, matrix)], DataType::F32),
+        Some("bin") if legacy_shape(path).is_ok() => write_matrix(path, matrix, MatrixFormat::Raw),
+        _ => write_matrix(path, matrix, MatrixFormat::Headered),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(layout: TensorLayout) -> HostMatrix {
+        let len = storage_len(&layout).unwrap();
+        HostMatrix::from_vec(layout, (0..len).map(|i| i as f32 * 0.5 - 3.0).collect()).unwrap()
+    }
+
+    #[test]
+    fn test_headered_round_trip() {
+        let mut padded = TensorLayout::column_major(5, 3);
+        padded.leading_dim = 8;
+        let layouts = [
+            TensorLayout::row_major(4, 7),
+            padded,
+            TensorLayout::tiled(5, 6, 4, 4),
+            TensorLayout::swizzled(5, 6, utils::Swizzle::new(3, 0, 3)),
+            TensorLayout::row_major(0, 3),
+        ];
+        for layout in layouts {
+            let matrix = sample(layout);
+            let bytes = encode(&matrix);
+            assert_eq!(bytes.len(), HEADER_LEN + matrix.as_slice().len() * 4);
+            assert_eq!(decode(&bytes).unwrap(), matrix);
+        }
+
+        // Standard CRC-32 check value.
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+    }
+
+    #[test]
+    fn test_corrupt_and_truncated_files() {
+        let bytes = encode(&sample(TensorLayout::tiled(5, 6, 4, 4)));
+        let error = |bytes: &[u8]| format!("{:#}", decode(bytes).unwrap_err());
+
+        let mut flipped = bytes.clone();
+        flipped[HEADER_LEN + 9] ^= 0x10;
+        assert!(error(&flipped).contains("corrupt"));
+
+        assert!(error(&bytes[..bytes.len() - 4]).contains("Truncated matrix file"));
+        assert!(error(&bytes[..HEADER_LEN - 1]).contains("Truncated matrix header"));
+        assert!(error(&[bytes.as_slice(), &[0; 4]].concat()).contains("trailing"));
+        assert!(error(&bytes[1..]).contains("bad magic"));
+
+        let patched = |offset: usize, value: u32| {
+            let mut bytes = bytes.clone();
+            bytes[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+            bytes
+        };
+        assert!(error(&patched(8, 2)).contains("version 2"));
+        assert!(error(&patched(12, 99)).contains("dtype code 99"));
+        assert!(error(&patched(16, 7)).contains("layout code 7"));
+        // Header fields are covered by the checksum too.
+        assert!(error(&patched(20, 16)).contains("Truncated"));
+        assert!(error(&patched(28, 1)).contains("corrupt"));
+        assert!(error(&patched(12, dtype_code(DataType::Bf16))).contains("bf16 are not supported"));
+    }
+
+    #[test]
+    fn test_corrupt_dims() {
+        let bytes = encode(&sample(TensorLayout::row_major(5, 6)));
+        let error = |bytes: &[u8]| format!("{:#}", decode(bytes).unwrap_err());
+        let patched = |offset: usize, value: u64| {
+            let mut bytes = bytes.clone();
+            bytes[offset..offset + 8].copy_from_slice(&value.to_le_bytes());
+            bytes
+        };
+
+        // A flipped high bit in the row count.
+        let mut flipped = bytes.clone();
+        flipped[39] ^= 0x80;
+        assert!(error(&flipped).contains("Row count"));
+        assert!(error(&patched(40, 1 << 40)).contains("Column count 1099511627776 exceeds the 30 elements"));
+        assert!(error(&patched(48, u64::MAX >> 1)).contains("Leading dimension"));
+        // Small enough for the file but still wrong.
+        assert!(error(&patched(32, 6)).contains("Truncated"));
+
+        let tiled = encode(&sample(TensorLayout::tiled(5, 6, 4, 4)));
+        let mut huge_tile = tiled.clone();
+        huge_tile[20..24].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(format!("{:#}", decode(&huge_tile).unwrap_err()).contains("Tile height"));
+
+        let huge = 1 << 40;
+        assert!(check_raw_len(16, huge, huge).unwrap_err().to_string().contains("too large"));
+        assert!(decode_raw(&[0; 16], huge, huge).is_err());
+    }
+
+    #[test]
+    fn test_files_and_legacy_names() {
+        assert_eq!(legacy_shape(Path::new("data/input_A_1024x512.bin")).unwrap(), (1024, 512));
+        assert_eq!(legacy_shape(Path::new("output_C_3x4.bin")).unwrap(), (3, 4));
+        assert!(legacy_shape(Path::new("data/a.bin")).is_err());
+
+        let dir = std::env::temp_dir().join(format!("gemm-matrix-io-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let matrix = sample(TensorLayout::column_major(3, 4));
+        let raw = dir.join("input_B_3x4.bin");
+        write_matrix(&raw, &matrix, MatrixFormat::Raw).unwrap();
+        let read = read_matrix(&raw).unwrap();
+        assert_eq!(read.layout(), &TensorLayout::row_major(3, 4));
+        assert_eq!(read.to_row_major(), matrix.to_row_major());
+        assert!(write_matrix(&dir.join("input_B_4x3.bin"), &matrix, MatrixFormat::Raw).is_err());
+
+        let headered = dir.join("b.mat");
+        write_matrix(&headered, &matrix, MatrixFormat::Headered).unwrap();
+        assert_eq!(read_matrix(&headered).unwrap(), matrix);
+        assert_eq!(read_matrix_shaped(&headered, 3, 4).unwrap(), matrix);
+        assert!(read_matrix_shaped(&headered, 4, 3).is_err());
+        assert_eq!(read_matrix_shaped(&raw, 2, 6).unwrap().cols(), 6);
+
+        for name in ["c.npy", "c.npz", "output_C_3x4.bin", "c.mat"] {
+            let path = dir.join(name);
+            write_operand(&path, "C", &matrix).unwrap();
+            assert_eq!(read_operand(&path, "c", 3, 4).unwrap().to_row_major(), matrix.to_row_major(), "{}", name);
+            // Raw files take whatever shape they are read with.
+            assert_eq!(read_operand(&path, "c", 4, 3).is_err(), !name.ends_with(".bin"), "{}", name);
+        }
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,472 @@
+//! NumPy `.npy` arrays (format versions 1.0, 2.0 and 3.0) as host matrices.
+//!
+//! Only 2-D arrays are accepted: C-order arrays load row-major and
+//! `fortran_order` arrays column-major. f32, f16, bf16 and int8 payloads are
+//! widened to f32 on load. NumPy has no bf16 dtype of its own, so bf16 is
+//! stored as `<V2`, which is how `ml_dtypes.bfloat16` arrays are saved;
+//! `.view(ml_dtypes.bfloat16)` recovers them in Python.
+
+use anyhow::{bail, ensure, Context, Result};
+use std::borrow::Cow;
+use std::path::Path;
+use utils::{DataType, MemoryLayout, TensorLayout};
+
+use crate::matrix::HostMatrix;
+
+pub const MAGIC: &[u8; 6] = b"\x93NUMPY";
+
+/// Header and payload together are padded to a multiple of this.
+const ALIGNMENT: usize = 64;
+
+/// The parsed header of a `.npy` file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NpyHeader {
+    pub dtype: DataType,
+    pub big_endian: bool,
+    pub fortran_order: bool,
+    pub shape: Vec<usize>,
+}
+
+impl NpyHeader {
+    /// The layout of the payload; fails unless the array is 2-D.
+    pub fn layout(&self) -> Result<TensorLayout> {
+        match (self.shape.as_slice(), self.fortran_order) {
+            (&[rows, cols], false) => Ok(TensorLayout::row_major(rows, cols)),
+            (&[rows, cols], true) => Ok(TensorLayout::column_major(rows, cols)),
+            (shape, _) => bail!("Expected a 2-D array, found shape {:?}", shape),
+        }
+    }
+}
+
+/// The `descr` NumPy writes for `dtype`.
+fn descr(dtype: DataType) -> Result<&'static str> {
+    Ok(match dtype {
+        DataType::F32 => "<f4",
+        DataType::F16 => "<f2",
+        DataType::Bf16 => "<V2",
+        DataType::Int8 => "|i1",
+        other => bail!("Cannot store {} in a .npy file; use f32, f16, bf16 or s8", other),
+    })
+}
+
+/// `(dtype, big_endian)` of a `descr` string such as `<f4`.
+fn parse_descr(descr: &str) -> Result<(DataType, bool)> {
+    let (order, code) = match descr.as_bytes().first() {
+        Some(b'<' | b'>' | b'|' | b'=') => descr.split_at(1),
+        _ => ("=", descr),
+    };
+    let dtype = match code {
+        "f4" => DataType::F32,
+        "f2" | "e" => DataType::F16,
+        "V2" => DataType::Bf16,
+        "i1" | "b" => DataType::Int8,
+        _ => bail!("Unsupported .npy dtype `{}`; expected f32, f16, bf16 (V2) or int8", descr),
+    };
+    Ok((dtype, order == ">"))
+}
+
+/// Python literals that appear in `.npy` headers.
+#[derive(Debug, Clone, PartialEq)]
+enum Literal {
+    Str(String),
+    Bool(bool),
+    Int(usize),
+    Tuple(Vec<Literal>),
+    Dict(Vec<(String, Literal)>),
+}
+
+struct LiteralParser<'a> {
+    text: &'a str,
+    pos: usize,
+}
+
+impl LiteralParser<'_> {
+    fn rest(&self) -> &str {
+        &self.text[self.pos..]
+    }
+
+    fn peek(&mut self) -> Result<char> {
+        self.pos = self.text.len() - self.rest().trim_start().len();
+        self.rest().chars().next().context("Unexpected end of .npy header")
+    }
+
+    fn value(&mut self) -> Result<Literal> {
+        match self.peek()? {
+            '{' => {
+                let mut members = Vec::new();
+                self.items('}', |parser| {
+                    let key = match parser.value()? {
+                        Literal::Str(key) => key,
+                        other => bail!("Expected a string key in .npy header, found {:?}", other),
+                    };
+                    ensure!(parser.peek()? == ':', "Expected `:` after `{}` in .npy header", key);
+                    parser.pos += 1;
+                    members.push((key, parser.value()?));
+                    Ok(())
+                })?;
+                Ok(Literal::Dict(members))
+            }
+            '(' => {
+                let mut values = Vec::new();
+                self.items(')', |parser| {
+                    values.push(parser.value()?);
+                    Ok(())
+                })?;
+                Ok(Literal::Tuple(values))
+            }
+            quote @ ('\'' | '"') => {
+                let end = self.rest()[1..]
+                    .find(quote)
+                    .context("Unterminated string in .npy header")?;
+                let value = self.rest()[1..=end].to_string();
+                self.pos += end + 2;
+                Ok(Literal::Str(value))
+            }
+            _ if self.rest().starts_with("True") => {
+                self.pos += 4;
+                Ok(Literal::Bool(true))
+            }
+            _ if self.rest().starts_with("False") => {
+                self.pos += 5;
+                Ok(Literal::Bool(false))
+            }
+            c if c.is_ascii_digit() => {
+                let digits = self.rest().len() - self.rest().trim_start_matches(|c: char| c.is_ascii_digit()).len();
+                let value = self.rest()[..digits].parse()?;
+                self.pos += digits;
+                // Python 2 long literals, e.g. `(3L, 4L)`.
+                if self.rest().starts_with('L') {
+                    self.pos += 1;
+                }
+                Ok(Literal::Int(value))
+            }
+            c => bail!("Unexpected `{}` at byte {} of .npy header", c, self.pos),
+        }
+    }
+
+    /// Comma-separated items up to `close`, a trailing comma allowed.
+    fn items(&mut self, close: char, mut item: impl FnMut(&mut Self) -> Result<()>) -> Result<()> {
+        self.pos += 1;
+        loop {
+            if self.peek()? == close {
+                self.pos += 1;
+                return Ok(());
+            }
+            item(self)?;
+            match self.peek()? {
+                ',' => self.pos += 1,
+                c if c == close => {}
+                c => bail!("Expected `,` or `{}` in .npy header, found `{}`", close, c),
+            }
+        }
+    }
+}
+
+fn parse_header_text(text: &str) -> Result<NpyHeader> {
+    let mut parser = LiteralParser { text, pos: 0 };
+    let Literal::Dict(members) = parser.value()? else {
+        bail!("The .npy header is not a dictionary");
+    };
+    let field = |key: &str| {
+        members
+            .iter()
+            .find(|(name, _)| name == key)
+            .map(|(_, value)| value)
+            .with_context(|| format!("The .npy header has no `{}`", key))
+    };
+
+    let Literal::Str(descr) = field("descr")? else {
+        bail!("Unsupported .npy dtype {:?}; structured arrays are not supported", field("descr")?);
+    };
+    let (dtype, big_endian) = parse_descr(descr)?;
+    let &Literal::Bool(fortran_order) = field("fortran_order")? else {
+        bail!("`fortran_order` in .npy header is not a boolean");
+    };
+    let Literal::Tuple(dims) = field("shape")? else {
+        bail!("`shape` in .npy header is not a tuple");
+    };
+    let shape = dims
+        .iter()
+        .map(|dim| match dim {
+            &Literal::Int(dim) => Ok(dim),
+            other => bail!("Invalid dimension {:?} in .npy shape", other),
+        })
+        .collect::<Result<_>>()?;
+    Ok(NpyHeader { dtype, big_endian, fortran_order, shape })
+}
+
+/// Parses the header of a `.npy` file, returning it and the payload offset.
+pub fn read_header(bytes: &[u8]) -> Result<(NpyHeader, usize)> {
+    ensure!(bytes.starts_with(MAGIC), "Not a .npy file (bad magic)");
+    let (major, minor) = (*bytes.get(6).unwrap_or(&0), *bytes.get(7).unwrap_or(&0));
+    let (len, start) = match major {
+        1 => (bytes.get(8..10).map(|b| u16::from_le_bytes([b[0], b[1]]) as usize), 10),
+        2 | 3 => (bytes.get(8..12).map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]) as usize), 12),
+        _ => bail!("Unsupported .npy format version {}.{}", major, minor),
+    };
+    let text = len
+        .and_then(|len| bytes.get(start..start + len))
+        .context("Truncated .npy header")?;
+    // Versions 1 and 2 are latin-1, which matches UTF-8 for any header NumPy
+    // writes; version 3 is UTF-8.
+    let text = std::str::from_utf8(text).context("The .npy header is not valid text")?;
+    let header = parse_header_text(text)?;
+    Ok((header, start + text.len()))
+}
+
+/// Parses a `.npy` file, widening the payload to f32.
+pub fn decode(bytes: &[u8]) -> Result<HostMatrix> {
+    let (header, offset) = read_header(bytes)?;
+    let layout = header.layout()?;
+    let width = header.dtype.bits() as usize / 8;
+    let payload = &bytes[offset..];
+    let expected = layout
+        .shape
+        .rows
+        .checked_mul(layout.shape.cols)
+        .and_then(|len| len.checked_mul(width))
+        .context("Array in .npy header is too large")?;
+    ensure!(
+        payload.len() >= expected,
+        "Truncated .npy file: {:?} {} needs {} payload bytes, found {}",
+        header.shape,
+        header.dtype,
+        expected,
+        payload.len()
+    );
+    ensure!(
+        payload.len() == expected,
+        "The .npy file has {} trailing bytes after its payload",
+        payload.len() - expected
+    );
+
+    let word = |chunk: &[u8]| {
+        let mut bytes = [0; 4];
+        bytes[..chunk.len()].copy_from_slice(chunk);
+        if header.big_endian {
+            bytes[..chunk.len()].reverse();
+        }
+        u32::from_le_bytes(bytes)
+    };
+    let values = payload
+        .chunks_exact(width)
+        .map(|chunk| match header.dtype {
+            DataType::F16 => f16_to_f32(word(chunk) as u16),
+            DataType::Bf16 => f32::from_bits(word(chunk) << 16),
+            DataType::Int8 => chunk[0] as i8 as f32,
+            _ => f32::from_bits(word(chunk)),
+        })
+        .collect();
+    HostMatrix::from_vec(layout, values)
+}
+
+/// `matrix` as a `.npy` file of `dtype`. Packed row- and column-major
+/// matrices keep their order; any other layout is written in C order.
+pub fn encode(matrix: &HostMatrix, dtype: DataType) -> Result<Vec<u8>> {
+    let descr = descr(dtype)?;
+    let layout = matrix.layout();
+    let fortran_order = layout.layout == MemoryLayout::ColumnMajor && layout.leading_dim == matrix.rows();
+    let packed = fortran_order || (layout.layout == MemoryLayout::RowMajor && layout.leading_dim == matrix.cols());
+    let values = match packed {
+        true => Cow::Borrowed(matrix.as_slice()),
+        false => Cow::Owned(matrix.to_row_major()),
+    };
+
+    let mut text = format!(
+        "{{'descr': '{}', 'fortran_order': {}, 'shape': ({}, {}), }}",
+        descr,
+        if fortran_order { "True" } else { "False" },
+        matrix.rows(),
+        matrix.cols()
+    );
+    let unpadded = MAGIC.len() + 4 + text.len() + 1;
+    text.push_str(&" ".repeat(unpadded.next_multiple_of(ALIGNMENT) - unpadded));
+    text.push('\n');
+
+    let mut bytes = Vec::with_capacity(MAGIC.len() + 4 + text.len() + values.len() * 4);
+    bytes.extend_from_slice(MAGIC);
+    bytes.extend_from_slice(&[1, 0]);
+    bytes.extend_from_slice(&(text.len() as u16).to_le_bytes());
+    bytes.extend_from_slice(text.as_bytes());
+    for (i, &value) in values.iter().enumerate() {
+        match dtype {
+            DataType::F16 => bytes.extend_from_slice(&f32_to_f16(value).to_le_bytes()),
+            DataType::Bf16 => bytes.extend_from_slice(&f32_to_bf16(value).to_le_bytes()),
+            DataType::Int8 => {
+                ensure!(
+                    value.fract() == 0.0 && (-128.0..=127.0).contains(&value),
+                    "Element {} ({}) is not representable as int8",
+                    i,
+                    value
+                );
+                bytes.push(value as i8 as u8);
+            }
+            _ => bytes.extend_from_slice(&value.to_le_bytes()),
+        }
+    }
+    Ok(bytes)
+}
+
+pub fn read_npy(path: &Path) -> Result<HostMatrix> {
+    let bytes = std::fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    decode(&bytes).with_context(|| format!("Failed to load {}", path.display()))
+}
+
+pub fn write_npy(path: &Path, matrix: &HostMatrix, dtype: DataType) -> Result<()> {
+    let bytes = encode(matrix, dtype)?;
+    std::fs::write(path, bytes).with_context(|| format!("Failed to write {}", path.display()))
+}
+
+fn f16_to_f32(half: u16) -> f32 {
+    let sign = if half & 0x8000 != 0 { -1.0 } else { 1.0 };
+    let exponent = (half >> 10) & 0x1f;
+    let mantissa = (half & 0x3ff) as u32;
+    match exponent {
+        0 => sign * mantissa as f32 * 2f32.powi(-24),
+        0x1f => f32::from_bits(((half as u32 & 0x8000) << 16) | 0x7f80_0000 | (mantissa << 13)),
+        _ => f32::from_bits(((half as u32 & 0x8000) << 16) | ((exponent as u32 + 112) << 23) | (mantissa << 13)),
+    }
+}
+
+/// Rounds to the nearest f16, ties to even; out-of-range values become
+/// infinities.
+fn f32_to_f16(value: f32) -> u16 {
+    let bits = value.to_bits();
+    let sign = ((bits >> 16) & 0x8000) as u16;
+    let exponent = ((bits >> 23) & 0xff) as i32;
+    let mantissa = bits & 0x7f_ffff;
+
+    if exponent == 0xff {
+        let nan = if mantissa != 0 { 0x200 | (mantissa >> 13) as u16 } else { 0 };
+        return sign | 0x7c00 | nan;
+    }
+    let biased = exponent - 127 + 15;
+    if biased >= 0x1f {
+        return sign | 0x7c00;
+    }
+
+    // Shift the significand down to f16 precision, subnormals included.
+    let (significand, shift, base) = match biased {
+        1.. => (mantissa, 13, (biased as u32) << 10),
+        -10..=0 => (mantissa | 0x80_0000, (14 - biased) as u32, 0),
+        _ => return sign,
+    };
+    let mut half = base | (significand >> shift);
+    let remainder = significand & ((1 << shift) - 1);
+    let halfway = 1 << (shift - 1);
+    if remainder > halfway || (remainder == halfway && half & 1 != 0) {
+        // A carry out of the mantissa correctly bumps the exponent.
+        half += 1;
+    }
+    sign | half as u16
+}
+
+/// Rounds to the nearest bf16, ties to even.
+fn f32_to_bf16(value: f32) -> u16 {
+    let bits = value.to_bits();
+    if value.is_nan() {
+        return ((bits >> 16) | 0x40) as u16;
+    }
+    (bits.wrapping_add(0x7fff + ((bits >> 16) & 1)) >> 16) as u16
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(layout: TensorLayout) -> HostMatrix {
+        let mut matrix = HostMatrix::zeros(layout).unwrap();
+        for row in 0..matrix.rows() {
+            for col in 0..matrix.cols() {
+                matrix.set(row, col, (row * 10 + col) as f32 - 20.0);
+            }
+        }
+        matrix
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let mut padded = TensorLayout::row_major(3, 4);
+        padded.leading_dim = 6;
+        for dtype in [DataType::F32, DataType::F16, DataType::Bf16, DataType::Int8] {
+            for layout in [TensorLayout::row_major(3, 4), TensorLayout::column_major(3, 4)] {
+                let matrix = sample(layout);
+                let bytes = encode(&matrix, dtype).unwrap();
+                let header_len = bytes.len() - matrix.as_slice().len() * dtype.bits() as usize / 8;
+                assert_eq!(header_len % ALIGNMENT, 0);
+                assert_eq!(decode(&bytes).unwrap(), matrix, "{} {}", dtype, layout);
+            }
+            for layout in [padded, TensorLayout::tiled(3, 4, 2, 2)] {
+                let matrix = sample(layout);
+                let decoded = decode(&encode(&matrix, dtype).unwrap()).unwrap();
+                assert_eq!(decoded.layout(), &TensorLayout::row_major(3, 4));
+                assert_eq!(decoded.to_row_major(), matrix.to_row_major());
+            }
+        }
+
+        let mut fraction = sample(TensorLayout::row_major(1, 2));
+        fraction.set(0, 1, 0.5);
+        assert!(encode(&fraction, DataType::Int8).unwrap_err().to_string().contains("int8"));
+        assert!(encode(&fraction, DataType::F64).is_err());
+    }
+
+    fn npy(version: u8, header: &str, payload: &[u8]) -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend_from_slice(&[version, 0]);
+        match version {
+            1 => bytes.extend_from_slice(&(header.len() as u16).to_le_bytes()),
+            _ => bytes.extend_from_slice(&(header.len() as u32).to_le_bytes()),
+        }
+        bytes.extend_from_slice(header.as_bytes());
+        bytes.extend_from_slice(payload);
+        bytes
+    }
+
+    #[test]
+    fn test_headers() {
+        let payload: Vec<u8> = [1.0f32, 2.0].iter().flat_map(|v| v.to_be_bytes()).collect();
+        for version in [1, 2, 3] {
+            let bytes = npy(version, "{'descr': '>f4', 'fortran_order': True, 'shape': (2, 1), }\n", &payload);
+            let matrix = decode(&bytes).unwrap();
+            assert_eq!(matrix.layout(), &TensorLayout::column_major(2, 1));
+            assert_eq!(matrix.as_slice(), [1.0, 2.0]);
+        }
+        // Python 2 style, double quotes and no trailing comma.
+        let bytes = npy(1, "{\"shape\": (1L, 2L), \"fortran_order\": False, \"descr\": \"|i1\"}", &[0xff, 7]);
+        assert_eq!(decode(&bytes).unwrap().as_slice(), [-1.0, 7.0]);
+
+        let error = |header: &str, payload: &[u8]| format!("{:#}", decode(&npy(1, header, payload)).unwrap_err());
+        assert!(error("{'descr': '<f8', 'fortran_order': False, 'shape': (1, 1), }", &[0; 8]).contains("<f8"));
+        assert!(error("{'descr': '<f4', 'fortran_order': False, 'shape': (3,), }", &[0; 12]).contains("2-D"));
+        assert!(error("{'descr': '<f4', 'fortran_order': False, 'shape': (1, 2), }", &[0; 7]).contains("Truncated"));
+        assert!(error("{'descr': '<f4', 'fortran_order': False, 'shape': (1, 1), }", &[0; 5]).contains("trailing"));
+        assert!(error("{'descr': '<f4', 'shape': (1, 1), }", &[0; 4]).contains("fortran_order"));
+        assert!(error("{'descr': '<f4', 'fortran_order': False, 'shape': (1, 1)", &[]).contains("end of .npy header"));
+        assert!(decode(&npy(4, "{}", &[])).unwrap_err().to_string().contains("version 4.0"));
+        assert!(decode(&npy(1, "{}", &[])[..11]).unwrap_err().to_string().contains("Truncated"));
+        assert!(decode(b"NUMPY").is_err());
+    }
+
+    #[test]
+    fn test_half_conversions() {
+        for half in 0..=u16::MAX {
+            let value = f16_to_f32(half);
+            if !value.is_nan() {
+                assert_eq!(f32_to_f16(value), half, "{:#06x}", half);
+            }
+        }
+        assert_eq!(f16_to_f32(0x3c00), 1.0);
+        assert_eq!(f16_to_f32(0x0001), 2f32.powi(-24));
+        assert_eq!(f32_to_f16(65520.0), 0x7c00);
+        assert_eq!(f32_to_f16(-1e-8), 0x8000);
+        // Ties round to even, in the normal and the subnormal range.
+        assert_eq!(f32_to_f16(1.0 + 2f32.powi(-11)), 0x3c00);
+        assert_eq!(f32_to_f16(1.0 + 3.0 * 2f32.powi(-11)), 0x3c02);
+        assert_eq!(f32_to_f16(1.5 * 2f32.powi(-24)), 0x0002);
+        assert!(f16_to_f32(f32_to_f16(f32::NAN)).is_nan());
+
+        assert_eq!(f32_to_bf16(1.0 + 2f32.powi(-8)), 0x3f80);
+        assert_eq!(f32_to_bf16(1.0 + 3.0 * 2f32.powi(-8)), 0x3f82);
+        assert!(f32::from_bits((f32_to_bf16(f32::NAN) as u32) << 16).is_nan());
+    }
+}
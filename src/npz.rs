@@ -0,0 +1,296 @@
+//! NumPy `.npz` archives: zip files of `.npy` members. Archives written by
+//! `np.savez` (stored) and `np.savez_compressed` (deflate) both load,
+//! including the zip64 headers NumPy always emits; archives are written
+//! stored, as `np.savez` does.
+
+use anyhow::{anyhow, bail, ensure, Context, Result};
+use miniz_oxide::inflate::decompress_to_vec_with_limit;
+use std::path::Path;
+use utils::DataType;
+
+use crate::matrix::HostMatrix;
+use crate::matrix_io::crc32;
+use crate::npy;
+
+const LOCAL_HEADER: u32 = 0x0403_4b50;
+const CENTRAL_HEADER: u32 = 0x0201_4b50;
+const END_OF_DIRECTORY: u32 = 0x0605_4b50;
+const ZIP64_LOCATOR: u32 = 0x0706_4b50;
+const ZIP64_END_OF_DIRECTORY: u32 = 0x0606_4b50;
+/// Marks a 32-bit field whose value lives in the zip64 extra field.
+const ZIP64_PLACEHOLDER: u32 = u32::MAX;
+/// 1980-01-01, the earliest date a zip file can hold.
+const DOS_EPOCH: u16 = 0x21;
+
+fn read<const N: usize>(bytes: &[u8], offset: usize) -> Result<[u8; N]> {
+    let field = offset.checked_add(N).and_then(|end| bytes.get(offset..end));
+    Ok(field.context("Truncated .npz archive")?.try_into().unwrap())
+}
+
+fn u16_at(bytes: &[u8], offset: usize) -> Result<u16> {
+    read(bytes, offset).map(u16::from_le_bytes)
+}
+
+fn u32_at(bytes: &[u8], offset: usize) -> Result<u32> {
+    read(bytes, offset).map(u32::from_le_bytes)
+}
+
+fn u64_at(bytes: &[u8], offset: usize) -> Result<usize> {
+    let value = read(bytes, offset).map(u64::from_le_bytes)?;
+    usize::try_from(value).context("Offset in .npz archive does not fit in memory")
+}
+
+/// `(entries, offset)` of the central directory.
+fn central_directory(bytes: &[u8]) -> Result<(usize, usize)> {
+    // The end record is 22 bytes plus a comment of at most 64 KiB.
+    let last = bytes.len().checked_sub(22).context("Not a .npz archive (too short)")?;
+    let end = (last.saturating_sub(u16::MAX as usize)..=last)
+        .rev()
+        .find(|&offset| u32_at(bytes, offset).ok() == Some(END_OF_DIRECTORY))
+        .context("Not a .npz archive (no zip directory)")?;
+
+    let entries = u16_at(bytes, end + 10)?;
+    let offset = u32_at(bytes, end + 16)?;
+    if entries != u16::MAX && offset != ZIP64_PLACEHOLDER {
+        return Ok((entries as usize, offset as usize));
+    }
+    let locator = end.checked_sub(20).context("Truncated .npz archive")?;
+    ensure!(u32_at(bytes, locator)? == ZIP64_LOCATOR, "Missing zip64 directory in .npz archive");
+    let record = u64_at(bytes, locator + 8)?;
+    ensure!(u32_at(bytes, record)? == ZIP64_END_OF_DIRECTORY, "Invalid zip64 directory in .npz archive");
+    Ok((u64_at(bytes, record + 32)?, u64_at(bytes, record + 48)?))
+}
+
+/// Parses a `.npz` archive into its members, named without the `.npy`
+/// suffix and in archive order.
+pub fn decode(bytes: &[u8]) -> Result<Vec<(String, HostMatrix)>> {
+    let (entries, mut offset) = central_directory(bytes)?;
+    let mut members = Vec::with_capacity(entries.min(1024));
+    for _ in 0..entries {
+        ensure!(u32_at(bytes, offset)? == CENTRAL_HEADER, "Corrupt .npz directory at byte {}", offset);
+        let flags = u16_at(bytes, offset + 8)?;
+        let method = u16_at(bytes, offset + 10)?;
+        let crc = u32_at(bytes, offset + 16)?;
+        let mut sizes = [u32_at(bytes, offset + 24)? as usize, u32_at(bytes, offset + 20)? as usize];
+        let name_len = u16_at(bytes, offset + 28)? as usize;
+        let extra_len = u16_at(bytes, offset + 30)? as usize;
+        let comment_len = u16_at(bytes, offset + 32)? as usize;
+        let mut local = u32_at(bytes, offset + 42)? as usize;
+        let name = bytes
+            .get(offset + 46..offset + 46 + name_len)
+            .context("Truncated .npz archive")?;
+        let name = String::from_utf8_lossy(name).into_owned();
+
+        // Fields that overflowed 32 bits are in the zip64 extra field, in
+        // this order.
+        let mut extra = offset + 46 + name_len;
+        let extra_end = extra + extra_len;
+        while extra + 4 <= extra_end {
+            let (id, len) = (u16_at(bytes, extra)?, u16_at(bytes, extra + 2)? as usize);
+            if id == 1 {
+                let mut field = extra + 4;
+                for value in sizes.iter_mut().chain([&mut local]) {
+                    if *value == ZIP64_PLACEHOLDER as usize {
+                        *value = u64_at(bytes, field)?;
+                        field += 8;
+                    }
+                }
+            }
+            extra += 4 + len;
+        }
+        offset = extra_end + comment_len;
+
+        let [size, compressed_size] = sizes;
+        let member = (|| {
+            ensure!(flags & 1 == 0, "encrypted members are not supported");
+            ensure!(u32_at(bytes, local)? == LOCAL_HEADER, "missing local header");
+            let start = local + 30 + u16_at(bytes, local + 26)? as usize + u16_at(bytes, local + 28)? as usize;
+            let data = start
+                .checked_add(compressed_size)
+                .and_then(|end| bytes.get(start..end))
+                .context("Truncated .npz archive")?;
+            let data = match method {
+                0 => data.to_vec(),
+                8 => inflate(data, size)?,
+                other => bail!("unsupported compression method {}", other),
+            };
+            ensure!(
+                data.len() == size && crc32(&data) == crc,
+                "member is corrupt (size or checksum mismatch)"
+            );
+            npy::decode(&data)
+        })()
+        .with_context(|| format!("Invalid .npz member `{}`", name))?;
+        let name = name.strip_suffix(".npy").unwrap_or(&name).to_string();
+        members.push((name, member));
+    }
+    Ok(members)
+}
+
+/// A stored (uncompressed) archive with one `<name>.npy` member per entry.
+pub fn encode(members: &[(&str, &HostMatrix)], dtype: DataType) -> Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+    let mut directory = Vec::new();
+    for &(name, matrix) in members {
+        let name = format!("{}.npy", name);
+        let data = npy::encode(matrix, dtype)?;
+        let (offset, size) = (u32::try_from(bytes.len()), u32::try_from(data.len()));
+        let (Ok(offset), Ok(size)) = (offset, size) else {
+            bail!("Member `{}` is too large for a .npz archive without zip64", name);
+        };
+
+        // Version, flags, method, time, date, CRC and sizes are shared by the
+        // local and the central header.
+        let mut common = Vec::with_capacity(24);
+        for field in [20, 0, 0, 0, DOS_EPOCH] {
+            common.extend_from_slice(&u16::to_le_bytes(field));
+        }
+        for field in [crc32(&data), size, size] {
+            common.extend_from_slice(&field.to_le_bytes());
+        }
+        common.extend_from_slice(&(name.len() as u16).to_le_bytes());
+
+        bytes.extend_from_slice(&LOCAL_HEADER.to_le_bytes());
+        bytes.extend_from_slice(&common);
+        bytes.extend_from_slice(&0u16.to_le_bytes());
+        bytes.extend_from_slice(name.as_bytes());
+        bytes.extend_from_slice(&data);
+
+        directory.extend_from_slice(&CENTRAL_HEADER.to_le_bytes());
+        directory.extend_from_slice(&20u16.to_le_bytes());
+        directory.extend_from_slice(&common);
+        // Extra, comment, disk, internal and external attributes.
+        directory.extend_from_slice(&[0; 12]);
+        directory.extend_from_slice(&offset.to_le_bytes());
+        directory.extend_from_slice(name.as_bytes());
+    }
+
+    let offset = u32::try_from(bytes.len()).context("Archive too large for a .npz without zip64")?;
+    let entries = u16::try_from(members.len()).context("Too many members for a .npz without zip64")?;
+    bytes.extend_from_slice(&directory);
+    bytes.extend_from_slice(&END_OF_DIRECTORY.to_le_bytes());
+    bytes.extend_from_slice(&[0; 4]);
+    bytes.extend_from_slice(&entries.to_le_bytes());
+    bytes.extend_from_slice(&entries.to_le_bytes());
+    bytes.extend_from_slice(&(directory.len() as u32).to_le_bytes());
+    bytes.extend_from_slice(&offset.to_le_bytes());
+    bytes.extend_from_slice(&0u16.to_le_bytes());
+    Ok(bytes)
+}
+
+pub fn read_npz(path: &Path) -> Result<Vec<(String, HostMatrix)>> {
+    let bytes = std::fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    decode(&bytes).with_context(|| format!("Failed to load {}", path.display()))
+}
+
+pub fn write_npz(path: &Path, members: &[(&str, &HostMatrix)], dtype: DataType) -> Result<()> {
+    let bytes = encode(members, dtype)?;
+    std::fs::write(path, bytes).with_context(|| format!("Failed to write {}", path.display()))
+}
+
+/// The member called `name` (ignoring case), or the only member when no
+/// member has that name.
+pub fn select(members: Vec<(String, HostMatrix)>, name: &str) -> Result<HostMatrix> {
+    let names: Vec<String> = members.iter().map(|(member, _)| member.clone()).collect();
+    let mut members = members;
+    match members.iter().position(|(member, _)| member.eq_ignore_ascii_case(name)) {
+        Some(index) => Ok(members.swap_remove(index).1),
+        None if members.len() == 1 => Ok(members.remove(0).1),
+        None => bail!("No member `{}` among {:?}", name, names),
+    }
+}
+
+/// Decompresses a raw deflate stream (RFC 1951) that should hold `size`
+/// bytes, stopping as soon as it would produce more.
+fn inflate(data: &[u8], size: usize) -> Result<Vec<u8>> {
+    decompress_to_vec_with_limit(data, size).map_err(|e| anyhow!("Invalid deflate stream: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use utils::TensorLayout;
+
+    fn hex(text: &str) -> Vec<u8> {
+        (0..text.len()).step_by(2).map(|i| u8::from_str_radix(&text[i..i + 2], 16).unwrap()).collect()
+    }
+
+    /// `np.savez_compressed` of a 2x3 f32 `A` and a Fortran-order 3x2 int8
+    /// `B`, written by Python's zipfile with zip64 local headers as NumPy does.
+    const SAVEZ_COMPRESSED: &str = "\
+        504b03042d0000000800000021007c9cdea2ffffffffffffffff05001400412e6e707901001000980000000000000058000000\
+        000000009bec17ea1b10c9c850c650ad9e925a9c5ca46ea5a06e9366a2aea3a09e965f54529498179f5f94920a12774bcc294e\
+        058a17672416a402f91a463a0ac69a3a0ab50a64032e0686067b060606072002e206205e00c4071c00504b03042d0000000800\
+        000021008a9fc3b0ffffffffffffffff05001400422e6e70790100100086000000000000004c000000000000009bec17ea1b10\
+        c9c850c650ad9e925a9c5ca46ea5a05e9369a8aea3a09e965f54529498179f5f94920a120f292a4d050a17672416a402b91ac6\
+        3a0a469a3a0ab50ae403aeff4c7f597eb30100504b01022d032d0000000800000021007c9cdea2580000009800000005000000\
+        0000000000000000800100000000412e6e7079504b01022d032d0000000800000021008a9fc3b04c0000008600000005000000\
+        000000000000000080018f000000422e6e7079504b0506000000000200020066000000120100000000";
+
+    #[test]
+    fn test_numpy_archive() {
+        let members = decode(&hex(SAVEZ_COMPRESSED)).unwrap();
+        let names: Vec<&str> = members.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, ["A", "B"]);
+        assert_eq!(members[0].1.layout(), &TensorLayout::row_major(2, 3));
+        assert_eq!(members[0].1.as_slice(), [1.0, 2.0, 3.0, 4.0, 5.0, 6.0]);
+        assert_eq!(members[1].1.layout(), &TensorLayout::column_major(3, 2));
+        assert_eq!(members[1].1.as_slice(), [-1.0, 2.0, -3.0, 4.0, -5.0, 6.0]);
+
+        let b = select(members.clone(), "b").unwrap();
+        assert_eq!(b.get(0, 1), 4.0);
+        assert!(select(members, "c").unwrap_err().to_string().contains("\"A\", \"B\""));
+
+        let mut corrupt = hex(SAVEZ_COMPRESSED);
+        corrupt[0x50] ^= 1;
+        assert!(decode(&corrupt).is_err());
+        assert!(decode(&hex(SAVEZ_COMPRESSED)[..300]).is_err());
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let a = HostMatrix::from_vec(TensorLayout::column_major(2, 2), vec![1.0, -2.0, 0.5, 8.0]).unwrap();
+        let c = HostMatrix::from_vec(TensorLayout::row_major(1, 3), vec![3.0, 2.0, 1.0]).unwrap();
+        let bytes = encode(&[("a", &a), ("C", &c)], DataType::F16).unwrap();
+        let members = decode(&bytes).unwrap();
+        assert_eq!(members, [("a".to_string(), a.clone()), ("C".to_string(), c)]);
+
+        let single = decode(&encode(&[("arr_0", &a)], DataType::F32).unwrap()).unwrap();
+        assert_eq!(select(single, "b").unwrap(), a);
+        assert!(decode(&encode(&[], DataType::F32).unwrap()).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_inflate() {
+        // zlib level 9 output: one dynamic Huffman block.
+        let dynamic = hex(
+            "3d8fc10dc0200c03576184120804759e8a17fb7fdb4a39bf62d971ecece79c72dd65ffb3e6ec3917fc48609ea0c1747606a6\
+             e04ac384d267826948ace219304b499c996ac98e232db55173bda21d24e74e904994d12f901c939119fa576f62af30419df1\
+             8117",
+        );
+        let expected: String = (0..40).map(|i| format!("gemm {}; ", i * i % 97)).collect();
+        assert_eq!(inflate(&dynamic, expected.len()).unwrap(), expected.as_bytes());
+        assert_eq!(inflate(&hex("010600f9ff73746f726564"), 6).unwrap(), b"stored");
+
+        // Output beyond the declared size is an error, not an allocation.
+        assert!(inflate(&dynamic, expected.len() - 1).is_err());
+        assert!(inflate(&dynamic[..40], expected.len()).is_err());
+        assert!(inflate(&hex("010600f8ff73746f726564"), 6).is_err());
+        assert!(inflate(&[0x07], 6).is_err());
+    }
+
+    #[test]
+    fn test_member_larger_than_declared() {
+        // 1 MiB of zeros deflates to about a kilobyte.
+        let bomb = miniz_oxide::deflate::compress_to_vec(&vec![0; 1 << 20], 9);
+        assert_eq!(inflate(&bomb, 1 << 20).unwrap().len(), 1 << 20);
+        assert!(inflate(&bomb, 5).is_err());
+
+        // `A` declaring fewer bytes than its stream holds.
+        let mut bytes = hex(SAVEZ_COMPRESSED);
+        let (_, central) = central_directory(&bytes).unwrap();
+        bytes[central + 24..central + 28].copy_from_slice(&16u32.to_le_bytes());
+        let error = format!("{:#}", decode(&bytes).unwrap_err());
+        assert!(error.contains("Invalid .npz member `A.npy`: Invalid deflate stream"), "{}", error);
+    }
+}
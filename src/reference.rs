@@ -0,0 +1,212 @@
+use anyhow::{bail, Error, Result};
+use std::fmt;
+use std::str::FromStr;
+
+use crate::cpu_gemm::check_shapes;
+use crate::matrix::MatrixRef;
+
+/// Pairwise summation falls back to a plain loop below this many terms.
+const PAIRWISE_BLOCK: usize = 8;
+
+/// How the f64 reference sums the K products of each output element.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum Summation {
+    /// Left-to-right f64 accumulation.
+    #[default]
+    Naive,
+    /// Kahan-compensated f64 accumulation.
+    Kahan,
+    /// Recursive pairwise summation; error grows with log K rather than K.
+    Pairwise,
+}
+
+impl Summation {
+    pub const ALL: [Summation; 3] = [Summation::Naive, Summation::Kahan, Summation::Pairwise];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Summation::Naive => "naive",
+            Summation::Kahan => "kahan",
+            Summation::Pairwise => "pairwise",
+        }
+    }
+
+    fn sum(&self, terms: &[f64]) -> f64 {
+        match self {
+            Summation::Naive => terms.iter().sum(),
+            Summation::Kahan => kahan_sum(terms),
+            Summation::Pairwise => pairwise_sum(terms),
+        }
+    }
+}
+
+impl fmt::Display for Summation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Summation {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "naive" => Ok(Summation::Naive),
+            "kahan" => Ok(Summation::Kahan),
+            "pairwise" => Ok(Summation::Pairwise),
+            other => bail!("Unknown summation `{}` (expected naive, kahan or pairwise)", other),
+        }
+    }
+}
+
+fn kahan_sum(terms: &[f64]) -> f64 {
+    let mut sum = 0.0;
+    let mut compensation = 0.0;
+    for &term in terms {
+        let y = term - compensation;
+        let t = sum + y;
+        compensation = (t - sum) - y;
+        sum = t;
+    }
+    sum
+}
+
+fn pairwise_sum(terms: &[f64]) -> f64 {
+    if terms.len() <= PAIRWISE_BLOCK {
+        return terms.iter().sum();
+    }
+    let (left, right) = terms.split_at(terms.len() / 2);
+    pairwise_sum(left) + pairwise_sum(right)
+}
+
+/// High-precision host GEMM used as ground truth for verification.
+///
+/// Products of f32 operands are exact in f64, so the only rounding left is
+/// in the K-length sums, which [`Summation`] controls. The result is returned
+/// as a dense row-major `m x n` f64 matrix regardless of the operand layouts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReferenceGemm {
+    pub summation: Summation,
+    pub threads: usize,
+}
+
+impl Default for ReferenceGemm {
+    fn default() -> Self {
+        Self {
+            summation: Summation::default(),
+            threads: std::thread::available_parallelism().map_or(1, |n| n.get()),
+        }
+    }
+}
+
+impl ReferenceGemm {
+    pub fn new(summation: Summation) -> Self {
+        Self { summation, ..Self::default() }
+    }
+
+    pub fn with_threads(mut self, threads: usize) -> Self {
+        self.threads = threads.max(1);
+        self
+    }
+
+    /// Computes `alpha * A * B + beta * C` in f64. As with
+    /// [`crate::CpuGemm::run`], `c` is not read when `beta == 0`.
+    pub fn run(&self, alpha: f32, a: MatrixRef<'_>, b: MatrixRef<'_>, beta: f32, c: MatrixRef<'_>) -> Result<Vec<f64>> {
+        check_shapes(a.layout(), b.layout(), c.layout())?;
+
+        let (m, n, k) = (c.rows(), c.cols(), a.cols());
+        let mut out = vec![0.0f64; m * n];
+        if m == 0 || n == 0 {
+            return Ok(out);
+        }
+
+        // B transposed so each output element is a dot of two contiguous rows.
+        let mut b_t = vec![0.0f64; n * k];
+        for (j, column) in b_t.chunks_exact_mut(k.max(1)).enumerate().take(n) {
+            for (p, value) in column.iter_mut().enumerate() {
+                *value = b.get(p, j) as f64;
+            }
+        }
+
+        let rows_per_thread = m.div_ceil(self.threads.max(1));
+        let work = |row0: usize, out: &mut [f64]| {
+            let mut a_row = vec![0.0f64; k];
+            let mut products = vec![0.0f64; k];
+            for (di, out_row) in out.chunks_exact_mut(n).enumerate() {
+                let i = row0 + di;
+                for (p, value) in a_row.iter_mut().enumerate() {
+                    *value = a.get(i, p) as f64;
+                }
+                for (j, dst) in out_row.iter_mut().enumerate() {
+                    let b_col = &b_t[j * k..(j + 1) * k];
+                    for ((product, &x), &y) in products.iter_mut().zip(&a_row).zip(b_col) {
+                        *product = x * y;
+                    }
+                    let value = alpha as f64 * self.summation.sum(&products);
+                    *dst = if beta == 0.0 {
+                        value
+                    } else {
+                        value + beta as f64 * c.get(i, j) as f64
+                    };
+                }
+            }
+        };
+
+        if rows_per_thread >= m {
+            work(0, &mut out);
+        } else {
+            std::thread::scope(|s| {
+                for (chunk, rows) in out.chunks_mut(rows_per_thread * n).enumerate() {
+                    s.spawn(move || work(chunk * rows_per_thread, rows));
+                }
+            });
+        }
+
+        Ok(out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::matrix::HostMatrix;
+    use utils::TensorLayout;
+
+    #[test]
+    fn test_summation_accuracy() {
+        // 1 followed by many terms that vanish when added to 1 one at a time.
+        let mut terms = vec![1.0];
+        terms.extend(std::iter::repeat_n(1e-16, 10_000));
+        let exact = 1.0 + 1e-12;
+
+        assert_eq!(Summation::Naive.sum(&terms), 1.0);
+        assert!((Summation::Kahan.sum(&terms) - exact).abs() < 1e-15);
+        assert!((Summation::Pairwise.sum(&terms) - exact).abs() < 1e-15);
+
+        for summation in Summation::ALL {
+            assert_eq!(summation.name().parse::<Summation>().unwrap(), summation);
+        }
+        assert!("f16".parse::<Summation>().is_err());
+    }
+
+    #[test]
+    fn test_reference_gemm() {
+        let a = HostMatrix::from_vec(TensorLayout::row_major(2, 3), vec![1.0, 2.0, 3.0, 4.0, 5.0, 6.0]).unwrap();
+        let b = HostMatrix::from_vec(TensorLayout::column_major(3, 2), vec![7.0, 9.0, 11.0, 8.0, 10.0, 12.0]).unwrap();
+        let c = HostMatrix::from_vec(TensorLayout::row_major(2, 2), vec![1.0; 4]).unwrap();
+
+        for summation in Summation::ALL {
+            for threads in [1, 2, 5] {
+                let gemm = ReferenceGemm::new(summation).with_threads(threads);
+                let out = gemm.run(2.0, a.view(), b.view(), 0.5, c.view()).unwrap();
+                assert_eq!(out, [116.5, 128.5, 278.5, 308.5]);
+            }
+        }
+
+        let nan = HostMatrix::from_vec(TensorLayout::row_major(2, 2), vec![f32::NAN; 4]).unwrap();
+        let out = ReferenceGemm::default().run(1.0, a.view(), b.view(), 0.0, nan.view()).unwrap();
+        assert_eq!(out, [58.0, 64.0, 139.0, 154.0]);
+
+        assert!(ReferenceGemm::default().run(1.0, b.view(), b.view(), 0.0, c.view()).is_err());
+    }
+}
@@ -0,0 +1,534 @@
+use anyhow::{bail, Context, Error, Result};
+use std::fmt;
+use std::io::Write;
+use std::path::Path;
+use std::str::FromStr;
+use utils::TensorLayout;
+
+use crate::backend::GemmBackend;
+use crate::bench::{time_gemm, BenchmarkResult, Timings};
+use crate::cli::SweepArgs;
+use crate::cpu_gemm::CpuGemm;
+use crate::generate::{STREAM_A, STREAM_B, STREAM_C};
+use crate::json;
+use crate::matrix::{MatrixMut, MatrixRef};
+use crate::variant::KernelVariant;
+use crate::verify::Verifier;
+
+/// Problem size of one GEMM: C is `m x n`, the reduction is over `k`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct GemmShape {
+    pub m: usize,
+    pub n: usize,
+    pub k: usize,
+}
+
+impl GemmShape {
+    pub const fn new(m: usize, n: usize, k: usize) -> Self {
+        Self { m, n, k }
+    }
+
+    pub const fn cube(size: usize) -> Self {
+        Self::new(size, size, size)
+    }
+}
+
+impl fmt::Display for GemmShape {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}x{}x{}", self.m, self.n, self.k)
+    }
+}
+
+/// `MxNxK`, or a single `S` for a cube.
+impl FromStr for GemmShape {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let dims = s
+            .split('x')
+            .map(|dim| dim.parse::<usize>())
+            .collect::<Result<Vec<_>, _>>()
+            .with_context(|| format!("Invalid shape `{}` (expected MxNxK)", s))?;
+        match dims[..] {
+            [m, n, k] => Ok(Self::new(m, n, k)),
+            [size] => Ok(Self::cube(size)),
+            _ => bail!("Invalid shape `{}` (expected MxNxK)", s),
+        }
+    }
+}
+
+const SMALL: &[GemmShape] = &[GemmShape::cube(16), GemmShape::cube(32), GemmShape::cube(64)];
+const MEDIUM: &[GemmShape] = &[GemmShape::cube(256), GemmShape::cube(512), GemmShape::cube(1024)];
+const LARGE: &[GemmShape] = &[GemmShape::cube(2048), GemmShape::cube(4096), GemmShape::cube(8192)];
+const NON_SQUARE: &[GemmShape] = &[
+    GemmShape::new(1024, 2048, 512),
+    GemmShape::new(512, 4096, 1024),
+    GemmShape::new(2048, 512, 2048),
+];
+
+/// The standard test cases from `data/README.md`: `small`, `medium`,
+/// `large`, `standard` (all square cases), `non-square` and `all`.
+pub fn preset(name: &str) -> Option<Vec<GemmShape>> {
+    let groups: &[&[GemmShape]] = match name {
+        "small" => &[SMALL],
+        "medium" => &[MEDIUM],
+        "large" => &[LARGE],
+        "standard" => &[SMALL, MEDIUM, LARGE],
+        "non-square" => &[NON_SQUARE],
+        "all" => &[SMALL, MEDIUM, LARGE, NON_SQUARE],
+        _ => return None,
+    };
+    Some(groups.concat())
+}
+
+/// Parses a list of shapes and preset names separated by commas or
+/// whitespace. `#` starts a comment that runs to the end of the line.
+pub fn parse_shapes(list: &str) -> Result<Vec<GemmShape>> {
+    let mut shapes = Vec::new();
+    for line in list.lines() {
+        let line = line.split('#').next().unwrap_or_default();
+        for token in line.split(|c: char| c == ',' || c.is_whitespace()).filter(|t| !t.is_empty()) {
+            match preset(token) {
+                Some(preset) => shapes.extend(preset),
+                None => shapes.push(token.parse()?),
+            }
+        }
+    }
+    Ok(shapes)
+}
+
+pub fn read_shapes_file(path: &Path) -> Result<Vec<GemmShape>> {
+    let list = std::fs::read_to_string(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    parse_shapes(&list).with_context(|| format!("Invalid shape list in {}", path.display()))
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OutputFormat {
+    #[default]
+    Csv,
+    Json,
+}
+
+impl FromStr for OutputFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "csv" => Ok(OutputFormat::Csv),
+            "json" => Ok(OutputFormat::Json),
+            other => bail!("Unknown output format `{}` (expected csv or json)", other),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SweepStatus {
+    Passed,
+    Failed,
+    /// Verification was turned off.
+    Skipped,
+    /// The device cannot run the variant.
+    Unsupported,
+    /// Allocation, launch or verification failed with this message.
+    Error(String),
+}
+
+impl SweepStatus {
+    pub fn name(&self) -> &'static str {
+        match self {
+            SweepStatus::Passed => "passed",
+            SweepStatus::Failed => "failed",
+            SweepStatus::Skipped => "skipped",
+            SweepStatus::Unsupported => "unsupported",
+            SweepStatus::Error(_) => "error",
+        }
+    }
+}
+
+/// Result of one (shape, variant) pair.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SweepRow {
+    pub shape: GemmShape,
+    pub variant: KernelVariant,
+    /// `None` when the variant did not run.
+    pub benchmark: Option<BenchmarkResult>,
+    pub status: SweepStatus,
+    pub max_abs_error: Option<f64>,
+}
+
+impl SweepRow {
+    pub fn gflops(&self) -> Option<f64> {
+        let GemmShape { m, n, k } = self.shape;
+        self.benchmark.as_ref().map(|result| result.timings.gflops(m, n, k))
+    }
+}
+
+/// Runs every variant in `args` on every shape. Per-shape failures are
+/// recorded in the row's [`SweepStatus`] rather than aborting the sweep.
+/// `progress` is called after each row.
+pub fn run_sweep<B: GemmBackend>(
+    backend: &B,
+    args: &SweepArgs,
+    mut progress: impl FnMut(&SweepRow),
+) -> Result<Vec<SweepRow>> {
+    let mut rows = Vec::with_capacity(args.shapes.len() * args.variants.len());
+    for &shape in &args.shapes {
+        let shape_rows = match run_shape(backend, args, shape) {
+            Ok(rows) => rows,
+            Err(e) => args
+                .variants
+                .iter()
+                .map(|&variant| SweepRow {
+                    shape,
+                    variant,
+                    benchmark: None,
+                    status: SweepStatus::Error(format!("{:#}", e)),
+                    max_abs_error: None,
+                })
+                .collect(),
+        };
+        for row in shape_rows {
+            progress(&row);
+            rows.push(row);
+        }
+    }
+    Ok(rows)
+}
+
+fn run_shape<B: GemmBackend>(backend: &B, args: &SweepArgs, shape: GemmShape) -> Result<Vec<SweepRow>> {
+    let GemmShape { m, n, k } = shape;
+    let run = &args.run;
+    // C is restored from the host before every run. A and B only need host
+    // copies for the reference, so without verification they are generated
+    // on the backend.
+    let c_host = run.generator.values(STREAM_C, m, n)?;
+    let mut a = backend.alloc(TensorLayout::row_major(m, k))?;
+    let mut b = backend.alloc(TensorLayout::row_major(k, n))?;
+    let mut c = backend.alloc(TensorLayout::row_major(m, n))?;
+    backend.generate(&mut a, &run.generator, STREAM_A)?;
+    backend.generate(&mut b, &run.generator, STREAM_B)?;
+
+    // One reference and tolerance per shape, shared by all variants.
+    let check = if run.verify {
+        let a_host = run.generator.values(STREAM_A, m, k)?;
+        let b_host = run.generator.values(STREAM_B, k, n)?;
+        let mut c_ref = c_host.clone();
+        CpuGemm::default().run(
+            run.alpha,
+            MatrixRef::new(TensorLayout::row_major(m, k), &a_host)?,
+            MatrixRef::new(TensorLayout::row_major(k, n), &b_host)?,
+            run.beta,
+            MatrixMut::new(TensorLayout::row_major(m, n), &mut c_ref)?,
+        )?;
+        let policy = run.tolerance.policy(
+            run.error_model(),
+            (m, n, k),
+            run.alpha,
+            &a_host,
+            &b_host,
+            run.beta,
+            &c_host,
+        )?;
+        Some((c_ref, policy))
+    } else {
+        None
+    };
+
+    let mut rows = Vec::with_capacity(args.variants.len());
+    for &variant in &args.variants {
+        let mut row = SweepRow {
+            shape,
+            variant,
+            benchmark: None,
+            status: SweepStatus::Skipped,
+            max_abs_error: None,
+        };
+        if let Some(info) = backend.device_info() {
+            if !variant.supports(info.compute_capability) {
+                row.status = SweepStatus::Unsupported;
+                rows.push(row);
+                continue;
+            }
+        }
+
+        let result = time_gemm(
+            backend,
+            variant,
+            run.alpha,
+            &a,
+            &b,
+            run.beta,
+            &mut c,
+            &c_host,
+            &run.benchmark,
+        );
+        match result {
+            Ok(result) => row.benchmark = Some(result),
+            Err(e) => {
+                row.status = SweepStatus::Error(format!("{:#}", e));
+                rows.push(row);
+                continue;
+            }
+        }
+
+        if let Some((c_ref, policy)) = &check {
+            let mut c_out = vec![0.0f32; m * n];
+            let report = backend
+                .download(&c, &mut c_out)
+                .and_then(|()| Verifier::new(&**policy).compare(m, n, c_ref, &c_out));
+            match report {
+                Ok(report) => {
+                    row.max_abs_error = Some(report.max_abs_error);
+                    row.status = if report.passed() { SweepStatus::Passed } else { SweepStatus::Failed };
+                }
+                Err(e) => row.status = SweepStatus::Error(format!("{:#}", e)),
+            }
+        }
+        rows.push(row);
+    }
+    Ok(rows)
+}
+
+const COLUMNS: [&str; 18] = [
+    "m",
+    "n",
+    "k",
+    "variant",
+    "iterations",
+    "outliers",
+    "min_ms",
+    "median_ms",
+    "mean_ms",
+    "p95_ms",
+    "max_ms",
+    "stddev_ms",
+    "ci_low_ms",
+    "ci_high_ms",
+    "gflops",
+    "verification",
+    "max_abs_error",
+    "error",
+];
+
+/// A row's values in [`COLUMNS`] order. Numbers are pre-formatted, `None`
+/// marks a missing value and strings are quoted by the writer.
+enum Value {
+    Number(String),
+    Text(String),
+    Missing,
+}
+
+fn values(row: &SweepRow) -> Vec<Value> {
+    let ms = |f: &dyn Fn(&BenchmarkResult) -> f64| match &row.benchmark {
+        Some(result) => Value::Number(format!("{:.6}", f(result) * 1e3)),
+        None => Value::Missing,
+    };
+    let stat = |f: fn(&Timings) -> f64| ms(&move |result: &BenchmarkResult| f(&result.timings));
+    let count = |f: fn(&BenchmarkResult) -> usize| Value::Number(row.benchmark.as_ref().map_or(0, f).to_string());
+    let error = match &row.status {
+        SweepStatus::Error(message) => Value::Text(message.clone()),
+        _ => Value::Missing,
+    };
+    vec![
+        Value::Number(row.shape.m.to_string()),
+        Value::Number(row.shape.n.to_string()),
+        Value::Number(row.shape.k.to_string()),
+        Value::Text(row.variant.name().to_string()),
+        count(|result| result.measured),
+        count(|result| result.outliers.len()),
+        stat(Timings::min),
+        stat(Timings::median),
+        stat(Timings::mean),
+        stat(Timings::p95),
+        stat(Timings::max),
+        stat(Timings::stddev),
+        ms(&|result| result.confidence_interval().0),
+        ms(&|result| result.confidence_interval().1),
+        row.gflops().map_or(Value::Missing, |g| Value::Number(format!("{:.3}", g))),
+        Value::Text(row.status.name().to_string()),
+        row.max_abs_error.map_or(Value::Missing, |e| Value::Number(format!("{:e}", e))),
+        error,
+    ]
+}
+
+fn csv_field(text: &str) -> String {
+    if text.contains([',', '"', '\n']) {
+        format!("\"{}\"", text.replace('"', "\"\""))
+    } else {
+        text.to_string()
+    }
+}
+
+pub fn write_csv(rows: &[SweepRow], mut out: impl Write) -> Result<()> {
+    writeln!(out, "{}", COLUMNS.join(","))?;
+    for row in rows {
+        let fields: Vec<String> = values(row)
+            .into_iter()
+            .map(|value| match value {
+                Value::Number(number) => number,
+                Value::Text(text) => csv_field(&text),
+                Value::Missing => String::new(),
+            })
+            .collect();
+        writeln!(out, "{}", fields.join(","))?;
+    }
+    out.flush()?;
+    Ok(())
+}
+
+pub fn write_json(rows: &[SweepRow], mut out: impl Write) -> Result<()> {
+    writeln!(out, "[")?;
+    for (i, row) in rows.iter().enumerate() {
+        let fields: Vec<String> = COLUMNS
+            .iter()
+            .zip(values(row))
+            .map(|(column, value)| {
+                let value = match value {
+                    Value::Number(number) => number,
+                    Value::Text(text) => json::quote(&text),
+                    Value::Missing => "null".to_string(),
+                };
+                format!("\"{}\": {}", column, value)
+            })
+            .collect();
+        let separator = if i + 1 < rows.len() { "," } else { "" };
+        writeln!(out, "  {{{}}}{}", fields.join(", "), separator)?;
+    }
+    writeln!(out, "]")?;
+    out.flush()?;
+    Ok(())
+}
+
+pub fn write_rows(rows: &[SweepRow], format: OutputFormat, out: impl Write) -> Result<()> {
+    match format {
+        OutputFormat::Csv => write_csv(rows, out),
+        OutputFormat::Json => write_json(rows, out),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::{CpuBackend, DeviceBackend};
+    use crate::bench::Benchmark;
+    use crate::cli::RunArgs;
+    use crate::driver::{DeviceInfo, MockDriver};
+    use crate::launch::DeviceLimits;
+
+    fn sweep_args(shapes: &str) -> SweepArgs {
+        SweepArgs {
+            run: RunArgs {
+                benchmark: Benchmark::new(2).with_warmup(0),
+                ..RunArgs::default()
+            },
+            shapes: parse_shapes(shapes).unwrap(),
+            variants: KernelVariant::ALL.to_vec(),
+            format: OutputFormat::Csv,
+            output: None,
+            record: None,
+        }
+    }
+
+    #[test]
+    fn test_shapes_and_presets() {
+        assert_eq!("64x32x16".parse::<GemmShape>().unwrap(), GemmShape::new(64, 32, 16));
+        assert_eq!("8".parse::<GemmShape>().unwrap(), GemmShape::cube(8));
+        assert!("8x8".parse::<GemmShape>().is_err());
+
+        assert_eq!(preset("standard").unwrap().len(), 9);
+        assert_eq!(preset("non-square").unwrap()[0], GemmShape::new(1024, 2048, 512));
+        assert!(preset("huge").is_none());
+
+        let shapes = parse_shapes("# debugging\n16x8x4, small\n 100 # cube\n").unwrap();
+        assert_eq!(shapes.len(), 5);
+        assert_eq!(shapes[0], GemmShape::new(16, 8, 4));
+        assert_eq!(shapes[4], GemmShape::cube(100));
+        assert!(parse_shapes("16x8x4,bogus").is_err());
+    }
+
+    #[test]
+    fn test_cpu_sweep() {
+        let mut args = sweep_args("8x12x5, 33x17x40");
+        args.run.beta = 0.5;
+        let mut seen = 0;
+        let rows = run_sweep(&CpuBackend::new(), &args, |_| seen += 1).unwrap();
+
+        assert_eq!(rows.len(), 6);
+        assert_eq!(seen, 6);
+        assert!(rows.iter().all(|row| row.status == SweepStatus::Passed));
+        assert_eq!(rows[3].shape, GemmShape::new(33, 17, 40));
+        assert_eq!(rows[3].variant, KernelVariant::Naive);
+        assert_eq!(rows[3].benchmark.as_ref().unwrap().measured, 2);
+
+        args.run.verify = false;
+        let rows = run_sweep(&CpuBackend::new(), &args, |_| {}).unwrap();
+        assert!(rows.iter().all(|row| row.status == SweepStatus::Skipped && row.gflops().is_some()));
+    }
+
+    #[test]
+    fn test_sweep_reports_unsupported_and_errors() {
+        let driver = MockDriver::with_device_info(DeviceInfo {
+            name: "Mock Turing".to_string(),
+            compute_capability: (7, 5),
+            limits: DeviceLimits::sm80(),
+        });
+        let backend = DeviceBackend::from_ptx(driver, "").unwrap();
+        let mut args = sweep_args("16");
+        args.run.verify = false;
+        let rows = run_sweep(&backend, &args, |_| {}).unwrap();
+        assert_eq!(rows[0].status, SweepStatus::Skipped);
+        assert_eq!(rows[2].status, SweepStatus::Unsupported);
+
+        let backend = DeviceBackend::from_ptx(MockDriver::new().with_memory_limit(1024), "").unwrap();
+        let rows = run_sweep(&backend, &sweep_args("64"), |_| {}).unwrap();
+        assert_eq!(rows.len(), 3);
+        assert!(matches!(&rows[0].status, SweepStatus::Error(e) if e.contains("allocate")));
+    }
+
+    #[test]
+    fn test_writers() {
+        let rows = vec![
+            SweepRow {
+                shape: GemmShape::new(2, 3, 4),
+                variant: KernelVariant::Tiled,
+                benchmark: Some(BenchmarkResult {
+                    timings: Timings { samples: vec![0.001, 0.003] },
+                    outliers: vec![0.05],
+                    measured: 3,
+                    warmup_runs: 1,
+                    steady: None,
+                    confidence: 0.95,
+                }),
+                status: SweepStatus::Passed,
+                max_abs_error: Some(0.0),
+            },
+            SweepRow {
+                shape: GemmShape::cube(8),
+                variant: KernelVariant::Wmma,
+                benchmark: None,
+                status: SweepStatus::Error("bad \"launch\", grid too large".to_string()),
+                max_abs_error: None,
+            },
+        ];
+
+        let mut csv = Vec::new();
+        write_rows(&rows, OutputFormat::Csv, &mut csv).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines[0], COLUMNS.join(","));
+        assert_eq!(
+            lines[1],
+            "2,3,4,tiled,3,1,1.000000,2.000000,2.000000,2.900000,3.000000,1.414214,-10.706205,14.706205,0.000,passed,0e0,"
+        );
+        assert_eq!(lines[2], "8,8,8,wmma,0,0,,,,,,,,,,error,,\"bad \"\"launch\"\", grid too large\"");
+
+        let mut json = Vec::new();
+        write_rows(&rows, OutputFormat::Json, &mut json).unwrap();
+        let json = String::from_utf8(json).unwrap();
+        assert!(json.starts_with("[\n  {\"m\": 2, \"n\": 3, \"k\": 4, \"variant\": \"tiled\""));
+        assert!(json.contains("\"gflops\": null, \"verification\": \"error\""));
+        assert!(json.contains("\"error\": \"bad \\\"launch\\\", grid too large\"}\n]"));
+        assert_eq!("json".parse::<OutputFormat>().unwrap(), OutputFormat::Json);
+        assert!("xml".parse::<OutputFormat>().is_err());
+    }
+}
@@ -0,0 +1,217 @@
+use anyhow::{bail, Error, Result};
+use std::fmt;
+use std::str::FromStr;
+use utils::wmma::{WMMA_BLOCK_TILE, WMMA_TILE, WMMA_WARPS_PER_ROW};
+use utils::TileConfig;
+
+use crate::launch::{validate_launch, DeviceLimits, LaunchConfig};
+
+/// `TILE_M`, `TILE_N` and `TILE_K` compiled into `gemm_kernel_tiled`; the
+/// kernel only computes correct results with this block tile.
+pub const TILED_KERNEL_TILE: TileConfig = TileConfig::ampere_default();
+
+/// Threads per side of the square block used by `gemm_kernel`.
+const NAIVE_BLOCK_DIM: u32 = 16;
+/// `gemm_kernel_wmma` runs one warp per tile of its block's square grid.
+const WMMA_WARPS_PER_BLOCK: u32 = WMMA_WARPS_PER_ROW * WMMA_WARPS_PER_ROW;
+/// `SMEM_A` (64x16) plus `SMEM_B` (16x64) in `gemm_kernel_wmma`.
+const WMMA_SHARED_MEM_BYTES: u32 = 2 * WMMA_BLOCK_TILE * WMMA_TILE * 4;
+
+/// The GEMM entry points exported by `cuda-kernel`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum KernelVariant {
+    /// `gemm_kernel`: one thread per output element, global memory only.
+    #[default]
+    Naive,
+    /// `gemm_kernel_tiled`: shared-memory tiles of [`TILED_KERNEL_TILE`]'s
+    /// compile-time shape.
+    Tiled,
+    /// `gemm_kernel_wmma`: warp-level Tensor Core MMA.
+    Wmma,
+}
+
+impl KernelVariant {
+    pub const ALL: [KernelVariant; 3] = [KernelVariant::Naive, KernelVariant::Tiled, KernelVariant::Wmma];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            KernelVariant::Naive => "naive",
+            KernelVariant::Tiled => "tiled",
+            KernelVariant::Wmma => "wmma",
+        }
+    }
+
+    /// Symbol name of the kernel in the PTX module.
+    pub fn entry_point(&self) -> &'static str {
+        match self {
+            KernelVariant::Naive => "gemm_kernel",
+            KernelVariant::Tiled => "gemm_kernel_tiled",
+            KernelVariant::Wmma => "gemm_kernel_wmma",
+        }
+    }
+
+    /// Lowest compute capability the variant can run on. The PTX is built
+    /// for `sm_80`, and the WMMA path relies on Ampere MMA shapes.
+    pub fn min_compute_capability(&self) -> (u32, u32) {
+        match self {
+            KernelVariant::Naive | KernelVariant::Tiled => (7, 0),
+            KernelVariant::Wmma => (8, 0),
+        }
+    }
+
+    pub fn supports(&self, compute_capability: (u32, u32)) -> bool {
+        compute_capability >= self.min_compute_capability()
+    }
+
+    /// Why the variant cannot run on a device with `compute_capability` and
+    /// `limits`, or `None` when it can. The launch is checked for a
+    /// one-block output, so only the block shape and shared memory count.
+    pub fn unavailable_reason(&self, compute_capability: (u32, u32), limits: &DeviceLimits) -> Option<String> {
+        if !self.supports(compute_capability) {
+            let (major, minor) = self.min_compute_capability();
+            return Some(format!("unsupported (needs compute capability {}.{})", major, minor));
+        }
+        let config = self.launch_config(1, 1, &TILED_KERNEL_TILE);
+        validate_launch(&config, &TILED_KERNEL_TILE, limits)
+            .err()
+            .map(|e| format!("not launchable ({})", e))
+    }
+
+    /// Thread block shape the kernel's indexing assumes.
+    pub fn block_dim(&self, tile: &TileConfig) -> (u32, u32, u32) {
+        match self {
+            KernelVariant::Naive => (NAIVE_BLOCK_DIM, NAIVE_BLOCK_DIM, 1),
+            KernelVariant::Tiled => (tile.tile_n as u32, tile.tile_m as u32, 1),
+            KernelVariant::Wmma => (WMMA_WARPS_PER_BLOCK * 32, 1, 1),
+        }
+    }
+
+    /// Output tile (rows, cols) of C computed by one thread block.
+    pub fn block_tile(&self, tile: &TileConfig) -> (u32, u32) {
+        match self {
+            KernelVariant::Naive => (NAIVE_BLOCK_DIM, NAIVE_BLOCK_DIM),
+            KernelVariant::Tiled => (tile.tile_m as u32, tile.tile_n as u32),
+            KernelVariant::Wmma => (WMMA_BLOCK_TILE, WMMA_BLOCK_TILE),
+        }
+    }
+
+    /// Grid covering an `m x n` output with [`KernelVariant::block_tile`]s.
+    pub fn grid_dim(&self, m: u32, n: u32, tile: &TileConfig) -> (u32, u32, u32) {
+        let (tile_rows, tile_cols) = self.block_tile(tile);
+        (n.div_ceil(tile_cols), m.div_ceil(tile_rows), 1)
+    }
+
+    /// Bytes of `#[shared]` statics the kernel declares.
+    pub fn static_shared_mem_bytes(&self, tile: &TileConfig) -> u32 {
+        match self {
+            KernelVariant::Naive => 0,
+            KernelVariant::Tiled => tile.shared_mem_bytes(std::mem::size_of::<f32>()) as u32,
+            KernelVariant::Wmma => WMMA_SHARED_MEM_BYTES,
+        }
+    }
+
+    /// The launch the variant needs for an `m x n` output, ready to be
+    /// checked with [`crate::validate_launch`].
+    pub fn launch_config(&self, m: u32, n: u32, tile: &TileConfig) -> LaunchConfig {
+        LaunchConfig {
+            grid: self.grid_dim(m, n, tile),
+            block: self.block_dim(tile),
+            static_shared_mem_bytes: self.static_shared_mem_bytes(tile),
+            dynamic_shared_mem_bytes: 0,
+        }
+    }
+}
+
+impl fmt::Display for KernelVariant {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for KernelVariant {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "naive" | "gemm_kernel" => Ok(KernelVariant::Naive),
+            "tiled" | "gemm_kernel_tiled" => Ok(KernelVariant::Tiled),
+            "wmma" | "gemm_kernel_wmma" => Ok(KernelVariant::Wmma),
+            other => bail!("Unknown kernel variant `{}` (expected naive, tiled or wmma)", other),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_grid_covers_output() {
+        let tile = TileConfig::ampere_default();
+
+        assert_eq!(KernelVariant::Naive.grid_dim(100, 70, &tile), (5, 7, 1));
+        assert_eq!(KernelVariant::Tiled.grid_dim(1000, 300, &tile), (3, 8, 1));
+        assert_eq!(KernelVariant::Wmma.grid_dim(100, 130, &tile), (3, 2, 1));
+        assert_eq!(KernelVariant::Wmma.grid_dim(64, 64, &tile), (1, 1, 1));
+    }
+
+    #[test]
+    fn test_block_dims() {
+        let tile = TileConfig::ampere_default();
+
+        assert_eq!(KernelVariant::Naive.block_dim(&tile), (16, 16, 1));
+        assert_eq!(KernelVariant::Tiled.block_dim(&tile), (128, 128, 1));
+        assert_eq!(KernelVariant::Wmma.block_dim(&tile), (512, 1, 1));
+
+        assert_eq!(KernelVariant::Naive.static_shared_mem_bytes(&tile), 0);
+        assert_eq!(KernelVariant::Tiled.static_shared_mem_bytes(&tile), 16 * 1024);
+        assert_eq!(KernelVariant::Wmma.static_shared_mem_bytes(&tile), 8 * 1024);
+    }
+
+    #[test]
+    fn test_wmma_warps_cover_block_tile() {
+        // Uses the kernel's own warp placement from `utils::wmma`.
+        let tile = TileConfig::ampere_default();
+        let (rows, cols) = KernelVariant::Wmma.block_tile(&tile);
+        let warps = KernelVariant::Wmma.block_dim(&tile).0 / 32;
+
+        let mut written = vec![0; (rows * cols) as usize];
+        for warp_id in 0..warps {
+            let (row0, col0) = utils::wmma::wmma_warp_origin(warp_id);
+            for row in row0..row0 + WMMA_TILE {
+                for col in col0..col0 + WMMA_TILE {
+                    assert!(row < rows && col < cols, "warp {} writes outside the block tile", warp_id);
+                    written[(row * cols + col) as usize] += 1;
+                }
+            }
+        }
+        assert!(written.iter().all(|&count| count == 1));
+    }
+
+    #[test]
+    fn test_parse_and_capability() {
+        for variant in KernelVariant::ALL {
+            assert_eq!(variant.name().parse::<KernelVariant>().unwrap(), variant);
+            assert_eq!(variant.entry_point().parse::<KernelVariant>().unwrap(), variant);
+        }
+        assert!("cublas".parse::<KernelVariant>().is_err());
+
+        assert!(KernelVariant::Naive.supports((7, 5)));
+        assert!(!KernelVariant::Wmma.supports((7, 5)));
+        assert!(KernelVariant::Wmma.supports((9, 0)));
+    }
+
+    #[test]
+    fn test_unavailable_reason() {
+        let limits = DeviceLimits::sm80();
+        assert_eq!(KernelVariant::Naive.unavailable_reason((8, 0), &limits), None);
+        assert_eq!(KernelVariant::Wmma.unavailable_reason((8, 0), &limits), None);
+        assert_eq!(
+            KernelVariant::Wmma.unavailable_reason((7, 5), &limits).unwrap(),
+            "unsupported (needs compute capability 8.0)"
+        );
+        // The 128x128 thread block never fits.
+        let tiled = KernelVariant::Tiled.unavailable_reason((9, 0), &DeviceLimits::sm90()).unwrap();
+        assert!(tiled.starts_with("not launchable (block has 16384 threads"), "{}", tiled);
+    }
+}
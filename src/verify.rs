@@ -0,0 +1,440 @@
+use anyhow::{ensure, Result};
+use std::fmt;
+
+/// Decides how far a computed element may deviate from the reference.
+pub trait TolerancePolicy {
+    /// Largest accepted `|actual - expected|` for the element at
+    /// `(row, col)` whose reference value is `expected`.
+    fn allowed_error(&self, row: usize, col: usize, expected: f64) -> f64;
+
+    /// One-line description for reports.
+    fn describe(&self) -> String;
+}
+
+impl<T: TolerancePolicy + ?Sized> TolerancePolicy for &T {
+    fn allowed_error(&self, row: usize, col: usize, expected: f64) -> f64 {
+        (**self).allowed_error(row, col, expected)
+    }
+
+    fn describe(&self) -> String {
+        (**self).describe()
+    }
+}
+
+/// Fixed absolute error bound.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AbsoluteTolerance(pub f64);
+
+impl TolerancePolicy for AbsoluteTolerance {
+    fn allowed_error(&self, _row: usize, _col: usize, _expected: f64) -> f64 {
+        self.0
+    }
+
+    fn describe(&self) -> String {
+        format!("|error| <= {:e}", self.0)
+    }
+}
+
+/// `absolute + relative * |expected|`; the absolute part keeps values that
+/// cancel to near zero from failing on rounding noise.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RelativeTolerance {
+    pub relative: f64,
+    pub absolute: f64,
+}
+
+impl TolerancePolicy for RelativeTolerance {
+    fn allowed_error(&self, _row: usize, _col: usize, expected: f64) -> f64 {
+        self.absolute + self.relative * expected.abs()
+    }
+
+    fn describe(&self) -> String {
+        format!("|error| <= {:e} + {:e} * |expected|", self.absolute, self.relative)
+    }
+}
+
+/// Within `n` units in the last place of the f32 reference value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UlpTolerance(pub u32);
+
+impl TolerancePolicy for UlpTolerance {
+    fn allowed_error(&self, _row: usize, _col: usize, expected: f64) -> f64 {
+        self.0 as f64 * ulp(expected as f32)
+    }
+
+    fn describe(&self) -> String {
+        format!("within {} ULP", self.0)
+    }
+}
+
+/// Relative bound that grows linearly with the reduction length `k`, in
+/// multiples of f32 machine epsilon. Magnitudes below `floor` are treated as
+/// `floor`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct KScaledTolerance {
+    pub k: usize,
+    pub factor: f64,
+    pub floor: f64,
+}
+
+impl KScaledTolerance {
+    pub fn new(k: usize) -> Self {
+        Self { k, factor: 1.0, floor: 1.0 }
+    }
+}
+
+impl TolerancePolicy for KScaledTolerance {
+    fn allowed_error(&self, _row: usize, _col: usize, expected: f64) -> f64 {
+        self.factor * self.k.max(1) as f64 * f32::EPSILON as f64 * expected.abs().max(self.floor)
+    }
+
+    fn describe(&self) -> String {
+        format!("|error| <= {} * K({}) * eps_f32 * max(|expected|, {})", self.factor, self.k, self.floor)
+    }
+}
+
+/// Spacing between `x` and the next f32 away from zero.
+fn ulp(x: f32) -> f64 {
+    let x = x.abs();
+    if !x.is_finite() {
+        return f64::NAN;
+    }
+    let next = f32::from_bits(x.to_bits() + 1);
+    (next as f64) - (x as f64)
+}
+
+/// Maps f32 bit patterns onto a line where adjacent floats differ by one.
+fn ordered_bits(x: f32) -> i64 {
+    let bits = x.to_bits() as i32;
+    if bits < 0 {
+        (i32::MIN as i64) - bits as i64
+    } else {
+        bits as i64
+    }
+}
+
+fn ulp_distance(a: f32, b: f32) -> u64 {
+    ordered_bits(a).abs_diff(ordered_bits(b))
+}
+
+/// A single compared element.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ElementError {
+    pub row: usize,
+    pub col: usize,
+    pub expected: f64,
+    pub actual: f32,
+    pub abs_error: f64,
+    pub rel_error: f64,
+    pub ulps: u64,
+    pub allowed: f64,
+}
+
+impl ElementError {
+    /// How many times over its tolerance the element is; above 1 fails.
+    pub fn severity(&self) -> f64 {
+        if self.abs_error.is_nan() {
+            f64::INFINITY
+        } else if self.allowed > 0.0 {
+            self.abs_error / self.allowed
+        } else if self.abs_error > 0.0 {
+            f64::INFINITY
+        } else {
+            0.0
+        }
+    }
+}
+
+impl fmt::Display for ElementError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "({}, {}): expected {}, got {}, abs error {:.3e}, rel error {:.3e}, {} ULP (allowed {:.3e})",
+            self.row, self.col, self.expected, self.actual, self.abs_error, self.rel_error, self.ulps, self.allowed
+        )
+    }
+}
+
+/// Outcome of comparing a result matrix against a reference.
+#[derive(Debug, Clone, PartialEq)]
+pub struct VerificationReport {
+    pub rows: usize,
+    pub cols: usize,
+    pub tolerance: String,
+    pub max_abs_error: f64,
+    pub max_rel_error: f64,
+    pub max_ulps: u64,
+    pub rms_error: f64,
+    /// Elements outside tolerance, including NaN/Inf mismatches.
+    pub failures: usize,
+    /// Elements where exactly one side is NaN, or infinities disagree.
+    pub non_finite_mismatches: usize,
+    /// The most severe elements, worst first, whether or not they failed.
+    pub worst: Vec<ElementError>,
+}
+
+impl VerificationReport {
+    pub fn passed(&self) -> bool {
+        self.failures == 0
+    }
+
+    pub fn elements(&self) -> usize {
+        self.rows * self.cols
+    }
+
+    /// Panics with the full report if any element failed.
+    pub fn assert_passed(&self) {
+        assert!(self.passed(), "{}", self);
+    }
+}
+
+impl fmt::Display for VerificationReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "Verification {} ({} elements, {})",
+            if self.passed() { "PASSED" } else { "FAILED" },
+            self.elements(),
+            self.tolerance
+        )?;
+        writeln!(
+            f,
+            "  max abs error: {:.3e}, max rel error: {:.3e}, max ULP: {}, RMS error: {:.3e}",
+            self.max_abs_error, self.max_rel_error, self.max_ulps, self.rms_error
+        )?;
+        write!(
+            f,
+            "  failures: {}, NaN/Inf mismatches: {}",
+            self.failures, self.non_finite_mismatches
+        )?;
+        if !self.worst.is_empty() {
+            write!(f, "\n  worst elements:")?;
+            for element in &self.worst {
+                write!(f, "\n    {}", element)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Errors of a kernel result and of the f32 host reference, both measured
+/// against an f64 reference.
+///
+/// A kernel whose error is comparable to the f32 reference's is showing
+/// ordinary rounding drift; one far above it points at a kernel bug.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReferenceComparison {
+    pub kernel: VerificationReport,
+    pub f32_reference: VerificationReport,
+}
+
+impl ReferenceComparison {
+    pub fn passed(&self) -> bool {
+        self.kernel.passed()
+    }
+
+    /// Kernel max absolute error over the f32 reference's.
+    pub fn drift_ratio(&self) -> f64 {
+        let (kernel, reference) = (self.kernel.max_abs_error, self.f32_reference.max_abs_error);
+        if reference > 0.0 {
+            kernel / reference
+        } else if kernel > 0.0 {
+            f64::INFINITY
+        } else {
+            0.0
+        }
+    }
+}
+
+impl fmt::Display for ReferenceComparison {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Kernel vs f64 reference:")?;
+        writeln!(f, "{}", self.kernel)?;
+        writeln!(f, "f32 reference vs f64 reference:")?;
+        writeln!(f, "{}", self.f32_reference)?;
+        write!(f, "Kernel error is {:.2}x the f32 reference error", self.drift_ratio())
+    }
+}
+
+/// Compares result matrices against a reference under a [`TolerancePolicy`].
+#[derive(Debug, Clone)]
+pub struct Verifier<P> {
+    policy: P,
+    worst: usize,
+}
+
+impl<P: TolerancePolicy> Verifier<P> {
+    pub fn new(policy: P) -> Self {
+        Self { policy, worst: 5 }
+    }
+
+    /// Number of worst elements kept in the report.
+    pub fn with_worst(mut self, count: usize) -> Self {
+        self.worst = count;
+        self
+    }
+
+    /// Compares dense row-major `rows x cols` matrices. The reference may be
+    /// f32 or a higher-precision type.
+    pub fn compare<E: Copy + Into<f64>>(
+        &self,
+        rows: usize,
+        cols: usize,
+        expected: &[E],
+        actual: &[f32],
+    ) -> Result<VerificationReport> {
+        ensure!(
+            expected.len() == rows * cols && actual.len() == rows * cols,
+            "Expected {}x{} = {} elements, got reference of {} and result of {}",
+            rows,
+            cols,
+            rows * cols,
+            expected.len(),
+            actual.len()
+        );
+
+        let mut report = VerificationReport {
+            rows,
+            cols,
+            tolerance: self.policy.describe(),
+            max_abs_error: 0.0,
+            max_rel_error: 0.0,
+            max_ulps: 0,
+            rms_error: 0.0,
+            failures: 0,
+            non_finite_mismatches: 0,
+            worst: Vec::with_capacity(self.worst + 1),
+        };
+        let mut sum_squares = 0.0;
+        let mut finite = 0usize;
+
+        for (idx, (&expected, &actual)) in expected.iter().zip(actual).enumerate() {
+            let expected: f64 = expected.into();
+            let (row, col) = (idx / cols, idx % cols);
+
+            let element = if expected.is_finite() && actual.is_finite() {
+                let abs_error = (actual as f64 - expected).abs();
+                let rel_error = if expected != 0.0 {
+                    abs_error / expected.abs()
+                } else if abs_error == 0.0 {
+                    0.0
+                } else {
+                    f64::INFINITY
+                };
+                let ulps = ulp_distance(actual, expected as f32);
+
+                report.max_abs_error = report.max_abs_error.max(abs_error);
+                report.max_rel_error = report.max_rel_error.max(rel_error);
+                report.max_ulps = report.max_ulps.max(ulps);
+                sum_squares += abs_error * abs_error;
+                finite += 1;
+
+                ElementError {
+                    row,
+                    col,
+                    expected,
+                    actual,
+                    abs_error,
+                    rel_error,
+                    ulps,
+                    allowed: self.policy.allowed_error(row, col, expected),
+                }
+            } else {
+                let matches = (expected.is_nan() && actual.is_nan()) || expected == actual as f64;
+                if !matches {
+                    report.non_finite_mismatches += 1;
+                }
+                ElementError {
+                    row,
+                    col,
+                    expected,
+                    actual,
+                    abs_error: if matches { 0.0 } else { f64::NAN },
+                    rel_error: if matches { 0.0 } else { f64::NAN },
+                    ulps: if matches { 0 } else { u64::MAX },
+                    allowed: 0.0,
+                }
+            };
+
+            let severity = element.severity();
+            if severity > 1.0 {
+                report.failures += 1;
+            }
+            self.track_worst(&mut report.worst, element, severity);
+        }
+
+        if finite > 0 {
+            report.rms_error = (sum_squares / finite as f64).sqrt();
+        }
+        Ok(report)
+    }
+
+    fn track_worst(&self, worst: &mut Vec<ElementError>, element: ElementError, severity: f64) {
+        if self.worst == 0 || severity == 0.0 {
+            return;
+        }
+        if worst.len() == self.worst && severity <= worst[worst.len() - 1].severity() {
+            return;
+        }
+        let pos = worst.partition_point(|e| e.severity() >= severity);
+        worst.insert(pos, element);
+        worst.truncate(self.worst);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_policies() {
+        let expected = [1.0f32, 100.0, -3.0, 0.0];
+        let actual = [1.0f32, 100.01, -3.0000002, 1e-7];
+
+        let report = Verifier::new(AbsoluteTolerance(1e-3)).compare(2, 2, &expected, &actual).unwrap();
+        assert_eq!(report.failures, 1);
+        assert_eq!((report.worst[0].row, report.worst[0].col), (0, 1));
+        assert!((report.max_abs_error - 0.01).abs() < 1e-5);
+        assert_eq!(report.max_rel_error, f64::INFINITY);
+
+        let relative = RelativeTolerance { relative: 1e-3, absolute: 1e-6 };
+        assert!(Verifier::new(relative).compare(2, 2, &expected, &actual).unwrap().passed());
+
+        // -3.0000002 is one f32 step from -3.0; 100.01 is far more.
+        let report = Verifier::new(UlpTolerance(1)).compare(2, 2, &expected, &actual).unwrap();
+        assert_eq!(report.worst[report.worst.len() - 1].ulps, 1);
+        assert_eq!(report.failures, 2);
+
+        let loose = KScaledTolerance { k: 4096, factor: 1.0, floor: 1.0 };
+        assert!(Verifier::new(loose).compare(2, 2, &expected, &actual).unwrap().passed());
+        assert!(!Verifier::new(KScaledTolerance::new(1)).compare(2, 2, &expected, &actual).unwrap().passed());
+    }
+
+    #[test]
+    fn test_non_finite_and_worst_order() {
+        let expected = [f64::NAN, f64::INFINITY, 1.0, 2.0, 3.0, 4.0];
+        let actual = [f32::NAN, 1.0, 1.5, 2.0, 3.25, f32::NAN];
+
+        let report = Verifier::new(AbsoluteTolerance(0.3))
+            .with_worst(3)
+            .compare(2, 3, &expected, &actual)
+            .unwrap();
+        assert_eq!(report.non_finite_mismatches, 2);
+        assert_eq!(report.failures, 3);
+        assert_eq!(report.max_abs_error, 0.5);
+        assert_eq!(report.worst.len(), 3);
+        assert!(report.worst[..2].iter().all(|e| e.severity().is_infinite()));
+        assert_eq!((report.worst[2].row, report.worst[2].col), (0, 2));
+        assert!(report.to_string().starts_with("Verification FAILED (6 elements"));
+
+        assert!(Verifier::new(AbsoluteTolerance(0.0)).compare(2, 2, &[0.0f32; 3], &[0.0; 4]).is_err());
+    }
+
+    #[test]
+    fn test_ulp_helpers() {
+        assert_eq!(ulp_distance(1.0, 1.0), 0);
+        assert_eq!(ulp_distance(-0.0, 0.0), 0);
+        assert_eq!(ulp_distance(f32::from_bits(1), -f32::from_bits(1)), 2);
+        assert_eq!(ulp(1.0), f32::EPSILON as f64);
+    }
+}
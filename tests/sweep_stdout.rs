@@ -0,0 +1,36 @@
+//! `sweep` must write nothing but its rows to stdout, so its output can be
+//! redirected straight into a CSV or JSON file.
+
+use std::process::Command;
+
+fn sweep(format: &str) -> (String, String) {
+    let output = Command::new(env!("CARGO_BIN_EXE_gemm-optimizer"))
+        .args(["sweep", "--backend", "cpu", "--shapes", "8x8x8,16x8x4", "--variants", "naive"])
+        .args(["--iterations", "2", "--warmup", "0", "--format", format])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    (String::from_utf8(output.stdout).unwrap(), String::from_utf8(output.stderr).unwrap())
+}
+
+#[test]
+fn test_csv_rows_are_the_only_stdout() {
+    let (stdout, stderr) = sweep("csv");
+    let lines: Vec<&str> = stdout.lines().collect();
+    assert_eq!(lines.len(), 3, "{}", stdout);
+    assert!(lines[0].starts_with("m,n,k,variant,"), "{}", lines[0]);
+    let columns = lines[0].split(',').count();
+    for line in &lines[1..] {
+        assert_eq!(line.split(',').count(), columns, "{}", line);
+    }
+    // Backend selection and progress go to stderr.
+    assert!(stderr.contains("Using CPU backend"), "{}", stderr);
+    assert!(stderr.contains("Sweeping 2 shape(s)"), "{}", stderr);
+}
+
+#[test]
+fn test_json_rows_are_the_only_stdout() {
+    let (stdout, _) = sweep("json");
+    assert!(stdout.starts_with("[\n") && stdout.ends_with("]\n"), "{}", stdout);
+    assert_eq!(stdout.matches("\"variant\": \"naive\"").count(), 2, "{}", stdout);
+}
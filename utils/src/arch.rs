@@ -0,0 +1,530 @@
+//! Per-architecture facts keyed by compute capability: SM resources for the
+//! occupancy math, tensor-core instruction shapes, default tile configs and
+//! published peak throughput of common SKUs.
+
+use std::fmt;
+
+use crate::occupancy::SmResources;
+use crate::tensor_defs::TileConfig;
+
+/// Element types the tensor cores (or CUDA cores, for F32/F64) operate on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DataType {
+    F64,
+    F32,
+    Tf32,
+    F16,
+    Bf16,
+    Fp8E4M3,
+    Fp8E5M2,
+    Fp4E2M1,
+    Int8,
+    Int4,
+    Int32,
+}
+
+impl DataType {
+    pub const fn bits(&self) -> u32 {
+        match self {
+            DataType::F64 => 64,
+            DataType::F32 | DataType::Int32 => 32,
+            // TF32 occupies a full 32-bit register even though only 19 bits
+            // take part in the multiply.
+            DataType::Tf32 => 32,
+            DataType::F16 | DataType::Bf16 => 16,
+            DataType::Fp8E4M3 | DataType::Fp8E5M2 | DataType::Int8 => 8,
+            DataType::Fp4E2M1 | DataType::Int4 => 4,
+        }
+    }
+
+    pub const fn name(&self) -> &'static str {
+        match self {
+            DataType::F64 => "f64",
+            DataType::F32 => "f32",
+            DataType::Tf32 => "tf32",
+            DataType::F16 => "f16",
+            DataType::Bf16 => "bf16",
+            DataType::Fp8E4M3 => "e4m3",
+            DataType::Fp8E5M2 => "e5m2",
+            DataType::Fp4E2M1 => "e2m1",
+            DataType::Int8 => "s8",
+            DataType::Int4 => "s4",
+            DataType::Int32 => "s32",
+        }
+    }
+
+    /// Significand precision in bits, including the implicit leading bit.
+    /// `None` for integer types.
+    pub const fn significand_bits(&self) -> Option<u32> {
+        match self {
+            DataType::F64 => Some(53),
+            DataType::F32 => Some(24),
+            DataType::Tf32 | DataType::F16 => Some(11),
+            DataType::Bf16 => Some(8),
+            DataType::Fp8E4M3 => Some(4),
+            DataType::Fp8E5M2 => Some(3),
+            DataType::Fp4E2M1 => Some(2),
+            DataType::Int8 | DataType::Int4 | DataType::Int32 => None,
+        }
+    }
+
+    /// Unit roundoff `u = 2^-t` for round-to-nearest, where `t` is
+    /// [`DataType::significand_bits`]. `None` for integer types.
+    pub fn unit_roundoff(&self) -> Option<f64> {
+        self.significand_bits().map(|t| 2f64.powi(-(t as i32)))
+    }
+}
+
+impl fmt::Display for DataType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// One tensor-core MMA instruction shape (`mma.sync`, `wgmma` or `tcgen05`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MmaShape {
+    pub m: u32,
+    pub n: u32,
+    pub k: u32,
+    pub input: DataType,
+    pub accumulator: DataType,
+}
+
+const fn mma(m: u32, n: u32, k: u32, input: DataType, accumulator: DataType) -> MmaShape {
+    MmaShape { m, n, k, input, accumulator }
+}
+
+/// A product built on an architecture, with its published dense (no
+/// sparsity) peak throughput. F64 is the tensor-core rate where the
+/// architecture has one, since that is what DGEMM runs on.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GpuSku {
+    pub name: &'static str,
+    /// Substring of the driver-reported device name identifying the SKU.
+    /// SKUs are matched in table order, so more specific patterns come first.
+    pub device_name_pattern: &'static str,
+    pub sm_count: u32,
+    pub memory_bandwidth_gbs: f64,
+    pub peak_tflops: &'static [(DataType, f64)],
+}
+
+impl GpuSku {
+    pub fn peak_tflops(&self, dtype: DataType) -> Option<f64> {
+        self.peak_tflops
+            .iter()
+            .find(|(t, _)| *t == dtype)
+            .map(|&(_, tflops)| tflops)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GpuArch {
+    pub name: &'static str,
+    pub compute_capability: (u32, u32),
+    pub sm: SmResources,
+    pub mma_shapes: &'static [MmaShape],
+    pub default_tile: TileConfig,
+    pub skus: &'static [GpuSku],
+}
+
+impl GpuArch {
+    pub fn all() -> &'static [GpuArch] {
+        &GPU_ARCHS
+    }
+
+    /// Exact lookup by compute capability.
+    pub fn lookup(major: u32, minor: u32) -> Option<&'static GpuArch> {
+        GPU_ARCHS.iter().find(|arch| arch.compute_capability == (major, minor))
+    }
+
+    /// The closest known architecture at or below `(major, minor)` with the
+    /// same major version, e.g. sm_87 (Orin) resolves to sm_86.
+    pub fn for_compute_capability(major: u32, minor: u32) -> Option<&'static GpuArch> {
+        GPU_ARCHS
+            .iter()
+            .filter(|arch| arch.compute_capability.0 == major && arch.compute_capability.1 <= minor)
+            .max_by_key(|arch| arch.compute_capability.1)
+    }
+
+    /// `sm_XY` target name.
+    pub fn target(&self) -> String {
+        format!("sm_{}{}", self.compute_capability.0, self.compute_capability.1)
+    }
+
+    pub fn supports(&self, dtype: DataType) -> bool {
+        matches!(dtype, DataType::F32 | DataType::F64)
+            || self.mma_shapes.iter().any(|shape| shape.input == dtype)
+    }
+
+    /// Input types with tensor-core support, in table order.
+    pub fn tensor_core_types(&self) -> Vec<DataType> {
+        let mut types = Vec::new();
+        for shape in self.mma_shapes {
+            if !types.contains(&shape.input) {
+                types.push(shape.input);
+            }
+        }
+        types
+    }
+
+    /// The first SKU whose pattern occurs as whole words in a
+    /// driver-reported device name, so "L4" does not match "NVIDIA L40".
+    /// Words are separated by spaces or dashes, as in "A100-SXM4-80GB".
+    pub fn sku(&self, device_name: &str) -> Option<&'static GpuSku> {
+        let device = name_words(device_name);
+        self.skus.iter().find(|sku| {
+            let pattern = name_words(sku.device_name_pattern);
+            device.windows(pattern.len()).any(|window| window == pattern)
+        })
+    }
+}
+
+fn name_words(name: &str) -> Vec<&str> {
+    name.split([' ', '-']).filter(|word| !word.is_empty()).collect()
+}
+
+impl fmt::Display for GpuArch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({})", self.name, self.target())
+    }
+}
+
+const fn sm_resources(
+    max_warps_per_sm: u32,
+    max_blocks_per_sm: u32,
+    shared_mem_per_sm_kb: u32,
+    max_shared_mem_per_block_kb: u32,
+    shared_mem_alloc_unit: u32,
+    reserved_shared_mem_per_block: u32,
+) -> SmResources {
+    SmResources {
+        warp_size: 32,
+        max_threads_per_block: 1024,
+        max_warps_per_sm,
+        max_blocks_per_sm,
+        registers_per_sm: 65536,
+        max_registers_per_thread: 255,
+        register_alloc_unit: 256,
+        register_file_partitions: 4,
+        shared_mem_per_sm: shared_mem_per_sm_kb * 1024,
+        max_shared_mem_per_block: max_shared_mem_per_block_kb * 1024,
+        shared_mem_alloc_unit,
+        reserved_shared_mem_per_block,
+    }
+}
+
+use DataType::*;
+
+const VOLTA_MMA: &[MmaShape] = &[mma(8, 8, 4, F16, F32)];
+
+const TURING_MMA: &[MmaShape] = &[
+    mma(16, 8, 8, F16, F32),
+    mma(8, 8, 16, Int8, Int32),
+    mma(8, 8, 32, Int4, Int32),
+];
+
+const AMPERE_MMA: &[MmaShape] = &[
+    mma(16, 8, 16, F16, F32),
+    mma(16, 8, 16, Bf16, F32),
+    mma(16, 8, 8, Tf32, F32),
+    mma(8, 8, 4, F64, F64),
+    mma(16, 8, 32, Int8, Int32),
+    mma(16, 8, 64, Int4, Int32),
+];
+
+const ADA_MMA: &[MmaShape] = &[
+    mma(16, 8, 16, F16, F32),
+    mma(16, 8, 16, Bf16, F32),
+    mma(16, 8, 8, Tf32, F32),
+    mma(16, 8, 32, Fp8E4M3, F32),
+    mma(16, 8, 32, Fp8E5M2, F32),
+    mma(16, 8, 32, Int8, Int32),
+    mma(16, 8, 64, Int4, Int32),
+];
+
+/// Largest `wgmma` shapes; N ranges over multiples of 8 up to 256.
+const HOPPER_MMA: &[MmaShape] = &[
+    mma(64, 256, 16, F16, F32),
+    mma(64, 256, 16, Bf16, F32),
+    mma(64, 256, 8, Tf32, F32),
+    mma(64, 256, 32, Fp8E4M3, F32),
+    mma(64, 256, 32, Fp8E5M2, F32),
+    mma(64, 256, 32, Int8, Int32),
+    mma(16, 8, 4, F64, F64),
+];
+
+/// Single-CTA `tcgen05.mma` shapes.
+const BLACKWELL_MMA: &[MmaShape] = &[
+    mma(128, 256, 16, F16, F32),
+    mma(128, 256, 16, Bf16, F32),
+    mma(128, 256, 8, Tf32, F32),
+    mma(128, 256, 32, Fp8E4M3, F32),
+    mma(128, 256, 32, Fp8E5M2, F32),
+    mma(128, 256, 64, Fp4E2M1, F32),
+    mma(128, 256, 32, Int8, Int32),
+];
+
+/// Consumer Blackwell keeps warp-level `mma.sync` and adds FP4.
+const BLACKWELL_CONSUMER_MMA: &[MmaShape] = &[
+    mma(16, 8, 16, F16, F32),
+    mma(16, 8, 16, Bf16, F32),
+    mma(16, 8, 8, Tf32, F32),
+    mma(16, 8, 32, Fp8E4M3, F32),
+    mma(16, 8, 32, Fp8E5M2, F32),
+    mma(16, 8, 64, Fp4E2M1, F32),
+    mma(16, 8, 32, Int8, Int32),
+];
+
+/// Volta and Turing have no `cp.async`, so the tile is kept shallow.
+const VOLTA_TILE: TileConfig = TileConfig {
+    tile_m: 128,
+    tile_n: 128,
+    tile_k: 32,
+    warp_m: 64,
+    warp_n: 64,
+    warp_k: 32,
+};
+
+static GPU_ARCHS: [GpuArch; 8] = [
+    GpuArch {
+        name: "Volta",
+        compute_capability: (7, 0),
+        sm: sm_resources(64, 32, 96, 96, 256, 0),
+        mma_shapes: VOLTA_MMA,
+        default_tile: VOLTA_TILE,
+        skus: &[GpuSku {
+            name: "Tesla V100 SXM2",
+            device_name_pattern: "V100",
+            sm_count: 80,
+            memory_bandwidth_gbs: 900.0,
+            peak_tflops: &[(F64, 7.8), (F32, 15.7), (F16, 125.0)],
+        }],
+    },
+    GpuArch {
+        name: "Turing",
+        compute_capability: (7, 5),
+        sm: sm_resources(32, 16, 64, 64, 256, 0),
+        mma_shapes: TURING_MMA,
+        default_tile: VOLTA_TILE,
+        skus: &[
+            GpuSku {
+                name: "Tesla T4",
+                device_name_pattern: "T4",
+                sm_count: 40,
+                memory_bandwidth_gbs: 320.0,
+                peak_tflops: &[(F32, 8.1), (F16, 65.0), (Int8, 130.0)],
+            },
+            GpuSku {
+                name: "GeForce RTX 2080 Ti",
+                device_name_pattern: "2080 Ti",
+                sm_count: 68,
+                memory_bandwidth_gbs: 616.0,
+                peak_tflops: &[(F32, 13.4), (F16, 53.8)],
+            },
+        ],
+    },
+    GpuArch {
+        name: "Ampere",
+        compute_capability: (8, 0),
+        sm: sm_resources(64, 32, 164, 163, 128, 1024),
+        mma_shapes: AMPERE_MMA,
+        default_tile: TileConfig::ampere_default(),
+        skus: &[GpuSku {
+            name: "A100 SXM4 80GB",
+            device_name_pattern: "A100",
+            sm_count: 108,
+            memory_bandwidth_gbs: 2039.0,
+            peak_tflops: &[
+                (F64, 19.5),
+                (F32, 19.5),
+                (Tf32, 156.0),
+                (F16, 312.0),
+                (Bf16, 312.0),
+                (Int8, 624.0),
+            ],
+        }],
+    },
+    GpuArch {
+        name: "Ampere (GA10x)",
+        compute_capability: (8, 6),
+        sm: sm_resources(48, 16, 100, 99, 128, 1024),
+        mma_shapes: AMPERE_MMA,
+        default_tile: TileConfig::ampere_default(),
+        skus: &[
+            GpuSku {
+                name: "A10",
+                device_name_pattern: "A10",
+                sm_count: 72,
+                memory_bandwidth_gbs: 600.0,
+                peak_tflops: &[(F32, 31.2), (Tf32, 62.5), (F16, 125.0), (Bf16, 125.0)],
+            },
+            GpuSku {
+                name: "GeForce RTX 3090",
+                device_name_pattern: "3090",
+                sm_count: 82,
+                memory_bandwidth_gbs: 936.0,
+                peak_tflops: &[(F32, 35.6), (Tf32, 35.6), (F16, 71.0), (Bf16, 71.0)],
+            },
+        ],
+    },
+    GpuArch {
+        name: "Ada Lovelace",
+        compute_capability: (8, 9),
+        sm: sm_resources(48, 24, 100, 99, 128, 1024),
+        mma_shapes: ADA_MMA,
+        default_tile: TileConfig::ampere_default(),
+        skus: &[
+            GpuSku {
+                name: "L40S",
+                device_name_pattern: "L40S",
+                sm_count: 142,
+                memory_bandwidth_gbs: 864.0,
+                peak_tflops: &[(F32, 91.6), (Tf32, 183.0), (F16, 362.0), (Bf16, 362.0), (Fp8E4M3, 733.0)],
+            },
+            GpuSku {
+                name: "L4",
+                device_name_pattern: "L4",
+                sm_count: 58,
+                memory_bandwidth_gbs: 300.0,
+                peak_tflops: &[(F32, 30.3), (Tf32, 60.0), (F16, 121.0), (Bf16, 121.0), (Fp8E4M3, 242.5)],
+            },
+            GpuSku {
+                name: "GeForce RTX 4090",
+                device_name_pattern: "4090",
+                sm_count: 128,
+                memory_bandwidth_gbs: 1008.0,
+                peak_tflops: &[(F32, 82.6), (Tf32, 82.6), (F16, 165.2), (Bf16, 165.2), (Fp8E4M3, 330.3)],
+            },
+        ],
+    },
+    GpuArch {
+        name: "Hopper",
+        compute_capability: (9, 0),
+        sm: sm_resources(64, 32, 228, 227, 128, 1024),
+        mma_shapes: HOPPER_MMA,
+        default_tile: TileConfig::hopper_default(),
+        skus: &[
+            GpuSku {
+                name: "H100 PCIe",
+                device_name_pattern: "H100 PCIe",
+                sm_count: 114,
+                memory_bandwidth_gbs: 2000.0,
+                peak_tflops: &[
+                    (F64, 51.2),
+                    (F32, 51.2),
+                    (Tf32, 378.0),
+                    (F16, 756.0),
+                    (Bf16, 756.0),
+                    (Fp8E4M3, 1513.0),
+                ],
+            },
+            GpuSku {
+                name: "H100 SXM5",
+                device_name_pattern: "H100",
+                sm_count: 132,
+                memory_bandwidth_gbs: 3350.0,
+                peak_tflops: &[
+                    (F64, 67.0),
+                    (F32, 67.0),
+                    (Tf32, 494.7),
+                    (F16, 989.4),
+                    (Bf16, 989.4),
+                    (Fp8E4M3, 1978.9),
+                ],
+            },
+        ],
+    },
+    GpuArch {
+        name: "Blackwell",
+        compute_capability: (10, 0),
+        sm: sm_resources(64, 32, 228, 227, 128, 1024),
+        mma_shapes: BLACKWELL_MMA,
+        default_tile: TileConfig::hopper_default(),
+        skus: &[GpuSku {
+            name: "B200",
+            device_name_pattern: "B200",
+            sm_count: 148,
+            memory_bandwidth_gbs: 8000.0,
+            peak_tflops: &[
+                (F64, 40.0),
+                (F32, 80.0),
+                (Tf32, 1100.0),
+                (F16, 2250.0),
+                (Bf16, 2250.0),
+                (Fp8E4M3, 4500.0),
+                (Fp4E2M1, 9000.0),
+            ],
+        }],
+    },
+    GpuArch {
+        name: "Blackwell (consumer)",
+        compute_capability: (12, 0),
+        sm: sm_resources(48, 32, 100, 99, 128, 1024),
+        mma_shapes: BLACKWELL_CONSUMER_MMA,
+        default_tile: TileConfig::ampere_default(),
+        skus: &[GpuSku {
+            name: "GeForce RTX 5090",
+            device_name_pattern: "5090",
+            sm_count: 170,
+            memory_bandwidth_gbs: 1792.0,
+            peak_tflops: &[(F32, 104.8), (Tf32, 104.8), (F16, 209.5), (Bf16, 209.5), (Fp8E4M3, 419.0)],
+        }],
+    },
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lookup() {
+        let a100 = GpuArch::lookup(8, 0).unwrap();
+        assert_eq!(a100.name, "Ampere");
+        assert_eq!(a100.to_string(), "Ampere (sm_80)");
+        assert_eq!(a100.sm.shared_mem_per_sm, 164 * 1024);
+        assert!(GpuArch::lookup(8, 7).is_none());
+        assert_eq!(GpuArch::for_compute_capability(8, 7).unwrap().compute_capability, (8, 6));
+        assert!(GpuArch::for_compute_capability(6, 1).is_none());
+
+        // The table is sorted and has no duplicates.
+        for pair in GpuArch::all().windows(2) {
+            assert!(pair[0].compute_capability < pair[1].compute_capability);
+        }
+    }
+
+    #[test]
+    fn test_data_types() {
+        let volta = GpuArch::lookup(7, 0).unwrap();
+        assert!(volta.supports(F16));
+        assert!(!volta.supports(Bf16));
+
+        let hopper = GpuArch::lookup(9, 0).unwrap();
+        assert!(hopper.supports(Fp8E4M3));
+        assert!(!hopper.supports(Fp4E2M1));
+        assert_eq!(hopper.tensor_core_types()[..3], [F16, Bf16, Tf32]);
+        assert!(GpuArch::lookup(12, 0).unwrap().supports(Fp4E2M1));
+
+        assert_eq!(F32.unit_roundoff(), Some(f32::EPSILON as f64 / 2.0));
+        assert_eq!(F64.unit_roundoff(), Some(f64::EPSILON / 2.0));
+        assert_eq!(Bf16.unit_roundoff(), Some(1.0 / 256.0));
+        assert_eq!(Int8.unit_roundoff(), None);
+    }
+
+    #[test]
+    fn test_sku_match() {
+        let hopper = GpuArch::lookup(9, 0).unwrap();
+        assert_eq!(hopper.sku("NVIDIA H100 PCIe").unwrap().sm_count, 114);
+        assert_eq!(hopper.sku("NVIDIA H100 80GB HBM3").unwrap().sm_count, 132);
+        assert!(hopper.sku("NVIDIA GH200").is_none());
+
+        let ada = GpuArch::lookup(8, 9).unwrap();
+        assert_eq!(ada.sku("NVIDIA L40S").unwrap().name, "L40S");
+        assert_eq!(ada.sku("NVIDIA L4").unwrap().name, "L4");
+        assert_eq!(ada.sku("NVIDIA L4").unwrap().peak_tflops(F32), Some(30.3));
+        assert!(ada.sku("NVIDIA L40").is_none());
+        assert_eq!(GpuArch::lookup(8, 6).unwrap().sku("NVIDIA A10").unwrap().name, "A10");
+        assert_eq!(GpuArch::lookup(8, 0).unwrap().sku("NVIDIA A100-SXM4-80GB").unwrap().device_name_pattern, "A100");
+        assert!(GpuArch::lookup(8, 6).unwrap().sku("NVIDIA A100").is_none());
+    }
+}
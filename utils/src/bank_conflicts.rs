@@ -0,0 +1,199 @@
+//! Shared-memory bank conflicts of one warp-wide access, so tile layouts can
+//! be checked on the host before a kernel uses them.
+//!
+//! Shared memory is 32 banks of 4-byte words. A warp's request is served in
+//! wavefronts: each wavefront reads at most one word per bank, and lanes
+//! reading the same word share it. Accesses wider than 4 bytes are split
+//! into phases of 128 bytes (half-warps for 8 bytes, quarter-warps for 16)
+//! that are served one after the other.
+
+use std::fmt;
+
+use crate::tensor_defs::TensorLayout;
+
+pub const WARP_SIZE: usize = 32;
+pub const BANKS: usize = 32;
+pub const BANK_BYTES: usize = 4;
+
+/// Cost of one warp's shared-memory request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BankReport {
+    pub wavefronts: usize,
+    /// Wavefronts the same request would need without conflicts: one per
+    /// phase with an active lane.
+    pub ideal_wavefronts: usize,
+    /// Most distinct words any bank serves in one phase, the `n` of an
+    /// `n`-way conflict.
+    pub max_ways: usize,
+}
+
+impl BankReport {
+    /// Extra wavefronts caused by conflicts, as Nsight Compute counts them.
+    pub fn conflicts(&self) -> usize {
+        self.wavefronts - self.ideal_wavefronts
+    }
+
+    pub fn is_conflict_free(&self) -> bool {
+        self.conflicts() == 0
+    }
+}
+
+impl fmt::Display for BankReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} wavefronts ({} ideal)", self.wavefronts, self.ideal_wavefronts)?;
+        if self.max_ways > 1 {
+            write!(f, ", {}-way conflict", self.max_ways)?;
+        }
+        Ok(())
+    }
+}
+
+/// Analyzes a request in which lane `i` accesses the `elem_bytes`-sized
+/// element at `offsets[i]`, or nothing if it is `None`. Lanes past the end
+/// of `offsets` are inactive.
+///
+/// Panics unless `elem_bytes` is 1, 2, 4, 8 or 16 and there are at most
+/// [`WARP_SIZE`] lanes.
+pub fn bank_conflicts(offsets: &[Option<usize>], elem_bytes: usize) -> BankReport {
+    assert!(
+        matches!(elem_bytes, 1 | 2 | 4 | 8 | 16),
+        "Element size must be 1, 2, 4, 8 or 16 bytes, got {}",
+        elem_bytes
+    );
+    assert!(offsets.len() <= WARP_SIZE, "{} lanes exceed a warp", offsets.len());
+
+    let lanes_per_phase = WARP_SIZE * BANK_BYTES / elem_bytes.max(BANK_BYTES);
+    let words_per_elem = elem_bytes.div_ceil(BANK_BYTES);
+    let mut report = BankReport { wavefronts: 0, ideal_wavefronts: 0, max_ways: 0 };
+    for phase in offsets.chunks(lanes_per_phase) {
+        let mut words: Vec<usize> = phase
+            .iter()
+            .flatten()
+            .flat_map(|&offset| {
+                let first = offset * elem_bytes / BANK_BYTES;
+                first..first + words_per_elem
+            })
+            .collect();
+        if words.is_empty() {
+            continue;
+        }
+        words.sort_unstable();
+        words.dedup();
+
+        let mut per_bank = [0; BANKS];
+        for word in words {
+            per_bank[word % BANKS] += 1;
+        }
+        let ways = per_bank.into_iter().max().unwrap_or(0);
+        report.wavefronts += ways;
+        report.ideal_wavefronts += 1;
+        report.max_ways = report.max_ways.max(ways);
+    }
+    report
+}
+
+/// [`bank_conflicts`] for a request in which lane `i` accesses element
+/// `access(i)` of a matrix stored with `layout`.
+pub fn layout_bank_conflicts(
+    layout: &TensorLayout,
+    elem_bytes: usize,
+    access: impl Fn(usize) -> Option<(usize, usize)>,
+) -> BankReport {
+    let offsets: Vec<_> = (0..WARP_SIZE)
+        .map(|lane| access(lane).map(|(row, col)| layout.index(row, col)))
+        .collect();
+    bank_conflicts(&offsets, elem_bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layout::{Layout, Swizzle, SwizzledLayout};
+
+    fn strided(stride: usize) -> Vec<Option<usize>> {
+        (0..WARP_SIZE).map(|lane| Some(lane * stride)).collect()
+    }
+
+    #[test]
+    fn test_access_patterns() {
+        let contiguous = bank_conflicts(&strided(1), 4);
+        assert_eq!(contiguous, BankReport { wavefronts: 1, ideal_wavefronts: 1, max_ways: 1 });
+        assert!(contiguous.is_conflict_free());
+
+        assert!(bank_conflicts(&strided(0), 4).is_conflict_free());
+        assert!(bank_conflicts(&strided(3), 4).is_conflict_free());
+        assert_eq!(bank_conflicts(&strided(2), 4).max_ways, 2);
+        let column = bank_conflicts(&strided(32), 4);
+        assert_eq!((column.wavefronts, column.conflicts()), (32, 31));
+        assert_eq!(column.to_string(), "32 wavefronts (1 ideal), 32-way conflict");
+
+        // Wide elements are served a half or quarter warp at a time.
+        let wide = bank_conflicts(&strided(1), 8);
+        assert_eq!((wide.wavefronts, wide.ideal_wavefronts), (2, 2));
+        assert_eq!(bank_conflicts(&strided(1), 16).wavefronts, 4);
+        assert_eq!(bank_conflicts(&strided(2), 8).max_ways, 2);
+        // Narrow elements share words.
+        assert_eq!(bank_conflicts(&strided(1), 2).wavefronts, 1);
+        assert_eq!(bank_conflicts(&strided(64), 2).max_ways, 32);
+
+        let mut half = strided(32);
+        half[16..].fill(None);
+        assert_eq!(bank_conflicts(&half, 4).wavefronts, 16);
+        assert_eq!(bank_conflicts(&[], 4), BankReport { wavefronts: 0, ideal_wavefronts: 0, max_ways: 0 });
+        assert_eq!(bank_conflicts(&[None, Some(7)], 8).ideal_wavefronts, 1);
+    }
+
+    #[test]
+    fn test_tiled_kernel_tile_a() {
+        // gemm_kernel_tiled's TILE_A is f32[128][16]. Its loads and the
+        // k-loop are row accesses, but a warp walking down a column (as a
+        // transposed load would) hits two banks 16 times each.
+        let plain = TensorLayout::row_major(128, 16);
+        let down_column = |lane: usize| Some((lane, 3));
+        let along_row = |lane: usize| (lane < 16).then_some((5, lane));
+        assert!(layout_bank_conflicts(&plain, 4, along_row).is_conflict_free());
+        assert_eq!(layout_bank_conflicts(&plain, 4, down_column).max_ways, 16);
+
+        // Sw<4,0,5> XORs row / 2 into the column, which fixes both.
+        let swizzled = TensorLayout::swizzled(128, 16, Swizzle::new(4, 0, 5));
+        assert!(layout_bank_conflicts(&swizzled, 4, along_row).is_conflict_free());
+        assert!(layout_bank_conflicts(&swizzled, 4, down_column).is_conflict_free());
+    }
+
+    #[test]
+    fn test_wmma_kernel_smem() {
+        // gemm_kernel_wmma's A fragment loads from the row-major f32[64][16]
+        // SMEM_A: for each value, lanes 4 apart read rows 2 apart, so only
+        // four banks are used.
+        let tv: Layout = "((4,8),(4,2)):((16,2),(64,1))".parse().unwrap();
+        // The first warp's 16x16 tile, in SMEM_A's (row, col) coordinates.
+        let warp_tile: Layout = "(16,16):(1,64)".parse().unwrap();
+        let wavefronts = |swizzle: Swizzle| {
+            let smem = SwizzledLayout::new(swizzle, Layout::row_major([64, 16]));
+            let tile = smem.composition(&warp_tile.composition(&tv).unwrap()).unwrap();
+            (0..8)
+                .map(|value| {
+                    let offsets: Vec<_> = (0..WARP_SIZE).map(|lane| Some(tile.index(&[lane, value].into()))).collect();
+                    bank_conflicts(&offsets, 4)
+                })
+                .collect::<Vec<_>>()
+        };
+        assert!(wavefronts(Swizzle::identity()).iter().all(|report| report.max_ways == 8));
+        assert!(wavefronts(Swizzle::new(3, 2, 3)).iter().all(BankReport::is_conflict_free));
+
+        // SMEM_B is f32[16][64]; reading one column across the 16 rows is a
+        // 16-way conflict unless the row is XORed into the bank.
+        let down_column = |lane: usize| (lane < 16).then_some((lane, 9));
+        assert_eq!(layout_bank_conflicts(&TensorLayout::row_major(16, 64), 4, down_column).max_ways, 16);
+        let candidates = [Swizzle::new(3, 2, 3), Swizzle::new(2, 0, 6), Swizzle::new(4, 1, 5), Swizzle::new(4, 0, 6)];
+        let conflict_free: Vec<_> = candidates
+            .into_iter()
+            .filter(|&swizzle| {
+                let layout = TensorLayout::swizzled(16, 64, swizzle);
+                layout_bank_conflicts(&layout, 4, down_column).is_conflict_free()
+                    && layout_bank_conflicts(&layout, 4, |lane| Some((2, lane))).is_conflict_free()
+            })
+            .collect();
+        assert_eq!(conflict_free, [Swizzle::new(4, 1, 5), Swizzle::new(4, 0, 6)]);
+    }
+}
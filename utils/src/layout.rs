@@ -0,0 +1,1097 @@
+//! CuTe-style layouts: a hierarchical shape paired with a congruent stride,
+//! mapping coordinates to offsets, and the algebra CUTLASS builds on them
+//! (coalesce, composition, complement, divide, product and inverse).
+//!
+//! The definitions follow `cute/layout.hpp` and its Python port `pycute`.
+//! Coordinates are colexicographic, so the leftmost mode varies fastest,
+//! and strides are non-negative. Where CuTe fails a static assertion on
+//! divisibility, the functions here return a [`LayoutError`].
+//!
+//! A [`SwizzledLayout`] applies a [`Swizzle`] to a layout's offsets, CuTe's
+//! `ComposedLayout<Swizzle<B, M, S>, 0, Layout>`, which is how shared-memory
+//! tiles avoid bank conflicts.
+
+use std::fmt;
+use std::str::FromStr;
+
+use crate::tensor_defs::{MemoryLayout, TensorLayout};
+
+/// An integer or a tuple of `IntTuple`s: the type of shapes, strides and
+/// coordinates.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum IntTuple {
+    Int(usize),
+    Tuple(Vec<IntTuple>),
+}
+
+impl IntTuple {
+    /// Number of top-level modes. An integer has rank 1.
+    pub fn rank(&self) -> usize {
+        match self {
+            IntTuple::Int(_) => 1,
+            IntTuple::Tuple(modes) => modes.len(),
+        }
+    }
+
+    /// Nesting depth. An integer has depth 0.
+    pub fn depth(&self) -> usize {
+        match self {
+            IntTuple::Int(_) => 0,
+            IntTuple::Tuple(modes) => 1 + modes.iter().map(IntTuple::depth).max().unwrap_or(0),
+        }
+    }
+
+    pub fn product(&self) -> usize {
+        match self {
+            IntTuple::Int(n) => *n,
+            IntTuple::Tuple(modes) => modes.iter().map(IntTuple::product).product(),
+        }
+    }
+
+    /// The top-level modes. An integer is its own only mode.
+    pub fn modes(&self) -> &[IntTuple] {
+        match self {
+            IntTuple::Int(_) => std::slice::from_ref(self),
+            IntTuple::Tuple(modes) => modes,
+        }
+    }
+
+    /// The integers in order, without the nesting.
+    pub fn flatten(&self) -> Vec<usize> {
+        let mut out = Vec::new();
+        self.flatten_into(&mut out);
+        out
+    }
+
+    fn flatten_into(&self, out: &mut Vec<usize>) {
+        match self {
+            IntTuple::Int(n) => out.push(*n),
+            IntTuple::Tuple(modes) => modes.iter().for_each(|mode| mode.flatten_into(out)),
+        }
+    }
+
+    /// Rebuilds this tuple's nesting around `values`, taken in flattened
+    /// order.
+    fn unflatten(&self, values: &mut impl Iterator<Item = usize>) -> IntTuple {
+        match self {
+            IntTuple::Int(_) => IntTuple::Int(values.next().expect("one value per integer")),
+            IntTuple::Tuple(modes) => IntTuple::Tuple(modes.iter().map(|mode| mode.unflatten(values)).collect()),
+        }
+    }
+
+    /// Whether `other` has the same nesting.
+    pub fn is_congruent(&self, other: &IntTuple) -> bool {
+        match (self, other) {
+            (IntTuple::Int(_), IntTuple::Int(_)) => true,
+            (IntTuple::Tuple(a), IntTuple::Tuple(b)) => {
+                a.len() == b.len() && a.iter().zip(b).all(|(a, b)| a.is_congruent(b))
+            }
+            _ => false,
+        }
+    }
+}
+
+impl From<usize> for IntTuple {
+    fn from(n: usize) -> Self {
+        IntTuple::Int(n)
+    }
+}
+
+impl<const N: usize> From<[usize; N]> for IntTuple {
+    fn from(values: [usize; N]) -> Self {
+        IntTuple::Tuple(values.into_iter().map(IntTuple::Int).collect())
+    }
+}
+
+impl From<Vec<IntTuple>> for IntTuple {
+    fn from(modes: Vec<IntTuple>) -> Self {
+        IntTuple::Tuple(modes)
+    }
+}
+
+impl fmt::Display for IntTuple {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IntTuple::Int(n) => write!(f, "{}", n),
+            IntTuple::Tuple(modes) => {
+                f.write_str("(")?;
+                for (i, mode) in modes.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(",")?;
+                    }
+                    write!(f, "{}", mode)?;
+                }
+                f.write_str(")")
+            }
+        }
+    }
+}
+
+impl FromStr for IntTuple {
+    type Err = LayoutError;
+
+    /// Parses CuTe notation such as `(4,(2,8))`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parser = Parser { text: s, pos: 0 };
+        let tuple = parser.tuple()?;
+        parser.finish()?;
+        Ok(tuple)
+    }
+}
+
+/// A layout that cannot be built, or an operation whose operands violate
+/// the algebra's divisibility conditions.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LayoutError {
+    NotCongruent { shape: IntTuple, stride: IntTuple },
+    ZeroExtent(IntTuple),
+    NotDivisible { extent: usize, divisor: usize },
+    /// Two modes address the same offsets, so there is no complement.
+    Overlapping { stride: usize },
+    RankMismatch { rank: usize, tilers: usize },
+    /// A swizzled layout has no plain [`Layout`].
+    NotAffine(Swizzle),
+    Parse(String),
+}
+
+impl fmt::Display for LayoutError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LayoutError::NotCongruent { shape, stride } => {
+                write!(f, "stride {} is not congruent with shape {}", stride, shape)
+            }
+            LayoutError::ZeroExtent(shape) => write!(f, "shape {} has a zero extent", shape),
+            LayoutError::NotDivisible { extent, divisor } => {
+                write!(f, "{} is not divisible by {}", extent, divisor)
+            }
+            LayoutError::Overlapping { stride } => write!(f, "modes overlap at stride {}", stride),
+            LayoutError::RankMismatch { rank, tilers } => {
+                write!(f, "{} tilers for a layout of rank {}", tilers, rank)
+            }
+            LayoutError::NotAffine(swizzle) => write!(f, "layout is swizzled by {}", swizzle),
+            LayoutError::Parse(message) => f.write_str(message),
+        }
+    }
+}
+
+impl std::error::Error for LayoutError {}
+
+/// A function from coordinates to offsets: the inner product of the
+/// coordinate with `stride`, after splitting it along `shape`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Layout {
+    shape: IntTuple,
+    stride: IntTuple,
+}
+
+impl Layout {
+    pub fn new(shape: impl Into<IntTuple>, stride: impl Into<IntTuple>) -> Result<Self, LayoutError> {
+        let (shape, stride) = (shape.into(), stride.into());
+        if !shape.is_congruent(&stride) {
+            return Err(LayoutError::NotCongruent { shape, stride });
+        }
+        if shape.flatten().contains(&0) {
+            return Err(LayoutError::ZeroExtent(shape));
+        }
+        Ok(Self { shape, stride })
+    }
+
+    /// Compact layout of `shape` with the leftmost mode contiguous, CuTe's
+    /// default (`LayoutLeft`).
+    pub fn column_major(shape: impl Into<IntTuple>) -> Self {
+        let shape = shape.into();
+        let mut next = 1;
+        let strides: Vec<usize> = shape
+            .flatten()
+            .into_iter()
+            .map(|extent| {
+                let stride = next;
+                next *= extent;
+                stride
+            })
+            .collect();
+        let stride = shape.unflatten(&mut strides.into_iter());
+        Self { shape, stride }
+    }
+
+    /// Compact layout of `shape` with the rightmost mode contiguous
+    /// (`LayoutRight`).
+    pub fn row_major(shape: impl Into<IntTuple>) -> Self {
+        let shape = shape.into();
+        let mut next = 1;
+        let mut strides: Vec<usize> = shape
+            .flatten()
+            .into_iter()
+            .rev()
+            .map(|extent| {
+                let stride = next;
+                next *= extent;
+                stride
+            })
+            .collect();
+        strides.reverse();
+        let stride = shape.unflatten(&mut strides.into_iter());
+        Self { shape, stride }
+    }
+
+    /// The layout whose modes are `modes` (CuTe's `make_layout`).
+    pub fn concat(modes: &[Layout]) -> Self {
+        Self {
+            shape: IntTuple::Tuple(modes.iter().map(|mode| mode.shape.clone()).collect()),
+            stride: IntTuple::Tuple(modes.iter().map(|mode| mode.stride.clone()).collect()),
+        }
+    }
+
+    /// A flat layout, or a plain integer one if there is a single mode.
+    fn from_modes(shapes: Vec<usize>, strides: Vec<usize>) -> Self {
+        if shapes.len() == 1 {
+            return Self { shape: IntTuple::Int(shapes[0]), stride: IntTuple::Int(strides[0]) };
+        }
+        Self { shape: shapes.into_iter().collect_tuple(), stride: strides.into_iter().collect_tuple() }
+    }
+
+    pub fn shape(&self) -> &IntTuple {
+        &self.shape
+    }
+
+    pub fn stride(&self) -> &IntTuple {
+        &self.stride
+    }
+
+    pub fn rank(&self) -> usize {
+        self.shape.rank()
+    }
+
+    pub fn depth(&self) -> usize {
+        self.shape.depth()
+    }
+
+    /// Number of coordinates in the domain.
+    pub fn size(&self) -> usize {
+        self.shape.product()
+    }
+
+    /// One past the largest offset in the image.
+    pub fn cosize(&self) -> usize {
+        self.call(self.size() - 1) + 1
+    }
+
+    pub fn mode(&self, i: usize) -> Layout {
+        Self { shape: self.shape.modes()[i].clone(), stride: self.stride.modes()[i].clone() }
+    }
+
+    pub fn modes(&self) -> impl Iterator<Item = Layout> + '_ {
+        (0..self.rank()).map(|i| self.mode(i))
+    }
+
+    /// Offset of the `idx`-th coordinate in colexicographic order. The last
+    /// mode is unbounded, so indices past [`Layout::size`] keep advancing
+    /// along it.
+    pub fn call(&self, idx: usize) -> usize {
+        let shape = self.shape.flatten();
+        let stride = self.stride.flatten();
+        let mut rest = idx;
+        let mut offset = 0;
+        for (i, (&extent, &step)) in shape.iter().zip(&stride).enumerate() {
+            let coord = if i + 1 == shape.len() { rest } else { rest % extent };
+            offset += coord * step;
+            rest /= extent;
+        }
+        offset
+    }
+
+    /// Offset of a hierarchical coordinate. Each level may be an integer,
+    /// which indexes its sub-layout as in [`Layout::call`], or a tuple
+    /// matching that level of the shape.
+    pub fn index(&self, coord: &IntTuple) -> usize {
+        match coord {
+            IntTuple::Int(idx) => self.call(*idx),
+            IntTuple::Tuple(coords) => {
+                assert!(
+                    matches!(self.shape, IntTuple::Tuple(_)) && coords.len() == self.rank(),
+                    "Coordinate {} is not congruent with shape {}",
+                    coord,
+                    self.shape
+                );
+                self.modes().zip(coords).map(|(mode, coord)| mode.index(coord)).sum()
+            }
+        }
+    }
+
+    /// The same function with the nesting removed.
+    pub fn flatten(&self) -> Layout {
+        match self.shape {
+            IntTuple::Int(_) => self.clone(),
+            IntTuple::Tuple(_) => Self {
+                shape: self.shape.flatten().into_iter().collect_tuple(),
+                stride: self.stride.flatten().into_iter().collect_tuple(),
+            },
+        }
+    }
+
+    /// The same function over `0..size` with as few modes as possible:
+    /// unit modes are dropped and contiguous neighbours merged.
+    pub fn coalesce(&self) -> Layout {
+        let mut shapes = vec![1];
+        let mut strides = vec![0];
+        for (extent, step) in self.shape.flatten().into_iter().zip(self.stride.flatten()) {
+            let last = shapes.len() - 1;
+            if extent == 1 {
+                continue;
+            } else if shapes[last] == 1 {
+                shapes[last] = extent;
+                strides[last] = step;
+            } else if shapes[last] * strides[last] == step {
+                shapes[last] *= extent;
+            } else {
+                shapes.push(extent);
+                strides.push(step);
+            }
+        }
+        Self::from_modes(shapes, strides)
+    }
+
+    /// `self ∘ rhs`: the layout `i -> self(rhs(i))`, with the shape of
+    /// `rhs`.
+    pub fn composition(&self, rhs: &Layout) -> Result<Layout, LayoutError> {
+        if let IntTuple::Tuple(_) = rhs.shape {
+            let modes = rhs.modes().map(|mode| self.composition(&mode)).collect::<Result<Vec<_>, _>>()?;
+            return Ok(Self::concat(&modes));
+        }
+        // Congruence makes both integers here.
+        let (mut rest_shape, mut rest_stride) = (rhs.shape.product(), rhs.stride.product());
+        if rest_stride == 0 {
+            return Ok(Self::from_modes(vec![rest_shape], vec![0]));
+        }
+
+        let lhs = self.coalesce();
+        let lhs_shape = lhs.shape.flatten();
+        let lhs_stride = lhs.stride.flatten();
+        let last = lhs_shape.len() - 1;
+        let mut shapes = Vec::new();
+        let mut strides = Vec::new();
+        for (&extent, &step) in lhs_shape[..last].iter().zip(&lhs_stride[..last]) {
+            if extent % rest_stride != 0 && rest_stride % extent != 0 {
+                return Err(LayoutError::NotDivisible {
+                    extent: extent.max(rest_stride),
+                    divisor: extent.min(rest_stride),
+                });
+            }
+            let taken = (extent / rest_stride).clamp(1, rest_shape);
+            if rest_shape % taken != 0 {
+                return Err(LayoutError::NotDivisible { extent: rest_shape, divisor: taken });
+            }
+            if taken != 1 {
+                shapes.push(taken);
+                strides.push(rest_stride * step);
+            }
+            rest_shape /= taken;
+            rest_stride = rest_stride.div_ceil(extent);
+            if rest_shape == 1 {
+                break;
+            }
+        }
+        if rest_shape != 1 || shapes.is_empty() {
+            shapes.push(rest_shape);
+            strides.push(rest_stride * lhs_stride[last]);
+        }
+        Ok(Self::from_modes(shapes, strides))
+    }
+
+    /// The layout that fills the gaps in this one's image, ordered so that
+    /// `(self, complement)` covers at least `0..cotarget`.
+    pub fn complement(&self, cotarget: usize) -> Result<Layout, LayoutError> {
+        let mut modes: Vec<(usize, usize)> = self
+            .stride
+            .flatten()
+            .into_iter()
+            .zip(self.shape.flatten())
+            .filter(|&(step, extent)| step != 0 && extent != 1)
+            .collect();
+        modes.sort_unstable();
+
+        let mut shapes = Vec::new();
+        let mut strides = Vec::new();
+        let mut current = 1;
+        for (step, extent) in modes {
+            if step < current {
+                return Err(LayoutError::Overlapping { stride: step });
+            }
+            if step % current != 0 {
+                return Err(LayoutError::NotDivisible { extent: step, divisor: current });
+            }
+            shapes.push(step / current);
+            strides.push(current);
+            current = extent * step;
+        }
+        shapes.push(cotarget.div_ceil(current));
+        strides.push(current);
+        Ok(Self::from_modes(shapes, strides).coalesce())
+    }
+
+    /// Splits the domain into `(tile, rest)`: mode 0 walks the elements
+    /// `tiler` selects and mode 1 walks the tiles.
+    pub fn logical_divide(&self, tiler: &Layout) -> Result<Layout, LayoutError> {
+        let tiles = Self::concat(&[tiler.clone(), tiler.complement(self.size())?]);
+        self.composition(&tiles)
+    }
+
+    /// Divides mode `i` by `tilers[i]`, giving `((tile0, rest0), (tile1,
+    /// rest1), ...)`. Modes past the tilers are kept as they are.
+    pub fn logical_divide_by_mode(&self, tilers: &[Layout]) -> Result<Layout, LayoutError> {
+        if tilers.len() > self.rank() {
+            return Err(LayoutError::RankMismatch { rank: self.rank(), tilers: tilers.len() });
+        }
+        let modes = self
+            .modes()
+            .enumerate()
+            .map(|(i, mode)| match tilers.get(i) {
+                Some(tiler) => mode.logical_divide(tiler),
+                None => Ok(mode),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Self::concat(&modes))
+    }
+
+    /// [`Layout::logical_divide_by_mode`] with the tile modes gathered
+    /// first: `((tile0, tile1, ...), (rest0, rest1, ...))`.
+    pub fn zipped_divide(&self, tilers: &[Layout]) -> Result<Layout, LayoutError> {
+        let divided = self.logical_divide_by_mode(tilers)?;
+        let (tile, rest): (Vec<_>, Vec<_>) = divided
+            .modes()
+            .enumerate()
+            .map(|(i, mode)| match i < tilers.len() {
+                true => (Some(mode.mode(0)), mode.mode(1)),
+                false => (None, mode),
+            })
+            .unzip();
+        let tile: Vec<_> = tile.into_iter().flatten().collect();
+        Ok(Self::concat(&[Self::concat(&tile), Self::concat(&rest)]))
+    }
+
+    /// `(self, rest)`: `rest` repeats this layout in the pattern of `tiler`
+    /// over offsets this one does not use.
+    pub fn logical_product(&self, tiler: &Layout) -> Result<Layout, LayoutError> {
+        let rest = self.complement(self.size() * tiler.cosize())?.composition(tiler)?;
+        Ok(Self::concat(&[self.clone(), rest]))
+    }
+
+    /// The largest compact layout `r` with `self(r(i)) == i` for every `i`
+    /// in its domain.
+    pub fn right_inverse(&self) -> Layout {
+        let shape = self.shape.flatten();
+        let mut next = 1;
+        let mut modes: Vec<(usize, usize, usize)> = shape
+            .iter()
+            .zip(self.stride.flatten())
+            .map(|(&extent, step)| {
+                let position = next;
+                next *= extent;
+                (step, extent, position)
+            })
+            .collect();
+        modes.sort_unstable();
+
+        let mut shapes = Vec::new();
+        let mut strides = Vec::new();
+        let mut current = 1;
+        for (step, extent, position) in modes {
+            if extent == 1 || step == 0 {
+                continue;
+            }
+            if step != current {
+                break;
+            }
+            shapes.push(extent);
+            strides.push(position);
+            current = extent * step;
+        }
+        Self::from_modes(shapes, strides).coalesce()
+    }
+}
+
+/// The same index function as [`TensorLayout::index`], over `(row, col)`
+/// coordinates. Tiled layouts are padded to whole tiles, so their modes
+/// may be larger than the matrix.
+/// Fails with [`LayoutError::ZeroExtent`] for empty shapes and tiles.
+impl TryFrom<TensorLayout> for SwizzledLayout {
+    type Error = LayoutError;
+
+    fn try_from(tensor: TensorLayout) -> Result<Self, Self::Error> {
+        let (rows, cols) = (tensor.shape.rows, tensor.shape.cols);
+        let ld = tensor.leading_dim;
+        match tensor.layout {
+            MemoryLayout::RowMajor => Ok(SwizzledLayout::new(Swizzle::identity(), Layout::new([rows, cols], [ld, 1])?)),
+            MemoryLayout::ColumnMajor => Ok(SwizzledLayout::new(Swizzle::identity(), Layout::new([rows, cols], [1, ld])?)),
+            MemoryLayout::Tiled { tile_m, tile_n } => {
+                if tile_m == 0 || tile_n == 0 {
+                    return Err(LayoutError::ZeroExtent([tile_m, tile_n].into()));
+                }
+                let tile = tile_m * tile_n;
+                let tiles_per_row = cols.div_ceil(tile_n);
+                let layout = Layout::new(
+                    vec![[tile_m, rows.div_ceil(tile_m)].into(), [tile_n, tiles_per_row].into()],
+                    vec![[tile_n, tiles_per_row * tile].into(), [1, tile].into()],
+                )?;
+                Ok(SwizzledLayout::new(Swizzle::identity(), layout))
+            }
+            MemoryLayout::Swizzled(swizzle) => Ok(SwizzledLayout::new(swizzle, Layout::new([rows, cols], [ld, 1])?)),
+        }
+    }
+}
+
+/// Like the [`SwizzledLayout`] conversion, for the layouts without a
+/// swizzle.
+impl TryFrom<TensorLayout> for Layout {
+    type Error = LayoutError;
+
+    fn try_from(tensor: TensorLayout) -> Result<Self, Self::Error> {
+        let SwizzledLayout { swizzle, layout } = SwizzledLayout::try_from(tensor)?;
+        match swizzle.is_identity() {
+            true => Ok(layout),
+            false => Err(LayoutError::NotAffine(swizzle)),
+        }
+    }
+}
+
+impl fmt::Display for Layout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.shape, self.stride)
+    }
+}
+
+impl FromStr for Layout {
+    type Err = LayoutError;
+
+    /// Parses CuTe notation such as `((4,8),(4,2)):((16,2),(64,1))`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parser = Parser { text: s, pos: 0 };
+        let shape = parser.tuple()?;
+        if !parser.eat(b':') {
+            return Err(parser.error("':'"));
+        }
+        let stride = parser.tuple()?;
+        parser.finish()?;
+        Layout::new(shape, stride)
+    }
+}
+
+/// CuTe's `Swizzle<B, M, S>`: XORs the `bits` offset bits starting at
+/// `base + shift` into the `bits` bits starting at `base`. With `2^base`
+/// elements per bank-sized unit, this spreads a column of a row-major tile
+/// over different banks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Swizzle {
+    pub bits: u32,
+    pub base: u32,
+    pub shift: u32,
+}
+
+impl Swizzle {
+    pub const fn new(bits: u32, base: u32, shift: u32) -> Self {
+        Self { bits, base, shift }
+    }
+
+    pub const fn identity() -> Self {
+        Self::new(0, 0, 0)
+    }
+
+    pub const fn is_identity(&self) -> bool {
+        self.bits == 0
+    }
+
+    /// The source and destination bits must not overlap, or the swizzle is
+    /// not a bijection.
+    pub const fn is_valid(&self) -> bool {
+        self.shift >= self.bits && (self.bits as u64 + self.base as u64 + self.shift as u64) < usize::BITS as u64
+    }
+
+    pub const fn apply(&self, offset: usize) -> usize {
+        let mask = ((1 << self.bits) - 1) << (self.base + self.shift);
+        offset ^ ((offset & mask) >> self.shift)
+    }
+
+    /// Offsets only move within aligned blocks of this many elements.
+    pub const fn block_len(&self) -> usize {
+        1 << (self.base + self.bits)
+    }
+}
+
+impl fmt::Display for Swizzle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Sw<{},{},{}>", self.bits, self.base, self.shift)
+    }
+}
+
+/// A [`Layout`] whose offsets go through a [`Swizzle`].
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct SwizzledLayout {
+    swizzle: Swizzle,
+    layout: Layout,
+}
+
+impl SwizzledLayout {
+    pub fn new(swizzle: Swizzle, layout: Layout) -> Self {
+        Self { swizzle, layout }
+    }
+
+    pub fn swizzle(&self) -> Swizzle {
+        self.swizzle
+    }
+
+    pub fn layout(&self) -> &Layout {
+        &self.layout
+    }
+
+    pub fn size(&self) -> usize {
+        self.layout.size()
+    }
+
+    /// One past the largest offset the swizzle can reach.
+    pub fn cosize(&self) -> usize {
+        self.layout.cosize().next_multiple_of(self.swizzle.block_len())
+    }
+
+    pub fn call(&self, idx: usize) -> usize {
+        self.swizzle.apply(self.layout.call(idx))
+    }
+
+    pub fn index(&self, coord: &IntTuple) -> usize {
+        self.swizzle.apply(self.layout.index(coord))
+    }
+
+    /// `self ∘ rhs`, keeping the swizzle on the outside.
+    pub fn composition(&self, rhs: &Layout) -> Result<SwizzledLayout, LayoutError> {
+        Ok(Self::new(self.swizzle, self.layout.composition(rhs)?))
+    }
+}
+
+impl fmt::Display for SwizzledLayout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} o {}", self.swizzle, self.layout)
+    }
+}
+
+trait CollectTuple {
+    fn collect_tuple(self) -> IntTuple;
+}
+
+impl<I: Iterator<Item = usize>> CollectTuple for I {
+    fn collect_tuple(self) -> IntTuple {
+        IntTuple::Tuple(self.map(IntTuple::Int).collect())
+    }
+}
+
+struct Parser<'a> {
+    text: &'a str,
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn eat(&mut self, byte: u8) -> bool {
+        self.skip_whitespace();
+        let found = self.text.as_bytes().get(self.pos) == Some(&byte);
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    fn skip_whitespace(&mut self) {
+        let bytes = self.text.as_bytes();
+        while bytes.get(self.pos).is_some_and(u8::is_ascii_whitespace) {
+            self.pos += 1;
+        }
+    }
+
+    fn tuple(&mut self) -> Result<IntTuple, LayoutError> {
+        if self.eat(b'(') {
+            let mut modes = vec![self.tuple()?];
+            while self.eat(b',') {
+                modes.push(self.tuple()?);
+            }
+            if !self.eat(b')') {
+                return Err(self.error("',' or ')'"));
+            }
+            return Ok(IntTuple::Tuple(modes));
+        }
+        let start = self.pos;
+        let bytes = self.text.as_bytes();
+        while bytes.get(self.pos).is_some_and(u8::is_ascii_digit) {
+            self.pos += 1;
+        }
+        match self.text[start..self.pos].parse() {
+            Ok(n) => Ok(IntTuple::Int(n)),
+            Err(_) => {
+                self.pos = start;
+                Err(self.error("an integer or '('"))
+            }
+        }
+    }
+
+    fn finish(&mut self) -> Result<(), LayoutError> {
+        self.skip_whitespace();
+        if self.pos != self.text.len() {
+            return Err(self.error("end of input"));
+        }
+        Ok(())
+    }
+
+    fn error(&self, expected: &str) -> LayoutError {
+        LayoutError::Parse(format!("Expected {} at offset {} of {:?}", expected, self.pos, self.text))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn layout(s: &str) -> Layout {
+        s.parse().unwrap()
+    }
+
+    fn offsets(layout: &Layout) -> Vec<usize> {
+        (0..layout.size()).map(|i| layout.call(i)).collect()
+    }
+
+    /// Layouts for the property tests: compact, strided, padded, broadcast
+    /// and nested.
+    fn samples() -> Vec<Layout> {
+        [
+            "1:0",
+            "8:1",
+            "8:2",
+            "4:0",
+            "(4,2):(1,4)",
+            "(4,2):(2,1)",
+            "(4,3):(3,1)",
+            "(2,4):(1,6)",
+            "(4,2,3):(2,1,8)",
+            "((2,2),2):((4,1),2)",
+            "(2,(3,2)):(3,(1,6))",
+            "((4,8),(4,2)):((16,2),(64,1))",
+            "(4,2):(0,1)",
+            "(2,2,2):(1,4,16)",
+        ]
+        .iter()
+        .map(|s| layout(s))
+        .collect()
+    }
+
+    #[test]
+    fn test_parse_and_display() {
+        let tv = layout("((4, 8), (4, 2)) : ((16, 2), (64, 1))");
+        assert_eq!(tv.to_string(), "((4,8),(4,2)):((16,2),(64,1))");
+        assert_eq!((tv.rank(), tv.depth(), tv.size(), tv.cosize()), (2, 2, 256, 256));
+        assert_eq!(tv.mode(1), layout("(4,2):(64,1)"));
+        assert_eq!("(3,(2,5))".parse::<IntTuple>().unwrap().flatten(), [3, 2, 5]);
+        assert_eq!(layout("8:2").to_string(), "8:2");
+
+        for bad in ["", "(4,2)", "(4,2):(1)", "(4,2):(1,4))", "(4,):(1,)", "4:-1", "(0,2):(1,2)"] {
+            assert!(bad.parse::<Layout>().is_err(), "{:?}", bad);
+        }
+        assert_eq!(
+            Layout::new([4, 2], 1).unwrap_err().to_string(),
+            "stride 1 is not congruent with shape (4,2)"
+        );
+    }
+
+    #[test]
+    fn test_call_and_index() {
+        let l = layout("(4,(2,3)):(2,(1,8))");
+        assert_eq!(offsets(&l)[..10], [0, 2, 4, 6, 1, 3, 5, 7, 8, 10]);
+        // Hierarchical, per-mode linear and flat coordinates all agree.
+        for i in 0..l.size() {
+            let (c0, c1, c2) = (i % 4, i / 4 % 2, i / 8);
+            let nested = IntTuple::Tuple(vec![c0.into(), [c1, c2].into()]);
+            assert_eq!(l.index(&nested), l.call(i));
+            assert_eq!(l.index(&[c0, c1 + 2 * c2].into()), l.call(i));
+            assert_eq!(l.index(&i.into()), l.call(i));
+        }
+        // The last mode is unbounded.
+        assert_eq!(layout("(4,2):(1,4)").call(9), 9);
+    }
+
+    #[test]
+    fn test_compact_layouts() {
+        let shape = IntTuple::Tuple(vec![[2, 3].into(), 4.into()]);
+        assert_eq!(Layout::column_major(shape.clone()).to_string(), "((2,3),4):((1,2),6)");
+        assert_eq!(Layout::row_major(shape).to_string(), "((2,3),4):((12,4),1)");
+        for l in [Layout::column_major([3, 5, 2]), Layout::row_major([3, 5, 2])] {
+            let mut seen = offsets(&l);
+            seen.sort_unstable();
+            assert_eq!(seen, (0..30).collect::<Vec<_>>());
+        }
+    }
+
+    #[test]
+    fn test_coalesce() {
+        assert_eq!(layout("(2,4):(1,2)").coalesce(), layout("8:1"));
+        assert_eq!(layout("(2,(1,4)):(1,(7,2))").coalesce(), layout("8:1"));
+        assert_eq!(layout("(4,2):(2,1)").coalesce(), layout("(4,2):(2,1)"));
+        assert_eq!(layout("(1,1):(3,5)").coalesce(), layout("1:0"));
+        for l in samples() {
+            let c = l.coalesce();
+            assert_eq!(c.size(), l.size());
+            assert!(c.depth() <= 1);
+            assert_eq!(offsets(&c), offsets(&l), "{}", l);
+            assert_eq!(l.flatten().coalesce(), c);
+        }
+    }
+
+    #[test]
+    fn test_composition() {
+        assert_eq!(
+            layout("(6,2):(8,2)").composition(&layout("(4,3):(3,1)")).unwrap(),
+            layout("((2,2),3):((24,2),8)")
+        );
+        assert_eq!(layout("20:2").composition(&layout("(5,4):(4,1)")).unwrap(), layout("(5,4):(8,2)"));
+        assert_eq!(
+            layout("(10,2):(16,4)").composition(&layout("(5,4):(1,5)")).unwrap(),
+            layout("(5,(2,2)):(16,(80,4))")
+        );
+        assert_eq!(layout("(4,3):(3,1)").composition(&layout("4:0")).unwrap(), layout("4:0"));
+        assert_eq!(
+            layout("(4,3):(3,1)").composition(&layout("6:1")).unwrap_err(),
+            LayoutError::NotDivisible { extent: 6, divisor: 4 }
+        );
+
+        let mut composed = 0;
+        for a in samples() {
+            for b in samples() {
+                if b.cosize() > a.size() {
+                    continue;
+                }
+                let Ok(c) = a.composition(&b) else { continue };
+                composed += 1;
+                assert_eq!(c.size(), b.size());
+                for i in 0..b.size() {
+                    assert_eq!(c.call(i), a.call(b.call(i)), "{} o {} at {}", a, b, i);
+                }
+            }
+        }
+        assert!(composed > 50, "{}", composed);
+    }
+
+    #[test]
+    fn test_complement() {
+        assert_eq!(layout("4:2").complement(24).unwrap(), layout("(2,3):(1,8)"));
+        assert_eq!(layout("(2,4):(1,6)").complement(24).unwrap(), layout("3:2"));
+        assert_eq!(layout("(2,2):(4,1)").complement(24).unwrap(), layout("(2,3):(2,8)"));
+        assert_eq!(layout("8:1").complement(8).unwrap(), layout("1:0"));
+        assert_eq!(layout("(2,2):(1,1)").complement(4).unwrap_err(), LayoutError::Overlapping { stride: 1 });
+        assert_eq!(
+            layout("(2,3):(1,3)").complement(12).unwrap_err(),
+            LayoutError::NotDivisible { extent: 3, divisor: 2 }
+        );
+
+        for l in samples() {
+            let mut image = offsets(&l);
+            image.sort_unstable();
+            image.dedup();
+            if image.len() != l.size() {
+                continue;
+            }
+            // An injective layout and its complement fill 0..size exactly
+            // once, and size reaches the cotarget.
+            let c = l.complement(64).unwrap();
+            let mut both = offsets(&Layout::concat(&[l.clone(), c.clone()]));
+            both.sort_unstable();
+            assert_eq!(both, (0..both.len()).collect::<Vec<_>>(), "{} and {}", l, c);
+            assert!(both.len() >= 64);
+        }
+    }
+
+    #[test]
+    fn test_divide() {
+        let a = layout("(4,2,3):(2,1,8)");
+        let divided = a.logical_divide(&layout("4:2")).unwrap();
+        assert_eq!(divided, layout("((2,2),(2,3)):((4,1),(2,8))"));
+
+        let b = layout("(9,(4,8)):(59,(13,1))");
+        let tilers = [layout("3:3"), layout("(2,4):(1,8)")];
+        assert_eq!(
+            b.logical_divide_by_mode(&tilers).unwrap(),
+            layout("((3,3),((2,4),(2,2))):((177,59),((13,2),(26,1)))")
+        );
+        assert_eq!(
+            b.zipped_divide(&tilers).unwrap(),
+            layout("((3,(2,4)),(3,(2,2))):((177,(13,2)),(59,(26,1)))")
+        );
+        assert_eq!(
+            b.zipped_divide(&[layout("3:1"), layout("2:1"), layout("2:1")]).unwrap_err(),
+            LayoutError::RankMismatch { rank: 2, tilers: 3 }
+        );
+
+        for l in samples() {
+            for tile in [layout("2:1"), layout("2:2"), layout("(2,2):(1,4)")] {
+                let Ok(divided) = l.logical_divide(&tile) else { continue };
+                let tiles = Layout::concat(&[tile.clone(), tile.complement(l.size()).unwrap()]);
+                assert_eq!(divided.size(), tiles.size());
+                for i in (0..divided.size()).filter(|&i| tiles.call(i) < l.size()) {
+                    assert_eq!(divided.call(i), l.call(tiles.call(i)), "{} / {} at {}", l, tile, i);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_logical_product() {
+        assert_eq!(
+            layout("(2,2):(4,1)").logical_product(&layout("6:1")).unwrap(),
+            layout("((2,2),(2,3)):((4,1),(2,8))")
+        );
+        assert_eq!(
+            layout("(2,2):(4,1)").logical_product(&layout("(4,2):(2,1)")).unwrap(),
+            layout("((2,2),(4,2)):((4,1),(8,2))")
+        );
+        // A 2x2 block repeated over a 3x4 grid covers a 6x8 matrix exactly
+        // once.
+        let block = Layout::column_major([2, 2]);
+        let product = block.logical_product(&Layout::column_major([3, 4])).unwrap();
+        let mut seen = offsets(&product);
+        seen.sort_unstable();
+        assert_eq!(seen, (0..48).collect::<Vec<_>>());
+        assert_eq!(product.mode(0), block);
+    }
+
+    #[test]
+    fn test_right_inverse() {
+        assert_eq!(layout("(4,2):(2,1)").right_inverse(), layout("(2,4):(4,1)"));
+        assert_eq!(layout("(2,4):(1,6)").right_inverse(), layout("2:1"));
+        assert_eq!(layout("8:2").right_inverse(), layout("1:0"));
+
+        for l in samples() {
+            let inverse = l.right_inverse();
+            for i in 0..inverse.size() {
+                assert_eq!(l.call(inverse.call(i)), i, "{} and {}", l, inverse);
+            }
+            // Bijections onto 0..size invert completely.
+            let mut image = offsets(&l);
+            image.sort_unstable();
+            if image == (0..l.size()).collect::<Vec<_>>() {
+                assert_eq!(inverse.size(), l.size(), "{}", l);
+                assert_eq!(inverse.composition(&l).unwrap().coalesce(), Layout::column_major(l.size()).coalesce());
+            }
+        }
+    }
+
+    #[test]
+    fn test_tensor_layouts() {
+        let tensors = [
+            TensorLayout::row_major(5, 7),
+            TensorLayout::column_major(5, 7),
+            TensorLayout { leading_dim: 9, ..TensorLayout::row_major(5, 7) },
+            TensorLayout::tiled(8, 12, 4, 3),
+            TensorLayout::tiled(7, 10, 4, 3),
+            TensorLayout::tiled(16, 16, 16, 16),
+            TensorLayout::swizzled(16, 16, Swizzle::new(3, 2, 3)),
+            TensorLayout::swizzled(7, 16, Swizzle::new(2, 0, 4)),
+        ];
+        for tensor in tensors {
+            let l = SwizzledLayout::try_from(tensor).unwrap();
+            for row in 0..tensor.shape.rows {
+                for col in 0..tensor.shape.cols {
+                    assert_eq!(l.index(&[row, col].into()), tensor.index(row, col), "{} at ({}, {})", l, row, col);
+                }
+            }
+        }
+        assert_eq!(Layout::try_from(TensorLayout::row_major(4, 3)).unwrap(), Layout::row_major([4, 3]));
+        assert_eq!(Layout::try_from(TensorLayout::column_major(4, 3)).unwrap(), Layout::column_major([4, 3]));
+        assert_eq!(
+            Layout::try_from(TensorLayout::swizzled(8, 8, Swizzle::new(3, 0, 3))).unwrap_err(),
+            LayoutError::NotAffine(Swizzle::new(3, 0, 3))
+        );
+        assert_eq!(
+            SwizzledLayout::try_from(TensorLayout::tiled(8, 12, 0, 3)).unwrap_err(),
+            LayoutError::ZeroExtent([0, 3].into())
+        );
+        assert!(matches!(Layout::try_from(TensorLayout::row_major(0, 3)), Err(LayoutError::ZeroExtent(_))));
+        assert!(matches!(SwizzledLayout::try_from(TensorLayout::tiled(4, 0, 2, 2)), Err(LayoutError::ZeroExtent(_))));
+
+        // Each tile of a tiled matrix is one contiguous block, and the tiles
+        // follow each other in row-major order.
+        let tiled = Layout::try_from(TensorLayout::tiled(8, 12, 4, 3)).unwrap();
+        let tiles = tiled.zipped_divide(&[layout("4:1"), layout("3:1")]).unwrap();
+        assert_eq!(tiles.mode(0), Layout::row_major([4, 3]));
+        assert_eq!(tiles.mode(1), layout("(2,4):(48,12)"));
+    }
+
+    #[test]
+    fn test_tiled_kernel_partition() {
+        // gemm_kernel_tiled: thread (tx, ty) of block (bx, by) computes
+        // C[by * 128 + ty][bx * 128 + tx].
+        let (m, n) = (256, 384);
+        let c = Layout::try_from(TensorLayout::row_major(m, n)).unwrap();
+        let blocks = c.zipped_divide(&[layout("128:1"), layout("128:1")]).unwrap();
+        assert_eq!(blocks, layout("((128,128),(2,3)):((384,1),(49152,128))"));
+        for (by, bx) in [(0, 0), (1, 2), (0, 1)] {
+            for (ty, tx) in [(0, 0), (5, 7), (127, 127), (64, 3)] {
+                let coord = IntTuple::Tuple(vec![[ty, tx].into(), [by, bx].into()]);
+                assert_eq!(blocks.index(&coord), (by * 128 + ty) * n + bx * 128 + tx);
+            }
+        }
+    }
+
+    #[test]
+    fn test_wmma_fragment_partition() {
+        // gemm_kernel_wmma: lane `t` holds element `i` of the A fragment at
+        // row (t / 4) * 2 + i / 4 and column (i % 4) * 4 + t % 4 of its 16x16
+        // tile. As a thread-value layout into the column-major tile:
+        let tv = layout("((4,8),(4,2)):((16,2),(64,1))");
+        let fragment = |t: usize, i: usize| ((t / 4) * 2 + i / 4, (i % 4) * 4 + t % 4);
+        for t in 0..32 {
+            for i in 0..8 {
+                let (row, col) = fragment(t, i);
+                assert_eq!(tv.index(&[t, i].into()), row + 16 * col);
+            }
+        }
+        // Every element of the tile belongs to exactly one (lane, value).
+        assert_eq!(tv.right_inverse().size(), 256);
+        assert_eq!(tv.complement(256).unwrap().size(), 1);
+
+        // SMEM_A is a row-major 64x16 array holding four warp tiles; the
+        // fragment loads are the tile layout composed with `tv`.
+        let smem = Layout::row_major([64, 16]);
+        let warp_tiles = smem.zipped_divide(&[layout("16:1"), layout("16:1")]).unwrap();
+        let loads = warp_tiles.mode(0).composition(&tv).unwrap();
+        for warp_row in 0..4 {
+            let base = warp_tiles.mode(1).call(warp_row);
+            for t in 0..32 {
+                for i in 0..8 {
+                    let (row, col) = fragment(t, i);
+                    assert_eq!(base + loads.index(&[t, i].into()), (warp_row * 16 + row) * 16 + col);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_swizzle() {
+        let swizzle = Swizzle::new(3, 2, 3);
+        assert_eq!(swizzle.to_string(), "Sw<3,2,3>");
+        assert!(swizzle.is_valid() && !Swizzle::new(3, 0, 2).is_valid());
+        assert_eq!(swizzle.block_len(), 32);
+        // Bits 5..8 flip bits 2..5; everything else is untouched.
+        assert_eq!(swizzle.apply(0b0010_0011), 0b0010_0111);
+        assert_eq!(swizzle.apply(0b1101_0101), 0b1100_1101);
+        assert_eq!(swizzle.apply(0b1_0000_0000), 0b1_0000_0000);
+        assert_eq!(Swizzle::identity().apply(12345), 12345);
+
+        // A swizzle only permutes offsets within each block.
+        for swizzle in [swizzle, Swizzle::new(2, 0, 4), Swizzle::new(4, 0, 5), Swizzle::new(1, 3, 1)] {
+            let block = swizzle.block_len() << swizzle.shift;
+            let mut seen: Vec<_> = (0..block).map(|i| swizzle.apply(i)).collect();
+            assert!(seen.iter().enumerate().all(|(i, &o)| o / swizzle.block_len() == i / swizzle.block_len()));
+            seen.sort_unstable();
+            assert_eq!(seen, (0..block).collect::<Vec<_>>(), "{}", swizzle);
+        }
+
+        let smem = SwizzledLayout::new(swizzle, Layout::row_major([16, 16]));
+        assert_eq!(smem.to_string(), "Sw<3,2,3> o (16,16):(16,1)");
+        assert_eq!(smem.cosize(), 256);
+        let column = smem.composition(&layout("16:1")).unwrap();
+        assert_eq!(column.layout(), &layout("16:16"));
+        assert_eq!(column.call(3), swizzle.apply(48));
+    }
+}
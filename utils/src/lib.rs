@@ -1,3 +1,13 @@
+pub mod arch;
+pub mod bank_conflicts;
+pub mod layout;
+pub mod occupancy;
+pub mod random;
 pub mod tensor_defs;
+pub mod wmma;
 
-pub use tensor_defs::{TensorLayout, TensorShape, MemoryLayout};
+pub use arch::{DataType, GpuArch, GpuSku, MmaShape};
+pub use bank_conflicts::{bank_conflicts, layout_bank_conflicts, BankReport};
+pub use layout::{IntTuple, Layout, LayoutError, Swizzle, SwizzledLayout};
+pub use occupancy::{occupancy, BlockLimits, KernelResources, Occupancy, OccupancyError, OccupancyLimiter, SmResources};
+pub use tensor_defs::{TensorLayout, TensorShape, MemoryLayout, Run, RunAxis, TileConfig};
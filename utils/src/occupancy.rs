@@ -0,0 +1,291 @@
+//! Theoretical occupancy, following the CUDA occupancy calculator spreadsheet
+//! (and `cuda_occupancy.h`). Everything here is pure arithmetic on published
+//! per-SM limits, so it works on machines without a GPU.
+
+use std::fmt;
+
+use crate::tensor_defs::TileConfig;
+
+/// Per-SM resources of one architecture. [`crate::GpuArch`] has the values
+/// for each supported compute capability.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SmResources {
+    pub warp_size: u32,
+    pub max_threads_per_block: u32,
+    pub max_warps_per_sm: u32,
+    pub max_blocks_per_sm: u32,
+    pub registers_per_sm: u32,
+    pub max_registers_per_thread: u32,
+    /// Registers are allocated per warp in units of this many.
+    pub register_alloc_unit: u32,
+    /// The register file is split evenly between this many schedulers and a
+    /// warp's registers must come from a single partition.
+    pub register_file_partitions: u32,
+    /// Shared memory available to blocks with the largest carve-out.
+    pub shared_mem_per_sm: u32,
+    /// Largest static plus dynamic shared memory a single block may use.
+    pub max_shared_mem_per_block: u32,
+    pub shared_mem_alloc_unit: u32,
+    /// Shared memory the driver reserves for every resident block.
+    pub reserved_shared_mem_per_block: u32,
+}
+
+/// What a kernel asks of the SM for each block.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KernelResources {
+    pub threads_per_block: u32,
+    pub registers_per_thread: u32,
+    pub static_shared_mem_bytes: u32,
+    pub dynamic_shared_mem_bytes: u32,
+}
+
+impl KernelResources {
+    /// Resources of a block computing one `tile` with `elem_bytes`-sized
+    /// operands staged in shared memory.
+    pub const fn for_tile(tile: &TileConfig, registers_per_thread: u32, elem_bytes: usize) -> Self {
+        Self {
+            threads_per_block: tile.threads_per_block() as u32,
+            registers_per_thread,
+            static_shared_mem_bytes: tile.shared_mem_bytes(elem_bytes) as u32,
+            dynamic_shared_mem_bytes: 0,
+        }
+    }
+
+    pub const fn shared_mem_bytes(&self) -> u32 {
+        self.static_shared_mem_bytes + self.dynamic_shared_mem_bytes
+    }
+}
+
+/// The resource that caps the number of resident blocks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum OccupancyLimiter {
+    Warps,
+    Registers,
+    SharedMemory,
+    Blocks,
+}
+
+impl fmt::Display for OccupancyLimiter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            OccupancyLimiter::Warps => "warps",
+            OccupancyLimiter::Registers => "registers",
+            OccupancyLimiter::SharedMemory => "shared memory",
+            OccupancyLimiter::Blocks => "blocks per SM",
+        })
+    }
+}
+
+/// Blocks per SM allowed by each resource on its own.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BlockLimits {
+    pub warps: u32,
+    pub registers: u32,
+    pub shared_memory: u32,
+    pub blocks: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Occupancy {
+    pub active_blocks_per_sm: u32,
+    pub active_warps_per_sm: u32,
+    pub max_warps_per_sm: u32,
+    pub limiter: OccupancyLimiter,
+    pub limits: BlockLimits,
+}
+
+impl Occupancy {
+    /// Active warps as a fraction of the SM's warp slots.
+    pub fn theoretical(&self) -> f64 {
+        self.active_warps_per_sm as f64 / self.max_warps_per_sm as f64
+    }
+}
+
+/// A block that cannot become resident at all.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OccupancyError {
+    EmptyBlock,
+    TooManyThreads { threads: u32, max: u32 },
+    TooManyRegisters { registers: u32, max: u32 },
+    TooMuchSharedMemory { bytes: u32, max: u32 },
+}
+
+impl fmt::Display for OccupancyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OccupancyError::EmptyBlock => write!(f, "block has no threads"),
+            OccupancyError::TooManyThreads { threads, max } => {
+                write!(f, "{} threads per block exceeds the limit of {}", threads, max)
+            }
+            OccupancyError::TooManyRegisters { registers, max } => {
+                write!(f, "{} registers per thread exceeds the limit of {}", registers, max)
+            }
+            OccupancyError::TooMuchSharedMemory { bytes, max } => {
+                write!(f, "{} bytes of shared memory per block exceeds the limit of {}", bytes, max)
+            }
+        }
+    }
+}
+
+impl std::error::Error for OccupancyError {}
+
+const fn round_up(value: u32, unit: u32) -> u32 {
+    value.div_ceil(unit) * unit
+}
+
+/// Computes how many blocks of `kernel` can be resident on one SM of `sm`.
+pub fn occupancy(kernel: &KernelResources, sm: &SmResources) -> Result<Occupancy, OccupancyError> {
+    if kernel.threads_per_block == 0 {
+        return Err(OccupancyError::EmptyBlock);
+    }
+    if kernel.threads_per_block > sm.max_threads_per_block {
+        return Err(OccupancyError::TooManyThreads {
+            threads: kernel.threads_per_block,
+            max: sm.max_threads_per_block,
+        });
+    }
+    if kernel.registers_per_thread > sm.max_registers_per_thread {
+        return Err(OccupancyError::TooManyRegisters {
+            registers: kernel.registers_per_thread,
+            max: sm.max_registers_per_thread,
+        });
+    }
+    if kernel.shared_mem_bytes() > sm.max_shared_mem_per_block {
+        return Err(OccupancyError::TooMuchSharedMemory {
+            bytes: kernel.shared_mem_bytes(),
+            max: sm.max_shared_mem_per_block,
+        });
+    }
+
+    let warps_per_block = kernel.threads_per_block.div_ceil(sm.warp_size);
+
+    let by_warps = sm.max_warps_per_sm / warps_per_block;
+
+    // Zero registers means "unknown", which the spreadsheet treats as no limit.
+    let by_registers = if kernel.registers_per_thread == 0 {
+        u32::MAX
+    } else {
+        let regs_per_warp = round_up(kernel.registers_per_thread * sm.warp_size, sm.register_alloc_unit);
+        let regs_per_partition = sm.registers_per_sm / sm.register_file_partitions;
+        let warps = (regs_per_partition / regs_per_warp) * sm.register_file_partitions;
+        warps / warps_per_block
+    };
+
+    let by_shared_memory = if kernel.shared_mem_bytes() == 0 {
+        u32::MAX
+    } else {
+        let per_block = round_up(
+            kernel.shared_mem_bytes() + sm.reserved_shared_mem_per_block,
+            sm.shared_mem_alloc_unit,
+        );
+        sm.shared_mem_per_sm / per_block
+    };
+
+    let limits = BlockLimits {
+        warps: by_warps,
+        registers: by_registers,
+        shared_memory: by_shared_memory,
+        blocks: sm.max_blocks_per_sm,
+    };
+    let (limiter, active_blocks_per_sm) = [
+        (OccupancyLimiter::Warps, limits.warps),
+        (OccupancyLimiter::Registers, limits.registers),
+        (OccupancyLimiter::SharedMemory, limits.shared_memory),
+        (OccupancyLimiter::Blocks, limits.blocks),
+    ]
+    .into_iter()
+    .min_by_key(|&(_, blocks)| blocks)
+    .unwrap();
+
+    Ok(Occupancy {
+        active_blocks_per_sm,
+        active_warps_per_sm: active_blocks_per_sm * warps_per_block,
+        max_warps_per_sm: sm.max_warps_per_sm,
+        limiter,
+        limits,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arch::GpuArch;
+
+    fn sm(major: u32, minor: u32) -> SmResources {
+        GpuArch::lookup(major, minor).unwrap().sm
+    }
+
+    fn kernel(threads: u32, registers: u32, shared_mem: u32) -> KernelResources {
+        KernelResources {
+            threads_per_block: threads,
+            registers_per_thread: registers,
+            static_shared_mem_bytes: shared_mem,
+            dynamic_shared_mem_bytes: 0,
+        }
+    }
+
+    #[test]
+    fn test_register_limited() {
+        // 256 threads x 64 registers on A100: 2048 registers per warp, 8 warps
+        // per register partition, 4 blocks of 8 warps -> 50%.
+        let occ = occupancy(&kernel(256, 64, 16 * 1024), &sm(8, 0)).unwrap();
+        assert_eq!(occ.active_blocks_per_sm, 4);
+        assert_eq!(occ.active_warps_per_sm, 32);
+        assert_eq!(occ.limiter, OccupancyLimiter::Registers);
+        assert_eq!(occ.theoretical(), 0.5);
+        assert_eq!(
+            occ.limits,
+            BlockLimits { warps: 8, registers: 4, shared_memory: 9, blocks: 32 }
+        );
+    }
+
+    #[test]
+    fn test_shared_memory_and_block_limits() {
+        // 48 KB + 1 KB reserved per block: 3 blocks fit in 164 KB.
+        let occ = occupancy(&kernel(128, 32, 48 * 1024), &sm(8, 0)).unwrap();
+        assert_eq!(occ.active_blocks_per_sm, 3);
+        assert_eq!(occ.limiter, OccupancyLimiter::SharedMemory);
+        assert_eq!(occ.theoretical(), 12.0 / 64.0);
+
+        // The same kernel fits 4 blocks in Hopper's 228 KB.
+        let occ = occupancy(&kernel(128, 32, 48 * 1024), &sm(9, 0)).unwrap();
+        assert_eq!(occ.active_blocks_per_sm, 4);
+
+        // Single-warp blocks run out of block slots before anything else.
+        let occ = occupancy(&kernel(32, 0, 0), &sm(8, 6)).unwrap();
+        assert_eq!(occ.active_blocks_per_sm, 16);
+        assert_eq!(occ.limiter, OccupancyLimiter::Blocks);
+        assert_eq!(occ.theoretical(), 16.0 / 48.0);
+    }
+
+    #[test]
+    fn test_for_tile() {
+        let tile = TileConfig::ampere_default();
+        let resources = KernelResources::for_tile(&tile, 128, 4);
+        assert_eq!(resources.threads_per_block, 512);
+        assert_eq!(resources.shared_mem_bytes(), 16 * 1024);
+
+        // 128 registers x 32 lanes = 4096 per warp, 4 warps per partition.
+        let occ = occupancy(&resources, &sm(8, 0)).unwrap();
+        assert_eq!(occ.active_blocks_per_sm, 1);
+        assert_eq!(occ.limiter, OccupancyLimiter::Registers);
+    }
+
+    #[test]
+    fn test_unlaunchable_blocks() {
+        let sm = sm(8, 0);
+        assert_eq!(occupancy(&kernel(0, 32, 0), &sm), Err(OccupancyError::EmptyBlock));
+        assert_eq!(
+            occupancy(&kernel(2048, 32, 0), &sm),
+            Err(OccupancyError::TooManyThreads { threads: 2048, max: 1024 })
+        );
+        assert_eq!(
+            occupancy(&kernel(256, 256, 0), &sm),
+            Err(OccupancyError::TooManyRegisters { registers: 256, max: 255 })
+        );
+        assert!(matches!(
+            occupancy(&kernel(256, 32, 200 * 1024), &sm),
+            Err(OccupancyError::TooMuchSharedMemory { .. })
+        ));
+    }
+}
@@ -0,0 +1,145 @@
+//! Counter-based random matrix elements, shared by the host crate and the
+//! `generate_kernel` in `cuda-kernel`, which includes this file with
+//! `#[path]`.
+//!
+//! An element is a pure function of the key (the seed), the stream (which
+//! operand), its row and column, and the distribution, so no thread count or
+//! launch geometry can change it. Host and device agree bit for bit because
+//! this file only uses `core`, and every floating-point step is exact or a
+//! single correctly rounded conversion: no transcendental functions, and no
+//! product that a fused multiply-add could round differently.
+
+/// Uniform in [-1, 1).
+pub const UNIFORM: u32 = 0;
+/// Approximately standard normal: the Irwin-Hall sum of 12 uniforms, less 6.
+pub const NORMAL: u32 = 1;
+/// Integers in [-param, param].
+pub const SMALL_INT: u32 = 2;
+/// `row * 2^param + col`, modulo 2^24 so the value stays exact.
+pub const INDEX: u32 = 3;
+/// A per-row constant plus uniform noise scaled by 2^-param: the columns
+/// are nearly parallel, so the condition number is about 2^param.
+pub const ILL_CONDITIONED: u32 = 4;
+/// Random mantissa and sign with a binary exponent in [-param, param].
+pub const WIDE_RANGE: u32 = 5;
+/// Uniform, except that a fraction param / 2^32 of the elements is NaN,
+/// +Inf, -Inf or a subnormal.
+pub const SPECIAL: u32 = 6;
+
+/// Philox4x32-10 (Salmon et al., "Parallel Random Numbers: As Easy as 1, 2,
+/// 3", SC 2011), the generator behind cuRAND's `Philox4_32_10`.
+pub fn philox4x32(counter: [u32; 4], key: [u32; 2]) -> [u32; 4] {
+    const M0: u64 = 0xD251_1F53;
+    const M1: u64 = 0xCD9E_8D57;
+    const W0: u32 = 0x9E37_79B9;
+    const W1: u32 = 0xBB67_AE85;
+
+    let (mut c, mut k) = (counter, key);
+    for _ in 0..10 {
+        let p0 = M0 * c[0] as u64;
+        let p1 = M1 * c[2] as u64;
+        c = [
+            (p1 >> 32) as u32 ^ c[1] ^ k[0],
+            p1 as u32,
+            (p0 >> 32) as u32 ^ c[3] ^ k[1],
+            p0 as u32,
+        ];
+        k = [k[0].wrapping_add(W0), k[1].wrapping_add(W1)];
+    }
+    c
+}
+
+/// Draw number of the per-row constant of [`ILL_CONDITIONED`]; element
+/// draws count up from 0.
+const ROW_DRAW: u32 = u32::MAX;
+
+/// 24 random bits as a float in [0, 2), exactly.
+fn unit(bits: u32) -> f32 {
+    (bits >> 8) as f32 * (1.0 / (1 << 23) as f32)
+}
+
+/// The element at (`row`, `col`) of stream `stream` under distribution
+/// `kind` with parameter `param`. Unknown kinds give NaN.
+pub fn element(key: [u32; 2], stream: u32, row: u32, col: u32, kind: u32, param: u32) -> f32 {
+    let draw = |n: u32| philox4x32([row, col, stream, n], key);
+    let x = draw(0);
+    match kind {
+        UNIFORM => unit(x[0]) - 1.0,
+        NORMAL => {
+            let mut sum = 0u32;
+            for n in 0..3 {
+                for bits in draw(n) {
+                    sum += bits >> 8;
+                }
+            }
+            (sum as i32 - (6 << 24)) as f32 * (1.0 / (1 << 24) as f32)
+        }
+        SMALL_INT => {
+            let span = 2 * param as u64 + 1;
+            ((x[0] as u64 * span) >> 32) as i64 as f32 - param as f32
+        }
+        INDEX => (((row << (param & 31)) | col) & 0xff_ffff) as f32,
+        ILL_CONDITIONED => {
+            let base = unit(philox4x32([row, 0, stream, ROW_DRAW], key)[0]) - 1.0;
+            let scale = f32::from_bits((127 - param.min(126)) << 23);
+            base + (unit(x[0]) - 1.0) * scale
+        }
+        WIDE_RANGE => {
+            let param = param.min(126);
+            let exponent = ((x[1] as u64 * (2 * param as u64 + 1)) >> 32) as u32 + 127 - param;
+            f32::from_bits((x[0] & 0x8000_0000) | (exponent << 23) | (x[2] & 0x7f_ffff))
+        }
+        SPECIAL if x[1] < param => match x[2] & 3 {
+            0 => f32::NAN,
+            1 => f32::INFINITY,
+            2 => f32::NEG_INFINITY,
+            _ => f32::from_bits((x[0] & 0x8000_0000) | (x[3] & 0x7f_ffff).max(1)),
+        },
+        SPECIAL => unit(x[0]) - 1.0,
+        _ => f32::NAN,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_philox_known_answers() {
+        // From the Random123 distribution's kat_vectors.
+        assert_eq!(philox4x32([0; 4], [0; 2]), [0x6627e8d5, 0xe169c58d, 0xbc57ac4c, 0x9b00dbd8]);
+        assert_eq!(
+            philox4x32([u32::MAX; 4], [u32::MAX; 2]),
+            [0x408f276d, 0x41c83b0e, 0xa20bc7c6, 0x6d5451fd]
+        );
+        assert_eq!(
+            philox4x32([0x243f6a88, 0x85a308d3, 0x13198a2e, 0x03707344], [0xa4093822, 0x299f31d0]),
+            [0xd16cfe09, 0x94fdcceb, 0x5001e420, 0x24126ea1]
+        );
+    }
+
+    #[test]
+    fn test_element_ranges() {
+        let key = [7, 0];
+        for row in 0..64 {
+            for col in 0..64 {
+                let at = |kind, param| element(key, 1, row, col, kind, param);
+                assert!((-1.0..1.0).contains(&at(UNIFORM, 0)));
+                assert!(at(NORMAL, 0).abs() <= 6.0);
+                let small = at(SMALL_INT, 3);
+                assert!(small.fract() == 0.0 && small.abs() <= 3.0);
+                assert_eq!(at(INDEX, 6), (row * 64 + col) as f32);
+                let wide = at(WIDE_RANGE, 20).abs();
+                assert!((2f32.powi(-20)..2f32.powi(21)).contains(&wide));
+                assert!(at(SPECIAL, u32::MAX).is_nan() || !at(SPECIAL, u32::MAX).is_normal());
+                assert!((-1.0..1.0).contains(&at(SPECIAL, 0)));
+            }
+            // Every element of a row is within 2^-10 of the row's constant.
+            let row_values: [f32; 8] = core::array::from_fn(|col| element(key, 1, row, col as u32, ILL_CONDITIONED, 10));
+            assert!(row_values.iter().all(|v| (v - row_values[0]).abs() <= 2.0 * 2f32.powi(-10)));
+        }
+        assert_ne!(element(key, 0, 3, 4, UNIFORM, 0), element(key, 1, 3, 4, UNIFORM, 0));
+        assert_ne!(element(key, 0, 3, 4, UNIFORM, 0), element([8, 0], 0, 3, 4, UNIFORM, 0));
+        assert!(element(key, 0, 0, 0, 99, 0).is_nan());
+    }
+}
@@ -1,10 +1,18 @@
 use core::fmt;
 
+use crate::layout::Swizzle;
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum MemoryLayout {
     RowMajor,
     ColumnMajor,
+    /// Row-major grid of `tile_m x tile_n` tiles, each stored row-major;
+    /// `leading_dim` is unused. Tiles on the bottom and right edges are
+    /// stored whole, and their padding past the last row or column is not
+    /// part of the matrix.
     Tiled { tile_m: usize, tile_n: usize },
+    /// Row-major with the offsets swizzled, for shared-memory tiles.
+    Swizzled(Swizzle),
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -33,7 +41,7 @@ impl fmt::Display for TensorShape {
     }
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct TensorLayout {
     pub shape: TensorShape,
     pub layout: MemoryLayout,
@@ -65,6 +73,14 @@ impl TensorLayout {
         }
     }
     
+    pub fn swizzled(rows: usize, cols: usize, swizzle: Swizzle) -> Self {
+        Self {
+            shape: TensorShape::new(rows, cols),
+            layout: MemoryLayout::Swizzled(swizzle),
+            leading_dim: cols,
+        }
+    }
+    
     pub fn index(&self, row: usize, col: usize) -> usize {
         debug_assert!(row < self.shape.rows, "Row index out of bounds");
         debug_assert!(col < self.shape.cols, "Column index out of bounds");
@@ -78,28 +94,140 @@ impl TensorLayout {
                 let in_tile_row = row % tile_m;
                 let in_tile_col = col % tile_n;
                 
-                let tiles_per_row = (self.shape.cols + tile_n - 1) / tile_n;
+                let tiles_per_row = self.shape.cols.div_ceil(tile_n);
                 let tile_idx = tile_row * tiles_per_row + tile_col;
                 let in_tile_idx = in_tile_row * tile_n + in_tile_col;
                 
                 tile_idx * (tile_m * tile_n) + in_tile_idx
             }
+            MemoryLayout::Swizzled(swizzle) => swizzle.apply(row * self.leading_dim + col),
         }
     }
     
-    pub fn row_stride(&self) -> usize {
+    /// Inverse of [`TensorLayout::index`]: the element stored at `offset`,
+    /// or `None` for padding and for offsets past the storage.
+    pub fn coords_of(&self, offset: usize) -> Option<(usize, usize)> {
+        if offset >= self.storage_len() {
+            return None;
+        }
+        let ld = self.leading_dim;
+        let (row, col) = match self.layout {
+            MemoryLayout::RowMajor => (offset / ld, offset % ld),
+            MemoryLayout::ColumnMajor => (offset % ld, offset / ld),
+            MemoryLayout::Tiled { tile_m, tile_n } => {
+                let tiles_per_row = self.shape.cols.div_ceil(tile_n);
+                let (tile_idx, in_tile_idx) = (offset / (tile_m * tile_n), offset % (tile_m * tile_n));
+                (
+                    tile_idx / tiles_per_row * tile_m + in_tile_idx / tile_n,
+                    tile_idx % tiles_per_row * tile_n + in_tile_idx % tile_n,
+                )
+            }
+            // A valid swizzle is its own inverse.
+            MemoryLayout::Swizzled(swizzle) => {
+                let unswizzled = swizzle.apply(offset);
+                (unswizzled / ld, unswizzled % ld)
+            }
+        };
+        (row < self.shape.rows && col < self.shape.cols).then_some((row, col))
+    }
+    
+    /// Elements of storage the layout spans, padding included. Assumes the
+    /// layout is valid: the leading dimension covers the contiguous
+    /// dimension, tiles are non-empty and the swizzle is valid.
+    ///
+    /// Panics if the length overflows `usize`; see
+    /// [`TensorLayout::checked_storage_len`].
+    pub fn storage_len(&self) -> usize {
+        self.checked_storage_len().expect("storage length overflows usize")
+    }
+    
+    /// [`TensorLayout::storage_len`], or `None` if it overflows `usize`.
+    pub fn checked_storage_len(&self) -> Option<usize> {
+        let TensorShape { rows, cols } = self.shape;
+        if !self.shape.is_valid() {
+            return Some(0);
+        }
+        let strided = |outer: usize, inner: usize| (outer - 1).checked_mul(self.leading_dim)?.checked_add(inner);
         match self.layout {
-            MemoryLayout::RowMajor => self.leading_dim,
-            MemoryLayout::ColumnMajor => 1,
-            MemoryLayout::Tiled { tile_m, tile_n } => tile_n,
+            MemoryLayout::RowMajor => strided(rows, cols),
+            MemoryLayout::ColumnMajor => strided(cols, rows),
+            MemoryLayout::Tiled { tile_m, tile_n } => rows
+                .div_ceil(tile_m)
+                .checked_mul(cols.div_ceil(tile_n))?
+                .checked_mul(tile_m)?
+                .checked_mul(tile_n),
+            // Swizzles move offsets within blocks, so the last block is whole.
+            MemoryLayout::Swizzled(swizzle) => strided(rows, cols)?.checked_next_multiple_of(swizzle.block_len()),
         }
     }
     
-    pub fn col_stride(&self) -> usize {
+    /// The elements as maximal [`Run`]s in storage order, skipping padding.
+    pub fn runs(&self) -> impl Iterator<Item = Run> {
+        let TensorLayout { shape: TensorShape { rows, cols }, leading_dim: ld, .. } = *self;
+        let along_row = |offset, row, col, len| Run { offset, row, col, len, axis: RunAxis::AlongRow };
+        let pieces: Box<dyn Iterator<Item = Run>> = match self.layout {
+            _ if !self.shape.is_valid() => Box::new(core::iter::empty()),
+            MemoryLayout::RowMajor => Box::new((0..rows).map(move |row| along_row(row * ld, row, 0, cols))),
+            MemoryLayout::ColumnMajor => Box::new((0..cols).map(move |col| Run {
+                offset: col * ld,
+                row: 0,
+                col,
+                len: rows,
+                axis: RunAxis::DownColumn,
+            })),
+            MemoryLayout::Tiled { tile_m, tile_n } => {
+                let tiles_per_row = cols.div_ceil(tile_n);
+                let tile_rows = rows.div_ceil(tile_m) * tiles_per_row * tile_m;
+                Box::new((0..tile_rows).filter_map(move |i| {
+                    let (tile_idx, in_tile_row) = (i / tile_m, i % tile_m);
+                    let row = tile_idx / tiles_per_row * tile_m + in_tile_row;
+                    let col = tile_idx % tiles_per_row * tile_n;
+                    let offset = tile_idx * tile_m * tile_n + in_tile_row * tile_n;
+                    (row < rows).then(|| along_row(offset, row, col, tile_n.min(cols - col)))
+                }))
+            }
+            // Aligned blocks of 2^base offsets keep their order, so each one
+            // is a contiguous stretch of the unswizzled row-major storage.
+            MemoryLayout::Swizzled(swizzle) => {
+                let block = 1 << swizzle.base;
+                Box::new((0..self.storage_len()).step_by(block).flat_map(move |start| {
+                    let first = swizzle.apply(start);
+                    (first / ld..(first + block).div_ceil(ld).min(rows)).filter_map(move |row| {
+                        let begin = first.max(row * ld);
+                        let end = (first + block).min(row * ld + cols);
+                        (begin < end).then(|| along_row(start + begin - first, row, begin - row * ld, end - begin))
+                    })
+                }))
+            }
+        };
+        
+        let mut pieces = pieces.peekable();
+        core::iter::from_fn(move || {
+            let mut run = pieces.next()?;
+            while let Some(next) = pieces.next_if(|next| run.is_followed_by(next)) {
+                run.len += next.len;
+            }
+            Some(run)
+        })
+    }
+    
+    /// Storage distance between vertically adjacent elements, if it is the
+    /// same everywhere.
+    pub fn row_stride(&self) -> Option<usize> {
+        match self.layout {
+            MemoryLayout::RowMajor => Some(self.leading_dim),
+            MemoryLayout::ColumnMajor => Some(1),
+            MemoryLayout::Tiled { .. } | MemoryLayout::Swizzled(_) => None,
+        }
+    }
+    
+    /// Storage distance between horizontally adjacent elements, if it is
+    /// the same everywhere.
+    pub fn col_stride(&self) -> Option<usize> {
         match self.layout {
-            MemoryLayout::RowMajor => 1,
-            MemoryLayout::ColumnMajor => self.leading_dim,
-            MemoryLayout::Tiled { tile_m, .. } => tile_m,
+            MemoryLayout::RowMajor => Some(1),
+            MemoryLayout::ColumnMajor => Some(self.leading_dim),
+            MemoryLayout::Tiled { .. } | MemoryLayout::Swizzled(_) => None,
         }
     }
     
@@ -120,7 +248,41 @@ impl fmt::Display for TensorLayout {
     }
 }
 
-#[derive(Debug, Clone, Copy)]
+/// Direction in which a [`Run`]'s elements advance.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RunAxis {
+    AlongRow,
+    DownColumn,
+}
+
+/// `len` neighbouring elements of one row (or column) that are also
+/// contiguous in storage, starting at `offset`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Run {
+    pub offset: usize,
+    pub row: usize,
+    pub col: usize,
+    pub len: usize,
+    pub axis: RunAxis,
+}
+
+impl Run {
+    /// Coordinates of element `i` of the run.
+    pub const fn coords(&self, i: usize) -> (usize, usize) {
+        match self.axis {
+            RunAxis::AlongRow => (self.row, self.col + i),
+            RunAxis::DownColumn => (self.row + i, self.col),
+        }
+    }
+    
+    fn is_followed_by(&self, next: &Run) -> bool {
+        next.axis == self.axis
+            && next.offset == self.offset + self.len
+            && (next.row, next.col) == self.coords(self.len)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct TileConfig {
     pub tile_m: usize,
     pub tile_n: usize,
@@ -154,14 +316,20 @@ impl TileConfig {
     }
     
     pub const fn warps_per_block(&self) -> usize {
-        let warps_m = (self.tile_m + self.warp_m - 1) / self.warp_m;
-        let warps_n = (self.tile_n + self.warp_n - 1) / self.warp_n;
+        let warps_m = self.tile_m.div_ceil(self.warp_m);
+        let warps_n = self.tile_n.div_ceil(self.warp_n);
         warps_m * warps_n
     }
     
     pub const fn threads_per_block(&self) -> usize {
         self.warps_per_block() * 32
     }
+
+    /// Shared memory for one A tile (tile_m x tile_k) and one B tile
+    /// (tile_k x tile_n) of `elem_bytes`-sized elements.
+    pub const fn shared_mem_bytes(&self, elem_bytes: usize) -> usize {
+        (self.tile_m * self.tile_k + self.tile_k * self.tile_n) * elem_bytes
+    }
 }
 
 #[cfg(test)]
@@ -195,6 +363,129 @@ mod tests {
         assert_eq!(layout.index(1, 1), 5);
     }
     
+    #[test]
+    fn test_swizzled_layout() {
+        let layout = TensorLayout::swizzled(8, 8, Swizzle::new(3, 0, 3));
+        // Row r has its columns XORed with r.
+        assert_eq!(layout.index(0, 5), 5);
+        assert_eq!(layout.index(1, 0), 9);
+        assert_eq!(layout.index(3, 6), 3 * 8 + 5);
+        assert_eq!(layout.index(7, 7), 56);
+    }
+    
+    /// Layouts covering padding, ragged edge tiles, swizzles and empty
+    /// shapes.
+    fn sample_layouts() -> Vec<TensorLayout> {
+        let mut padded_rows = TensorLayout::row_major(5, 3);
+        padded_rows.leading_dim = 7;
+        let mut padded_cols = TensorLayout::column_major(5, 3);
+        padded_cols.leading_dim = 8;
+        let mut padded_swizzle = TensorLayout::swizzled(6, 10, Swizzle::new(2, 1, 3));
+        padded_swizzle.leading_dim = 12;
+        vec![
+            TensorLayout::row_major(4, 3),
+            TensorLayout::column_major(4, 3),
+            padded_rows,
+            padded_cols,
+            TensorLayout::tiled(8, 12, 4, 3),
+            TensorLayout::tiled(5, 5, 4, 4),
+            TensorLayout::tiled(7, 10, 3, 4),
+            TensorLayout::tiled(3, 9, 1, 4),
+            TensorLayout::tiled(2, 2, 4, 4),
+            TensorLayout::swizzled(8, 8, Swizzle::new(3, 0, 3)),
+            TensorLayout::swizzled(16, 16, Swizzle::new(3, 2, 3)),
+            TensorLayout::swizzled(5, 6, Swizzle::new(1, 2, 1)),
+            padded_swizzle,
+            TensorLayout::row_major(0, 3),
+            TensorLayout::tiled(0, 5, 2, 2),
+        ]
+    }
+    
+    #[test]
+    fn test_index_and_coords_are_inverse() {
+        for layout in sample_layouts() {
+            let len = layout.storage_len();
+            let mut elements = 0;
+            for offset in 0..len + 3 {
+                if let Some((row, col)) = layout.coords_of(offset) {
+                    assert_eq!(layout.index(row, col), offset, "{}", layout);
+                    elements += 1;
+                }
+            }
+            assert_eq!(elements, layout.shape.size(), "{}", layout);
+            for row in 0..layout.shape.rows {
+                for col in 0..layout.shape.cols {
+                    let offset = layout.index(row, col);
+                    assert!(offset < len, "{} at ({}, {})", layout, row, col);
+                    assert_eq!(layout.coords_of(offset), Some((row, col)), "{}", layout);
+                }
+            }
+        }
+    }
+    
+    #[test]
+    fn test_ragged_tiles() {
+        // A 5x5 matrix in 4x4 tiles is stored as four whole tiles.
+        let layout = TensorLayout::tiled(5, 5, 4, 4);
+        assert_eq!(layout.storage_len(), 64);
+        assert_eq!(layout.coords_of(3), Some((0, 3)));
+        assert_eq!(layout.coords_of(16), Some((0, 4)));
+        assert_eq!(layout.coords_of(17), None);
+        assert_eq!(layout.coords_of(32), Some((4, 0)));
+        assert_eq!(layout.coords_of(36), None);
+        assert_eq!(layout.coords_of(64), None);
+        assert_eq!(layout.row_stride(), None);
+        assert_eq!(TensorLayout::column_major(4, 3).row_stride(), Some(1));
+        assert_eq!(TensorLayout::column_major(4, 3).col_stride(), Some(4));
+    }
+    
+    #[test]
+    fn test_checked_storage_len() {
+        let huge = 1 << 40;
+        assert_eq!(TensorLayout::row_major(3, 4).checked_storage_len(), Some(12));
+        assert_eq!(TensorLayout::row_major(0, usize::MAX).checked_storage_len(), Some(0));
+        assert_eq!(TensorLayout::row_major(huge, huge).checked_storage_len(), None);
+        assert_eq!(TensorLayout::column_major(huge, huge).checked_storage_len(), None);
+        assert_eq!(TensorLayout::tiled(huge, huge, 4, 4).checked_storage_len(), None);
+        assert_eq!(TensorLayout::tiled(1, 1, 1 << 33, 1 << 33).checked_storage_len(), None);
+        assert_eq!(TensorLayout::swizzled(2, usize::MAX - 2, Swizzle::new(3, 0, 3)).checked_storage_len(), None);
+        assert!(!Swizzle::new(1 << 31, 0, 1 << 31).is_valid());
+    }
+    
+    #[test]
+    fn test_runs() {
+        for layout in sample_layouts() {
+            let runs: Vec<_> = layout.runs().collect();
+            let mut seen = vec![false; layout.storage_len()];
+            for (i, run) in runs.iter().enumerate() {
+                assert!(run.len > 0);
+                for j in 0..run.len {
+                    let (row, col) = run.coords(j);
+                    assert_eq!(layout.index(row, col), run.offset + j, "{} {:?}", layout, run);
+                    assert!(!seen[run.offset + j]);
+                    seen[run.offset + j] = true;
+                }
+                // In storage order, and not extendable into the next run.
+                if let Some(next) = runs.get(i + 1) {
+                    assert!(next.offset >= run.offset + run.len);
+                    assert!(!run.is_followed_by(next), "{} {:?}", layout, run);
+                }
+            }
+            assert_eq!(seen.iter().filter(|&&element| element).count(), layout.shape.size());
+        }
+        
+        let runs = |layout: TensorLayout| layout.runs().map(|run| (run.offset, run.len)).collect::<Vec<_>>();
+        assert_eq!(runs(TensorLayout::row_major(2, 3)), [(0, 3), (3, 3)]);
+        assert_eq!(runs(TensorLayout::tiled(5, 5, 4, 4))[..6], [(0, 4), (4, 4), (8, 4), (12, 4), (16, 1), (20, 1)]);
+        // One-row tiles side by side join up.
+        assert_eq!(runs(TensorLayout::tiled(3, 9, 1, 4)), [(0, 9), (12, 9), (24, 9)]);
+        // Sw<3,2,3> leaves rows 0 and 1 alone and moves aligned groups of
+        // four elsewhere.
+        let swizzled = runs(TensorLayout::swizzled(16, 16, Swizzle::new(3, 2, 3)));
+        assert_eq!(swizzled[..2], [(0, 16), (16, 16)]);
+        assert!(swizzled.iter().all(|&(_, len)| len % 4 == 0));
+    }
+    
     #[test]
     fn test_gemm_compatibility() {
         let a = TensorLayout::row_major(10, 20);
@@ -210,5 +501,7 @@ mod tests {
         assert_eq!(config.tile_m, 128);
         assert_eq!(config.tile_n, 128);
         assert!(config.threads_per_block() > 0);
+        // 128x16 A tile plus 16x128 B tile of f32.
+        assert_eq!(config.shared_mem_bytes(4), 16 * 1024);
     }
 }
@@ -0,0 +1,15 @@
+//! Block geometry of `gemm_kernel_wmma`, shared by the host crate and
+//! `cuda-kernel`, which includes this file with `#[path]`, so the launch
+//! the host computes always matches where the kernel's warps write.
+
+/// Side of the 16x16 output tile one warp computes per WMMA operation.
+pub const WMMA_TILE: u32 = 16;
+/// A block's warps form a square grid of tiles this many warps wide.
+pub const WMMA_WARPS_PER_ROW: u32 = 4;
+/// Rows and columns of C one block computes.
+pub const WMMA_BLOCK_TILE: u32 = WMMA_WARPS_PER_ROW * WMMA_TILE;
+
+/// Block-relative (row, col) of the tile warp `warp_id` computes.
+pub const fn wmma_warp_origin(warp_id: u32) -> (u32, u32) {
+    ((warp_id / WMMA_WARPS_PER_ROW) * WMMA_TILE, (warp_id % WMMA_WARPS_PER_ROW) * WMMA_TILE)
+}